@@ -1,11 +1,37 @@
 /* Things are always a struct until they become something else */
 
-use crate::{cli::Args, error::Error, state_machine::StateMachine, torrent::Torrent};
-use {clap::Parser, log::LevelFilter, simple_logger::SimpleLogger};
+use crate::{
+    cli::{Args, Command},
+    dht::{DhtNode, NodeId, RoutingTable},
+    error::Error,
+    http::{ScrapeRequest, TrackerAddress},
+    magnet::{MagnetLink, MetadataFetcher},
+    state_machine::{identity::generate_random_identity, StateMachine},
+    torrent::Torrent,
+    utp::UtpSocket,
+};
+use {
+    bendy::decoding::Decoder,
+    clap::Parser,
+    crossbeam_channel::Receiver,
+    log::LevelFilter,
+    simple_logger::SimpleLogger,
+    std::{
+        fs,
+        net::{Ipv4Addr, SocketAddr},
+        path::{Path, PathBuf},
+        sync::Arc,
+    },
+};
 
 pub struct App {}
 
 impl App {
+    /// File of the working directory keeping the DHT routing table between runs
+    const DHT_ROUTING_TABLE_FILE: &'static str = ".dht_routing_table";
+    /// UDP port of the DHT node, the one of the client being taken by uTP
+    const DHT_PORT: u16 = StateMachine::CLIENT_PORT + 1;
+
     pub fn run() -> Result<(), Error> {
         let args = Args::parse();
         if args.debug() {
@@ -14,14 +40,182 @@ impl App {
             Self::init_logger(LevelFilter::Info);
         }
 
-        let torrent = Torrent::from_file(args.torrent_file())?;
+        if let Some(Command::Scrape { torrent_file }) = args.command() {
+            return Self::scrape(torrent_file);
+        }
+
+        let torrent = match args.torrent_file().to_str() {
+            Some(link) if MagnetLink::is_magnet_link(link) => {
+                Self::torrent_from_magnet_link(link, args.save_torrent())?
+            }
+            _ => Torrent::from_file(args.torrent_file())?,
+        };
         let directory = args.working_directory();
         let mock_peers = args.mock();
-        StateMachine::new(torrent, directory, mock_peers).run();
+        let shutdown = Self::shutdown_on_interrupt()?;
+
+        if torrent.is_private() {
+            log::info!("Private torrent, the DHT is not used");
+        }
+        let dht = match args.dht() && !mock_peers && !torrent.is_private() {
+            true => Self::start_dht(directory),
+            false => None,
+        };
+        let bootstrap_nodes = match dht {
+            Some(_) => Self::dht_bootstrap_nodes(&args, &torrent),
+            None => vec![],
+        };
+        let mut state_machine =
+            StateMachine::new(torrent, directory, mock_peers, args.encryption())
+                .with_request_queue_depth(args.request_queue_depth());
+        if let Some(dht) = &dht {
+            state_machine = state_machine.with_dht(dht.clone(), bootstrap_nodes);
+        }
+        if let Some(utp_socket) = Self::start_utp(args.utp() && !mock_peers) {
+            state_machine = state_machine.with_utp(utp_socket);
+        }
+        state_machine.run(shutdown);
+
+        if let Some(dht) = dht {
+            let path = directory.join(Self::DHT_ROUTING_TABLE_FILE);
+            if let Err(e) = dht.save_routing_table(&path) {
+                log::warn!("Could not save the DHT routing table: {:?}", e);
+            }
+        }
 
         Ok(())
     }
 
+    /// Binds the uTP socket on the port of the client, the peers are only
+    /// reached over TCP if it cannot be bound.
+    fn start_utp(enabled: bool) -> Option<Arc<UtpSocket>> {
+        if !enabled {
+            return None;
+        }
+        let address = SocketAddr::from((Ipv4Addr::UNSPECIFIED, StateMachine::CLIENT_PORT));
+
+        match UtpSocket::bind(address) {
+            Ok(utp_socket) => {
+                log::info!("uTP listening on {:?}", utp_socket.local_addr());
+                Some(Arc::new(utp_socket))
+            }
+            Err(e) => {
+                log::warn!("Could not start uTP: {:?}", e);
+                None
+            }
+        }
+    }
+
+    /// Starts a DHT node on its own port, with the routing table of
+    /// the previous run if there is one. The download goes on without the
+    /// DHT if the node cannot be started.
+    fn start_dht(directory: &Path) -> Option<Arc<DhtNode>> {
+        let routing_table = RoutingTable::load(&directory.join(Self::DHT_ROUTING_TABLE_FILE))
+            .unwrap_or_else(|_| RoutingTable::new(NodeId::random()));
+        let address = SocketAddr::from((Ipv4Addr::UNSPECIFIED, Self::DHT_PORT));
+
+        match DhtNode::bind(address, routing_table) {
+            Ok(dht) => Some(Arc::new(dht)),
+            Err(e) => {
+                log::warn!("Could not start the DHT node: {:?}", e);
+                None
+            }
+        }
+    }
+
+    /// The nodes given on the command line, or the well-known ones, and the
+    /// nodes of the torrent.
+    fn dht_bootstrap_nodes(args: &Args, torrent: &Torrent) -> Vec<SocketAddr> {
+        let mut nodes = match args.dht_nodes().is_empty() {
+            true => DhtNode::BOOTSTRAP_NODES.map(String::from).to_vec(),
+            false => args.dht_nodes().clone(),
+        };
+        nodes.extend(
+            torrent
+                .nodes()
+                .iter()
+                .map(|(host, port)| match host.contains(':') {
+                    true => format!("[{}]:{}", host, port),
+                    false => format!("{}:{}", host, port),
+                }),
+        );
+
+        DhtNode::resolve(&nodes)
+    }
+
+    /// Downloads the info dictionary from the peers, then loads the torrent
+    /// as if it came from a .torrent file.
+    fn torrent_from_magnet_link(
+        link: &str,
+        save_torrent: Option<&PathBuf>,
+    ) -> Result<Torrent, Error> {
+        let magnet_link = MagnetLink::parse(link)?;
+        log::info!(
+            "Downloading the metadata of {}",
+            magnet_link.display_name().unwrap_or(&link.to_string())
+        );
+
+        let info_dictionary =
+            MetadataFetcher::new(magnet_link.clone(), generate_random_identity()).fetch()?;
+        let bencode = magnet_link.torrent_bencode(&info_dictionary);
+
+        if let Some(path) = save_torrent {
+            fs::write(path, &bencode).map_err(|_| Error::FailedToWriteTorrentFile)?;
+            log::info!("Torrent saved to {:?}", path);
+        }
+
+        Torrent::from_bencode(&mut Decoder::new(&bencode))
+    }
+
+    /// Prints the statistics of the first tracker answering the scrape.
+    fn scrape(torrent_file: &Path) -> Result<(), Error> {
+        let torrent = Torrent::from_file(torrent_file)?;
+        let mut last_error = Error::NoTrackerAvailable;
+
+        for announce in torrent.announce_tiers().concat() {
+            let response = TrackerAddress::from_announce(&announce).and_then(|tracker| {
+                ScrapeRequest::send_request(ScrapeRequest::new(vec![torrent.info_hash()]), tracker)
+            });
+
+            match response {
+                Ok(response) if response.failure().is_some() => {
+                    log::warn!("{} refused the scrape: {:?}", announce, response.failure());
+                    last_error = Error::TrackerRefusedAnnounce;
+                }
+                Ok(response) => match response.statistics(&torrent.info_hash()) {
+                    Some(statistics) => {
+                        println!("Tracker: {}", announce);
+                        println!("Seeders (complete): {}", statistics.complete());
+                        println!("Leechers (incomplete): {}", statistics.incomplete());
+                        println!(
+                            "Completed downloads (downloaded): {}",
+                            statistics.downloaded()
+                        );
+                        return Ok(());
+                    }
+                    None => log::warn!("{} does not know the torrent", announce),
+                },
+                Err(e) => {
+                    log::warn!("Could not scrape {}: {:?}", announce, e);
+                    last_error = e;
+                }
+            }
+        }
+
+        Err(last_error)
+    }
+
+    /// The returned receiver gets a message when the user hits Ctrl-C.
+    fn shutdown_on_interrupt() -> Result<Receiver<()>, Error> {
+        let (shutdown_sender, shutdown_receiver) = crossbeam_channel::bounded(1);
+        ctrlc::set_handler(move || {
+            let _ = shutdown_sender.try_send(());
+        })
+        .map_err(|_| Error::FailedToSetInterruptHandler)?;
+
+        Ok(shutdown_receiver)
+    }
+
     fn init_logger(level: LevelFilter) {
         SimpleLogger::new().with_level(level).init().unwrap();
     }
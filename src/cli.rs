@@ -1,14 +1,21 @@
-use clap::{ArgAction, Parser};
+use crate::{state_machine::block_requests::BlockRequests, tcp::EncryptionPolicy};
+use clap::{ArgAction, Parser, Subcommand};
 use std::path::PathBuf;
 
 /// A very humble Torrent client made with all our effort
 #[derive(Parser, Debug)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct Args {
-    /// The .torrent file path
-    torrent_file: PathBuf,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// The .torrent file path, or a magnet link
+    #[arg(required = true)]
+    torrent_file: Option<PathBuf>,
 
     /// The download path to store/upload the file described in .torrent
-    working_directory: PathBuf,
+    #[arg(required = true)]
+    working_directory: Option<PathBuf>,
 
     /// Gives network peers information (bittorrent application, address IP, port, download/upload piece state)
     #[arg(short, long, action = ArgAction::SetTrue)]
@@ -18,18 +25,57 @@ pub struct Args {
     #[arg(short, long,  action = ArgAction::SetTrue)]
     debug: bool,
 
+    /// Save the .torrent file rebuilt from a magnet link
+    #[arg(short, long, value_name = "FILE")]
+    save_torrent: Option<PathBuf>,
+
     /// Communicate directly with three local peers using ports 2001, 2002 and 2003.
     #[arg(short, long,  action = ArgAction::SetTrue)]
     mock: bool,
+
+    /// Do not look for peers in the DHT
+    #[arg(long, action = ArgAction::SetTrue)]
+    no_dht: bool,
+
+    /// DHT node to bootstrap from, instead of the well-known ones (can be repeated)
+    #[arg(long, value_name = "HOST:PORT")]
+    dht_node: Vec<String>,
+
+    /// Encryption of the connections with the peers
+    #[arg(long, value_enum, default_value_t = EncryptionPolicy::Prefer)]
+    encryption: EncryptionPolicy,
+
+    /// Connect to the peers over uTP first, which yields to the other traffic of the link
+    #[arg(long, action = ArgAction::SetTrue)]
+    utp: bool,
+
+    /// Block requests kept outstanding with each peer, fewer if the peer accepts less
+    #[arg(long, value_name = "BLOCKS", default_value_t = BlockRequests::DEFAULT_QUEUE_DEPTH)]
+    request_queue_depth: usize,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Print the seeders, leechers and completed downloads reported by the trackers
+    Scrape {
+        /// The .torrent file path
+        torrent_file: PathBuf,
+    },
 }
 
 impl Args {
+    pub fn command(&self) -> Option<&Command> {
+        self.command.as_ref()
+    }
+
+    /// Always given when there is no subcommand.
     pub fn torrent_file(&self) -> &PathBuf {
-        &self.torrent_file
+        self.torrent_file.as_ref().unwrap()
     }
 
+    /// Always given when there is no subcommand.
     pub fn working_directory(&self) -> &PathBuf {
-        &self.working_directory
+        self.working_directory.as_ref().unwrap()
     }
 
     pub fn info(&self) -> bool {
@@ -40,7 +86,31 @@ impl Args {
         self.debug
     }
 
+    pub fn save_torrent(&self) -> Option<&PathBuf> {
+        self.save_torrent.as_ref()
+    }
+
     pub fn mock(&self) -> bool {
         self.mock
     }
+
+    pub fn dht(&self) -> bool {
+        !self.no_dht
+    }
+
+    pub fn dht_nodes(&self) -> &Vec<String> {
+        &self.dht_node
+    }
+
+    pub fn encryption(&self) -> EncryptionPolicy {
+        self.encryption
+    }
+
+    pub fn utp(&self) -> bool {
+        self.utp
+    }
+
+    pub fn request_queue_depth(&self) -> usize {
+        self.request_queue_depth
+    }
 }
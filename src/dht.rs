@@ -0,0 +1,19 @@
+mod dht_node;
+mod krpc_message;
+mod krpc_query;
+mod krpc_response;
+mod node_id;
+mod node_info;
+mod peer_store;
+mod routing_table;
+mod token_secret;
+
+pub use dht_node::DhtNode;
+pub use krpc_message::KrpcMessage;
+pub use krpc_query::KrpcQuery;
+pub use krpc_response::KrpcResponse;
+pub use node_id::NodeId;
+pub use node_info::NodeInfo;
+pub use peer_store::PeerStore;
+pub use routing_table::RoutingTable;
+pub use token_secret::TokenSecret;
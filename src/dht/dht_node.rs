@@ -0,0 +1,491 @@
+use {
+    crate::{
+        dht::{
+            KrpcMessage, KrpcQuery, KrpcResponse, NodeId, NodeInfo, PeerStore, RoutingTable,
+            TokenSecret,
+        },
+        http::Peer,
+        pwp::{FromBytes, IntoBytes},
+        Error,
+    },
+    crossbeam_channel::Sender,
+    std::{
+        collections::{BTreeMap, HashMap, HashSet},
+        net::{SocketAddr, ToSocketAddrs, UdpSocket},
+        path::Path,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc, Mutex,
+        },
+        thread::{self, JoinHandle},
+        time::{Duration, Instant},
+    },
+};
+
+/// Node of the mainline DHT (BEP 5). A thread answers the queries of the
+/// other nodes and hands the answers to our own queries back to the threads
+/// waiting for them.
+#[derive(Debug)]
+pub struct DhtNode {
+    socket: UdpSocket,
+    local_address: SocketAddr,
+    shared: Arc<SharedState>,
+    receiver: Option<JoinHandle<()>>,
+    query_timeout: Duration,
+}
+
+/// State used by both the node and the thread receiving the messages
+#[derive(Debug)]
+struct SharedState {
+    id: NodeId,
+    tables: Mutex<Tables>,
+    pending_queries: Mutex<HashMap<Vec<u8>, PendingQuery>>,
+    running: AtomicBool,
+}
+
+#[derive(Debug)]
+struct Tables {
+    routing_table: RoutingTable,
+    token_secret: TokenSecret,
+    peer_store: PeerStore,
+}
+
+/// Query waiting for its answer. Without sender, nobody waits for it and the
+/// answering node is only added to the routing table.
+#[derive(Debug)]
+struct PendingQuery {
+    address: SocketAddr,
+    sender: Option<Sender<KrpcMessage>>,
+    sent_at: Instant,
+}
+
+/// Outcome of an iterative lookup
+struct Lookup {
+    /// the closest nodes that answered, with the token they gave
+    closest: Vec<(NodeInfo, Option<Vec<u8>>)>,
+    peers: Vec<Peer>,
+}
+
+impl DhtNode {
+    pub const QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+    /// Number of nodes queried at the same time during lookups
+    pub const ALPHA: usize = 3;
+    /// Well-known nodes used to join the DHT
+    pub const BOOTSTRAP_NODES: [&'static str; 3] = [
+        "router.bittorrent.com:6881",
+        "dht.transmissionbt.com:6881",
+        "router.utorrent.com:6881",
+    ];
+    const MAX_PACKET_SIZE: usize = 2048;
+    /// How often the receiving thread checks whether the node was dropped
+    const READ_TIMEOUT: Duration = Duration::from_millis(100);
+
+    pub fn bind(address: SocketAddr, routing_table: RoutingTable) -> Result<Self, Error> {
+        Self::with_timeout(address, routing_table, Self::QUERY_TIMEOUT)
+    }
+
+    pub fn with_timeout(
+        address: SocketAddr,
+        routing_table: RoutingTable,
+        query_timeout: Duration,
+    ) -> Result<Self, Error> {
+        let socket = UdpSocket::bind(address).map_err(|_| Error::FailedToBindDhtSocket)?;
+        socket
+            .set_read_timeout(Some(Self::READ_TIMEOUT))
+            .map_err(|_| Error::FailedToBindDhtSocket)?;
+        let local_address = socket
+            .local_addr()
+            .map_err(|_| Error::FailedToBindDhtSocket)?;
+        let receiving_socket = socket
+            .try_clone()
+            .map_err(|_| Error::FailedToCloneSocketHandle)?;
+
+        let shared = Arc::new(SharedState {
+            id: routing_table.own_id(),
+            tables: Mutex::new(Tables {
+                routing_table,
+                token_secret: TokenSecret::new(Instant::now()),
+                peer_store: PeerStore::new(),
+            }),
+            pending_queries: Mutex::new(HashMap::new()),
+            running: AtomicBool::new(true),
+        });
+        let receiver = {
+            let shared = shared.clone();
+            thread::spawn(move || shared.receive_messages(receiving_socket))
+        };
+
+        Ok(Self {
+            socket,
+            local_address,
+            shared,
+            receiver: Some(receiver),
+            query_timeout,
+        })
+    }
+
+    pub fn id(&self) -> NodeId {
+        self.shared.id
+    }
+
+    pub fn local_address(&self) -> SocketAddr {
+        self.local_address
+    }
+
+    pub fn routing_table(&self) -> RoutingTable {
+        self.shared.tables.lock().unwrap().routing_table.clone()
+    }
+
+    pub fn save_routing_table(&self, path: &Path) -> Result<(), Error> {
+        self.routing_table().save(path)
+    }
+
+    /// Resolves host names, such as the ones of `BOOTSTRAP_NODES`, skipping
+    /// the ones that cannot be.
+    pub fn resolve(addresses: &[String]) -> Vec<SocketAddr> {
+        addresses
+            .iter()
+            .filter_map(|address| match address.to_socket_addrs() {
+                Ok(mut resolved) => resolved.find(SocketAddr::is_ipv4),
+                Err(_) => {
+                    log::warn!("Could not resolve DHT node {}", address);
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Joins the DHT by asking the given nodes for the nodes closest to us.
+    /// Returns the number of nodes known afterwards.
+    pub fn bootstrap(&self, addresses: &[SocketAddr]) -> usize {
+        thread::scope(|scope| {
+            for address in addresses {
+                scope.spawn(move || {
+                    if let Err(e) = self.find_node(*address, self.id()) {
+                        log::debug!("DHT bootstrap node {} failed: {:?}", address, e);
+                    }
+                });
+            }
+        });
+        self.lookup(self.id(), false);
+
+        self.routing_table().len()
+    }
+
+    /// Pings a node without waiting for its answer, it is added to the
+    /// routing table if it answers.
+    pub fn add_node(&self, address: SocketAddr) {
+        if let Err(e) = self.send_query(address, KrpcQuery::Ping, None) {
+            log::debug!("Could not ping DHT node {}: {:?}", address, e);
+        }
+    }
+
+    pub fn find_node(&self, address: SocketAddr, target: NodeId) -> Result<Vec<NodeInfo>, Error> {
+        Ok(self
+            .query(address, KrpcQuery::FindNode { target })?
+            .nodes()
+            .clone())
+    }
+
+    pub fn get_peers(
+        &self,
+        address: SocketAddr,
+        info_hash: [u8; 20],
+    ) -> Result<KrpcResponse, Error> {
+        self.query(address, KrpcQuery::GetPeers { info_hash })
+    }
+
+    pub fn announce_peer(
+        &self,
+        address: SocketAddr,
+        info_hash: [u8; 20],
+        port: u16,
+        token: Vec<u8>,
+    ) -> Result<(), Error> {
+        let query = KrpcQuery::AnnouncePeer {
+            info_hash,
+            port,
+            implied_port: false,
+            token,
+        };
+        self.query(address, query).map(|_| ())
+    }
+
+    /// Finds the peers of a torrent, and announces that we have it too, on
+    /// `port`, to the closest nodes.
+    pub fn announce(&self, info_hash: [u8; 20], port: u16) -> Vec<Peer> {
+        let lookup = self.lookup(NodeId::new(info_hash), true);
+
+        for (node, token) in lookup.closest {
+            if let Some(token) = token {
+                if let Err(e) = self.announce_peer(node.address(), info_hash, port, token) {
+                    log::debug!("Could not announce to DHT node {:?}: {:?}", node, e);
+                }
+            }
+        }
+
+        lookup.peers
+    }
+
+    /// Iterative lookup of the nodes closest to `target`: the closest known
+    /// nodes are queried, then the closest of the nodes they return, until
+    /// the `K` closest nodes found all answered. With `get_peers`, the peers
+    /// and tokens returned on the way are kept.
+    fn lookup(&self, target: NodeId, get_peers: bool) -> Lookup {
+        let mut candidates: BTreeMap<[u8; 20], NodeInfo> = self
+            .routing_table()
+            .closest(&target, RoutingTable::K)
+            .into_iter()
+            .map(|node| (node.id().distance(&target), node))
+            .collect();
+        let mut queried = HashSet::new();
+        let mut answered = BTreeMap::new();
+        let mut peers = HashSet::new();
+
+        loop {
+            let to_query: Vec<NodeInfo> = candidates
+                .values()
+                .take(RoutingTable::K)
+                .filter(|node| !queried.contains(&node.id()))
+                .take(Self::ALPHA)
+                .copied()
+                .collect();
+            if to_query.is_empty() {
+                break;
+            }
+
+            let answers: Vec<_> = thread::scope(|scope| {
+                let queries: Vec<_> = to_query
+                    .iter()
+                    .map(|node| {
+                        scope.spawn(move || {
+                            let answer = match get_peers {
+                                true => self.get_peers(node.address(), target.bytes()),
+                                false => self.query(node.address(), KrpcQuery::FindNode { target }),
+                            };
+                            (*node, answer)
+                        })
+                    })
+                    .collect();
+                queries
+                    .into_iter()
+                    .map(|query| query.join().unwrap())
+                    .collect()
+            });
+
+            for (node, answer) in answers {
+                queried.insert(node.id());
+                let distance = node.id().distance(&target);
+                match answer {
+                    Ok(response) => {
+                        peers.extend(response.values().iter().copied());
+                        for found in response.nodes() {
+                            if found.id() != self.id() && !queried.contains(&found.id()) {
+                                candidates
+                                    .entry(found.id().distance(&target))
+                                    .or_insert(*found);
+                            }
+                        }
+                        answered.insert(distance, (node, response.token().cloned()));
+                    }
+                    Err(_) => {
+                        candidates.remove(&distance);
+                        let mut tables = self.shared.tables.lock().unwrap();
+                        tables.routing_table.remove(&node.id());
+                    }
+                }
+            }
+        }
+
+        Lookup {
+            closest: answered.into_values().take(RoutingTable::K).collect(),
+            peers: peers.into_iter().collect(),
+        }
+    }
+
+    fn query(&self, address: SocketAddr, query: KrpcQuery) -> Result<KrpcResponse, Error> {
+        let (sender, receiver) = crossbeam_channel::bounded(1);
+        let transaction_id = self.send_query(address, query, Some(sender))?;
+
+        let answer = receiver.recv_timeout(self.query_timeout);
+        self.shared
+            .pending_queries
+            .lock()
+            .unwrap()
+            .remove(&transaction_id);
+
+        match answer {
+            Ok(KrpcMessage::Response { response, .. }) => Ok(response),
+            Ok(KrpcMessage::Error { code, message, .. }) => {
+                log::debug!("DHT node {} answered error {}: {}", address, code, message);
+                Err(Error::DhtNodeReturnedAnError)
+            }
+            _ => Err(Error::DhtNodeDidNotAnswer),
+        }
+    }
+
+    /// Returns the transaction id of the query.
+    fn send_query(
+        &self,
+        address: SocketAddr,
+        query: KrpcQuery,
+        sender: Option<Sender<KrpcMessage>>,
+    ) -> Result<Vec<u8>, Error> {
+        let transaction_id = rand::random::<[u8; 4]>().to_vec();
+        let message = KrpcMessage::Query {
+            transaction_id: transaction_id.clone(),
+            sender: self.id(),
+            query,
+        };
+
+        {
+            let now = Instant::now();
+            let mut pending_queries = self.shared.pending_queries.lock().unwrap();
+            // the answers of older queries are not expected anymore
+            pending_queries
+                .retain(|_, query| now.duration_since(query.sent_at) < self.query_timeout);
+            pending_queries.insert(
+                transaction_id.clone(),
+                PendingQuery {
+                    address,
+                    sender,
+                    sent_at: now,
+                },
+            );
+        }
+
+        self.socket
+            .send_to(&message.into_bytes(), address)
+            .map_err(|_| Error::FailedToSendDhtPacket)?;
+
+        Ok(transaction_id)
+    }
+}
+
+impl Drop for DhtNode {
+    fn drop(&mut self) {
+        self.shared.running.store(false, Ordering::Relaxed);
+        if let Some(receiver) = self.receiver.take() {
+            let _ = receiver.join();
+        }
+    }
+}
+
+impl SharedState {
+    fn receive_messages(&self, socket: UdpSocket) {
+        let mut buffer = [0u8; DhtNode::MAX_PACKET_SIZE];
+
+        while self.running.load(Ordering::Relaxed) {
+            // errors are mostly read timeouts
+            let (length, source) = match socket.recv_from(&mut buffer) {
+                Ok(received) => received,
+                Err(_) => continue,
+            };
+
+            let message = match KrpcMessage::from_bytes(&buffer[..length]) {
+                Ok((message, _)) => message,
+                Err(_) => {
+                    log::debug!("Invalid DHT message from {}", source);
+                    continue;
+                }
+            };
+
+            match message {
+                KrpcMessage::Query {
+                    transaction_id,
+                    sender,
+                    query,
+                } => {
+                    let answer = self.answer(transaction_id, NodeInfo::new(sender, source), query);
+                    if socket.send_to(&answer.into_bytes(), source).is_err() {
+                        log::debug!("Could not answer DHT node {}", source);
+                    }
+                }
+                answer => self.receive_answer(answer, source),
+            }
+        }
+    }
+
+    fn answer(
+        &self,
+        transaction_id: Vec<u8>,
+        querying_node: NodeInfo,
+        query: KrpcQuery,
+    ) -> KrpcMessage {
+        let now = Instant::now();
+        let source = querying_node.address();
+        let mut tables = self.tables.lock().unwrap();
+        tables.routing_table.insert(querying_node, now);
+
+        let response = match query {
+            KrpcQuery::Ping => KrpcResponse::empty(self.id),
+            KrpcQuery::FindNode { target } => KrpcResponse::new(
+                self.id,
+                tables.routing_table.closest(&target, RoutingTable::K),
+                vec![],
+                None,
+            ),
+            KrpcQuery::GetPeers { info_hash } => KrpcResponse::new(
+                self.id,
+                tables
+                    .routing_table
+                    .closest(&NodeId::new(info_hash), RoutingTable::K),
+                tables.peer_store.peers(&info_hash, now),
+                Some(tables.token_secret.token(source.ip(), now)),
+            ),
+            KrpcQuery::AnnouncePeer {
+                info_hash,
+                port,
+                implied_port,
+                token,
+            } => {
+                if !tables.token_secret.is_valid(&token, source.ip(), now) {
+                    return KrpcMessage::Error {
+                        transaction_id,
+                        code: KrpcMessage::PROTOCOL_ERROR,
+                        message: "Bad token".to_string(),
+                    };
+                }
+
+                let port = if implied_port { source.port() } else { port };
+                let peer = Peer::from_socket_address(SocketAddr::new(source.ip(), port));
+                tables.peer_store.announce(info_hash, peer, now);
+                KrpcResponse::empty(self.id)
+            }
+        };
+
+        KrpcMessage::Response {
+            transaction_id,
+            response,
+        }
+    }
+
+    /// Answers are only accepted from the node the query was sent to.
+    fn receive_answer(&self, answer: KrpcMessage, source: SocketAddr) {
+        let pending_query = {
+            let mut pending_queries = self.pending_queries.lock().unwrap();
+            match pending_queries.get(answer.transaction_id()) {
+                Some(query) if query.address == source => {
+                    pending_queries.remove(answer.transaction_id())
+                }
+                _ => None,
+            }
+        };
+        let pending_query = match pending_query {
+            Some(query) => query,
+            None => {
+                log::debug!("Unexpected DHT answer from {}", source);
+                return;
+            }
+        };
+
+        if let KrpcMessage::Response { response, .. } = &answer {
+            let node = NodeInfo::new(response.sender(), source);
+            let mut tables = self.tables.lock().unwrap();
+            tables.routing_table.insert(node, Instant::now());
+        }
+        if let Some(sender) = pending_query.sender {
+            let _ = sender.send(answer);
+        }
+    }
+}
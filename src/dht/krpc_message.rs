@@ -0,0 +1,328 @@
+use {
+    crate::{
+        dht::{KrpcQuery, KrpcResponse, NodeId, NodeInfo},
+        http::Peer,
+        pwp::{FromBytes, IntoBytes},
+        Error,
+    },
+    bendy::decoding::{Decoder, Object},
+};
+
+/// Message of the KRPC protocol used by the DHT (BEP 5): a bencoded
+/// dictionary sent in a single UDP packet. Responses and errors carry the
+/// transaction id of the query they answer.
+#[derive(Debug, Clone, PartialEq)]
+pub enum KrpcMessage {
+    Query {
+        transaction_id: Vec<u8>,
+        sender: NodeId,
+        query: KrpcQuery,
+    },
+    Response {
+        transaction_id: Vec<u8>,
+        response: KrpcResponse,
+    },
+    Error {
+        transaction_id: Vec<u8>,
+        code: u32,
+        message: String,
+    },
+}
+
+/// Fields of the `a` and `r` dictionaries, before knowing which query or
+/// response they belong to
+#[derive(Default)]
+struct KrpcArguments {
+    id: Option<NodeId>,
+    target: Option<NodeId>,
+    info_hash: Option<[u8; 20]>,
+    port: Option<u16>,
+    implied_port: bool,
+    token: Option<Vec<u8>>,
+    nodes: Vec<NodeInfo>,
+    values: Vec<Peer>,
+}
+
+impl KrpcMessage {
+    /// Error code of malformed queries, such as announces with a bad token
+    pub const PROTOCOL_ERROR: u32 = 203;
+
+    pub fn transaction_id(&self) -> &Vec<u8> {
+        match self {
+            KrpcMessage::Query { transaction_id, .. }
+            | KrpcMessage::Response { transaction_id, .. }
+            | KrpcMessage::Error { transaction_id, .. } => transaction_id,
+        }
+    }
+
+    fn bencode_string(bytes: &[u8]) -> Vec<u8> {
+        let mut string = format!("{}:", bytes.len()).into_bytes();
+        string.extend(bytes);
+        string
+    }
+
+    /// Arguments of a query, keys in sorted order
+    fn query_arguments(sender: NodeId, query: &KrpcQuery) -> Vec<u8> {
+        let mut bytes = b"d2:id".to_vec();
+        bytes.extend(Self::bencode_string(&sender.bytes()));
+        match query {
+            KrpcQuery::Ping => (),
+            KrpcQuery::FindNode { target } => {
+                bytes.extend(b"6:target");
+                bytes.extend(Self::bencode_string(&target.bytes()));
+            }
+            KrpcQuery::GetPeers { info_hash } => {
+                bytes.extend(b"9:info_hash");
+                bytes.extend(Self::bencode_string(info_hash));
+            }
+            KrpcQuery::AnnouncePeer {
+                info_hash,
+                port,
+                implied_port,
+                token,
+            } => {
+                bytes.extend(format!("12:implied_porti{}e", *implied_port as u8).into_bytes());
+                bytes.extend(b"9:info_hash");
+                bytes.extend(Self::bencode_string(info_hash));
+                bytes.extend(format!("4:porti{}e", port).into_bytes());
+                bytes.extend(b"5:token");
+                bytes.extend(Self::bencode_string(token));
+            }
+        }
+        bytes.push(b'e');
+
+        bytes
+    }
+
+    /// Response values, keys in sorted order
+    fn response_values(response: &KrpcResponse) -> Vec<u8> {
+        let mut bytes = b"d2:id".to_vec();
+        bytes.extend(Self::bencode_string(&response.sender().bytes()));
+        if !response.nodes().is_empty() {
+            bytes.extend(b"5:nodes");
+            bytes.extend(Self::bencode_string(&NodeInfo::into_compact_bytes(
+                response.nodes(),
+            )));
+        }
+        if let Some(token) = response.token() {
+            bytes.extend(b"5:token");
+            bytes.extend(Self::bencode_string(token));
+        }
+        if !response.values().is_empty() {
+            bytes.extend(b"6:valuesl");
+            for peer in response.values() {
+                bytes.extend(Self::bencode_string(&peer.compact_bytes()));
+            }
+            bytes.push(b'e');
+        }
+        bytes.push(b'e');
+
+        bytes
+    }
+
+    fn parse_integer<T: std::str::FromStr>(object: Object) -> Result<T, Error> {
+        match object {
+            Object::Integer(integer) => integer.parse().map_err(|_| Error::InvalidKrpcMessage),
+            _ => Err(Error::InvalidKrpcMessage),
+        }
+    }
+
+    fn parse_bytes(object: Object) -> Result<Vec<u8>, Error> {
+        match object {
+            Object::Bytes(bytes) => Ok(bytes.to_vec()),
+            _ => Err(Error::InvalidKrpcMessage),
+        }
+    }
+
+    fn parse_id(object: Object) -> Result<NodeId, Error> {
+        NodeId::from_slice(&Self::parse_bytes(object)?).ok_or(Error::InvalidKrpcMessage)
+    }
+
+    /// Unknown keys are ignored, so that queries of newer versions of the
+    /// protocol can still be answered.
+    fn parse_arguments(object: Object) -> Result<KrpcArguments, Error> {
+        let mut dictionary = match object {
+            Object::Dict(dictionary) => dictionary,
+            _ => return Err(Error::InvalidKrpcMessage),
+        };
+
+        let mut arguments = KrpcArguments::default();
+        while let Some((key, value)) = dictionary
+            .next_pair()
+            .map_err(|_| Error::InvalidKrpcMessage)?
+        {
+            match key {
+                b"id" => arguments.id = Some(Self::parse_id(value)?),
+                b"target" => arguments.target = Some(Self::parse_id(value)?),
+                b"info_hash" => arguments.info_hash = Some(Self::parse_id(value)?.bytes()),
+                b"port" => arguments.port = Some(Self::parse_integer(value)?),
+                b"implied_port" => arguments.implied_port = Self::parse_integer::<u8>(value)? != 0,
+                b"token" => arguments.token = Some(Self::parse_bytes(value)?),
+                b"nodes" => {
+                    arguments.nodes = NodeInfo::from_compact_bytes(&Self::parse_bytes(value)?)
+                }
+                b"values" => {
+                    let mut values = match value {
+                        Object::List(values) => values,
+                        _ => return Err(Error::InvalidKrpcMessage),
+                    };
+                    while let Ok(Some(Object::Bytes(peer))) = values.next_object() {
+                        match peer.len() {
+                            6 => arguments.values.push(Peer::from_bytes(peer)),
+                            18 => arguments.values.push(Peer::from_ipv6_bytes(peer)),
+                            _ => (),
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        Ok(arguments)
+    }
+
+    fn parse_query(method: &[u8], arguments: KrpcArguments) -> Result<KrpcQuery, Error> {
+        match method {
+            b"ping" => Ok(KrpcQuery::Ping),
+            b"find_node" => Ok(KrpcQuery::FindNode {
+                target: arguments.target.ok_or(Error::InvalidKrpcMessage)?,
+            }),
+            b"get_peers" => Ok(KrpcQuery::GetPeers {
+                info_hash: arguments.info_hash.ok_or(Error::InvalidKrpcMessage)?,
+            }),
+            b"announce_peer" => Ok(KrpcQuery::AnnouncePeer {
+                info_hash: arguments.info_hash.ok_or(Error::InvalidKrpcMessage)?,
+                port: arguments.port.ok_or(Error::InvalidKrpcMessage)?,
+                implied_port: arguments.implied_port,
+                token: arguments.token.ok_or(Error::InvalidKrpcMessage)?,
+            }),
+            _ => Err(Error::InvalidKrpcMessage),
+        }
+    }
+
+    fn parse_error(object: Object) -> Result<(u32, String), Error> {
+        let mut list = match object {
+            Object::List(list) => list,
+            _ => return Err(Error::InvalidKrpcMessage),
+        };
+        let code = match list.next_object() {
+            Ok(Some(code)) => Self::parse_integer(code)?,
+            _ => return Err(Error::InvalidKrpcMessage),
+        };
+        let message = match list.next_object() {
+            Ok(Some(Object::Bytes(message))) => String::from_utf8_lossy(message).into_owned(),
+            _ => String::new(),
+        };
+
+        Ok((code, message))
+    }
+}
+
+impl IntoBytes for KrpcMessage {
+    fn into_bytes(self) -> Vec<u8> {
+        let mut bytes = b"d".to_vec();
+        match &self {
+            KrpcMessage::Query {
+                transaction_id,
+                sender,
+                query,
+            } => {
+                bytes.extend(b"1:a");
+                bytes.extend(Self::query_arguments(*sender, query));
+                bytes.extend(b"1:q");
+                bytes.extend(Self::bencode_string(query.method_name().as_bytes()));
+                bytes.extend(b"1:t");
+                bytes.extend(Self::bencode_string(transaction_id));
+                bytes.extend(b"1:y1:q");
+            }
+            KrpcMessage::Response {
+                transaction_id,
+                response,
+            } => {
+                bytes.extend(b"1:r");
+                bytes.extend(Self::response_values(response));
+                bytes.extend(b"1:t");
+                bytes.extend(Self::bencode_string(transaction_id));
+                bytes.extend(b"1:y1:r");
+            }
+            KrpcMessage::Error {
+                transaction_id,
+                code,
+                message,
+            } => {
+                bytes.extend(format!("1:eli{}e", code).into_bytes());
+                bytes.extend(Self::bencode_string(message.as_bytes()));
+                bytes.extend(b"e1:t");
+                bytes.extend(Self::bencode_string(transaction_id));
+                bytes.extend(b"1:y1:e");
+            }
+        }
+        bytes.push(b'e');
+
+        bytes
+    }
+}
+
+impl FromBytes for KrpcMessage {
+    fn from_bytes(bytes: &[u8]) -> Result<(Self, usize), Error> {
+        let mut decoder = Decoder::new(bytes);
+        let mut dictionary = match decoder.next_object() {
+            Ok(Some(Object::Dict(dictionary))) => dictionary,
+            _ => return Err(Error::InvalidKrpcMessage),
+        };
+
+        let mut transaction_id = None;
+        let mut message_type = None;
+        let mut method = None;
+        let mut arguments = None;
+        let mut error = None;
+        while let Some((key, value)) = dictionary
+            .next_pair()
+            .map_err(|_| Error::InvalidKrpcMessage)?
+        {
+            match key {
+                b"t" => transaction_id = Some(Self::parse_bytes(value)?),
+                b"y" => message_type = Some(Self::parse_bytes(value)?),
+                b"q" => method = Some(Self::parse_bytes(value)?),
+                b"a" | b"r" => arguments = Some(Self::parse_arguments(value)?),
+                b"e" => error = Some(Self::parse_error(value)?),
+                _ => (),
+            }
+        }
+
+        let transaction_id = transaction_id.ok_or(Error::InvalidKrpcMessage)?;
+        let message = match message_type.as_deref() {
+            Some(b"q") => {
+                let arguments = arguments.ok_or(Error::InvalidKrpcMessage)?;
+                KrpcMessage::Query {
+                    transaction_id,
+                    sender: arguments.id.ok_or(Error::InvalidKrpcMessage)?,
+                    query: Self::parse_query(&method.ok_or(Error::InvalidKrpcMessage)?, arguments)?,
+                }
+            }
+            Some(b"r") => {
+                let arguments = arguments.ok_or(Error::InvalidKrpcMessage)?;
+                KrpcMessage::Response {
+                    transaction_id,
+                    response: KrpcResponse::new(
+                        arguments.id.ok_or(Error::InvalidKrpcMessage)?,
+                        arguments.nodes,
+                        arguments.values,
+                        arguments.token,
+                    ),
+                }
+            }
+            Some(b"e") => {
+                let (code, message) = error.ok_or(Error::InvalidKrpcMessage)?;
+                KrpcMessage::Error {
+                    transaction_id,
+                    code,
+                    message,
+                }
+            }
+            _ => return Err(Error::InvalidKrpcMessage),
+        };
+
+        Ok((message, bytes.len()))
+    }
+}
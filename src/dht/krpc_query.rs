@@ -0,0 +1,33 @@
+use crate::dht::NodeId;
+
+/// Queries of the DHT protocol (BEP 5), without the id of the querying node.
+#[derive(Debug, Clone, PartialEq)]
+pub enum KrpcQuery {
+    Ping,
+    FindNode {
+        target: NodeId,
+    },
+    GetPeers {
+        info_hash: [u8; 20],
+    },
+    /// `token` comes from a previous `get_peers` response of the queried
+    /// node. With `implied_port`, the source port of the packet is announced
+    /// instead of `port`.
+    AnnouncePeer {
+        info_hash: [u8; 20],
+        port: u16,
+        implied_port: bool,
+        token: Vec<u8>,
+    },
+}
+
+impl KrpcQuery {
+    pub fn method_name(&self) -> &'static str {
+        match self {
+            KrpcQuery::Ping => "ping",
+            KrpcQuery::FindNode { .. } => "find_node",
+            KrpcQuery::GetPeers { .. } => "get_peers",
+            KrpcQuery::AnnouncePeer { .. } => "announce_peer",
+        }
+    }
+}
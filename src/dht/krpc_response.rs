@@ -0,0 +1,51 @@
+use crate::{
+    dht::{NodeId, NodeInfo},
+    http::Peer,
+};
+
+/// Response to any query, the fields used depending on the query: `nodes`
+/// for `find_node`, `token` with `nodes` or `values` for `get_peers`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KrpcResponse {
+    sender: NodeId,
+    nodes: Vec<NodeInfo>,
+    values: Vec<Peer>,
+    token: Option<Vec<u8>>,
+}
+
+impl KrpcResponse {
+    pub fn new(
+        sender: NodeId,
+        nodes: Vec<NodeInfo>,
+        values: Vec<Peer>,
+        token: Option<Vec<u8>>,
+    ) -> Self {
+        Self {
+            sender,
+            nodes,
+            values,
+            token,
+        }
+    }
+
+    /// Response carrying only the id of the sender, as for `ping`
+    pub fn empty(sender: NodeId) -> Self {
+        Self::new(sender, vec![], vec![], None)
+    }
+
+    pub fn sender(&self) -> NodeId {
+        self.sender
+    }
+
+    pub fn nodes(&self) -> &Vec<NodeInfo> {
+        &self.nodes
+    }
+
+    pub fn values(&self) -> &Vec<Peer> {
+        &self.values
+    }
+
+    pub fn token(&self) -> Option<&Vec<u8>> {
+        self.token.as_ref()
+    }
+}
@@ -0,0 +1,43 @@
+/// 160-bit identifier of a DHT node, in the same space as the info hashes.
+/// Nodes are compared by the XOR of their ids (Kademlia distance).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId([u8; 20]);
+
+impl NodeId {
+    pub const LENGTH: usize = 20;
+
+    pub fn new(bytes: [u8; 20]) -> Self {
+        Self(bytes)
+    }
+
+    pub fn random() -> Self {
+        Self(rand::random())
+    }
+
+    pub fn from_slice(bytes: &[u8]) -> Option<Self> {
+        Some(Self(bytes.try_into().ok()?))
+    }
+
+    pub fn bytes(&self) -> [u8; 20] {
+        self.0
+    }
+
+    /// XOR of the ids, compared as a big-endian integer
+    pub fn distance(&self, other: &NodeId) -> [u8; 20] {
+        let mut distance = [0u8; 20];
+        for (index, byte) in distance.iter_mut().enumerate() {
+            *byte = self.0[index] ^ other.0[index];
+        }
+
+        distance
+    }
+
+    /// Number of leading bits shared by both ids, None for the same id. The
+    /// higher it is, the closer the nodes are.
+    pub fn common_prefix_length(&self, other: &NodeId) -> Option<usize> {
+        let distance = self.distance(other);
+        let first_different_byte = distance.iter().position(|byte| *byte != 0)?;
+
+        Some(first_different_byte * 8 + distance[first_different_byte].leading_zeros() as usize)
+    }
+}
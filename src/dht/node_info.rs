@@ -0,0 +1,66 @@
+use {
+    crate::dht::NodeId,
+    std::net::{IpAddr, Ipv4Addr, SocketAddr},
+};
+
+/// Contact information of a DHT node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NodeInfo {
+    id: NodeId,
+    address: SocketAddr,
+}
+
+impl NodeInfo {
+    /// Compact form, 20 bytes of id, 4 bytes of IPv4 address and 2 bytes of
+    /// port
+    pub const COMPACT_LENGTH: usize = 26;
+
+    pub fn new(id: NodeId, address: SocketAddr) -> Self {
+        Self { id, address }
+    }
+
+    pub fn id(&self) -> NodeId {
+        self.id
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Only IPv4 nodes have a compact form (the `nodes6` key of BEP 32 is
+    /// not supported).
+    pub fn compact_bytes(&self) -> Option<Vec<u8>> {
+        let ip = match self.address.ip() {
+            IpAddr::V4(ip) => ip,
+            IpAddr::V6(_) => return None,
+        };
+
+        let mut bytes = self.id.bytes().to_vec();
+        bytes.extend(ip.octets());
+        bytes.extend(self.address.port().to_be_bytes());
+
+        Some(bytes)
+    }
+
+    pub fn into_compact_bytes(nodes: &[NodeInfo]) -> Vec<u8> {
+        nodes
+            .iter()
+            .filter_map(NodeInfo::compact_bytes)
+            .flatten()
+            .collect()
+    }
+
+    /// Trailing bytes not making a whole node are ignored.
+    pub fn from_compact_bytes(bytes: &[u8]) -> Vec<NodeInfo> {
+        bytes
+            .chunks_exact(Self::COMPACT_LENGTH)
+            .map(|chunk| {
+                let id = NodeId::from_slice(&chunk[..NodeId::LENGTH]).unwrap();
+                let ip = Ipv4Addr::new(chunk[20], chunk[21], chunk[22], chunk[23]);
+                let port = u16::from_be_bytes([chunk[24], chunk[25]]);
+
+                NodeInfo::new(id, SocketAddr::new(IpAddr::V4(ip), port))
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,38 @@
+use {
+    crate::http::Peer,
+    std::{
+        collections::HashMap,
+        time::{Duration, Instant},
+    },
+};
+
+/// Peers announced to this node, by info hash. Announces expire after a
+/// while, peers having to announce again.
+#[derive(Debug, Default)]
+pub struct PeerStore {
+    peers: HashMap<[u8; 20], HashMap<Peer, Instant>>,
+}
+
+impl PeerStore {
+    pub const ANNOUNCE_LIFETIME: Duration = Duration::from_secs(30 * 60);
+    /// At most this many peers are returned, to fit in a UDP packet
+    pub const MAX_PEERS: usize = 50;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn announce(&mut self, info_hash: [u8; 20], peer: Peer, now: Instant) {
+        self.peers.entry(info_hash).or_default().insert(peer, now);
+    }
+
+    pub fn peers(&mut self, info_hash: &[u8; 20], now: Instant) -> Vec<Peer> {
+        let peers = match self.peers.get_mut(info_hash) {
+            Some(peers) => peers,
+            None => return vec![],
+        };
+        peers.retain(|_, announced_at| now.duration_since(*announced_at) < Self::ANNOUNCE_LIFETIME);
+
+        peers.keys().take(Self::MAX_PEERS).copied().collect()
+    }
+}
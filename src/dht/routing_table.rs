@@ -0,0 +1,137 @@
+use {
+    crate::{
+        dht::{NodeId, NodeInfo},
+        Error,
+    },
+    bendy::decoding::{Decoder, Object},
+    std::{
+        fs,
+        path::Path,
+        time::{Duration, Instant},
+    },
+};
+
+/// Kademlia routing table: the known nodes, in buckets by the length of the
+/// prefix their id shares with ours. Each bucket holds up to `K` nodes, so
+/// that many close nodes and few far ones are known.
+#[derive(Debug, Clone)]
+pub struct RoutingTable {
+    own_id: NodeId,
+    /// nodes and when they were last heard from, the least recently seen
+    /// first
+    buckets: Vec<Vec<(NodeInfo, Instant)>>,
+}
+
+impl RoutingTable {
+    pub const K: usize = 8;
+    const NUMBER_OF_BUCKETS: usize = NodeId::LENGTH * 8;
+    /// Nodes not heard from for this long may be replaced by new ones
+    pub const QUESTIONABLE_AFTER: Duration = Duration::from_secs(15 * 60);
+
+    pub fn new(own_id: NodeId) -> Self {
+        Self {
+            own_id,
+            buckets: vec![Vec::new(); Self::NUMBER_OF_BUCKETS],
+        }
+    }
+
+    pub fn own_id(&self) -> NodeId {
+        self.own_id
+    }
+
+    pub fn len(&self) -> usize {
+        self.buckets.iter().map(Vec::len).sum()
+    }
+
+    pub fn nodes(&self) -> Vec<NodeInfo> {
+        self.buckets
+            .iter()
+            .flatten()
+            .map(|(node, _)| *node)
+            .collect()
+    }
+
+    /// Adds a node that was just heard from, or refreshes it. When its
+    /// bucket is full, the node replaces the least recently seen one if that
+    /// one became questionable, and is dropped otherwise. Returns whether the
+    /// node is in the table.
+    pub fn insert(&mut self, node: NodeInfo, now: Instant) -> bool {
+        let bucket = match self.own_id.common_prefix_length(&node.id()) {
+            Some(index) => &mut self.buckets[index],
+            None => return false,
+        };
+
+        if let Some(position) = bucket.iter().position(|(known, _)| known.id() == node.id()) {
+            bucket.remove(position);
+            bucket.push((node, now));
+            return true;
+        }
+
+        if bucket.len() >= Self::K {
+            let (_, last_seen) = bucket[0];
+            if now.duration_since(last_seen) < Self::QUESTIONABLE_AFTER {
+                return false;
+            }
+            bucket.remove(0);
+        }
+        bucket.push((node, now));
+
+        true
+    }
+
+    pub fn remove(&mut self, id: &NodeId) {
+        if let Some(index) = self.own_id.common_prefix_length(id) {
+            self.buckets[index].retain(|(node, _)| node.id() != *id);
+        }
+    }
+
+    /// The `count` known nodes closest to `target`
+    pub fn closest(&self, target: &NodeId, count: usize) -> Vec<NodeInfo> {
+        let mut nodes = self.nodes();
+        nodes.sort_by_key(|node| node.id().distance(target));
+        nodes.truncate(count);
+
+        nodes
+    }
+
+    /// Saves our id and the known nodes, in compact form.
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let nodes = NodeInfo::into_compact_bytes(&self.nodes());
+        let mut bytes = b"d2:id20:".to_vec();
+        bytes.extend(self.own_id.bytes());
+        bytes.extend(format!("5:nodes{}:", nodes.len()).into_bytes());
+        bytes.extend(nodes);
+        bytes.push(b'e');
+
+        fs::write(path, bytes).map_err(|_| Error::FailedToWriteRoutingTable)
+    }
+
+    /// Loads a table saved by `save`. The nodes are considered as just heard
+    /// from, they are dropped once they fail to answer.
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let bytes = fs::read(path).map_err(|_| Error::FailedToReadRoutingTable)?;
+        let mut decoder = Decoder::new(&bytes);
+        let mut dictionary = match decoder.next_object() {
+            Ok(Some(Object::Dict(dictionary))) => dictionary,
+            _ => return Err(Error::InvalidRoutingTableFile),
+        };
+
+        let mut own_id = None;
+        let mut nodes = vec![];
+        while let Ok(Some(pair)) = dictionary.next_pair() {
+            match pair {
+                (b"id", Object::Bytes(id)) => own_id = NodeId::from_slice(id),
+                (b"nodes", Object::Bytes(compact)) => nodes = NodeInfo::from_compact_bytes(compact),
+                _ => (),
+            }
+        }
+
+        let mut routing_table = Self::new(own_id.ok_or(Error::InvalidRoutingTableFile)?);
+        let now = Instant::now();
+        for node in nodes {
+            routing_table.insert(node, now);
+        }
+
+        Ok(routing_table)
+    }
+}
@@ -0,0 +1,64 @@
+use {
+    sha1::{Digest, Sha1},
+    std::{
+        net::IpAddr,
+        time::{Duration, Instant},
+    },
+};
+
+/// Tokens handed out with `get_peers` responses, that must be sent back with
+/// `announce_peer` from the same IP address. They are the hash of the address
+/// and of a secret changed every five minutes, tokens of the previous secret
+/// being still accepted.
+#[derive(Debug)]
+pub struct TokenSecret {
+    secret: [u8; 16],
+    previous_secret: [u8; 16],
+    changed_at: Instant,
+}
+
+impl TokenSecret {
+    pub const LIFETIME: Duration = Duration::from_secs(5 * 60);
+
+    pub fn new(now: Instant) -> Self {
+        let secret = rand::random();
+
+        Self {
+            secret,
+            previous_secret: secret,
+            changed_at: now,
+        }
+    }
+
+    pub fn token(&mut self, ip: IpAddr, now: Instant) -> Vec<u8> {
+        self.refresh(now);
+
+        Self::hash(&self.secret, ip)
+    }
+
+    pub fn is_valid(&mut self, token: &[u8], ip: IpAddr, now: Instant) -> bool {
+        self.refresh(now);
+
+        token == Self::hash(&self.secret, ip) || token == Self::hash(&self.previous_secret, ip)
+    }
+
+    fn refresh(&mut self, now: Instant) {
+        if now.duration_since(self.changed_at) >= Self::LIFETIME {
+            self.previous_secret = self.secret;
+            self.secret = rand::random();
+            self.changed_at = now;
+        }
+    }
+
+    /// The first 8 bytes of the hash are enough
+    fn hash(secret: &[u8], ip: IpAddr) -> Vec<u8> {
+        let mut hasher = Sha1::new();
+        hasher.update(secret);
+        match ip {
+            IpAddr::V4(ip) => hasher.update(ip.octets()),
+            IpAddr::V6(ip) => hasher.update(ip.octets()),
+        }
+
+        hasher.finalize()[..8].to_vec()
+    }
+}
@@ -12,6 +12,9 @@ pub enum Error {
     PieceLengthStringCannotBeConvertedToInteger,
     LengthStringCannotBeConvertedToInteger,
     NameBytesCannotBeConvertedToString,
+    FilePathNotFoundDuringParsing,
+    InvalidFilePathComponent,
+    TooManyPiecesInTorrent,
 
     // HTTP announce error
     FailedToParseUrl,
@@ -23,9 +26,53 @@ pub enum Error {
     TrackerPortNotProvided,
     FailedToDecodeBencodeData,
     TrackerConnectionNotPossible,
+    UnsupportedTrackerProtocol,
+    NoTrackerAvailable,
+    TrackerRefusedAnnounce,
+    TrackerDoesNotSupportScrape,
+    PeerAddressNotProvided,
+    InvalidPeerPort,
+
+    // UDP announce error
+    FailedToResolveTrackerAddress,
+    FailedToBindUdpSocket,
+    FailedToSendUdpPacket,
+    UdpTrackerDidNotAnswer,
+    UdpTrackerResponseTooShort,
+    UnexpectedUdpTrackerAction,
+    UdpTrackerReturnedAnError,
+
+    // Magnet link error
+    InvalidMagnetLink,
+    MagnetLinkWithoutInfoHash,
+    InvalidMagnetLinkInfoHash,
+    MagnetLinkWithoutTrackers,
+    InvalidExtensionHandshake,
+    InvalidMetadataMessage,
+    PeerDoesNotHaveTheTorrent,
+    PeerDoesNotSupportMetadataExchange,
+    MetadataRequestRejected,
+    MetadataDoesNotMatchInfoHash,
+    MetadataNotAvailable,
+    FailedToSendMessage,
+    FailedToWriteTorrentFile,
+
+    // DHT error
+    FailedToBindDhtSocket,
+    FailedToSendDhtPacket,
+    InvalidKrpcMessage,
+    DhtNodeDidNotAnswer,
+    DhtNodeReturnedAnError,
+    FailedToReadRoutingTable,
+    FailedToWriteRoutingTable,
+    InvalidRoutingTableFile,
 
     // State machine errors
     NoPeersAvailable,
+    FailedToSetInterruptHandler,
+    HandshakeInfoHashMismatch,
+    ConnectedToOurselves,
+    DuplicatePeerConnection,
     // Handshake message error
     FailedToParseBitTorrentHandshakeProtocolNameField,
     FailedToParseBitTorrentHandshakeReservedField,
@@ -38,6 +85,7 @@ pub enum Error {
     MessageTypeDoesNotMatchWithExpectedOne,
     BytesArrayTooShortToContrainMessageFields,
     FailedToFindTheMessageTypeOfRawBytes,
+    MessageTooLong,
     // For request message
     FailedToParseBitTorrentRequestMessagePieceIndex,
     FailedToParseBitTorrentRequestMessageBeginOffset,
@@ -54,6 +102,14 @@ pub enum Error {
     FailedToParseBitTorrentCancelMessagePieceLength,
     // For port message
     FailedToParseBitTorrentPortMessagePieceIndex,
+    // For fast extension messages
+    FailedToParseBitTorrentSuggestPieceMessagePieceIndex,
+    FailedToParseBitTorrentRejectRequestMessagePieceIndex,
+    FailedToParseBitTorrentRejectRequestMessageBeginOffset,
+    FailedToParseBitTorrentRejectRequestMessagePieceLength,
+    FailedToParseBitTorrentAllowedFastMessagePieceIndex,
+    // For extended messages
+    InvalidPexMessage,
 
     // TCP error
     FailedToConnectToPeer,
@@ -68,9 +124,27 @@ pub enum Error {
     FailedToSetSocketWriteTimeout,
     FailedToPeekData,
     NotEnoughBytesToRead,
+    PeerClosedConnection,
+    FailedToCreateEventLoop,
+    TooManyConnections,
+
+    // Encryption error
+    EncryptionHandshakeFailed,
+    EncryptionSynchronizationFailed,
+    UnknownEncryptedInfoHash,
+    NoCommonEncryptionMethod,
+    PlaintextConnectionRefused,
+    EncryptedConnectionRefused,
+
+    // uTP error
+    InvalidUtpPacket,
+    FailedToBindUtpSocket,
+    UtpConnectionFailed,
+    UtpSocketClosed,
 
     // File management error
     DirectoryDoesNotExist,
+    FailedToCreateDirectory,
     FailedToCreateFile,
     FailedToWriteToFile,
     FailedToReadFromFile,
@@ -3,3 +3,6 @@ pub use block_reader_writer::BlockReaderWriter;
 
 mod local_bitfield;
 pub use local_bitfield::local_bitfield;
+
+mod piece_hash;
+pub use piece_hash::{is_piece_valid, piece_hash};
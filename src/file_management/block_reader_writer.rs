@@ -1,82 +1,175 @@
 use {
-    crate::Error,
+    crate::{Error, Torrent},
     std::{
-        fs::{File, OpenOptions},
+        fs::{self, File, OpenOptions},
         os::unix::fs::FileExt,
-        path::Path,
+        path::{Path, PathBuf},
     },
 };
+
+/// A file on disk and the range of the torrent data it stores
 #[derive(Debug)]
-pub struct BlockReaderWriter {
-    piece_length: u32,
-    file_size: usize,
+struct FileSegment {
     file: File,
+    /// offset of the first byte of the file in the torrent data
+    offset: u64,
+    length: u64,
+}
+
+impl FileSegment {
+    fn end(&self) -> u64 {
+        self.offset + self.length
+    }
+}
+
+#[derive(Debug)]
+pub struct BlockReaderWriter {
+    piece_length: u64,
+    file_size: u64,
+    segments: Vec<FileSegment>,
 }
 
 impl BlockReaderWriter {
     pub const BIT_TORRENT_BLOCK_SIZE: usize = 16 * 1024;
 
-    pub fn new(filepath: &Path, piece_length: u32, file_size: usize) -> Result<Self, Error> {
-        let file = OpenOptions::new()
-            .write(true)
-            .read(true)
-            .create(true)
-            .open(filepath)
-            .map_err(|_| Error::FailedToCreateFile)?;
+    /// Reader-writer over a single file holding all the torrent data.
+    pub fn new(filepath: &Path, piece_length: u64, file_size: u64) -> Result<Self, Error> {
+        Self::from_files(vec![(filepath.to_path_buf(), file_size)], piece_length)
+    }
+
+    /// Reader-writer over the files described by the torrent. Single-file
+    /// torrents are stored in `working_directory/name` (or directly at
+    /// `working_directory` if it is not a directory), multi-file torrents in
+    /// the `working_directory/name/` tree, which is created if needed.
+    pub fn from_torrent(torrent: &Torrent, working_directory: &Path) -> Result<Self, Error> {
+        let files = if torrent.is_multi_file() {
+            let root = working_directory.join(torrent.name());
+            torrent
+                .files()
+                .iter()
+                .map(|file| (root.join(file.relative_path()), file.length()))
+                .collect()
+        } else if working_directory.is_dir() {
+            let filepath = working_directory.join(torrent.name());
+            vec![(filepath, torrent.total_length_in_bytes())]
+        } else {
+            let filepath = working_directory.to_path_buf();
+            vec![(filepath, torrent.total_length_in_bytes())]
+        };
+
+        Self::from_files(files, torrent.piece_length_in_bytes())
+    }
+
+    fn from_files(files: Vec<(PathBuf, u64)>, piece_length: u64) -> Result<Self, Error> {
+        let mut segments = Vec::new();
+        let mut offset = 0;
+
+        for (filepath, length) in files {
+            if let Some(parent) = filepath.parent() {
+                if !parent.as_os_str().is_empty() {
+                    fs::create_dir_all(parent).map_err(|_| Error::FailedToCreateDirectory)?;
+                }
+            }
+
+            let file = OpenOptions::new()
+                .write(true)
+                .read(true)
+                .create(true)
+                .truncate(false)
+                .open(&filepath)
+                .map_err(|_| Error::FailedToCreateFile)?;
+
+            segments.push(FileSegment {
+                file,
+                offset,
+                length,
+            });
+            offset += length;
+        }
 
         Ok(Self {
             piece_length,
-            file,
-            file_size,
+            file_size: offset,
+            segments,
         })
     }
 
     pub fn write(&self, piece_index: u32, piece_offset: u32, data: &[u8]) -> Result<(), Error> {
-        if data.len() > Self::BIT_TORRENT_BLOCK_SIZE as usize {
+        if data.len() > Self::BIT_TORRENT_BLOCK_SIZE {
             return Err(Error::UnexpectedBlockSize);
         }
 
         let offset = Self::calculate_offset(piece_index, self.piece_length(), piece_offset);
-        if offset as usize + data.len() > self.file_size {
+        if offset + data.len() as u64 > self.file_size {
             return Err(Error::InvalidWriteOffset);
         }
 
-        self.file
-            .write_at(data, offset.into())
-            .map_err(|_| Error::FailedToWriteToFile)?;
+        for (segment, range, data_offset) in self.segments_in_range(offset, data.len()) {
+            segment
+                .file
+                .write_at(&data[data_offset..data_offset + range.1], range.0)
+                .map_err(|_| Error::FailedToWriteToFile)?;
+        }
 
         Ok(())
     }
 
     pub fn read(&self, piece_index: u32, piece_offset: u32) -> Result<Vec<u8>, Error> {
         let offset = Self::calculate_offset(piece_index, self.piece_length(), piece_offset);
-        let bytes_to_read = self.bytes_to_read(offset);
-
-        if offset as usize + bytes_to_read > self.file_size {
+        if offset >= self.file_size {
             return Err(Error::InvalidReadOffset);
         }
+        let bytes_to_read = self.bytes_to_read(offset);
 
         let mut data = vec![0u8; bytes_to_read];
-        self.file
-            .read_exact_at(&mut data, offset as u64)
-            .map_err(|_| Error::FailedToReadFromFile)?;
+        for (segment, range, data_offset) in self.segments_in_range(offset, bytes_to_read) {
+            segment
+                .file
+                .read_exact_at(&mut data[data_offset..data_offset + range.1], range.0)
+                .map_err(|_| Error::FailedToReadFromFile)?;
+        }
 
         Ok(data)
     }
 
-    pub fn piece_length(&self) -> u32 {
+    pub fn piece_length(&self) -> u64 {
         self.piece_length
     }
 
-    pub fn calculate_offset(piece_index: u32, piece_length: u32, piece_offset: u32) -> u32 {
-        piece_index * piece_length + piece_offset
+    pub fn calculate_offset(piece_index: u32, piece_length: u64, piece_offset: u32) -> u64 {
+        piece_index as u64 * piece_length + piece_offset as u64
     }
 
-    fn bytes_to_read(&self, offset: u32) -> usize {
-        if (offset as usize + Self::BIT_TORRENT_BLOCK_SIZE) > self.file_size {
-            self.file_size - offset as usize
+    fn bytes_to_read(&self, offset: u64) -> usize {
+        if (offset + Self::BIT_TORRENT_BLOCK_SIZE as u64) > self.file_size {
+            (self.file_size - offset) as usize
         } else {
             Self::BIT_TORRENT_BLOCK_SIZE
         }
     }
+
+    /// Splits the torrent data range `[offset, offset + length)` across the
+    /// files it spans. Each item holds the file, the (offset in file, length)
+    /// range to access and the matching offset in the caller's buffer.
+    fn segments_in_range(
+        &self,
+        offset: u64,
+        length: usize,
+    ) -> impl Iterator<Item = (&FileSegment, (u64, usize), usize)> {
+        let end = offset + length as u64;
+
+        self.segments
+            .iter()
+            .filter(move |segment| segment.offset < end && segment.end() > offset)
+            .map(move |segment| {
+                let begin = offset.max(segment.offset);
+                let finish = end.min(segment.end());
+
+                (
+                    segment,
+                    (begin - segment.offset, (finish - begin) as usize),
+                    (begin - offset) as usize,
+                )
+            })
+    }
 }
@@ -1,13 +1,14 @@
 use {
-    crate::{file_management::BlockReaderWriter, torrent, Torrent},
+    crate::{
+        file_management::{piece_hash, BlockReaderWriter},
+        Torrent,
+    },
     bit_vec::BitVec,
-    sha1::{Digest, Sha1},
     std::path::Path,
 };
 
 pub fn local_bitfield(torrent: &Torrent, working_dir: &Path) -> BitVec {
-    let pieces = read_pieces_from_disk(torrent, working_dir);
-    let real_hashes = calculate_piece_hashes(&pieces);
+    let real_hashes = calculate_piece_hashes(torrent, working_dir);
     let expected_hashes = torrent.piece_hashes();
     let mut bitfield = create_bitfield_from_hashes(&expected_hashes, &real_hashes);
 
@@ -20,49 +21,13 @@ pub fn local_bitfield(torrent: &Torrent, working_dir: &Path) -> BitVec {
     bitfield
 }
 
-fn read_pieces_from_disk(torrent: &Torrent, working_dir: &Path) -> Vec<Vec<u8>> {
-    let filepath = working_dir.join(torrent.name());
-    let piece_length = torrent.piece_length_in_bytes();
-    let total_length = torrent.total_length_in_bytes() as usize;
+/// Hashes the pieces one at a time, so that only a single piece is held in
+/// memory even for torrents of several gigabytes.
+fn calculate_piece_hashes(torrent: &Torrent, working_dir: &Path) -> Vec<[u8; 20]> {
+    let reader_writer = BlockReaderWriter::from_torrent(torrent, working_dir).unwrap();
 
-    let reader_writer = BlockReaderWriter::new(&filepath, piece_length, total_length).unwrap();
-    let piece_length = piece_length as usize;
-    let total_pieces = torrent::div_ceil(total_length as u32, piece_length as u32);
-
-    let mut pieces = Vec::new();
-    for piece_id in 0..total_pieces {
-        let mut piece = Vec::new();
-
-        let blocks_per_piece = torrent::expected_blocks_in_piece(piece_id, torrent);
-
-        for block_id in 0..blocks_per_piece {
-            let maybe_block = reader_writer.read(
-                piece_id as u32,
-                block_id as u32 * BlockReaderWriter::BIT_TORRENT_BLOCK_SIZE as u32,
-            );
-
-            let block = match maybe_block {
-                Ok(block) => block,
-                Err(_) => vec![0],
-            };
-
-            piece.extend(block);
-        }
-
-        pieces.push(piece);
-    }
-
-    pieces
-}
-
-fn calculate_piece_hashes(pieces: &Vec<Vec<u8>>) -> Vec<[u8; 20]> {
-    pieces
-        .iter()
-        .map(|piece| {
-            let mut hasher = Sha1::new();
-            hasher.update(piece);
-            hasher.finalize().into()
-        })
+    (0..torrent.number_of_pieces())
+        .map(|piece_id| piece_hash(torrent, &reader_writer, piece_id))
         .collect()
 }
 
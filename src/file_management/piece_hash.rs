@@ -0,0 +1,41 @@
+use {
+    crate::{file_management::BlockReaderWriter, torrent, Torrent},
+    sha1::{Digest, Sha1},
+};
+
+/// Hashes a piece block by block as it is stored on disk. Blocks that cannot
+/// be read are hashed as a single zero byte, so the result never matches.
+pub fn piece_hash(
+    torrent: &Torrent,
+    reader_writer: &BlockReaderWriter,
+    piece_index: u32,
+) -> [u8; 20] {
+    let mut hasher = Sha1::new();
+    let blocks_per_piece = torrent::expected_blocks_in_piece(piece_index, torrent);
+
+    for block_id in 0..blocks_per_piece {
+        let maybe_block = reader_writer.read(
+            piece_index,
+            block_id as u32 * BlockReaderWriter::BIT_TORRENT_BLOCK_SIZE as u32,
+        );
+
+        match maybe_block {
+            Ok(block) => hasher.update(block),
+            Err(_) => hasher.update([0]),
+        }
+    }
+
+    hasher.finalize().into()
+}
+
+/// Compares the SHA-1 of the piece on disk with the one in the torrent.
+pub fn is_piece_valid(
+    torrent: &Torrent,
+    reader_writer: &BlockReaderWriter,
+    piece_index: u32,
+) -> bool {
+    match torrent.piece_hashes().get(piece_index as usize) {
+        Some(expected_hash) => piece_hash(torrent, reader_writer, piece_index) == *expected_hash,
+        None => false,
+    }
+}
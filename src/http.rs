@@ -1,11 +1,19 @@
 mod event;
 mod peer;
+mod scrape_request;
+mod scrape_response;
+mod scrape_statistics;
 mod tracker_address;
+mod tracker_list;
 mod tracker_request;
 mod tracker_response;
 
 pub use event::Event;
 pub use peer::Peer;
-pub use tracker_address::TrackerAddress;
+pub use scrape_request::ScrapeRequest;
+pub use scrape_response::ScrapeResponse;
+pub use scrape_statistics::ScrapeStatistics;
+pub use tracker_address::{TrackerAddress, TrackerProtocol};
+pub use tracker_list::TrackerList;
 pub use tracker_request::TrackerRequest;
 pub use tracker_response::TrackerResponse;
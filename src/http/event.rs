@@ -1,13 +1,24 @@
 use std::fmt;
 
-#[derive(Debug)]
-#[allow(dead_code)]
+#[derive(Debug, Clone)]
 pub enum Event {
     Started,
     Stopped,
     Completed,
 }
 
+impl Event {
+    /// Event identifier used by UDP trackers, 0 meaning no event
+    pub fn udp_id(event: Option<&Event>) -> u32 {
+        match event {
+            None => 0,
+            Some(Event::Completed) => 1,
+            Some(Event::Started) => 2,
+            Some(Event::Stopped) => 3,
+        }
+    }
+}
+
 impl fmt::Display for Event {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let text = match self {
@@ -1,4 +1,4 @@
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 
 #[derive(Debug, Clone, Copy, Eq, Hash, PartialEq)]
 pub struct Peer {
@@ -6,6 +6,7 @@ pub struct Peer {
 }
 
 impl Peer {
+    /// Compact IPv4 form, 4 bytes of address followed by 2 bytes of port
     pub fn from_bytes(chunk: &[u8]) -> Self {
         let ip = Ipv4Addr::new(chunk[0], chunk[1], chunk[2], chunk[3]);
         let port = (chunk[4] as u16 * 256) + chunk[5] as u16;
@@ -15,11 +16,51 @@ impl Peer {
         }
     }
 
+    /// Compact IPv6 form (BEP 7), 16 bytes of address followed by 2 bytes of
+    /// port
+    pub fn from_ipv6_bytes(chunk: &[u8]) -> Self {
+        let octets: [u8; 16] = chunk[..16].try_into().unwrap();
+        let port = (chunk[16] as u16 * 256) + chunk[17] as u16;
+
+        Self::from_socket_address(SocketAddr::new(IpAddr::V6(Ipv6Addr::from(octets)), port))
+    }
+
+    /// IPv4 peers accepted on a dual-stack socket show up with an
+    /// IPv4-mapped IPv6 address, they are stored with their IPv4 one.
     pub fn from_socket_address(socket_address: SocketAddr) -> Self {
-        Peer { socket_address }
+        let ip = match socket_address.ip() {
+            IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+                Some(ip) => IpAddr::V4(ip),
+                None => IpAddr::V6(ip),
+            },
+            ip => ip,
+        };
+
+        Peer {
+            socket_address: SocketAddr::new(ip, socket_address.port()),
+        }
     }
 
     pub fn socket_address(self) -> String {
         self.socket_address.to_string()
     }
+
+    /// Compact form, 6 bytes for IPv4 peers and 18 bytes for IPv6 ones
+    pub fn compact_bytes(&self) -> Vec<u8> {
+        let mut bytes = match self.socket_address.ip() {
+            IpAddr::V4(ip) => ip.octets().to_vec(),
+            IpAddr::V6(ip) => ip.octets().to_vec(),
+        };
+        bytes.extend(self.socket_address.port().to_be_bytes());
+
+        bytes
+    }
+
+    pub fn ip(&self) -> IpAddr {
+        self.socket_address.ip()
+    }
+
+    pub fn port(&self) -> u16 {
+        self.socket_address.port()
+    }
 }
@@ -0,0 +1,65 @@
+use crate::{
+    http::{ScrapeResponse, TrackerAddress, TrackerProtocol},
+    udp::UdpTracker,
+    Error,
+};
+
+use reqwest::Url;
+
+#[derive(Debug, Clone)]
+pub struct ScrapeRequest {
+    info_hashes: Vec<[u8; 20]>,
+}
+
+impl ScrapeRequest {
+    pub fn new(info_hashes: Vec<[u8; 20]>) -> Self {
+        Self { info_hashes }
+    }
+
+    pub fn send_request(
+        scrape_request: ScrapeRequest,
+        tracker: TrackerAddress,
+    ) -> Result<ScrapeResponse, Error> {
+        let url = match tracker.protocol() {
+            TrackerProtocol::Udp => {
+                let statistics = UdpTracker::new(&tracker)?.scrape(&scrape_request.info_hashes)?;
+                let files = scrape_request.info_hashes.into_iter().zip(statistics);
+
+                return Ok(ScrapeResponse::new(files.collect()));
+            }
+            _ => scrape_request.into_url(&tracker)?,
+        };
+
+        let mut response =
+            reqwest::blocking::get(url).map_err(|_| Error::TrackerConnectionNotPossible)?;
+        let mut bencode = Vec::new();
+        response
+            .copy_to(&mut bencode)
+            .map_err(|_| Error::TrackerConnectionNotPossible)?;
+
+        ScrapeResponse::from_bencode(&bencode)
+    }
+
+    /// The info hashes are percent-encoded by hand, since they are not UTF-8.
+    pub fn into_url(self, tracker: &TrackerAddress) -> Result<Url, Error> {
+        let scheme = match tracker.protocol() {
+            TrackerProtocol::Https => "https",
+            _ => "http",
+        };
+        let mut url = format!(
+            "{}://{}:{}{}",
+            scheme,
+            tracker.host(),
+            tracker.port(),
+            tracker.scrape_path()?
+        );
+
+        for (index, info_hash) in self.info_hashes.iter().enumerate() {
+            url.push(if index == 0 { '?' } else { '&' });
+            url.push_str("info_hash=");
+            url.push_str(&urlencoding::encode_binary(info_hash));
+        }
+
+        Url::parse(&url).map_err(|_| Error::FailedToParseUrl)
+    }
+}
@@ -0,0 +1,98 @@
+use crate::Error;
+
+use {
+    crate::http::{ScrapeStatistics, TrackerResponse},
+    bendy::decoding::{Decoder, DictDecoder, Object},
+    std::str,
+};
+
+#[derive(Debug)]
+pub struct ScrapeResponse {
+    failure: Option<String>,
+    /// statistics of each scraped torrent, by info hash
+    files: Vec<([u8; 20], ScrapeStatistics)>,
+}
+
+impl ScrapeResponse {
+    pub fn new(files: Vec<([u8; 20], ScrapeStatistics)>) -> Self {
+        Self {
+            failure: None,
+            files,
+        }
+    }
+
+    pub fn failure(&self) -> Option<&String> {
+        self.failure.as_ref()
+    }
+
+    pub fn statistics(&self, info_hash: &[u8; 20]) -> Option<&ScrapeStatistics> {
+        self.files
+            .iter()
+            .find(|(hash, _)| hash == info_hash)
+            .map(|(_, statistics)| statistics)
+    }
+
+    pub fn from_bencode(data: &[u8]) -> Result<Self, Error> {
+        let mut decoder = Decoder::new(data);
+        let object = decoder
+            .next_object()
+            .map_err(|_| Error::UnexpectedResponseFromTracker)?
+            .ok_or(Error::UnexpectedResponseFromTracker)?;
+
+        let mut dictionary =
+            object.dictionary_or_else(|_| Err(Error::UnexpectedResponseFromTracker))?;
+        let mut response = Self::new(Vec::new());
+
+        while let Ok(Some(pair)) = dictionary.next_pair() {
+            match pair {
+                (b"files", Object::Dict(mut files)) => {
+                    response.files = Self::parse_files(&mut files)?;
+                }
+                (b"failure reason", value) => {
+                    response
+                        .failure
+                        .replace(TrackerResponse::parse_failure(value)?);
+                }
+                (key, _) => {
+                    log::warn!("unhandled parameter [{}]", str::from_utf8(key).unwrap());
+                }
+            }
+        }
+
+        Ok(response)
+    }
+
+    fn parse_files(files: &mut DictDecoder) -> Result<Vec<([u8; 20], ScrapeStatistics)>, Error> {
+        let mut statistics = Vec::new();
+
+        while let Ok(Some((info_hash, value))) = files.next_pair() {
+            let info_hash = info_hash
+                .try_into()
+                .map_err(|_| Error::UnexpectedResponseFromTracker)?;
+            let mut file =
+                value.dictionary_or_else(|_| Err(Error::BencodeObjectHasUnexpectedType))?;
+
+            statistics.push((info_hash, Self::parse_statistics(&mut file)?));
+        }
+
+        Ok(statistics)
+    }
+
+    /// Missing counts are reported as 0.
+    fn parse_statistics(file: &mut DictDecoder) -> Result<ScrapeStatistics, Error> {
+        let (mut complete, mut downloaded, mut incomplete) = (0, 0, 0);
+
+        while let Ok(Some(pair)) = file.next_pair() {
+            match pair {
+                (b"complete", value) => complete = TrackerResponse::parse_integer(value)?,
+                (b"downloaded", value) => downloaded = TrackerResponse::parse_integer(value)?,
+                (b"incomplete", value) => incomplete = TrackerResponse::parse_integer(value)?,
+                (key, _) => {
+                    log::debug!("unhandled scrape field [{}]", String::from_utf8_lossy(key));
+                }
+            }
+        }
+
+        Ok(ScrapeStatistics::new(complete, downloaded, incomplete))
+    }
+}
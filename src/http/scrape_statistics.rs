@@ -0,0 +1,32 @@
+/// Swarm statistics of a torrent, as returned by a tracker scrape.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScrapeStatistics {
+    /// number of peers with the entire file (seeders)
+    complete: usize,
+    /// number of times the tracker registered a completion
+    downloaded: usize,
+    /// number of non-seeder peers (leechers)
+    incomplete: usize,
+}
+
+impl ScrapeStatistics {
+    pub fn new(complete: usize, downloaded: usize, incomplete: usize) -> Self {
+        Self {
+            complete,
+            downloaded,
+            incomplete,
+        }
+    }
+
+    pub fn complete(&self) -> usize {
+        self.complete
+    }
+
+    pub fn downloaded(&self) -> usize {
+        self.downloaded
+    }
+
+    pub fn incomplete(&self) -> usize {
+        self.incomplete
+    }
+}
@@ -1,23 +1,58 @@
-use crate::{error::Error, torrent::Torrent};
+use crate::error::Error;
 use reqwest::Url;
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TrackerProtocol {
+    Http,
+    Https,
+    Udp,
+}
+
+#[derive(Debug, Clone)]
 pub struct TrackerAddress {
+    protocol: TrackerProtocol,
     host: String,
     port: u16,
+    /// path of the announce URL, such as `/announce`
+    path: String,
+    /// query of the announce URL, such as a passkey, kept in the announces
+    query: Option<String>,
 }
 
 impl TrackerAddress {
-    pub fn from_torrent(torrent: &Torrent) -> Result<Self, Error> {
-        let tracker_url = Url::parse(torrent.announce()).map_err(|_| Error::InvalidURLAddress)?;
+    pub fn from_announce(announce: &str) -> Result<Self, Error> {
+        let tracker_url = Url::parse(announce).map_err(|_| Error::InvalidURLAddress)?;
 
         TrackerAddress::from_url(tracker_url)
     }
 
+    /// HTTP(S) trackers default to the well-known ports, UDP trackers must
+    /// always provide one.
     pub fn from_url(url: Url) -> Result<Self, Error> {
+        let protocol = match url.scheme() {
+            "http" => TrackerProtocol::Http,
+            "https" => TrackerProtocol::Https,
+            "udp" => TrackerProtocol::Udp,
+            _ => return Err(Error::UnsupportedTrackerProtocol),
+        };
         let host = url.host().ok_or(Error::TrackerHostNotProvided)?.to_string();
-        let port = url.port().or_else(|| Some(80)).unwrap();
+        let port = url
+            .port_or_known_default()
+            .ok_or(Error::TrackerPortNotProvided)?;
+        let path = url.path().to_string();
+        let query = url.query().map(String::from);
+
+        Ok(Self {
+            protocol,
+            host,
+            port,
+            path,
+            query,
+        })
+    }
 
-        Ok(Self { host, port })
+    pub fn protocol(&self) -> TrackerProtocol {
+        self.protocol
     }
 
     pub fn host(&self) -> &str {
@@ -27,4 +62,27 @@ impl TrackerAddress {
     pub fn port(&self) -> u16 {
         self.port
     }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn query(&self) -> Option<&str> {
+        self.query.as_deref()
+    }
+
+    /// By convention, the scrape path is the announce path with the
+    /// `announce` at the start of its last component replaced by `scrape`.
+    /// Trackers whose announce path does not follow it do not support scrape.
+    pub fn scrape_path(&self) -> Result<String, Error> {
+        let (directory, last_component) = self
+            .path
+            .rsplit_once('/')
+            .ok_or(Error::TrackerDoesNotSupportScrape)?;
+        let suffix = last_component
+            .strip_prefix("announce")
+            .ok_or(Error::TrackerDoesNotSupportScrape)?;
+
+        Ok(format!("{}/scrape{}", directory, suffix))
+    }
 }
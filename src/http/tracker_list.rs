@@ -0,0 +1,69 @@
+use {
+    crate::{http::TrackerResponse, torrent::Torrent, Error},
+    rand::{seq::SliceRandom, thread_rng},
+};
+
+/// Tiers of trackers following the BEP 12 semantics: trackers are tried
+/// tier by tier, in order, and a tracker answering successfully is moved
+/// to the front of its tier.
+#[derive(Debug)]
+pub struct TrackerList {
+    tiers: Vec<Vec<String>>,
+}
+
+impl TrackerList {
+    pub fn new(tiers: Vec<Vec<String>>) -> Self {
+        Self { tiers }
+    }
+
+    /// Builds the list from the torrent, shuffling each tier once.
+    pub fn from_torrent(torrent: &Torrent) -> Self {
+        let mut tiers = torrent.announce_tiers();
+        tiers
+            .iter_mut()
+            .for_each(|tier| tier.shuffle(&mut thread_rng()));
+
+        Self::new(tiers)
+    }
+
+    pub fn tiers(&self) -> &Vec<Vec<String>> {
+        &self.tiers
+    }
+
+    /// Announces to the first tracker that answers without failure and
+    /// promotes it within its tier. Returns the last error if every tracker
+    /// failed.
+    pub fn announce(
+        &mut self,
+        mut send_request: impl FnMut(&str) -> Result<TrackerResponse, Error>,
+    ) -> Result<TrackerResponse, Error> {
+        let mut last_error = Error::NoTrackerAvailable;
+
+        for tier in self.tiers.iter_mut() {
+            for index in 0..tier.len() {
+                match send_request(&tier[index]) {
+                    Ok(response) if response.failure().is_none() => {
+                        let tracker = tier.remove(index);
+                        tier.insert(0, tracker);
+
+                        return Ok(response);
+                    }
+                    Ok(response) => {
+                        log::warn!(
+                            "Tracker {} refused the announce: {:?}",
+                            tier[index],
+                            response.failure()
+                        );
+                        last_error = Error::TrackerRefusedAnnounce;
+                    }
+                    Err(error) => {
+                        log::warn!("Tracker {} is not reachable: {:?}", tier[index], error);
+                        last_error = error;
+                    }
+                }
+            }
+        }
+
+        Err(last_error)
+    }
+}
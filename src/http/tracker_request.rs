@@ -1,20 +1,21 @@
 use crate::{
-    http::{Event, TrackerAddress, TrackerResponse},
+    http::{Event, TrackerAddress, TrackerProtocol, TrackerResponse},
     state_machine::StateMachine,
     torrent::Torrent,
+    udp::UdpTrackers,
     Error,
 };
 
 use reqwest::Url;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct TrackerRequest {
     info_hash: [u8; 20],
     peer_id: [u8; 20],
     port: u16,
-    uploaded: usize,
-    downloaded: usize,
-    left: usize,
+    uploaded: u64,
+    downloaded: u64,
+    left: u64,
     compact: bool,
     event: Option<Event>,
 }
@@ -24,9 +25,9 @@ impl TrackerRequest {
         info_hash: [u8; 20],
         peer_id: [u8; 20],
         port: u16,
-        uploaded: usize,
-        downloaded: usize,
-        left: usize,
+        uploaded: u64,
+        downloaded: u64,
+        left: u64,
         compact: bool,
         event: Option<Event>,
     ) -> Self {
@@ -42,45 +43,99 @@ impl TrackerRequest {
         }
     }
 
+    /// UDP trackers are announced to through their client in `udp_trackers`.
     pub fn send_request(
         tracker_request: TrackerRequest,
         tracker: TrackerAddress,
+        udp_trackers: &mut UdpTrackers,
     ) -> Result<TrackerResponse, Error> {
-        let url = tracker_request.into_url(tracker.host(), tracker.port())?;
+        let url = match tracker.protocol() {
+            TrackerProtocol::Udp => {
+                return udp_trackers.tracker(&tracker)?.announce(&tracker_request);
+            }
+            _ => tracker_request.into_url(&tracker)?,
+        };
 
         let mut response =
             reqwest::blocking::get(url).map_err(|_| Error::TrackerConnectionNotPossible)?;
         let mut bencode = Vec::new();
-        response.copy_to(&mut bencode).unwrap();
+        response
+            .copy_to(&mut bencode)
+            .map_err(|_| Error::TrackerConnectionNotPossible)?;
         let parsed_response = TrackerResponse::from_bencode(&bencode);
 
         parsed_response
     }
 
+    pub fn info_hash(&self) -> [u8; 20] {
+        self.info_hash
+    }
+
+    pub fn peer_id(&self) -> [u8; 20] {
+        self.peer_id
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn uploaded(&self) -> u64 {
+        self.uploaded
+    }
+
+    pub fn downloaded(&self) -> u64 {
+        self.downloaded
+    }
+
+    pub fn left(&self) -> u64 {
+        self.left
+    }
+
+    pub fn event(&self) -> Option<&Event> {
+        self.event.as_ref()
+    }
+
     pub fn from_torrent(
         torrent: &Torrent,
         peer_id: [u8; 20],
-        left_to_download: u32,
+        uploaded: u64,
+        downloaded: u64,
+        left_to_download: u64,
+        event: Option<Event>,
     ) -> TrackerRequest {
         let info_hash = torrent.info_hash();
         let tracker_request = TrackerRequest::new(
             info_hash,
             peer_id,
             StateMachine::CLIENT_PORT,
-            0,
-            0,
-            left_to_download as usize,
+            uploaded,
+            downloaded,
+            left_to_download,
             true,
-            Some(Event::Started),
+            event,
         );
 
         tracker_request
     }
-    pub fn into_url(self, host: &str, port: u16) -> Result<Url, Error> {
+
+    /// The announce URL of the tracker, with its query such as a passkey
+    /// kept ahead of the parameters of the request.
+    pub fn into_url(self, tracker: &TrackerAddress) -> Result<Url, Error> {
+        let scheme = match tracker.protocol() {
+            TrackerProtocol::Https => "https",
+            _ => "http",
+        };
+        let query = match tracker.query() {
+            Some(query) if !query.is_empty() => format!("{}&", query),
+            _ => String::new(),
+        };
         let mut url = Url::parse(&format!(
-            "http://{}:{}/announce?info_hash={}&peer_id={}",
-            host,
-            port,
+            "{}://{}:{}{}?{}info_hash={}&peer_id={}",
+            scheme,
+            tracker.host(),
+            tracker.port(),
+            tracker.path(),
+            query,
             urlencoding::encode_binary(&self.info_hash),
             urlencoding::encode_binary(&self.peer_id),
         ))
@@ -2,8 +2,11 @@ use crate::Error;
 
 use {
     crate::http::Peer,
-    bendy::decoding::{Decoder, Object},
-    std::str,
+    bendy::decoding::{Decoder, DictDecoder, Object},
+    std::{
+        net::{IpAddr, SocketAddr, ToSocketAddrs},
+        str,
+    },
 };
 
 #[derive(Debug)]
@@ -13,10 +16,48 @@ pub struct TrackerResponse {
     downloaded: Option<usize>,
     incomplete: Option<usize>,
     interval: Option<usize>,
+    min_interval: Option<usize>,
     peers: Option<Vec<Peer>>,
 }
 
 impl TrackerResponse {
+    pub fn new(interval: usize, complete: usize, incomplete: usize, peers: Vec<Peer>) -> Self {
+        Self {
+            complete: Some(complete),
+            incomplete: Some(incomplete),
+            interval: Some(interval),
+            peers: Some(peers),
+            ..Self::empty()
+        }
+    }
+
+    pub fn from_failure(failure: String) -> Self {
+        Self {
+            failure: Some(failure),
+            ..Self::empty()
+        }
+    }
+
+    pub fn failure(&self) -> Option<&String> {
+        self.failure.as_ref()
+    }
+
+    pub fn complete(&self) -> Option<usize> {
+        self.complete
+    }
+
+    pub fn incomplete(&self) -> Option<usize> {
+        self.incomplete
+    }
+
+    pub fn interval(&self) -> Option<usize> {
+        self.interval
+    }
+
+    pub fn min_interval(&self) -> Option<usize> {
+        self.min_interval
+    }
+
     pub fn peers(&self) -> Option<&Vec<Peer>> {
         self.peers.as_ref()
     }
@@ -49,6 +90,7 @@ impl TrackerResponse {
             downloaded: None,
             incomplete: None,
             interval: None,
+            min_interval: None,
             peers: None,
         }
     }
@@ -67,8 +109,19 @@ impl TrackerResponse {
             (b"interval", value) => {
                 self.interval.replace(Self::parse_integer(value)?);
             }
+            (b"min interval", value) => {
+                self.min_interval.replace(Self::parse_integer(value)?);
+            }
             (b"peers", value) => {
-                self.peers.replace(Self::parse_peers(value)?);
+                let peers = Self::parse_peers(value)?;
+                self.peers.get_or_insert_with(Vec::new).extend(peers);
+            }
+            (b"peers6", value) => {
+                let bytes = value
+                    .try_into_bytes()
+                    .map_err(|_| Error::BencodeObjectHasUnexpectedType)?;
+                let peers = Self::parse_compact_ipv6_peers(bytes);
+                self.peers.get_or_insert_with(Vec::new).extend(peers);
             }
             (b"failure reason", value) => {
                 self.failure.replace(Self::parse_failure(value)?);
@@ -81,7 +134,7 @@ impl TrackerResponse {
         Ok(())
     }
 
-    fn parse_integer(object: Object) -> Result<usize, Error> {
+    pub(crate) fn parse_integer(object: Object) -> Result<usize, Error> {
         let integer = object
             .try_into_integer()
             .map_err(|_| Error::BencodeObjectHasUnexpectedType)?
@@ -91,18 +144,81 @@ impl TrackerResponse {
         Ok(integer)
     }
 
+    /// Trackers send either the compact string or, when they do not support
+    /// it, a list of dictionaries.
     fn parse_peers(object: Object) -> Result<Vec<Peer>, Error> {
-        let peers = object
-            .try_into_bytes()
-            .map_err(|_| Error::BencodeObjectHasUnexpectedType)?
-            .chunks(6)
-            .map(|chunk| Peer::from_bytes(chunk))
-            .collect();
+        match object {
+            Object::Bytes(bytes) => Ok(Self::parse_compact_peers(bytes)),
+            Object::List(mut list) => {
+                let mut peers = Vec::new();
+                while let Ok(Some(object)) = list.next_object() {
+                    let mut dictionary = object
+                        .dictionary_or_else(|_| Err(Error::BencodeObjectHasUnexpectedType))?;
+                    match Self::parse_peer_dictionary(&mut dictionary)? {
+                        Some(peer) => peers.push(peer),
+                        None => log::warn!("Skipping a peer whose address cannot be resolved."),
+                    }
+                }
+
+                Ok(peers)
+            }
+            _ => Err(Error::BencodeObjectHasUnexpectedType),
+        }
+    }
+
+    /// Parses `{peer id, ip, port}`, `ip` being an IPv4 address, an IPv6
+    /// address or a DNS name. The peer id is not needed to connect to the
+    /// peer.
+    fn parse_peer_dictionary(dictionary: &mut DictDecoder) -> Result<Option<Peer>, Error> {
+        let mut ip = None;
+        let mut port = None;
+
+        while let Ok(Some(pair)) = dictionary.next_pair() {
+            match pair {
+                (b"ip", value) => {
+                    let bytes = value
+                        .try_into_bytes()
+                        .map_err(|_| Error::BencodeObjectHasUnexpectedType)?;
+                    ip = Some(
+                        str::from_utf8(bytes)
+                            .map_err(|_| Error::BencodeObjectHasUnexpectedType)?
+                            .to_string(),
+                    );
+                }
+                (b"port", value) => {
+                    let integer = Self::parse_integer(value)?;
+                    port = Some(u16::try_from(integer).map_err(|_| Error::InvalidPeerPort)?);
+                }
+                _ => (),
+            }
+        }
+
+        let ip = ip.ok_or(Error::PeerAddressNotProvided)?;
+        let port = port.ok_or(Error::InvalidPeerPort)?;
+        let socket_address = match ip.parse::<IpAddr>() {
+            Ok(ip) => Some(SocketAddr::new(ip, port)),
+            Err(_) => (ip.as_str(), port)
+                .to_socket_addrs()
+                .ok()
+                .and_then(|mut addresses| addresses.next()),
+        };
+
+        Ok(socket_address.map(Peer::from_socket_address))
+    }
+
+    /// Parses the compact peer list format, 4 bytes of IPv4 address followed
+    /// by 2 bytes of port for each peer.
+    pub fn parse_compact_peers(bytes: &[u8]) -> Vec<Peer> {
+        bytes.chunks_exact(6).map(Peer::from_bytes).collect()
+    }
 
-        Ok(peers)
+    /// Parses the compact IPv6 peer list of the `peers6` key, 16 bytes of
+    /// IPv6 address followed by 2 bytes of port for each peer.
+    pub fn parse_compact_ipv6_peers(bytes: &[u8]) -> Vec<Peer> {
+        bytes.chunks_exact(18).map(Peer::from_ipv6_bytes).collect()
     }
 
-    fn parse_failure(object: Object) -> Result<String, Error> {
+    pub(crate) fn parse_failure(object: Object) -> Result<String, Error> {
         let failure_message = str::from_utf8(
             object
                 .try_into_bytes()
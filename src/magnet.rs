@@ -0,0 +1,7 @@
+mod magnet_link;
+mod metadata_fetcher;
+mod metadata_message;
+
+pub use magnet_link::MagnetLink;
+pub use metadata_fetcher::MetadataFetcher;
+pub use metadata_message::MetadataMessage;
@@ -0,0 +1,147 @@
+use crate::Error;
+use reqwest::Url;
+
+/// A `magnet:?xt=urn:btih:<info hash>` link. Only the parameters needed to
+/// download the torrent are kept: the info hash, the display name and the
+/// trackers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MagnetLink {
+    info_hash: [u8; 20],
+    /// `dn`, suggested name to display while the metadata is not known
+    display_name: Option<String>,
+    /// `tr`, tracker URLs
+    trackers: Vec<String>,
+}
+
+impl MagnetLink {
+    const BTIH_PREFIX: &'static str = "urn:btih:";
+    const BASE32_ALPHABET: &'static [u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+    pub fn new(info_hash: [u8; 20], display_name: Option<String>, trackers: Vec<String>) -> Self {
+        Self {
+            info_hash,
+            display_name,
+            trackers,
+        }
+    }
+
+    pub fn is_magnet_link(link: &str) -> bool {
+        link.starts_with("magnet:?")
+    }
+
+    pub fn info_hash(&self) -> [u8; 20] {
+        self.info_hash
+    }
+
+    pub fn display_name(&self) -> Option<&String> {
+        self.display_name.as_ref()
+    }
+
+    pub fn trackers(&self) -> &Vec<String> {
+        &self.trackers
+    }
+
+    /// Each tracker of the link is tried in turn, as if it was a tier of its
+    /// own.
+    pub fn announce_tiers(&self) -> Vec<Vec<String>> {
+        self.trackers
+            .iter()
+            .map(|tracker| vec![tracker.clone()])
+            .collect()
+    }
+
+    /// Bencoded metainfo made of the trackers of the link and of the info
+    /// dictionary downloaded from the peers.
+    pub fn torrent_bencode(&self, info_dictionary: &[u8]) -> Vec<u8> {
+        let bencode_string = |string: &str| format!("{}:{}", string.len(), string);
+
+        let mut bencode = b"d".to_vec();
+        if let Some(announce) = self.trackers.first() {
+            bencode.extend(format!("8:announce{}", bencode_string(announce)).into_bytes());
+            bencode.extend(b"13:announce-listl");
+            for tracker in self.trackers.iter() {
+                bencode.extend(format!("l{}e", bencode_string(tracker)).into_bytes());
+            }
+            bencode.push(b'e');
+        }
+        bencode.extend(b"4:info");
+        bencode.extend(info_dictionary);
+        bencode.push(b'e');
+
+        bencode
+    }
+
+    pub fn parse(link: &str) -> Result<Self, Error> {
+        if !Self::is_magnet_link(link) {
+            return Err(Error::InvalidMagnetLink);
+        }
+        let url = Url::parse(link).map_err(|_| Error::InvalidMagnetLink)?;
+
+        let mut info_hash = None;
+        let mut display_name = None;
+        let mut trackers = Vec::new();
+        for (key, value) in url.query_pairs() {
+            match key.as_ref() {
+                "xt" => {
+                    if let Some(hash) = value.strip_prefix(Self::BTIH_PREFIX) {
+                        info_hash = Some(Self::decode_info_hash(hash)?);
+                    }
+                }
+                "dn" => display_name = Some(value.into_owned()),
+                "tr" => trackers.push(value.into_owned()),
+                _ => log::debug!("Skipping magnet link parameter {}.", key),
+            }
+        }
+
+        let info_hash = info_hash.ok_or(Error::MagnetLinkWithoutInfoHash)?;
+
+        Ok(Self::new(info_hash, display_name, trackers))
+    }
+
+    /// The info hash is either hex encoded (40 characters) or base32 encoded
+    /// (32 characters).
+    fn decode_info_hash(hash: &str) -> Result<[u8; 20], Error> {
+        match hash.len() {
+            40 => Self::decode_hex(hash),
+            32 => Self::decode_base32(hash),
+            _ => Err(Error::InvalidMagnetLinkInfoHash),
+        }
+    }
+
+    fn decode_hex(hash: &str) -> Result<[u8; 20], Error> {
+        let mut info_hash = [0u8; 20];
+        for (index, byte) in info_hash.iter_mut().enumerate() {
+            let digits = hash
+                .get(index * 2..index * 2 + 2)
+                .ok_or(Error::InvalidMagnetLinkInfoHash)?;
+            *byte = u8::from_str_radix(digits, 16).map_err(|_| Error::InvalidMagnetLinkInfoHash)?;
+        }
+
+        Ok(info_hash)
+    }
+
+    /// RFC 4648 base32, 32 characters of 5 bits giving exactly 20 bytes.
+    fn decode_base32(hash: &str) -> Result<[u8; 20], Error> {
+        let mut info_hash = [0u8; 20];
+        let mut buffer: u64 = 0;
+        let mut buffered_bits = 0;
+        let mut index = 0;
+
+        for character in hash.bytes() {
+            let value = Self::BASE32_ALPHABET
+                .iter()
+                .position(|letter| *letter == character.to_ascii_uppercase())
+                .ok_or(Error::InvalidMagnetLinkInfoHash)?;
+            buffer = (buffer << 5) | value as u64;
+            buffered_bits += 5;
+
+            if buffered_bits >= 8 {
+                buffered_bits -= 8;
+                info_hash[index] = (buffer >> buffered_bits) as u8;
+                index += 1;
+            }
+        }
+
+        Ok(info_hash)
+    }
+}
@@ -0,0 +1,266 @@
+use {
+    crate::{
+        http::{Event, Peer, TrackerAddress, TrackerList, TrackerRequest},
+        magnet::{MagnetLink, MetadataMessage},
+        pwp::{
+            Extended, ExtensionHandshake, ExtensionRegistry, FromBytes, Handshake, IntoBytes,
+            MessageType,
+        },
+        state_machine::{identity::CLIENT_VERSION, StateMachine},
+        udp::UdpTrackers,
+        Error,
+    },
+    sha1::{Digest, Sha1},
+    std::{
+        io::{Read, Write},
+        net::{SocketAddr, TcpStream},
+        time::Duration,
+    },
+};
+
+/// Downloads the info dictionary of a magnet link from the peers supporting
+/// the `ut_metadata` extension (BEP 9), and checks it against the info hash.
+#[derive(Debug)]
+pub struct MetadataFetcher {
+    magnet_link: MagnetLink,
+    peer_id: [u8; 20],
+    timeout: Duration,
+}
+
+impl MetadataFetcher {
+    pub const TIMEOUT: Duration = Duration::from_secs(10);
+    /// Larger info dictionaries are refused
+    pub const MAX_METADATA_SIZE: u64 = 16 * 1024 * 1024;
+    /// The size of the torrent is not known yet, but trackers only send
+    /// seeders to peers with something left to download.
+    const UNKNOWN_LEFT: u64 = MetadataMessage::PIECE_SIZE as u64;
+    /// Messages sent by the peer before the metadata, such as a large
+    /// bitfield, are read and dropped up to this length
+    const MAX_MESSAGE_LENGTH: u32 = 4 * 1024 * 1024;
+
+    pub fn new(magnet_link: MagnetLink, peer_id: [u8; 20]) -> Self {
+        Self::with_timeout(magnet_link, peer_id, Self::TIMEOUT)
+    }
+
+    pub fn with_timeout(magnet_link: MagnetLink, peer_id: [u8; 20], timeout: Duration) -> Self {
+        Self {
+            magnet_link,
+            peer_id,
+            timeout,
+        }
+    }
+
+    /// Asks the trackers of the link for peers, then tries them in turn until
+    /// one of them sends the metadata.
+    pub fn fetch(&self) -> Result<Vec<u8>, Error> {
+        for peer in self.find_peers()? {
+            match self.fetch_from(peer) {
+                Ok(metadata) => return Ok(metadata),
+                Err(e) => log::warn!("Could not get the metadata from {:?}: {:?}", peer, e),
+            }
+        }
+
+        Err(Error::MetadataNotAvailable)
+    }
+
+    /// Peers are only found through trackers, DHT is not supported.
+    fn find_peers(&self) -> Result<Vec<Peer>, Error> {
+        if self.magnet_link.trackers().is_empty() {
+            return Err(Error::MagnetLinkWithoutTrackers);
+        }
+
+        let tracker_request = TrackerRequest::new(
+            self.magnet_link.info_hash(),
+            self.peer_id,
+            StateMachine::CLIENT_PORT,
+            0,
+            0,
+            Self::UNKNOWN_LEFT,
+            true,
+            Some(Event::Started),
+        );
+
+        let mut tracker_list = TrackerList::new(self.magnet_link.announce_tiers());
+        let mut udp_trackers = UdpTrackers::new();
+        let response = tracker_list.announce(|announce| {
+            let tracker_address = TrackerAddress::from_announce(announce)?;
+            TrackerRequest::send_request(
+                tracker_request.clone(),
+                tracker_address,
+                &mut udp_trackers,
+            )
+        })?;
+
+        response.peers().cloned().ok_or(Error::NoPeersAvailable)
+    }
+
+    /// Downloads the whole metadata from a single peer.
+    pub fn fetch_from(&self, peer: Peer) -> Result<Vec<u8>, Error> {
+        let address = peer
+            .socket_address()
+            .parse::<SocketAddr>()
+            .map_err(|_| Error::FailedToConnectToPeer)?;
+        let mut stream = TcpStream::connect_timeout(&address, self.timeout)
+            .map_err(|_| Error::FailedToConnectToPeer)?;
+        stream
+            .set_read_timeout(Some(self.timeout))
+            .map_err(|_| Error::FailedToSetSocketWriteTimeout)?;
+        stream
+            .set_write_timeout(Some(self.timeout))
+            .map_err(|_| Error::FailedToSetSocketWriteTimeout)?;
+
+        self.exchange_handshakes(&mut stream)?;
+        let mut extensions = ExtensionRegistry::new(&[MetadataMessage::EXTENSION_NAME]);
+        let metadata_size = self.exchange_extension_handshakes(&mut stream, &mut extensions)?;
+        let peer_ut_metadata = extensions
+            .remote_id(MetadataMessage::EXTENSION_NAME)
+            .ok_or(Error::PeerDoesNotSupportMetadataExchange)?;
+
+        let number_of_pieces = metadata_size.div_ceil(MetadataMessage::PIECE_SIZE as u64) as u32;
+        let mut metadata = Vec::with_capacity(metadata_size as usize);
+        for piece in 0..number_of_pieces {
+            let request = MetadataMessage::Request { piece };
+            Self::send(
+                &mut stream,
+                Extended::new(peer_ut_metadata, request.into_bytes()),
+            )?;
+
+            let data = self.receive_metadata_piece(&mut stream, &extensions, piece)?;
+            let expected_length =
+                (metadata_size - metadata.len() as u64).min(MetadataMessage::PIECE_SIZE as u64);
+            if data.len() as u64 != expected_length {
+                return Err(Error::InvalidMetadataMessage);
+            }
+            metadata.extend(data);
+        }
+
+        if Sha1::digest(&metadata).as_slice() != self.magnet_link.info_hash() {
+            return Err(Error::MetadataDoesNotMatchInfoHash);
+        }
+
+        Ok(metadata)
+    }
+
+    fn exchange_handshakes(&self, stream: &mut TcpStream) -> Result<(), Error> {
+        let info_hash = self.magnet_link.info_hash();
+        Self::send(
+            stream,
+            Handshake::with_extension_protocol(info_hash, self.peer_id),
+        )?;
+
+        let mut bytes = [0u8; Handshake::HANDSHAKE_VERSION_1_MESSAGE_LENGTH];
+        stream
+            .read_exact(&mut bytes)
+            .map_err(|_| Error::FailedToReadFromSocket)?;
+        let (handshake, _) = Handshake::from_bytes(&bytes)?;
+
+        if handshake.info_hash() != info_hash {
+            return Err(Error::PeerDoesNotHaveTheTorrent);
+        }
+        if !handshake.supports_extension_protocol() {
+            return Err(Error::PeerDoesNotSupportMetadataExchange);
+        }
+
+        Ok(())
+    }
+
+    /// Records the extensions of the peer and returns the size of the metadata.
+    fn exchange_extension_handshakes(
+        &self,
+        stream: &mut TcpStream,
+        extensions: &mut ExtensionRegistry,
+    ) -> Result<u64, Error> {
+        let handshake = ExtensionHandshake::new(
+            extensions.local_extensions().clone(),
+            Some(CLIENT_VERSION.to_string()),
+            None,
+            None,
+            None,
+        );
+        Self::send(
+            stream,
+            Extended::new(Extended::HANDSHAKE_ID, handshake.into_bytes()),
+        )?;
+
+        let handshake = loop {
+            let extended = Self::receive_extended(stream)?;
+            if extended.extended_message_id() == Extended::HANDSHAKE_ID {
+                break ExtensionHandshake::from_bytes(extended.payload())?.0;
+            }
+        };
+
+        extensions.update_remote(&handshake);
+        let metadata_size = handshake
+            .metadata_size()
+            .filter(|size| (1..=Self::MAX_METADATA_SIZE).contains(size))
+            .ok_or(Error::InvalidExtensionHandshake)?;
+
+        Ok(metadata_size)
+    }
+
+    fn receive_metadata_piece(
+        &self,
+        stream: &mut TcpStream,
+        extensions: &ExtensionRegistry,
+        piece: u32,
+    ) -> Result<Vec<u8>, Error> {
+        loop {
+            let extended = Self::receive_extended(stream)?;
+            if extensions.extension_name(extended.extended_message_id())
+                != Some(MetadataMessage::EXTENSION_NAME)
+            {
+                continue;
+            }
+
+            match MetadataMessage::from_bytes(extended.payload())?.0 {
+                MetadataMessage::Data {
+                    piece: data_piece,
+                    data,
+                    ..
+                } if data_piece == piece => return Ok(data),
+                MetadataMessage::Reject { .. } => return Err(Error::MetadataRequestRejected),
+                MetadataMessage::Request { piece } => {
+                    // we do not have the metadata to share yet
+                    let reject = MetadataMessage::Reject { piece };
+                    if let Some(peer_ut_metadata) =
+                        extensions.remote_id(MetadataMessage::EXTENSION_NAME)
+                    {
+                        Self::send(stream, Extended::new(peer_ut_metadata, reject.into_bytes()))?;
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// Reads messages until an extended one, dropping the others.
+    fn receive_extended(stream: &mut TcpStream) -> Result<Extended, Error> {
+        loop {
+            let mut length = [0u8; 4];
+            stream
+                .read_exact(&mut length)
+                .map_err(|_| Error::FailedToReadFromSocket)?;
+            let message_length = u32::from_be_bytes(length);
+            if message_length > Self::MAX_MESSAGE_LENGTH {
+                return Err(Error::MessageLengthDoesNotMatchWithExpectedOne);
+            }
+
+            let mut message = length.to_vec();
+            message.resize(4 + message_length as usize, 0);
+            stream
+                .read_exact(&mut message[4..])
+                .map_err(|_| Error::FailedToReadFromSocket)?;
+
+            // keep-alive messages have no type
+            if message_length > 0 && message[4] == MessageType::Extended.id() {
+                return Ok(Extended::from_bytes(&message)?.0);
+            }
+        }
+    }
+
+    fn send(stream: &mut TcpStream, message: impl IntoBytes) -> Result<(), Error> {
+        stream
+            .write_all(&message.into_bytes())
+            .map_err(|_| Error::FailedToSendMessage)
+    }
+}
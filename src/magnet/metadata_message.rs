@@ -0,0 +1,107 @@
+use crate::{
+    pwp::{FromBytes, IntoBytes},
+    Error,
+};
+use bendy::decoding::{Decoder, Object};
+
+/// Payload of a `ut_metadata` extension message (BEP 9). The metadata, that
+/// is the bencoded info dictionary, is exchanged in pieces of 16 KiB.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MetadataMessage {
+    /// `{msg_type: 0, piece}`
+    Request { piece: u32 },
+    /// `{msg_type: 1, piece, total_size}` followed by the piece data
+    Data {
+        piece: u32,
+        total_size: u64,
+        data: Vec<u8>,
+    },
+    /// `{msg_type: 2, piece}`
+    Reject { piece: u32 },
+}
+
+impl MetadataMessage {
+    pub const PIECE_SIZE: usize = 16 * 1024;
+    pub const EXTENSION_NAME: &'static str = "ut_metadata";
+
+    const REQUEST: u64 = 0;
+    const DATA: u64 = 1;
+    const REJECT: u64 = 2;
+
+    fn parse_integer(object: Object) -> Result<u64, Error> {
+        match object {
+            Object::Integer(integer) => integer.parse().map_err(|_| Error::InvalidMetadataMessage),
+            _ => Err(Error::InvalidMetadataMessage),
+        }
+    }
+}
+
+impl IntoBytes for MetadataMessage {
+    fn into_bytes(self) -> Vec<u8> {
+        match self {
+            MetadataMessage::Request { piece } => {
+                format!("d8:msg_typei{}e5:piecei{}ee", Self::REQUEST, piece).into_bytes()
+            }
+            MetadataMessage::Data {
+                piece,
+                total_size,
+                data,
+            } => {
+                let mut bytes = format!(
+                    "d8:msg_typei{}e5:piecei{}e10:total_sizei{}ee",
+                    Self::DATA,
+                    piece,
+                    total_size
+                )
+                .into_bytes();
+                bytes.extend(data);
+                bytes
+            }
+            MetadataMessage::Reject { piece } => {
+                format!("d8:msg_typei{}e5:piecei{}ee", Self::REJECT, piece).into_bytes()
+            }
+        }
+    }
+}
+
+impl FromBytes for MetadataMessage {
+    fn from_bytes(bytes: &[u8]) -> Result<(Self, usize), Error> {
+        let mut decoder = Decoder::new(bytes);
+        let mut dictionary = match decoder.next_object() {
+            Ok(Some(Object::Dict(dictionary))) => dictionary,
+            _ => return Err(Error::InvalidMetadataMessage),
+        };
+
+        let mut message_type = None;
+        let mut piece = None;
+        let mut total_size = None;
+        while let Ok(Some(pair)) = dictionary.next_pair() {
+            match pair {
+                (b"msg_type", value) => message_type = Some(Self::parse_integer(value)?),
+                (b"piece", value) => piece = Some(Self::parse_integer(value)?),
+                (b"total_size", value) => total_size = Some(Self::parse_integer(value)?),
+                _ => (),
+            }
+        }
+        let dictionary_length = dictionary
+            .into_raw()
+            .map_err(|_| Error::InvalidMetadataMessage)?
+            .len();
+
+        let piece = piece
+            .and_then(|piece| u32::try_from(piece).ok())
+            .ok_or(Error::InvalidMetadataMessage)?;
+        let message = match message_type {
+            Some(Self::REQUEST) => MetadataMessage::Request { piece },
+            Some(Self::DATA) => MetadataMessage::Data {
+                piece,
+                total_size: total_size.ok_or(Error::InvalidMetadataMessage)?,
+                data: bytes[dictionary_length..].to_vec(),
+            },
+            Some(Self::REJECT) => MetadataMessage::Reject { piece },
+            _ => return Err(Error::InvalidMetadataMessage),
+        };
+
+        Ok((message, bytes.len()))
+    }
+}
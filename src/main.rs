@@ -11,15 +11,17 @@ mod app;
 use app::App;
 
 mod cli;
+mod dht;
 mod http;
+mod magnet;
 mod pwp;
 pub use pwp::*;
 mod tcp;
+mod udp;
+mod utp;
 
 mod state_machine;
 
-mod adaptative_wait;
-
 mod pieces_selection;
 
 #[cfg(test)]
@@ -1,11 +1,17 @@
-pub mod distributed_selection;
+pub mod piece_availability;
 pub mod piece_selection;
 pub mod pieces_selection;
 pub mod rarest_piece_selection;
+#[cfg(test)]
 pub mod simple_selection;
+pub mod suggested_pieces;
 
-pub use distributed_selection::DistributedSelector;
+pub use piece_availability::PieceAvailability;
 pub use piece_selection::PieceSelection;
-pub use pieces_selection::{PiecesSelection, PriorityPiecesSelection};
+#[cfg(test)]
+pub use pieces_selection::PiecesSelection;
+pub use pieces_selection::PriorityPiecesSelection;
 pub use rarest_piece_selection::RarestPiecesSelector;
+#[cfg(test)]
 pub use simple_selection::SimpleSelector;
+pub use suggested_pieces::SuggestedPieces;
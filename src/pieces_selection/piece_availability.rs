@@ -0,0 +1,69 @@
+use bit_vec::BitVec;
+use rand::seq::SliceRandom;
+
+/// Number of connected peers having each piece, kept up to date as their
+/// bitfields and Have messages arrive and as they disconnect.
+#[derive(Debug)]
+pub struct PieceAvailability {
+    peers_having: Vec<u32>,
+}
+
+impl PieceAvailability {
+    pub fn new(number_of_pieces: usize) -> Self {
+        Self {
+            peers_having: vec![0; number_of_pieces],
+        }
+    }
+
+    /// The spare bits of the last byte of a bitfield are ignored.
+    pub fn add_bitfield(&mut self, bitfield: &BitVec) {
+        for (peers_having, has_piece) in self.peers_having.iter_mut().zip(bitfield.iter()) {
+            if has_piece {
+                *peers_having += 1;
+            }
+        }
+    }
+
+    pub fn remove_bitfield(&mut self, bitfield: &BitVec) {
+        for (peers_having, has_piece) in self.peers_having.iter_mut().zip(bitfield.iter()) {
+            if has_piece {
+                *peers_having = peers_having.saturating_sub(1);
+            }
+        }
+    }
+
+    /// A peer got a piece it did not have.
+    pub fn add_piece(&mut self, piece_index: u32) {
+        if let Some(peers_having) = self.peers_having.get_mut(piece_index as usize) {
+            *peers_having += 1;
+        }
+    }
+
+    pub fn peers_having(&self, piece_index: u32) -> u32 {
+        self.peers_having
+            .get(piece_index as usize)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// The pieces of a peer accepted by `is_wanted`, the rarest first. The
+    /// pieces as rare as each other are shuffled, so that the peers do not
+    /// all start with the same ones.
+    pub fn rarest_first(
+        &self,
+        peer_bitfield: &BitVec,
+        is_wanted: impl Fn(u32) -> bool,
+    ) -> Vec<u32> {
+        let mut pieces: Vec<u32> = peer_bitfield
+            .iter()
+            .take(self.peers_having.len())
+            .enumerate()
+            .filter(|(piece_index, has_piece)| *has_piece && is_wanted(*piece_index as u32))
+            .map(|(piece_index, _)| piece_index as u32)
+            .collect();
+
+        pieces.shuffle(&mut rand::thread_rng());
+        pieces.sort_by_key(|piece_index| self.peers_having(*piece_index));
+        pieces
+    }
+}
@@ -4,6 +4,7 @@ use std::collections::{HashMap, VecDeque};
 use super::PieceSelection;
 use crate::http::Peer;
 
+#[cfg(test)]
 pub trait PiecesSelection {
     /// Returns a associative table matching a piece_id with a peer for requesting the piece associated with the piece_id
     fn pieces_selection(
@@ -0,0 +1,72 @@
+use std::collections::{HashMap, VecDeque};
+
+use bit_vec::BitVec;
+
+use crate::{http::Peer, pieces_selection::PieceSelection};
+
+/// Pieces the peers suggested we download from them (BEP 6), usually
+/// because they have them in cache.
+#[derive(Debug, Default)]
+pub struct SuggestedPieces {
+    suggestions: HashMap<Peer, VecDeque<u32>>,
+}
+
+impl SuggestedPieces {
+    /// Only the latest suggestions of a peer are kept
+    pub const MAX_SUGGESTIONS_PER_PEER: usize = 16;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn suggest(&mut self, peer: Peer, piece_id: u32) {
+        let suggestions = self.suggestions.entry(peer).or_default();
+        if suggestions.contains(&piece_id) {
+            return;
+        }
+
+        suggestions.push_back(piece_id);
+        if suggestions.len() > Self::MAX_SUGGESTIONS_PER_PEER {
+            suggestions.pop_front();
+        }
+    }
+
+    pub fn remove_peer(&mut self, peer: &Peer) {
+        self.suggestions.remove(peer);
+    }
+
+    /// Moves the suggested pieces we miss in front of `selection`, assigned
+    /// to the peer that suggested them if it has them.
+    pub fn prioritize(
+        &self,
+        selection: Vec<PieceSelection>,
+        mybitfield: &BitVec,
+        peers_bitfields: &HashMap<Peer, BitVec>,
+    ) -> Vec<PieceSelection> {
+        let mut prioritized: Vec<PieceSelection> = Vec::new();
+
+        for (peer, suggestions) in &self.suggestions {
+            let peer_bitfield = match peers_bitfields.get(peer) {
+                Some(peer_bitfield) => peer_bitfield,
+                None => continue,
+            };
+
+            for &piece_id in suggestions {
+                let is_missing = mybitfield.get(piece_id as usize) == Some(false);
+                let peer_has_it = peer_bitfield.get(piece_id as usize) == Some(true);
+                let suggestion = PieceSelection::new(piece_id, *peer);
+                if is_missing && peer_has_it && !prioritized.contains(&suggestion) {
+                    prioritized.push(suggestion);
+                }
+            }
+        }
+
+        let others: Vec<PieceSelection> = selection
+            .into_iter()
+            .filter(|piece_selection| !prioritized.contains(piece_selection))
+            .collect();
+        prioritized.extend(others);
+
+        prioritized
+    }
+}
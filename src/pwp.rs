@@ -1,35 +1,55 @@
+mod allowed_fast;
 mod bitfield;
 mod cancel;
 mod choke;
+mod extended;
+mod extension_handshake;
+mod extension_registry;
 pub(crate) mod from_bytes;
 mod handshake;
 mod have;
+mod have_all;
+mod have_none;
 mod interested;
 mod into_bytes;
 mod keep_alive;
 mod mandatory_bittorrent_message_fields;
 mod message;
+mod message_decoder;
 mod message_type;
 mod not_interested;
+mod pex_message;
 mod piece;
 mod port;
+mod reject_request;
 mod request;
+mod suggest_piece;
 mod unchoke;
 
+pub use allowed_fast::AllowedFast;
 pub use bitfield::Bitfield;
 pub use cancel::Cancel;
 pub use choke::Choke;
+pub use extended::Extended;
+pub use extension_handshake::ExtensionHandshake;
+pub use extension_registry::ExtensionRegistry;
 pub use from_bytes::{identity_first_message_type_of, FromBytes};
 pub use handshake::Handshake;
 pub use have::Have;
+pub use have_all::HaveAll;
+pub use have_none::HaveNone;
 pub use interested::Interested;
 pub use into_bytes::IntoBytes;
 pub use keep_alive::KeepAlive;
 pub use mandatory_bittorrent_message_fields::MandatoryBitTorrentMessageFields;
 pub use message::Message;
+pub use message_decoder::MessageDecoder;
 pub use message_type::MessageType;
 pub use not_interested::NotInterested;
+pub use pex_message::PexMessage;
 pub use piece::Piece;
 pub use port::Port;
+pub use reject_request::RejectRequest;
 pub use request::Request;
+pub use suggest_piece::SuggestPiece;
 pub use unchoke::Unchoke;
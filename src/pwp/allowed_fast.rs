@@ -0,0 +1,127 @@
+use crate::pwp::{from_bytes, FromBytes, IntoBytes, MandatoryBitTorrentMessageFields, MessageType};
+use crate::Error;
+use sha1::{Digest, Sha1};
+use std::net::Ipv4Addr;
+
+/// allowed fast: <len=0005><id=17><index>
+#[derive(Debug)]
+pub struct AllowedFast {
+    message_length: u32,
+    message_type: u8,
+    piece_index: u32,
+}
+
+impl AllowedFast {
+    /// Number of pieces a choked peer is allowed to request
+    pub const ALLOWED_FAST_SET_SIZE: u32 = 10;
+
+    /// Canonical allowed fast set of a peer (BEP 6): the pieces are derived
+    /// from its /24 network and the info hash, so that a peer cannot get
+    /// more of them by reconnecting from another address of its network.
+    pub fn allowed_fast_set(
+        ip: Ipv4Addr,
+        info_hash: [u8; 20],
+        pieces_count: u32,
+        set_size: u32,
+    ) -> Vec<u32> {
+        let set_size = set_size.min(pieces_count) as usize;
+        let mut allowed_fast_set = Vec::with_capacity(set_size);
+
+        let mut hash = (u32::from(ip) & 0xFFFFFF00).to_be_bytes().to_vec();
+        hash.extend_from_slice(&info_hash);
+
+        while allowed_fast_set.len() < set_size {
+            hash = Sha1::digest(&hash).to_vec();
+            for word in hash.chunks_exact(4) {
+                if allowed_fast_set.len() == set_size {
+                    break;
+                }
+                let piece_index = u32::from_be_bytes(word.try_into().unwrap()) % pieces_count;
+                if !allowed_fast_set.contains(&piece_index) {
+                    allowed_fast_set.push(piece_index);
+                }
+            }
+        }
+
+        allowed_fast_set
+    }
+
+    pub fn new(piece_index: u32) -> Self {
+        Self {
+            message_length: MessageType::AllowedFast.base_length(),
+            message_type: MessageType::AllowedFast.id(),
+            piece_index,
+        }
+    }
+    pub fn message_length(&self) -> u32 {
+        self.message_length
+    }
+
+    pub fn message_type(&self) -> u8 {
+        self.message_type
+    }
+
+    pub fn piece_index(&self) -> u32 {
+        self.piece_index
+    }
+}
+
+impl MandatoryBitTorrentMessageFields for AllowedFast {
+    fn message_length(&self) -> u32 {
+        self.message_length
+    }
+
+    fn message_type(&self) -> u8 {
+        self.message_type
+    }
+}
+
+impl IntoBytes for AllowedFast {
+    fn into_bytes(self) -> Vec<u8> {
+        let mut serialized_message: Vec<u8> = Vec::new();
+        serialized_message.extend(self.message_length.to_be_bytes());
+        serialized_message.push(self.message_type);
+        serialized_message.extend(self.piece_index.to_be_bytes());
+        serialized_message
+    }
+}
+
+impl FromBytes for AllowedFast {
+    fn from_bytes(bytes: &[u8]) -> Result<(Self, usize), Error> {
+        if (bytes.len() as u32)
+            < (MessageType::AllowedFast.base_length()
+                + from_bytes::PWP_MESSAGE_LENGTH_FIELD_SIZE_IN_BYTES)
+        {
+            return Err(Error::BytesArrayTooShort);
+        }
+
+        let message_length = u32::from_be_bytes(
+            bytes[0..4]
+                .try_into()
+                .map_err(|_| Error::FailedToParseBitTorrentMessageLength)?,
+        );
+        if message_length != MessageType::AllowedFast.base_length() {
+            return Err(Error::MessageLengthDoesNotMatchWithExpectedOne);
+        }
+
+        let message_type = bytes[4];
+        if message_type != MessageType::AllowedFast.id() {
+            return Err(Error::MessageTypeDoesNotMatchWithExpectedOne);
+        }
+
+        let piece_index = u32::from_be_bytes(
+            bytes[5..9]
+                .try_into()
+                .map_err(|_| Error::FailedToParseBitTorrentAllowedFastMessagePieceIndex)?,
+        );
+
+        Ok((
+            Self {
+                message_length,
+                message_type,
+                piece_index,
+            },
+            (message_length + from_bytes::PWP_MESSAGE_LENGTH_FIELD_SIZE_IN_BYTES) as usize,
+        ))
+    }
+}
@@ -0,0 +1,107 @@
+use crate::pwp::{from_bytes, FromBytes, IntoBytes, MandatoryBitTorrentMessageFields, MessageType};
+use crate::Error;
+
+/// Message of the extension protocol (BEP 10). The extended message id 0 is
+/// the extension handshake, the other ids are the ones the receiving peer
+/// assigned to its extensions in its own extension handshake.
+#[derive(Debug)]
+pub struct Extended {
+    message_length: u32,
+    message_type: u8,
+    extended_message_id: u8,
+    payload: Vec<u8>,
+}
+
+impl Extended {
+    pub const HANDSHAKE_ID: u8 = 0;
+
+    pub fn new(extended_message_id: u8, payload: Vec<u8>) -> Self {
+        Self {
+            message_length: MessageType::Extended.base_length() + payload.len() as u32,
+            message_type: MessageType::Extended.id(),
+            extended_message_id,
+            payload,
+        }
+    }
+
+    pub fn message_length(&self) -> u32 {
+        self.message_length
+    }
+
+    pub fn message_type(&self) -> u8 {
+        self.message_type
+    }
+
+    pub fn extended_message_id(&self) -> u8 {
+        self.extended_message_id
+    }
+
+    /// Bencoded dictionary, possibly followed by raw data
+    pub fn payload(&self) -> &Vec<u8> {
+        &self.payload
+    }
+}
+
+impl MandatoryBitTorrentMessageFields for Extended {
+    fn message_length(&self) -> u32 {
+        self.message_length
+    }
+
+    fn message_type(&self) -> u8 {
+        self.message_type
+    }
+}
+
+impl IntoBytes for Extended {
+    fn into_bytes(self) -> Vec<u8> {
+        let mut serialized_message: Vec<u8> = Vec::new();
+        serialized_message.extend(self.message_length.to_be_bytes());
+        serialized_message.push(self.message_type);
+        serialized_message.push(self.extended_message_id);
+        serialized_message.extend(self.payload);
+        serialized_message
+    }
+}
+
+impl FromBytes for Extended {
+    fn from_bytes(bytes: &[u8]) -> Result<(Self, usize), Error> {
+        if (bytes.len() as u32)
+            < (MessageType::Extended.base_length()
+                + from_bytes::PWP_MESSAGE_LENGTH_FIELD_SIZE_IN_BYTES)
+        {
+            return Err(Error::BytesArrayTooShort);
+        }
+
+        let message_length = u32::from_be_bytes(
+            bytes[0..4]
+                .try_into()
+                .map_err(|_| Error::FailedToParseBitTorrentMessageLength)?,
+        );
+        if message_length < MessageType::Extended.base_length() {
+            return Err(Error::MessageLengthDoesNotMatchWithExpectedOne);
+        }
+        let message_end =
+            (message_length + from_bytes::PWP_MESSAGE_LENGTH_FIELD_SIZE_IN_BYTES) as usize;
+        if bytes.len() < message_end {
+            return Err(Error::BytesArrayTooShortToContrainMessageFields);
+        }
+
+        let message_type = bytes[4];
+        if message_type != MessageType::Extended.id() {
+            return Err(Error::MessageTypeDoesNotMatchWithExpectedOne);
+        }
+
+        let extended_message_id = bytes[5];
+        let payload = bytes[6..message_end].to_vec();
+
+        Ok((
+            Self {
+                message_length,
+                message_type,
+                extended_message_id,
+                payload,
+            },
+            message_end,
+        ))
+    }
+}
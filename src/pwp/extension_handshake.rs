@@ -0,0 +1,177 @@
+use crate::{
+    pwp::{FromBytes, IntoBytes},
+    Error,
+};
+use {
+    bendy::decoding::{Decoder, Object},
+    std::{
+        collections::BTreeMap,
+        net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    },
+};
+
+/// Payload of the extension handshake (BEP 10), the extended message 0. Only
+/// the fields used by the client are kept.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExtensionHandshake {
+    /// `m`, extended message id assigned by the sender to each extension it
+    /// supports, 0 meaning the extension is disabled
+    extensions: BTreeMap<String, u8>,
+    /// `v`, client name and version of the sender
+    client_version: Option<String>,
+    /// `reqq`, number of outstanding requests the sender accepts
+    reqq: Option<u32>,
+    /// `yourip`, address of the receiver as seen by the sender
+    yourip: Option<IpAddr>,
+    /// `metadata_size`, size of the info dictionary in bytes (BEP 9)
+    metadata_size: Option<u64>,
+    /// `p`, port the sender listens on, which the connections it initiated
+    /// do not come from
+    listen_port: Option<u16>,
+}
+
+impl ExtensionHandshake {
+    pub fn new(
+        extensions: BTreeMap<String, u8>,
+        client_version: Option<String>,
+        reqq: Option<u32>,
+        yourip: Option<IpAddr>,
+        metadata_size: Option<u64>,
+    ) -> Self {
+        Self {
+            extensions,
+            client_version,
+            reqq,
+            yourip,
+            metadata_size,
+            listen_port: None,
+        }
+    }
+
+    pub fn with_listen_port(mut self, listen_port: u16) -> Self {
+        self.listen_port = Some(listen_port);
+        self
+    }
+
+    pub fn extensions(&self) -> &BTreeMap<String, u8> {
+        &self.extensions
+    }
+
+    pub fn client_version(&self) -> Option<&String> {
+        self.client_version.as_ref()
+    }
+
+    pub fn reqq(&self) -> Option<u32> {
+        self.reqq
+    }
+
+    pub fn yourip(&self) -> Option<IpAddr> {
+        self.yourip
+    }
+
+    pub fn metadata_size(&self) -> Option<u64> {
+        self.metadata_size
+    }
+
+    pub fn listen_port(&self) -> Option<u16> {
+        self.listen_port
+    }
+
+    fn parse_integer<T: std::str::FromStr>(object: Object) -> Option<T> {
+        match object {
+            Object::Integer(integer) => integer.parse().ok(),
+            _ => None,
+        }
+    }
+
+    /// `yourip` is the raw 4 or 16 bytes of the address.
+    fn parse_ip(bytes: &[u8]) -> Option<IpAddr> {
+        match bytes.len() {
+            4 => Some(IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(bytes).ok()?))),
+            16 => Some(IpAddr::V6(Ipv6Addr::from(
+                <[u8; 16]>::try_from(bytes).ok()?,
+            ))),
+            _ => None,
+        }
+    }
+}
+
+impl IntoBytes for ExtensionHandshake {
+    /// Keys are written in the sorted order bencode requires.
+    fn into_bytes(self) -> Vec<u8> {
+        let bencode_string = |bytes: &[u8]| {
+            let mut string = format!("{}:", bytes.len()).into_bytes();
+            string.extend(bytes);
+            string
+        };
+
+        let mut bytes = b"d1:md".to_vec();
+        for (name, id) in self.extensions.iter() {
+            bytes.extend(bencode_string(name.as_bytes()));
+            bytes.extend(format!("i{}e", id).into_bytes());
+        }
+        bytes.push(b'e');
+        if let Some(metadata_size) = self.metadata_size {
+            bytes.extend(format!("13:metadata_sizei{}e", metadata_size).into_bytes());
+        }
+        if let Some(listen_port) = self.listen_port {
+            bytes.extend(format!("1:pi{}e", listen_port).into_bytes());
+        }
+        if let Some(reqq) = self.reqq {
+            bytes.extend(format!("4:reqqi{}e", reqq).into_bytes());
+        }
+        if let Some(client_version) = &self.client_version {
+            bytes.extend(b"1:v");
+            bytes.extend(bencode_string(client_version.as_bytes()));
+        }
+        if let Some(yourip) = self.yourip {
+            bytes.extend(b"6:yourip");
+            match yourip {
+                IpAddr::V4(ip) => bytes.extend(bencode_string(&ip.octets())),
+                IpAddr::V6(ip) => bytes.extend(bencode_string(&ip.octets())),
+            }
+        }
+        bytes.push(b'e');
+
+        bytes
+    }
+}
+
+impl FromBytes for ExtensionHandshake {
+    /// Unknown keys and fields of unexpected types are ignored, as the
+    /// extension protocol asks.
+    fn from_bytes(bytes: &[u8]) -> Result<(Self, usize), Error> {
+        let mut decoder = Decoder::new(bytes);
+        let mut dictionary = match decoder.next_object() {
+            Ok(Some(Object::Dict(dictionary))) => dictionary,
+            _ => return Err(Error::InvalidExtensionHandshake),
+        };
+
+        let mut handshake = Self::new(BTreeMap::new(), None, None, None, None);
+        while let Ok(Some(pair)) = dictionary.next_pair() {
+            match pair {
+                (b"m", Object::Dict(mut extensions)) => {
+                    while let Ok(Some((name, id))) = extensions.next_pair() {
+                        if let (Ok(name), Some(id)) =
+                            (String::from_utf8(name.to_vec()), Self::parse_integer(id))
+                        {
+                            handshake.extensions.insert(name, id);
+                        }
+                    }
+                }
+                (b"metadata_size", value) => handshake.metadata_size = Self::parse_integer(value),
+                (b"p", value) => {
+                    handshake.listen_port = Self::parse_integer(value).filter(|port| *port != 0)
+                }
+                (b"reqq", value) => handshake.reqq = Self::parse_integer(value),
+                (b"v", Object::Bytes(version)) => {
+                    handshake.client_version = Some(String::from_utf8_lossy(version).into_owned())
+                }
+                (b"yourip", Object::Bytes(ip)) => handshake.yourip = Self::parse_ip(ip),
+                _ => (),
+            }
+        }
+
+        Ok((handshake, bytes.len()))
+    }
+}
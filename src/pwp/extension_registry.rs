@@ -0,0 +1,59 @@
+use {crate::pwp::ExtensionHandshake, std::collections::BTreeMap};
+
+/// Extended message ids of the extensions (BEP 10). Each side assigns its own
+/// ids: messages are sent with the id the remote peer assigned, and received
+/// with the id we assigned.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExtensionRegistry {
+    local: BTreeMap<String, u8>,
+    remote: BTreeMap<String, u8>,
+}
+
+impl ExtensionRegistry {
+    /// Assigns the ids 1, 2, ... to the extensions supported by the client.
+    pub fn new(local_extensions: &[&str]) -> Self {
+        let local = local_extensions
+            .iter()
+            .zip(1..)
+            .map(|(name, id)| (name.to_string(), id))
+            .collect();
+
+        Self {
+            local,
+            remote: BTreeMap::new(),
+        }
+    }
+
+    /// The `m` dictionary of our extension handshake
+    pub fn local_extensions(&self) -> &BTreeMap<String, u8> {
+        &self.local
+    }
+
+    pub fn local_id(&self, name: &str) -> Option<u8> {
+        self.local.get(name).copied()
+    }
+
+    /// Id to send the extension messages with, if the peer supports it
+    pub fn remote_id(&self, name: &str) -> Option<u8> {
+        self.remote.get(name).copied()
+    }
+
+    /// Name of the extension of a received message
+    pub fn extension_name(&self, local_id: u8) -> Option<&str> {
+        self.local
+            .iter()
+            .find(|(_, id)| **id == local_id)
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// Applies an extension handshake of the peer. Handshakes may be sent
+    /// again to update the ids, an id of 0 disabling the extension.
+    pub fn update_remote(&mut self, handshake: &ExtensionHandshake) {
+        for (name, id) in handshake.extensions() {
+            match id {
+                0 => self.remote.remove(name),
+                id => self.remote.insert(name.clone(), *id),
+            };
+        }
+    }
+}
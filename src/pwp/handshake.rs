@@ -27,6 +27,15 @@ impl Handshake {
         Handshake::BITTORRENT_VERSION_1_PROTOCOL_NAME.len() as u8;
     pub const HANDSHAKE_VERSION_1_MESSAGE_LENGTH: usize = Handshake::HANDSHAKE_MIN_MESSAGE_SIZE
         + Handshake::BITTORRENT_VERSION_1_PROTOCOL_NAME_LENGTH as usize;
+    /// The extension protocol is advertised by the 20th bit from the right
+    const EXTENSION_PROTOCOL_BYTE: usize = 5;
+    const EXTENSION_PROTOCOL_BIT: u8 = 0x10;
+    /// DHT support is advertised by the last bit (BEP 5)
+    const DHT_BYTE: usize = 7;
+    const DHT_BIT: u8 = 0x01;
+    /// The fast extension is advertised by the third bit of the last byte (BEP 6)
+    const FAST_EXTENSION_BYTE: usize = 7;
+    const FAST_EXTENSION_BIT: u8 = 0x04;
 
     pub fn new(info_hash: [u8; 20], peer_id: [u8; 20]) -> Self {
         let pstr = Handshake::BITTORRENT_VERSION_1_PROTOCOL_NAME.to_string();
@@ -42,6 +51,39 @@ impl Handshake {
         }
     }
 
+    /// Handshake advertising the extension protocol (BEP 10)
+    pub fn with_extension_protocol(info_hash: [u8; 20], peer_id: [u8; 20]) -> Self {
+        let mut handshake = Handshake::new(info_hash, peer_id);
+        handshake.reserved[Self::EXTENSION_PROTOCOL_BYTE] |= Self::EXTENSION_PROTOCOL_BIT;
+
+        handshake
+    }
+
+    pub fn supports_extension_protocol(&self) -> bool {
+        self.reserved[Self::EXTENSION_PROTOCOL_BYTE] & Self::EXTENSION_PROTOCOL_BIT != 0
+    }
+
+    /// Advertises a DHT node, whose port is then sent in a `Port` message
+    pub fn advertise_dht(mut self) -> Self {
+        self.reserved[Self::DHT_BYTE] |= Self::DHT_BIT;
+
+        self
+    }
+
+    pub fn supports_dht(&self) -> bool {
+        self.reserved[Self::DHT_BYTE] & Self::DHT_BIT != 0
+    }
+
+    pub fn advertise_fast_extension(mut self) -> Self {
+        self.reserved[Self::FAST_EXTENSION_BYTE] |= Self::FAST_EXTENSION_BIT;
+
+        self
+    }
+
+    pub fn supports_fast_extension(&self) -> bool {
+        self.reserved[Self::FAST_EXTENSION_BYTE] & Self::FAST_EXTENSION_BIT != 0
+    }
+
     pub fn pstrlen(&self) -> u8 {
         self.pstrlen
     }
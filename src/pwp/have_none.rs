@@ -0,0 +1,78 @@
+use crate::pwp::{from_bytes, FromBytes, IntoBytes, MandatoryBitTorrentMessageFields, MessageType};
+use crate::Error;
+
+/// have none: <len=0001><id=15>
+#[derive(Debug)]
+pub struct HaveNone {
+    message_length: u32,
+    message_type: u8,
+}
+
+impl HaveNone {
+    pub fn new() -> Self {
+        Self {
+            message_length: MessageType::HaveNone.base_length(),
+            message_type: MessageType::HaveNone.id(),
+        }
+    }
+}
+
+impl Default for HaveNone {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MandatoryBitTorrentMessageFields for HaveNone {
+    fn message_length(&self) -> u32 {
+        self.message_length
+    }
+
+    fn message_type(&self) -> u8 {
+        self.message_type
+    }
+}
+
+impl IntoBytes for HaveNone {
+    fn into_bytes(self) -> Vec<u8> {
+        let mut serialized_message = Vec::new();
+
+        serialized_message.extend(self.message_length.to_be_bytes());
+        serialized_message.push(self.message_type);
+
+        serialized_message
+    }
+}
+
+impl FromBytes for HaveNone {
+    fn from_bytes(bytes: &[u8]) -> Result<(Self, usize), Error> {
+        if (bytes.len() as u32)
+            < MessageType::HaveNone.base_length()
+                + from_bytes::PWP_MESSAGE_LENGTH_FIELD_SIZE_IN_BYTES
+        {
+            return Err(Error::BytesArrayTooShort);
+        }
+
+        let message_length = u32::from_be_bytes(
+            bytes[0..4]
+                .try_into()
+                .map_err(|_| Error::FailedToParseBitTorrentMessageLength)?,
+        );
+        if message_length != MessageType::HaveNone.base_length() {
+            return Err(Error::MessageLengthDoesNotMatchWithExpectedOne);
+        }
+
+        let message_type = bytes[4];
+        if message_type != MessageType::HaveNone.id() {
+            return Err(Error::MessageTypeDoesNotMatchWithExpectedOne);
+        }
+
+        Ok((
+            Self {
+                message_length,
+                message_type,
+            },
+            (message_length + from_bytes::PWP_MESSAGE_LENGTH_FIELD_SIZE_IN_BYTES) as usize,
+        ))
+    }
+}
@@ -14,6 +14,12 @@ pub enum Message {
     Choke(pwp::Choke),
     Cancel(pwp::Cancel),
     Port(pwp::Port),
+    SuggestPiece(pwp::SuggestPiece),
+    HaveAll(pwp::HaveAll),
+    HaveNone(pwp::HaveNone),
+    RejectRequest(pwp::RejectRequest),
+    AllowedFast(pwp::AllowedFast),
+    Extended(pwp::Extended),
 }
 
 impl IntoBytes for Message {
@@ -31,6 +37,12 @@ impl IntoBytes for Message {
             Message::Choke(m) => m.into_bytes(),
             Message::Cancel(m) => m.into_bytes(),
             Message::Port(m) => m.into_bytes(),
+            Message::SuggestPiece(m) => m.into_bytes(),
+            Message::HaveAll(m) => m.into_bytes(),
+            Message::HaveNone(m) => m.into_bytes(),
+            Message::RejectRequest(m) => m.into_bytes(),
+            Message::AllowedFast(m) => m.into_bytes(),
+            Message::Extended(m) => m.into_bytes(),
         }
     }
 }
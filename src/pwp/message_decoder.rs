@@ -0,0 +1,107 @@
+use crate::{
+    pwp::{
+        AllowedFast, Bitfield, Cancel, Choke, Extended, FromBytes, Handshake, Have, HaveAll,
+        HaveNone, Interested, KeepAlive, Message, MessageType, NotInterested, Piece, Port,
+        RejectRequest, Request, SuggestPiece, Unchoke,
+    },
+    Error,
+};
+
+/// Streaming decoder of the messages of a connection: the received bytes
+/// are fed in chunks of any size, and the messages are taken out once they
+/// are complete.
+#[derive(Debug, Default)]
+pub struct MessageDecoder {
+    /// received bytes that do not form a complete message yet
+    buffer: Vec<u8>,
+}
+
+impl MessageDecoder {
+    /// Longest message accepted, which fits the bitfield of 8 million pieces
+    pub const MAX_MESSAGE_LENGTH: u32 = 1024 * 1024;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Number of bytes received and not decoded yet
+    pub fn buffered_length(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Returns the next complete message, or None until enough bytes were
+    /// fed. Messages of unknown ids are skipped.
+    pub fn decode(&mut self) -> Result<Option<Message>, Error> {
+        loop {
+            // A length with 19 as first byte would be too long: it is the
+            // protocol name length of a handshake
+            if self.buffer.first() == Some(&Handshake::BITTORRENT_VERSION_1_PROTOCOL_NAME_LENGTH) {
+                return self.decode_handshake();
+            }
+
+            let length_field_size = MessageType::PWP_MESSAGE_LENGTH_FIELD_SIZE as usize;
+            if self.buffer.len() < length_field_size {
+                return Ok(None);
+            }
+            let message_length = u32::from_be_bytes(self.buffer[..4].try_into().unwrap());
+            if message_length > Self::MAX_MESSAGE_LENGTH {
+                return Err(Error::MessageTooLong);
+            }
+            let frame_length = length_field_size + message_length as usize;
+            if self.buffer.len() < frame_length {
+                return Ok(None);
+            }
+
+            let frame: Vec<u8> = self.buffer.drain(..frame_length).collect();
+            if message_length == 0 {
+                return Ok(Some(Message::KeepAlive(KeepAlive::new())));
+            }
+            match MessageType::from_id(frame[4]) {
+                Some(message_type) => return Self::decode_frame(message_type, &frame).map(Some),
+                None => log::debug!("Skipping a message of unknown id {}", frame[4]),
+            }
+        }
+    }
+
+    fn decode_handshake(&mut self) -> Result<Option<Message>, Error> {
+        let length = Handshake::HANDSHAKE_VERSION_1_MESSAGE_LENGTH;
+        if self.buffer.len() < length {
+            return Ok(None);
+        }
+        let (handshake, _) = Handshake::from_bytes(&self.buffer[..length])?;
+        self.buffer.drain(..length);
+
+        Ok(Some(Message::Handshake(handshake)))
+    }
+
+    /// `frame` is a whole message, length field included.
+    fn decode_frame(message_type: MessageType, frame: &[u8]) -> Result<Message, Error> {
+        Ok(match message_type {
+            MessageType::Choke => Message::Choke(Choke::from_bytes(frame)?.0),
+            MessageType::Unchoke => Message::Unchoke(Unchoke::from_bytes(frame)?.0),
+            MessageType::Interested => Message::Interested(Interested::from_bytes(frame)?.0),
+            MessageType::NotInterested => {
+                Message::NotInterested(NotInterested::from_bytes(frame)?.0)
+            }
+            MessageType::Have => Message::Have(Have::from_bytes(frame)?.0),
+            MessageType::Bitfield => Message::Bitfield(Bitfield::from_bytes(frame)?.0),
+            MessageType::Request => Message::Request(Request::from_bytes(frame)?.0),
+            MessageType::Piece => Message::Piece(Piece::from_bytes(frame)?.0),
+            MessageType::Cancel => Message::Cancel(Cancel::from_bytes(frame)?.0),
+            MessageType::Port => Message::Port(Port::from_bytes(frame)?.0),
+            MessageType::SuggestPiece => Message::SuggestPiece(SuggestPiece::from_bytes(frame)?.0),
+            MessageType::HaveAll => Message::HaveAll(HaveAll::from_bytes(frame)?.0),
+            MessageType::HaveNone => Message::HaveNone(HaveNone::from_bytes(frame)?.0),
+            MessageType::RejectRequest => {
+                Message::RejectRequest(RejectRequest::from_bytes(frame)?.0)
+            }
+            MessageType::AllowedFast => Message::AllowedFast(AllowedFast::from_bytes(frame)?.0),
+            MessageType::Extended => Message::Extended(Extended::from_bytes(frame)?.0),
+            MessageType::KeepAlive => Message::KeepAlive(KeepAlive::new()),
+        })
+    }
+}
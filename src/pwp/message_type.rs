@@ -1,3 +1,4 @@
+use strum::IntoEnumIterator;
 use strum_macros::EnumIter;
 
 #[derive(Debug, PartialEq, EnumIter, Copy, Clone)]
@@ -13,12 +14,24 @@ pub enum MessageType {
     KeepAlive,
     Cancel,
     Port,
+    SuggestPiece,
+    HaveAll,
+    HaveNone,
+    RejectRequest,
+    AllowedFast,
+    Extended,
 }
 
 // Documentation for message: https://wiki.theory.org/BitTorrentSpecification#Messages
 impl MessageType {
     pub const PWP_MESSAGE_LENGTH_FIELD_SIZE: u32 = 4;
 
+    /// Type of the messages of id `id`, keep-alives have no id.
+    pub fn from_id(id: u8) -> Option<Self> {
+        MessageType::iter()
+            .find(|message_type| *message_type != MessageType::KeepAlive && message_type.id() == id)
+    }
+
     pub fn id(self) -> u8 {
         match self {
             MessageType::Choke => 0,
@@ -31,6 +44,12 @@ impl MessageType {
             MessageType::Piece => 7,
             MessageType::Cancel => 8,
             MessageType::Port => 9,
+            MessageType::SuggestPiece => 13,
+            MessageType::HaveAll => 14,
+            MessageType::HaveNone => 15,
+            MessageType::RejectRequest => 16,
+            MessageType::AllowedFast => 17,
+            MessageType::Extended => 20,
             MessageType::KeepAlive => 255, // meaningless value that must not be used
         }
     }
@@ -38,17 +57,23 @@ impl MessageType {
     /// Length of the message without variable size field and length field (4 bytes) taken in account
     pub fn base_length(self) -> u32 {
         match self {
-            MessageType::KeepAlive => 0,       // "nothing"
-            MessageType::Choke => 1,           // id
-            MessageType::Unchoke => 1,         // id
-            MessageType::Interested => 1,      // id
-            MessageType::NotInterested => 1,   // id
-            MessageType::Have => 1 + 4,        // id + piece index
-            MessageType::Bitfield => 1,        // id
-            MessageType::Request => 1 + 3 * 4, // id + index + begin + length
-            MessageType::Piece => 1 + 2 * 4,   // id + index + begin
-            MessageType::Cancel => 1 + 3 * 4,  // id + index + begin + length
-            MessageType::Port => 1 + 2,        // id + listen-port
+            MessageType::KeepAlive => 0,             // "nothing"
+            MessageType::Choke => 1,                 // id
+            MessageType::Unchoke => 1,               // id
+            MessageType::Interested => 1,            // id
+            MessageType::NotInterested => 1,         // id
+            MessageType::Have => 1 + 4,              // id + piece index
+            MessageType::Bitfield => 1,              // id
+            MessageType::Request => 1 + 3 * 4,       // id + index + begin + length
+            MessageType::Piece => 1 + 2 * 4,         // id + index + begin
+            MessageType::Cancel => 1 + 3 * 4,        // id + index + begin + length
+            MessageType::Port => 1 + 2,              // id + listen-port
+            MessageType::SuggestPiece => 1 + 4,      // id + piece index
+            MessageType::HaveAll => 1,               // id
+            MessageType::HaveNone => 1,              // id
+            MessageType::RejectRequest => 1 + 3 * 4, // id + index + begin + length
+            MessageType::AllowedFast => 1 + 4,       // id + piece index
+            MessageType::Extended => 1 + 1,          // id + extended message id
         }
     }
 }
@@ -0,0 +1,148 @@
+use crate::{
+    http::Peer,
+    pwp::{FromBytes, IntoBytes},
+    Error,
+};
+use bendy::decoding::{Decoder, Object};
+
+/// Payload of a `ut_pex` extension message (BEP 11): the peers connected or
+/// disconnected since the previous message, in compact form.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PexMessage {
+    /// new peers, with their flags
+    added: Vec<(Peer, u8)>,
+    dropped: Vec<Peer>,
+}
+
+impl PexMessage {
+    pub const EXTENSION_NAME: &'static str = "ut_pex";
+    /// Messages should not carry more added, or dropped, peers
+    pub const MAX_PEERS: usize = 50;
+
+    /// Flag of the added peers that have the whole torrent
+    pub const SEED_FLAG: u8 = 0x02;
+
+    const IPV4_LENGTH: usize = 6;
+    const IPV6_LENGTH: usize = 18;
+
+    pub fn new(added: Vec<(Peer, u8)>, dropped: Vec<Peer>) -> Self {
+        Self { added, dropped }
+    }
+
+    pub fn added(&self) -> &Vec<(Peer, u8)> {
+        &self.added
+    }
+
+    pub fn dropped(&self) -> &Vec<Peer> {
+        &self.dropped
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.dropped.is_empty()
+    }
+
+    fn bencode_string(bytes: &[u8]) -> Vec<u8> {
+        let mut string = format!("{}:", bytes.len()).into_bytes();
+        string.extend(bytes);
+        string
+    }
+
+    fn parse_peers(bytes: &[u8], entry_length: usize) -> Result<Vec<Peer>, Error> {
+        if !bytes.len().is_multiple_of(entry_length) {
+            return Err(Error::InvalidPexMessage);
+        }
+
+        Ok(bytes
+            .chunks(entry_length)
+            .map(|chunk| match entry_length {
+                Self::IPV4_LENGTH => Peer::from_bytes(chunk),
+                _ => Peer::from_ipv6_bytes(chunk),
+            })
+            .collect())
+    }
+
+    /// Missing flags are taken as 0.
+    fn with_flags(peers: Vec<Peer>, flags: &[u8]) -> Vec<(Peer, u8)> {
+        peers
+            .into_iter()
+            .enumerate()
+            .map(|(index, peer)| (peer, flags.get(index).copied().unwrap_or(0)))
+            .collect()
+    }
+}
+
+impl IntoBytes for PexMessage {
+    /// IPv4 and IPv6 peers go to separate keys, written in sorted order.
+    fn into_bytes(self) -> Vec<u8> {
+        let (added, added6): (Vec<_>, Vec<_>) =
+            self.added.iter().partition(|(peer, _)| peer.ip().is_ipv4());
+        let (dropped, dropped6): (Vec<_>, Vec<_>) =
+            self.dropped.iter().partition(|peer| peer.ip().is_ipv4());
+
+        let compact_peers = |peers: &[&(Peer, u8)]| -> Vec<u8> {
+            peers
+                .iter()
+                .flat_map(|(peer, _)| peer.compact_bytes())
+                .collect()
+        };
+        let flags = |peers: &[&(Peer, u8)]| -> Vec<u8> { peers.iter().map(|(_, f)| *f).collect() };
+        let compact_dropped = |peers: &[&Peer]| -> Vec<u8> {
+            peers.iter().flat_map(|peer| peer.compact_bytes()).collect()
+        };
+
+        let mut bytes = b"d".to_vec();
+        bytes.extend(b"5:added");
+        bytes.extend(Self::bencode_string(&compact_peers(&added)));
+        bytes.extend(b"7:added.f");
+        bytes.extend(Self::bencode_string(&flags(&added)));
+        bytes.extend(b"6:added6");
+        bytes.extend(Self::bencode_string(&compact_peers(&added6)));
+        bytes.extend(b"8:added6.f");
+        bytes.extend(Self::bencode_string(&flags(&added6)));
+        bytes.extend(b"7:dropped");
+        bytes.extend(Self::bencode_string(&compact_dropped(&dropped)));
+        bytes.extend(b"8:dropped6");
+        bytes.extend(Self::bencode_string(&compact_dropped(&dropped6)));
+        bytes.push(b'e');
+
+        bytes
+    }
+}
+
+impl FromBytes for PexMessage {
+    fn from_bytes(bytes: &[u8]) -> Result<(Self, usize), Error> {
+        let mut decoder = Decoder::new(bytes);
+        let mut dictionary = match decoder.next_object() {
+            Ok(Some(Object::Dict(dictionary))) => dictionary,
+            _ => return Err(Error::InvalidPexMessage),
+        };
+
+        let (mut added, mut added_flags, mut added6, mut added6_flags) =
+            (vec![], vec![], vec![], vec![]);
+        let mut dropped = vec![];
+        while let Ok(Some(pair)) = dictionary.next_pair() {
+            match pair {
+                (b"added", Object::Bytes(peers)) => {
+                    added = Self::parse_peers(peers, Self::IPV4_LENGTH)?
+                }
+                (b"added.f", Object::Bytes(flags)) => added_flags = flags.to_vec(),
+                (b"added6", Object::Bytes(peers)) => {
+                    added6 = Self::parse_peers(peers, Self::IPV6_LENGTH)?
+                }
+                (b"added6.f", Object::Bytes(flags)) => added6_flags = flags.to_vec(),
+                (b"dropped", Object::Bytes(peers)) => {
+                    dropped.extend(Self::parse_peers(peers, Self::IPV4_LENGTH)?)
+                }
+                (b"dropped6", Object::Bytes(peers)) => {
+                    dropped.extend(Self::parse_peers(peers, Self::IPV6_LENGTH)?)
+                }
+                _ => (),
+            }
+        }
+
+        let mut added_peers = Self::with_flags(added, &added_flags);
+        added_peers.extend(Self::with_flags(added6, &added6_flags));
+
+        Ok((Self::new(added_peers, dropped), bytes.len()))
+    }
+}
@@ -0,0 +1,91 @@
+use crate::pwp::{from_bytes, FromBytes, IntoBytes, MandatoryBitTorrentMessageFields, MessageType};
+use crate::Error;
+
+/// suggest piece: <len=0005><id=13><index>
+#[derive(Debug)]
+pub struct SuggestPiece {
+    message_length: u32,
+    message_type: u8,
+    piece_index: u32,
+}
+
+impl SuggestPiece {
+    pub fn new(piece_index: u32) -> Self {
+        Self {
+            message_length: MessageType::SuggestPiece.base_length(),
+            message_type: MessageType::SuggestPiece.id(),
+            piece_index,
+        }
+    }
+    pub fn message_length(&self) -> u32 {
+        self.message_length
+    }
+
+    pub fn message_type(&self) -> u8 {
+        self.message_type
+    }
+
+    pub fn piece_index(&self) -> u32 {
+        self.piece_index
+    }
+}
+
+impl MandatoryBitTorrentMessageFields for SuggestPiece {
+    fn message_length(&self) -> u32 {
+        self.message_length
+    }
+
+    fn message_type(&self) -> u8 {
+        self.message_type
+    }
+}
+
+impl IntoBytes for SuggestPiece {
+    fn into_bytes(self) -> Vec<u8> {
+        let mut serialized_message: Vec<u8> = Vec::new();
+        serialized_message.extend(self.message_length.to_be_bytes());
+        serialized_message.push(self.message_type);
+        serialized_message.extend(self.piece_index.to_be_bytes());
+        serialized_message
+    }
+}
+
+impl FromBytes for SuggestPiece {
+    fn from_bytes(bytes: &[u8]) -> Result<(Self, usize), Error> {
+        if (bytes.len() as u32)
+            < (MessageType::SuggestPiece.base_length()
+                + from_bytes::PWP_MESSAGE_LENGTH_FIELD_SIZE_IN_BYTES)
+        {
+            return Err(Error::BytesArrayTooShort);
+        }
+
+        let message_length = u32::from_be_bytes(
+            bytes[0..4]
+                .try_into()
+                .map_err(|_| Error::FailedToParseBitTorrentMessageLength)?,
+        );
+        if message_length != MessageType::SuggestPiece.base_length() {
+            return Err(Error::MessageLengthDoesNotMatchWithExpectedOne);
+        }
+
+        let message_type = bytes[4];
+        if message_type != MessageType::SuggestPiece.id() {
+            return Err(Error::MessageTypeDoesNotMatchWithExpectedOne);
+        }
+
+        let piece_index = u32::from_be_bytes(
+            bytes[5..9]
+                .try_into()
+                .map_err(|_| Error::FailedToParseBitTorrentSuggestPieceMessagePieceIndex)?,
+        );
+
+        Ok((
+            Self {
+                message_length,
+                message_type,
+                piece_index,
+            },
+            (message_length + from_bytes::PWP_MESSAGE_LENGTH_FIELD_SIZE_IN_BYTES) as usize,
+        ))
+    }
+}
@@ -1,126 +1,373 @@
 use {
     crate::{
+        dht::{DhtNode, RoutingTable},
         error::Error,
-        file_management::local_bitfield,
-        http::{Peer, TrackerAddress, TrackerRequest, TrackerResponse},
-        pieces_selection::{DistributedSelector, PieceSelection, PiecesSelection},
+        file_management::{is_piece_valid, local_bitfield},
+        http::{Event, Peer, TrackerList, TrackerRequest, TrackerResponse},
+        pieces_selection::{PieceAvailability, PieceSelection, SuggestedPieces},
         pwp::{
-            Bitfield, Handshake, Have, Interested, Message, NotInterested, Piece, Request, Unchoke,
+            AllowedFast, Bitfield, Cancel, Choke, Extended, ExtensionHandshake, ExtensionRegistry,
+            FromBytes, Handshake, Have, HaveAll, HaveNone, Interested, IntoBytes, Message,
+            NotInterested, PexMessage, Piece, Port, RejectRequest, Request, SuggestPiece, Unchoke,
         },
+        tcp::EncryptionPolicy,
         torrent::{self, Torrent},
+        utp::UtpSocket,
         BlockReaderWriter,
     },
-    crossbeam_channel::Receiver,
-    std::path::PathBuf,
-    std::thread,
+    crossbeam_channel::{select, Receiver, Sender},
+    std::{
+        net::{IpAddr, SocketAddr},
+        path::PathBuf,
+        sync::Arc,
+        thread,
+    },
 };
 
+pub(crate) mod event_loop;
+pub(crate) mod negotiators;
 mod tcp_handler;
-use std::{collections::HashMap, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
 
 use bit_vec::BitVec;
 use tcp_handler::TcpHandler;
 
-mod wait;
-pub use wait::Wait;
-
 pub(crate) mod identity;
-use identity::generate_random_identity;
+use identity::{generate_random_identity, CLIENT_VERSION};
+
+pub(crate) mod hash_failures;
+use hash_failures::HashFailures;
+
+pub(crate) mod tracker_scheduler;
+use tracker_scheduler::TrackerScheduler;
+
+pub(crate) mod tracker_announcer;
+use tracker_announcer::TrackerAnnouncer;
+
+pub(crate) mod transfer_statistics;
+use transfer_statistics::TransferStatistics;
+
+pub(crate) mod peer_extensions;
+use peer_extensions::PeerExtensions;
+
+pub(crate) mod peer_exchange;
+use peer_exchange::PeerExchange;
+
+pub(crate) mod peer_event;
+use peer_event::PeerEvent;
+
+pub(crate) mod peer_handshakes;
+use peer_handshakes::PeerHandshakes;
+
+pub(crate) mod reconnect_backoff;
+use reconnect_backoff::ReconnectBackoff;
+
+pub(crate) mod choker;
+use choker::Choker;
+
+pub(crate) mod block_requests;
+use block_requests::{Block, BlockRequests};
 
 #[derive(Debug)]
 pub struct StateMachine {
-    message_receiver: Receiver<(Peer, Message)>,
+    message_receiver: Receiver<(Peer, PeerEvent)>,
     tcp_handler: TcpHandler,
     torrent: Torrent,
+    tracker_announcer: TrackerAnnouncer,
+    /// an announce was sent to the trackers and is not answered yet
+    announcing: bool,
+    tracker_scheduler: TrackerScheduler,
+    /// event to send with the next announce, until a tracker receives it
+    tracker_event: Option<Event>,
+    transfer_statistics: TransferStatistics,
     client_id: [u8; 20],
-    seeder_peers: HashMap<Peer, MyLeecherState>,
+    peers: HashMap<Peer, PeerState>,
     peers_bitfield: HashMap<Peer, BitVec>,
-    leecher_peers: HashMap<Peer, MySeederState>,
+    piece_availability: PieceAvailability,
+    peer_handshakes: PeerHandshakes,
+    /// addresses that turned out to be ours, never connected to again
+    own_addresses: HashSet<Peer>,
+    /// extensions of the peers that sent an extension handshake
+    peer_extensions: HashMap<Peer, PeerExtensions>,
+    peer_exchange: PeerExchange,
+    /// allowed fast set granted to each peer supporting the fast extension
+    fast_peers: HashMap<Peer, Vec<u32>>,
+    suggested_pieces: SuggestedPieces,
+    dht: Option<Arc<DhtNode>>,
+    /// peers found in the DHT, by the thread looking for them
+    dht_peers: Receiver<Vec<Peer>>,
     bitfield: BitVec,
-    requested_pieces: BitVec,
+    block_requests: BlockRequests,
+    reconnect_backoff: ReconnectBackoff,
+    choker: Choker,
     block_reader_writer: BlockReaderWriter,
-    blocks_by_piece: HashMap<u32, usize>,
+    hash_failures: HashFailures,
     mock_peers: bool,
 }
 
-#[derive(Debug, Clone)]
-enum MySeederState {
-    //Upload states
-    WaitingHandshake,
-    NotInterestingAndChoking,
-    InterestingAndUnchoking,
-}
-
-#[derive(Debug, Clone, PartialEq)]
-enum MyLeecherState {
-    //Download states
+/// Setting up of the connection with a peer
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ConnectionState {
+    /// to connect to, or to connect to again
     Unconnected,
+    /// we connected to the peer and sent our handshake
     WaitingHandshake,
+    /// the peer we connected to sends its bitfield before we send ours
     WaitingBitfield,
     BitfieldSent,
-    NotInterestedAndChoked,
-    InterestedAndChoked,
-    InterestedAndUnchoked,
-    NotInterestedAndUnchoked,
+    /// any message may be exchanged
+    Ready,
+}
+
+/// Choking and interest of both sides of a connection, which start out
+/// choked and not interested.
+#[derive(Debug, Clone)]
+pub(crate) struct PeerState {
+    pub(crate) connection: ConnectionState,
+    /// we connected to the peer, rather than it to us, so its address is
+    /// the one it listens on
+    pub(crate) dialed: bool,
+    pub(crate) am_choking: bool,
+    pub(crate) am_interested: bool,
+    pub(crate) peer_choking: bool,
+    pub(crate) peer_interested: bool,
+}
+
+impl PeerState {
+    /// The peers are dialed unless they are set up once connected.
+    fn new(connection: ConnectionState) -> Self {
+        Self {
+            connection,
+            dialed: matches!(
+                connection,
+                ConnectionState::Unconnected | ConnectionState::WaitingHandshake
+            ),
+            am_choking: true,
+            am_interested: false,
+            peer_choking: true,
+            peer_interested: false,
+        }
+    }
+
+    fn is_past_handshake(&self) -> bool {
+        !matches!(
+            self.connection,
+            ConnectionState::Unconnected | ConnectionState::WaitingHandshake
+        )
+    }
 }
 
 impl StateMachine {
     pub const CLIENT_PORT: u16 = 6882;
-    pub const CLIENT_IP_PORT: &str = "127.0.0.1:6882";
-
-    pub fn new(torrent: Torrent, working_directory: &PathBuf, mock_peers: bool) -> Self {
+    /// Extensions supported while downloading
+    const LOCAL_EXTENSIONS: [&'static str; 1] = [PexMessage::EXTENSION_NAME];
+    /// How often the torrent is looked up and announced in the DHT
+    pub const DHT_ANNOUNCE_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+    pub fn new(
+        torrent: Torrent,
+        working_directory: &PathBuf,
+        mock_peers: bool,
+        encryption: EncryptionPolicy,
+    ) -> Self {
         let (message_sender, message_receiver) = crossbeam_channel::unbounded();
-        let tcp_handler = TcpHandler::new(message_sender);
+        let transfer_statistics = TransferStatistics::new();
+        let tcp_handler = TcpHandler::new(
+            message_sender,
+            transfer_statistics.clone(),
+            torrent.info_hash(),
+            encryption,
+        )
+        .unwrap();
+
+        Self::with_tcp_handler(
+            torrent,
+            working_directory,
+            mock_peers,
+            tcp_handler,
+            message_receiver,
+            transfer_statistics,
+        )
+    }
+
+    fn with_tcp_handler(
+        torrent: Torrent,
+        working_directory: &PathBuf,
+        mock_peers: bool,
+        tcp_handler: TcpHandler,
+        message_receiver: Receiver<(Peer, PeerEvent)>,
+        transfer_statistics: TransferStatistics,
+    ) -> Self {
+        let client_id = generate_random_identity();
+        let peer_handshakes = PeerHandshakes::new(torrent.info_hash(), client_id);
         let bitfield = local_bitfield(&torrent, working_directory);
-        let bitfield_length = bitfield.len();
-        let filename;
-        if working_directory.is_dir() {
-            filename = working_directory.join(torrent.name());
-        } else {
-            filename = working_directory.to_path_buf();
-        }
-        let piece_length = torrent.piece_length_in_bytes();
-        let file_size = torrent.total_length_in_bytes();
+        let number_of_pieces = torrent.number_of_pieces() as usize;
         let block_reader_writer =
-            BlockReaderWriter::new(&filename, piece_length, file_size as usize).unwrap();
+            BlockReaderWriter::from_torrent(&torrent, working_directory).unwrap();
 
         Self {
             message_receiver,
             tcp_handler,
+            tracker_announcer: TrackerAnnouncer::new(TrackerList::from_torrent(&torrent)),
+            announcing: false,
+            tracker_scheduler: TrackerScheduler::new(),
+            tracker_event: Some(Event::Started),
+            transfer_statistics,
             torrent,
-            client_id: generate_random_identity(),
-            seeder_peers: HashMap::new(),
+            client_id,
+            peers: HashMap::new(),
             peers_bitfield: HashMap::new(),
-            leecher_peers: HashMap::new(),
+            piece_availability: PieceAvailability::new(number_of_pieces),
+            peer_handshakes,
+            own_addresses: HashSet::new(),
+            peer_extensions: HashMap::new(),
+            peer_exchange: PeerExchange::new(PeerExchange::INTERVAL),
+            fast_peers: HashMap::new(),
+            suggested_pieces: SuggestedPieces::new(),
+            dht: None,
+            dht_peers: crossbeam_channel::never(),
             bitfield,
-            requested_pieces: BitVec::from_elem(bitfield_length, false),
+            block_requests: BlockRequests::new(BlockRequests::DEFAULT_QUEUE_DEPTH),
+            reconnect_backoff: ReconnectBackoff::new(),
+            choker: Choker::new(Instant::now()),
             block_reader_writer,
-            blocks_by_piece: HashMap::new(),
+            hash_failures: HashFailures::new(HashFailures::DEFAULT_MAX_STRIKES),
             mock_peers,
         }
     }
 
+    /// Also connects to the peers over uTP, through `utp_socket`.
+    pub fn with_utp(mut self, utp_socket: Arc<UtpSocket>) -> Self {
+        self.tcp_handler = self.tcp_handler.with_utp(utp_socket);
+        self
+    }
+
+    /// Keeps up to `queue_depth` block requests outstanding with each peer,
+    /// fewer if the peer accepts less.
+    pub fn with_request_queue_depth(mut self, queue_depth: usize) -> Self {
+        self.block_requests = BlockRequests::new(queue_depth);
+        self
+    }
+
+    /// Also looks for peers in the DHT, joining it through `bootstrap_nodes`
+    /// when too few nodes are known. The peers of a private torrent only come
+    /// from its trackers, which leaves it out of the DHT.
+    pub fn with_dht(mut self, dht: Arc<DhtNode>, bootstrap_nodes: Vec<SocketAddr>) -> Self {
+        if self.torrent.is_private() {
+            return self;
+        }
+        let (peer_sender, peer_receiver) = crossbeam_channel::unbounded();
+        let info_hash = self.torrent.info_hash();
+        let node = dht.clone();
+        thread::spawn(move || {
+            Self::look_for_dht_peers(node, bootstrap_nodes, info_hash, peer_sender)
+        });
+
+        self.dht = Some(dht);
+        self.dht_peers = peer_receiver;
+        self
+    }
+
+    /// Runs until the state machine is dropped.
+    fn look_for_dht_peers(
+        dht: Arc<DhtNode>,
+        bootstrap_nodes: Vec<SocketAddr>,
+        info_hash: [u8; 20],
+        peer_sender: Sender<Vec<Peer>>,
+    ) {
+        loop {
+            if dht.routing_table().len() < RoutingTable::K {
+                let known_nodes = dht.bootstrap(&bootstrap_nodes);
+                log::info!("{} DHT nodes known after bootstrap", known_nodes);
+            }
+
+            let peers = dht.announce(info_hash, Self::CLIENT_PORT);
+            log::info!("DHT returned {} peers", peers.len());
+            if peer_sender.send(peers).is_err() {
+                return;
+            }
+
+            thread::sleep(Self::DHT_ANNOUNCE_INTERVAL);
+        }
+    }
+
     fn client_id(&self) -> [u8; 20] {
         self.client_id
     }
 
-    pub fn run(&mut self) {
+    /// Extensions of a peer, known once it sent its extension handshake
+    pub fn peer_extensions(&self, peer: &Peer) -> Option<&PeerExtensions> {
+        self.peer_extensions.get(peer)
+    }
+
+    /// Runs until a message is received on `shutdown`.
+    pub fn run(&mut self, shutdown: Receiver<()>) {
         log::info!("Starting main loop");
 
         if self.is_file_on_disk() {
             log::info!("File already on disk");
         }
 
-        self.connect_to_tracker();
+        if self.mock_peers {
+            self.mock_peers();
+        }
 
+        let message_receiver = self.message_receiver.clone();
+        let dht_peers = self.dht_peers.clone();
+        let mut announced = self.tracker_announcer.announced();
         loop {
+            if !self.mock_peers && !self.announcing && self.tracker_scheduler.is_announce_due() {
+                self.announce();
+            }
+
+            self.expire_block_requests();
             self.handle_current_downloads();
+            self.exchange_peers();
+            if self.choker.is_round_due(Instant::now()) {
+                self.run_choker();
+            }
 
-            if let Ok((peer, message)) = self.message_receiver.recv() {
-                log::debug!("Received message {:?} from {:?}", message, peer);
-                self.handle_messsage(peer, message);
+            select! {
+                recv(message_receiver) -> event => {
+                    match event {
+                        Ok((peer, PeerEvent::Message(message))) => {
+                            log::debug!("Received message {:?} from {:?}", message, peer);
+                            self.handle_messsage(peer, message);
+                        }
+                        Ok((peer, event)) => self.handle_disconnection(peer, event),
+                        Err(_) => (),
+                    }
+                }
+                recv(announced) -> response => {
+                    match response {
+                        Ok((request, response)) => self.handle_tracker_response(request, response),
+                        Err(_) => {
+                            self.restart_tracker_announcer();
+                            announced = self.tracker_announcer.announced();
+                        }
+                    }
+                }
+                recv(dht_peers) -> peers => {
+                    if let Ok(peers) = peers {
+                        if !self.is_file_on_disk() {
+                            peers.into_iter().for_each(|peer| {
+                                self.add_peer(peer);
+                            });
+                        }
+                    }
+                }
+                recv(shutdown) -> _ => break,
+                default(self.time_until_next_wake_up()) => (),
             }
         }
+
+        log::info!("Shutting down");
+        if !self.mock_peers {
+            self.disconnect_from_tracker();
+        }
     }
 
     fn is_file_on_disk(&self) -> bool {
@@ -129,208 +376,667 @@ impl StateMachine {
     }
 
     fn handle_messsage(&mut self, peer: Peer, message: Message) {
-        let peer_download_state = self.seeder_peers.get(&peer);
-        let peer_upload_state = self.leecher_peers.get(&peer);
+        if self.hash_failures.is_banned(&peer) {
+            log::debug!("Ignoring {:?} from banned peer {:?}", message, peer);
+            return;
+        }
 
-        match (peer_download_state, peer_upload_state) {
-            (None, None) => self.handle_handshake(peer, message),
-            (Some(MyLeecherState::WaitingHandshake), _) => self.handle_handshake(peer, message),
-            (Some(MyLeecherState::WaitingBitfield | MyLeecherState::BitfieldSent), _) => {
-                self.handle_bitfield(peer, message)
+        // these messages may arrive in any state after the handshake
+        let message = match message {
+            Message::Extended(extended) => return self.handle_extended(peer, extended),
+            Message::Port(port) => return self.handle_port(peer, port),
+            Message::Request(request) => return self.handle_request(peer, request),
+            // blocks requested before a choke may still be sent
+            Message::Piece(piece) => return self.handle_piece(peer, piece),
+            Message::RejectRequest(reject) => return self.handle_reject_request(peer, reject),
+            Message::Cancel(cancel) => return self.handle_cancel(peer, cancel),
+            Message::KeepAlive(_) => return,
+            Message::SuggestPiece(suggest) => return self.handle_suggest_piece(peer, suggest),
+            Message::AllowedFast(allowed_fast) => {
+                return self.handle_allowed_fast(peer, allowed_fast)
             }
-            (Some(MyLeecherState::InterestedAndChoked), _) => self.handle_unchoke(peer, message),
-            (Some(MyLeecherState::InterestedAndUnchoked), _) => self.handle_piece(peer, message),
-            (_, Some(MySeederState::NotInterestingAndChoking)) => {
-                self.handle_interested(peer, message)
+            message => message,
+        };
+
+        match self.connection_state(peer) {
+            None | Some(ConnectionState::Unconnected | ConnectionState::WaitingHandshake) => {
+                self.handle_handshake(peer, message)
             }
-            (_, Some(MySeederState::InterestingAndUnchoking)) => self.handle_request(peer, message),
-            _ => {
-                log::warn!("Message {:?} received, but we were not expecting it. Download state: {:?} | Upload state: {:?}", message, peer_download_state, peer_upload_state);
+            Some(ConnectionState::WaitingBitfield | ConnectionState::BitfieldSent) => {
+                match message {
+                    // the bitfield is optional for the peers without pieces
+                    message @ (Message::Have(_)
+                    | Message::Interested(_)
+                    | Message::NotInterested(_)
+                    | Message::Choke(_)
+                    | Message::Unchoke(_)) => {
+                        self.handle_bitfield(peer, Message::HaveNone(HaveNone::new()));
+                        self.handle_messsage(peer, message);
+                    }
+                    message => self.handle_bitfield(peer, message),
+                }
             }
+            // the choking and interest of a peer may change at any time once
+            // the connection is set up
+            Some(ConnectionState::Ready) => match message {
+                Message::Choke(_) => self.handle_choke(peer),
+                Message::Unchoke(_) => self.handle_unchoke(peer),
+                Message::Interested(_) => self.handle_interest(peer, true),
+                Message::NotInterested(_) => self.handle_interest(peer, false),
+                Message::Have(have) => self.handle_have(peer, have),
+                message => log::warn!(
+                    "Message {:?} received from {:?}, but we were not expecting it.",
+                    message,
+                    peer
+                ),
+            },
+        }
+    }
+
+    fn connection_state(&self, peer: Peer) -> Option<ConnectionState> {
+        self.peers.get(&peer).map(|state| state.connection)
+    }
+
+    /// Sets up the state of a peer, or moves it to the next stage of the
+    /// connection.
+    fn set_connection_state(&mut self, peer: Peer, connection: ConnectionState) {
+        self.peers
+            .entry(peer)
+            .or_insert_with(|| PeerState::new(connection))
+            .connection = connection;
+    }
+
+    /// Forgets a peer whose connection was closed. The peers we connected
+    /// to are connected to again later, unless they keep failing.
+    fn handle_disconnection(&mut self, peer: Peer, event: PeerEvent) {
+        match event {
+            PeerEvent::Error(e) => log::warn!("Lost the connection with {:?}: {:?}", peer, e),
+            _ => log::info!("{:?} disconnected", peer),
+        }
+        self.forget_peer(peer);
+
+        if self.is_file_on_disk() {
+            self.reconnect_backoff.forget(&peer);
+        } else if self.reconnect_backoff.failed(peer, Instant::now()) {
+            self.peers
+                .insert(peer, PeerState::new(ConnectionState::Unconnected));
+        }
+    }
+
+    /// Removes the state of a peer, the pieces requested from it being
+    /// requested again.
+    fn forget_peer(&mut self, peer: Peer) {
+        self.peers.remove(&peer);
+        if let Some(peer_bitfield) = self.peers_bitfield.remove(&peer) {
+            self.piece_availability.remove_bitfield(&peer_bitfield);
+        }
+        self.peer_handshakes.remove(&peer);
+        self.fast_peers.remove(&peer);
+        self.peer_extensions.remove(&peer);
+        self.peer_exchange.remove_peer(&peer);
+        self.suggested_pieces.remove_peer(&peer);
+        self.choker.remove_peer(&peer);
+        self.block_requests.release_peer(&peer);
+    }
+
+    /// The blocks of the peers that did not answer in time are requested
+    /// again, possibly from other peers.
+    fn expire_block_requests(&mut self) {
+        for peer in self.block_requests.expire(Instant::now()) {
+            log::debug!("{:?} did not send the blocks requested in time", peer);
         }
     }
 
     fn handle_current_downloads(&mut self) {
-        let selector = DistributedSelector::pieces_selection(
-            self.bitfield.clone(),
-            self.peers_bitfield.clone(),
-        );
+        self.begin_peer_connections();
 
-        self.seeder_peers
-            .clone()
+        let peers: Vec<Peer> = self
+            .peers
             .iter()
-            .for_each(|(peer, state)| match state {
-                MyLeecherState::Unconnected => self.begin_peer_connections(),
-                MyLeecherState::InterestedAndUnchoked => {
-                    self.request_pieces(peer.clone(), &selector)
-                }
-                _ => (),
-            });
+            .filter(|(_, state)| state.am_interested && !state.peer_choking)
+            .map(|(peer, _)| *peer)
+            .collect();
+        for peer in peers {
+            self.request_pieces(peer);
+        }
     }
 
     fn handle_handshake(&mut self, peer: Peer, message: Message) {
         log::debug!("Handling handshake");
 
         match message {
-            Message::Handshake(_message) => {
-                if self.is_connection_started(peer) {
-                    self.seeder_peers.insert(peer, MyLeecherState::WaitingBitfield);
+            Message::Handshake(message) => {
+                match self.peer_handshakes.validate(peer, &message) {
+                    Ok(()) => (),
+                    Err(Error::ConnectedToOurselves) => {
+                        log::info!("{:?} is our own address", peer);
+                        // only the address we connected to is worth remembering
+                        if self.is_connection_started(peer) {
+                            self.own_addresses.insert(peer);
+                        }
+                        return self.drop_peer(peer);
+                    }
+                    Err(e) => {
+                        log::warn!("Dropping {:?}, invalid handshake: {:?}", peer, e);
+                        return self.drop_peer(peer);
+                    }
+                }
+                self.reconnect_backoff.connected(peer);
+                self.choker.add_peer(peer, Instant::now());
+                if message.supports_fast_extension() {
+                    self.fast_peers.insert(peer, self.allowed_fast_set(peer));
+                }
+
+                if self.is_connection_started(peer) && message.supports_fast_extension() {
+                    // the fast extension wants our pieces right after the handshake
+                    self.send_bitfield_message(peer);
+                    self.set_connection_state(peer, ConnectionState::BitfieldSent);
+                } else if self.is_connection_started(peer) {
+                    self.set_connection_state(peer, ConnectionState::WaitingBitfield);
                 } else {
                     self.answer_handshake(peer);
-                    self.leecher_peers.insert(peer, MySeederState::NotInterestingAndChoking);
-                    self.seeder_peers.insert(peer, MyLeecherState::BitfieldSent);
+                    self.set_connection_state(peer, ConnectionState::BitfieldSent);
+                }
+
+                if message.supports_fast_extension() {
+                    self.send_allowed_fast_messages(peer);
                 }
+                if message.supports_extension_protocol() {
+                    self.send_extension_handshake(peer);
+                }
+                if message.supports_dht() {
+                    self.send_port_message(peer);
+                }
+                self.peer_handshakes.insert(peer, message);
             },
             _ => log::warn!("Unexpected message from {:?}, cannot initiate a connection without a Handshake message", peer)
         }
     }
 
-    //  If we as a leecher, start a connection sending a handshake
-    //  We will also update the leecher_peers list in order to know
-    //  If the arriving handshake is from a passive or active connection
-    // The objective of this mess, is to not send a bitfield message twice
-    // which will be the case if
-    fn is_connection_started(&self, peer: Peer) -> bool {
-        let is_connected;
-        match self.leecher_peers.get(&peer) {
-            Some(_) => is_connected = true,
-            None => is_connected = false,
+    /// Closes the connection with a peer we do not want to talk to, and
+    /// forgets it.
+    fn drop_peer(&mut self, peer: Peer) {
+        self.tcp_handler.disconnect(peer);
+        self.forget_peer(peer);
+        self.reconnect_backoff.forget(&peer);
+    }
+
+    /// The DHT node of the peer is added to the routing table if it answers.
+    fn handle_port(&mut self, peer: Peer, message: Port) {
+        if let Some(dht) = &self.dht {
+            dht.add_node(SocketAddr::new(peer.ip(), message.listen_port()));
+        }
+    }
+
+    fn handle_extended(&mut self, peer: Peer, message: Extended) {
+        let id = message.extended_message_id();
+        if id != Extended::HANDSHAKE_ID {
+            let name = self
+                .peer_extensions(&peer)
+                .and_then(|extensions| extensions.registry().extension_name(id));
+            match name {
+                Some(PexMessage::EXTENSION_NAME) => self.handle_pex(peer, message.payload()),
+                _ => log::debug!(
+                    "Ignoring extended message {} ({:?}) from {:?}",
+                    id,
+                    name,
+                    peer
+                ),
+            }
+            return;
         }
 
-        is_connected
+        let handshake = match ExtensionHandshake::from_bytes(message.payload()) {
+            Ok((handshake, _)) => handshake,
+            Err(e) => {
+                log::warn!("Invalid extension handshake from {:?}: {:?}", peer, e);
+                return;
+            }
+        };
+
+        let extensions = self.peer_extensions.entry(peer).or_insert_with(|| {
+            PeerExtensions::new(ExtensionRegistry::new(Self::local_extensions(
+                &self.torrent,
+            )))
+        });
+        extensions.update(&handshake);
+        log::info!(
+            "Peer {:?} runs {:?}, accepts {:?} outstanding requests, sees us as {:?}, supports {:?}",
+            peer,
+            extensions.client_version(),
+            extensions.reqq(),
+            extensions.yourip(),
+            handshake.extensions().keys().collect::<Vec<_>>()
+        );
     }
 
+    /// The peers of a private torrent only come from its trackers, so it
+    /// does without the peer exchange.
+    fn local_extensions(torrent: &Torrent) -> &'static [&'static str] {
+        match torrent.is_private() {
+            true => &[],
+            false => &Self::LOCAL_EXTENSIONS,
+        }
+    }
+
+    /// Peers learnt from other peers are connected to like the ones returned
+    /// by the tracker. Dropped peers are left alone, they may still be
+    /// reachable from here.
+    fn handle_pex(&mut self, peer: Peer, payload: &[u8]) {
+        if self.torrent.is_private() {
+            log::debug!(
+                "Ignoring peer exchange message from {:?}, the torrent is private",
+                peer
+            );
+            return;
+        }
+        if !self.peer_exchange.accept(peer, Instant::now()) {
+            log::debug!(
+                "Ignoring peer exchange message sent too early by {:?}",
+                peer
+            );
+            return;
+        }
+
+        let message = match PexMessage::from_bytes(payload) {
+            Ok((message, _)) => message,
+            Err(e) => {
+                log::warn!("Invalid peer exchange message from {:?}: {:?}", peer, e);
+                return;
+            }
+        };
+
+        if self.is_file_on_disk() {
+            return;
+        }
+
+        let added_peers = message
+            .added()
+            .iter()
+            .take(PexMessage::MAX_PEERS)
+            .filter(|(added, _)| self.add_peer(*added))
+            .count();
+        log::info!("{} new peers learnt from {:?}", added_peers, peer);
+    }
+
+    /// Sends the changes in the connected peers to the peers supporting the
+    /// peer exchange, when they are due.
+    fn exchange_peers(&mut self) {
+        if self.torrent.is_private() {
+            return;
+        }
+        let receivers: Vec<(Peer, u8)> = self
+            .peer_extensions
+            .iter()
+            .filter_map(|(peer, extensions)| {
+                extensions
+                    .registry()
+                    .remote_id(PexMessage::EXTENSION_NAME)
+                    .map(|id| (*peer, id))
+            })
+            .collect();
+        if receivers.is_empty() {
+            return;
+        }
+
+        let connected_peers = self.connected_peers();
+        let now = Instant::now();
+        for (peer, id) in receivers {
+            if !self
+                .peers
+                .get(&peer)
+                .is_some_and(PeerState::is_past_handshake)
+            {
+                continue;
+            }
+            if let Some(message) = self.peer_exchange.message_for(peer, &connected_peers, now) {
+                let extended = Extended::new(id, message.into_bytes());
+                self.send_message(peer, Message::Extended(extended));
+            }
+        }
+    }
+
+    /// Peers past the handshake, with their peer exchange flags, at the
+    /// address they listen on. The peers that connected to us are only
+    /// known there once they told their port in their extension handshake.
+    fn connected_peers(&self) -> HashMap<Peer, u8> {
+        self.peers
+            .iter()
+            .filter(|(_, state)| state.is_past_handshake())
+            .filter_map(|(peer, state)| {
+                let listen_address = match state.dialed {
+                    true => *peer,
+                    false => {
+                        let port = self.peer_extensions(peer)?.listen_port()?;
+                        Peer::from_socket_address(SocketAddr::new(peer.ip(), port))
+                    }
+                };
+                let is_seed = self
+                    .peers_bitfield
+                    .get(peer)
+                    .is_some_and(|bitfield| bitfield.all());
+                let flags = if is_seed { PexMessage::SEED_FLAG } else { 0 };
+                Some((listen_address, flags))
+            })
+            .collect()
+    }
+
+    /// We connected to the peer, rather than it to us.
+    fn is_connection_started(&self, peer: Peer) -> bool {
+        self.connection_state(peer) == Some(ConnectionState::WaitingHandshake)
+    }
+
+    /// Have All and Have None replace the bitfield of full and empty peers
+    /// supporting the fast extension. The bitfield of the peer is kept the
+    /// size of ours, its padding bits cleared.
     fn handle_bitfield(&mut self, peer: Peer, message: Message) {
         log::debug!("Handling bitfield");
-        match message {
-            Message::Bitfield(message) => {
+        let number_of_pieces = self.torrent.number_of_pieces() as usize;
+        let mut peer_bitfield = match message {
+            Message::Bitfield(message) => message.bitfield().clone(),
+            Message::HaveAll(_) => BitVec::from_elem(number_of_pieces, true),
+            Message::HaveNone(_) => BitVec::from_elem(number_of_pieces, false),
+            _ => {
+                log::warn!("Unexpected message from peer {:?}, waiting for Handshake response or Bitfield message", peer);
+                return;
+            }
+        };
+        peer_bitfield.truncate(number_of_pieces);
+        peer_bitfield.grow(self.bitfield.len() - peer_bitfield.len(), false);
 
-                if let Some(MyLeecherState::WaitingBitfield) = self.seeder_peers.get(&peer){
-                    self.send_bitfield_message(peer);
-                }
+        if self.connection_state(peer) == Some(ConnectionState::WaitingBitfield) {
+            self.send_bitfield_message(peer);
+        }
 
-                self.seeder_peers.insert(peer, MyLeecherState::NotInterestedAndChoked);
-                self.leecher_peers.insert(peer, MySeederState::NotInterestingAndChoking);
-                self.peers_bitfield.insert(peer,message.bitfield().clone());
+        self.set_connection_state(peer, ConnectionState::Ready);
+        self.piece_availability.add_bitfield(&peer_bitfield);
+        if let Some(previous_bitfield) = self.peers_bitfield.insert(peer, peer_bitfield) {
+            self.piece_availability.remove_bitfield(&previous_bitfield);
+        }
+        self.update_interest(peer);
+    }
 
-                if self.missing_bitfields() == 0 {
-                    log::info!("All expected bitfields received");
-                    self.handle_all_bitfields();
-                } else {
-                    log::info!("Waiting for missing {} bitfields", self.missing_bitfields());
+    /// The peer got a piece, which may be one we want.
+    fn handle_have(&mut self, peer: Peer, message: Have) {
+        let piece_index = message.piece_index();
+        let number_of_pieces = self.torrent.number_of_pieces() as usize;
+        match self.peers_bitfield.get_mut(&peer) {
+            Some(peer_bitfield) if (piece_index as usize) < number_of_pieces => {
+                if peer_bitfield.get(piece_index as usize) == Some(false) {
+                    peer_bitfield.set(piece_index as usize, true);
+                    self.piece_availability.add_piece(piece_index);
                 }
-            },
-            _ => log::warn!("Unexpected message from peer {:?}, waiting for Handshake response or Bitfield message", peer)
+            }
+            _ => {
+                log::warn!("{:?} has piece {}, which does not exist", peer, piece_index);
+                return;
+            }
+        }
+        self.update_interest(peer);
+    }
+
+    /// Tells the peer when it starts or stops having pieces we want.
+    fn update_interest(&mut self, peer: Peer) {
+        let is_interesting = self
+            .peers_bitfield
+            .get(&peer)
+            .is_some_and(|peer_bitfield| self.interesting_pieces(peer_bitfield.clone()).any());
+        match self.peers.get_mut(&peer) {
+            Some(state)
+                if state.connection == ConnectionState::Ready
+                    && state.am_interested != is_interesting =>
+            {
+                state.am_interested = is_interesting
+            }
+            _ => return,
+        }
+
+        if is_interesting {
+            log::info!("{:?} has pieces we want", peer);
+            self.send_interested_message(peer);
+        } else {
+            log::info!("{:?} sent all the pieces we needed from it.", peer);
+            self.send_not_interested_message(peer);
         }
     }
 
-    fn missing_bitfields(&self) -> usize {
-        self.seeder_peers.len() - self.peers_bitfield.len()
+    /// Blocks of the requests the peer did not answer yet are not coming,
+    /// unless it supports the fast extension, which rejects them explicitly.
+    fn handle_choke(&mut self, peer: Peer) {
+        match self.peers.get_mut(&peer) {
+            Some(state) if !state.peer_choking => state.peer_choking = true,
+            _ => return,
+        }
+        log::debug!("{:?} choked us", peer);
+
+        if !self.fast_peers.contains_key(&peer) {
+            self.block_requests.release_peer(&peer);
+        }
     }
 
-    fn handle_unchoke(&mut self, peer: Peer, message: Message) {
-        match message {
-            Message::Unchoke(_message) => {
-                self.seeder_peers
-                    .insert(peer, MyLeecherState::InterestedAndUnchoked);
-            }
-            _ => log::warn!(
-                "Unexpected message from peer {:?}, waiting for Unchoke message",
-                peer
-            ),
+    fn handle_unchoke(&mut self, peer: Peer) {
+        if let Some(state) = self.peers.get_mut(&peer) {
+            log::debug!("{:?} unchoked us", peer);
+            state.peer_choking = false;
         }
     }
 
-    fn handle_interested(&mut self, peer: Peer, message: Message) {
-        log::debug!("Handling Interested message");
-        match message {
-            Message::Interested(_message) => {
-                self.leecher_peers
-                    .insert(peer, MySeederState::InterestingAndUnchoking);
-                self.send_unchoke_message(peer.clone());
-            }
-            _ => log::warn!(
-                "Unexpected message from peer {:?}, it has not been unchoked",
-                peer
-            ),
+    /// Peers becoming interested are unchoked right away while a slot is
+    /// free, instead of waiting for the next round of the choker.
+    fn handle_interest(&mut self, peer: Peer, interested: bool) {
+        log::debug!("{:?} is interested: {}", peer, interested);
+        let unchoke = interested && self.unchoked_peers() < Choker::UNCHOKE_SLOTS;
+        let state = match self.peers.get_mut(&peer) {
+            Some(state) => state,
+            None => return,
+        };
+        state.peer_interested = interested;
+        if unchoke && state.am_choking {
+            state.am_choking = false;
+            self.send_unchoke_message(peer);
         }
     }
 
-    fn handle_all_bitfields(&mut self) {
-        self.update_all_my_leecher_states(MyLeecherState::NotInterestedAndChoked);
-        self.send_interested_messages();
+    fn unchoked_peers(&self) -> usize {
+        self.peers
+            .values()
+            .filter(|state| !state.am_choking)
+            .count()
+    }
+
+    /// Unchokes the peers picked by the choker, and chokes the others.
+    fn run_choker(&mut self) {
+        let interested: Vec<Peer> = self
+            .peers
+            .iter()
+            .filter(|(_, state)| state.peer_interested)
+            .map(|(peer, _)| *peer)
+            .collect();
+        let seeding = self.is_file_on_disk();
+        let unchoked = self.choker.run_round(&interested, seeding, Instant::now());
+        log::debug!(
+            "Unchoking {:?}, optimistically {:?}",
+            unchoked,
+            self.choker.optimistic_unchoke()
+        );
+
+        for (peer, state) in self.peers.clone() {
+            let am_choking = !unchoked.contains(&peer);
+            if state.connection != ConnectionState::Ready || state.am_choking == am_choking {
+                continue;
+            }
+            match am_choking {
+                true => self.send_choke_message(peer),
+                false => self.send_unchoke_message(peer),
+            }
+            if let Some(state) = self.peers.get_mut(&peer) {
+                state.am_choking = am_choking;
+            }
+        }
     }
 
     fn answer_handshake(&mut self, peer: Peer) {
         self.send_handshake_message(peer);
-        if self.bitfield.any() {
+        if self.bitfield.any() || self.fast_peers.contains_key(&peer) {
             self.send_bitfield_message(peer);
         }
     }
 
-    fn request_pieces(&mut self, peer: Peer, selector: &[PieceSelection]) {
-        let mut pieces_to_request = 1;
+    /// Fills the request queue of the peer with the blocks of the pieces
+    /// in flight first, so that they complete sooner, then with the blocks
+    /// of the pieces it suggested and of its rarest pieces. In endgame, the
+    /// blocks requested from other peers are requested from it too.
+    fn request_pieces(&mut self, peer: Peer) {
+        let reqq = self
+            .peer_extensions(&peer)
+            .and_then(|extensions| extensions.reqq());
+        let queue_depth = self.block_requests.queue_depth(reqq);
+        let peer_bitfield = match self.peers_bitfield.get(&peer) {
+            Some(peer_bitfield) => peer_bitfield.clone(),
+            None => return,
+        };
 
-        selector
+        let pieces_in_flight = self
+            .block_requests
+            .pieces_with_unrequested_blocks()
+            .into_iter()
+            .filter(|piece_index| peer_bitfield.get(*piece_index as usize) == Some(true));
+        let rarest_pieces: Vec<PieceSelection> = self
+            .piece_availability
+            .rarest_first(&peer_bitfield, |piece_index| {
+                self.bitfield.get(piece_index as usize) == Some(false)
+                    && !self.block_requests.is_piece_started(piece_index)
+            })
+            .into_iter()
+            .map(|piece_index| PieceSelection::new(piece_index, peer))
+            .collect();
+        let new_pieces: Vec<u32> = self
+            .suggested_pieces
+            .prioritize(rarest_pieces, &self.bitfield, &self.peers_bitfield)
             .iter()
             .filter(|piece_selection| piece_selection.peer() == peer)
-            .for_each(|piece_selection| {
-                if pieces_to_request > 0 {
-                    self.request_piece(piece_selection.piece_id(), piece_selection.peer());
-                    pieces_to_request -= 1;
-                }
-            });
-    }
-
-    fn request_piece(&mut self, piece_index: u32, peer: Peer) {
-        if let Some(true) = self.requested_pieces.get(piece_index as usize) {
-            return;
-        }
+            .map(|piece_selection| piece_selection.piece_id())
+            .collect();
 
-        let blocks_per_piece = torrent::expected_blocks_in_piece(piece_index, &self.torrent) as u32;
+        for piece_index in pieces_in_flight.chain(new_pieces) {
+            if self.block_requests.outstanding(&peer) >= queue_depth {
+                break;
+            }
+            // suggested pieces may already be on disk
+            if self.is_piece_on_disk(piece_index) {
+                continue;
+            }
+            if !self.block_requests.is_piece_started(piece_index) {
+                let blocks = self.blocks_of_piece(piece_index);
+                self.block_requests.start_piece(piece_index, blocks);
+            }
 
-        for block in 0..blocks_per_piece {
-            let block_length = torrent::expected_block_length(piece_index, block, &self.torrent);
+            let blocks =
+                self.block_requests
+                    .request_blocks(peer, piece_index, queue_depth, Instant::now());
+            self.send_block_requests(peer, blocks);
+        }
 
-            let request = Request::new(
-                piece_index as u32,
-                block as u32 * BlockReaderWriter::BIT_TORRENT_BLOCK_SIZE as u32,
-                block_length,
+        if !self.is_endgame() {
+            return;
+        }
+        for piece_index in self.block_requests.pieces_in_flight() {
+            if self.block_requests.outstanding(&peer) >= queue_depth {
+                break;
+            }
+            if peer_bitfield.get(piece_index as usize) != Some(true) {
+                continue;
+            }
+            let blocks = self.block_requests.request_duplicates(
+                peer,
+                piece_index,
+                queue_depth,
+                Instant::now(),
             );
+            if !blocks.is_empty() {
+                log::debug!(
+                    "Endgame: requesting {} blocks from {:?} too",
+                    blocks.len(),
+                    peer
+                );
+            }
+            self.send_block_requests(peer, blocks);
+        }
+    }
 
+    /// Every block left to download is requested, the download would wait
+    /// for the slowest of the peers they were requested from. The padding
+    /// bits of the bitfield are not pieces.
+    fn is_endgame(&self) -> bool {
+        self.block_requests
+            .pieces_with_unrequested_blocks()
+            .is_empty()
+            && self
+                .bitfield
+                .iter()
+                .take(self.torrent.number_of_pieces() as usize)
+                .enumerate()
+                .filter(|(_, is_on_disk)| !is_on_disk)
+                .all(|(piece_index, _)| self.block_requests.is_piece_started(piece_index as u32))
+    }
+
+    fn send_block_requests(&mut self, peer: Peer, blocks: Vec<Block>) {
+        for block in blocks {
             log::debug!(
                 "Requesting block 0x{:x} from piece {:?} to {:?}",
-                block as u32 * BlockReaderWriter::BIT_TORRENT_BLOCK_SIZE as u32,
-                piece_index,
+                block.offset(),
+                block.piece_index(),
                 peer
             );
-
+            let request = Request::new(block.piece_index(), block.offset(), block.length());
             self.send_message(peer, Message::Request(request));
         }
-
-        self.requested_pieces.set(piece_index as usize, true);
     }
 
-    //TODO: write update_peer_bitfield function at a Have message in any state
-    fn handle_piece(&mut self, peer: Peer, message: Message) {
-        match message {
-            Message::Piece(piece) => {
-                self.save_piece(&piece);
-                self.print_download_status(&piece);
-                let piece_index = piece.piece_index();
-
-                if let Some(true) = self.bitfield.get(piece_index as usize) {
-                    let have = Message::Have(Have::new(piece_index));
-                    self.send_message(peer, have);
-                }
+    fn blocks_of_piece(&self, piece_index: u32) -> Vec<Block> {
+        let blocks_per_piece = torrent::expected_blocks_in_piece(piece_index, &self.torrent) as u32;
 
-                if !self.is_peer_still_interesting(peer) {
-                    self.finish_download_with_peer(peer)
-                }
+        (0..blocks_per_piece)
+            .map(|block| {
+                Block::new(
+                    piece_index,
+                    block * BlockReaderWriter::BIT_TORRENT_BLOCK_SIZE as u32,
+                    torrent::expected_block_length(piece_index, block, &self.torrent),
+                )
+            })
+            .collect()
+    }
+
+    fn handle_piece(&mut self, peer: Peer, piece: Piece) {
+        let block = Block::new(
+            piece.piece_index(),
+            piece.begin_offset_of_piece(),
+            piece.data().len() as u32,
+        );
+        let duplicates = match self.block_requests.received(peer, block) {
+            Some(duplicates) => duplicates,
+            None => {
+                log::debug!(
+                    "Dropping {} bytes at 0x{:x} of piece {}, not expected from {:?}",
+                    block.length(),
+                    block.offset(),
+                    block.piece_index(),
+                    peer
+                );
+                return;
             }
-            // Message::Have(message) => self.update_peer_bitfield(peer),
-            _ => log::warn!("Unexpected message, waiting for piece."),
+        };
+        for (other_peer, block) in duplicates {
+            self.send_cancel_message(other_peer, block);
+        }
+        self.choker
+            .record_downloaded(peer, piece.data().len() as u64);
+        self.save_piece(peer, &piece);
+        if self.hash_failures.is_banned(&peer) {
+            return;
         }
+
+        self.print_download_status(&piece);
     }
 
     fn print_download_status(&self, piece: &Piece) {
@@ -352,89 +1058,218 @@ impl StateMachine {
         )
     }
 
-    fn save_piece(&mut self, piece: &Piece) {
-        self.block_reader_writer
-            .write(
-                piece.piece_index(),
-                piece.begin_offset_of_piece(),
-                piece.data(),
-            )
-            .unwrap();
+    fn save_piece(&mut self, peer: Peer, piece: &Piece) {
+        let piece_index = piece.piece_index();
+        let written = self.block_reader_writer.write(
+            piece_index,
+            piece.begin_offset_of_piece(),
+            piece.data(),
+        );
+        if let Err(e) = written {
+            log::warn!(
+                "Could not write the block of piece {} from {:?}, dropping it: {:?}",
+                piece_index,
+                peer,
+                e
+            );
+            self.block_requests.finish_piece(piece_index);
+            self.hash_failures.piece_abandoned(piece_index);
+            return self.drop_peer(peer);
+        }
+        self.hash_failures.record_block(piece_index, peer);
 
-        if let Some(blocks) = self.blocks_by_piece.get_mut(&piece.piece_index()) {
-            *blocks = *blocks + 1;
+        if self.block_requests.is_piece_complete(piece_index) {
+            self.block_requests.finish_piece(piece_index);
+            self.verify_piece(piece_index);
+        }
+    }
 
-            let expected_blocks_in_piece =
-                torrent::expected_blocks_in_piece(piece.piece_index(), &self.torrent);
+    /// Checks the SHA-1 of a piece whose blocks were all received. A corrupted
+    /// piece is discarded to be requested again, and the peers that sent it
+    /// are banned if they are repeat offenders.
+    fn verify_piece(&mut self, piece_index: u32) {
+        if is_piece_valid(&self.torrent, &self.block_reader_writer, piece_index) {
+            self.hash_failures.piece_verified(piece_index);
+            self.bitfield.set(piece_index as usize, true);
+            self.broadcast_have(piece_index);
+            // the peers having no other piece we want are not interesting anymore
+            let peers: Vec<Peer> = self.peers.keys().copied().collect();
+            for peer in peers {
+                self.update_interest(peer);
+            }
 
-            if *blocks == expected_blocks_in_piece {
-                self.bitfield.set(piece.piece_index() as usize, true);
+            if self.is_file_on_disk() {
+                self.download_completed();
             }
-        } else {
-            self.blocks_by_piece.insert(piece.piece_index(), 1);
+            return;
+        }
+
+        log::warn!(
+            "Piece {} failed its hash check, discarding it.",
+            piece_index
+        );
+
+        for peer in self.hash_failures.piece_failed(piece_index) {
+            self.ban_peer(peer);
         }
     }
 
-    fn handle_request(&self, peer: Peer, message: Message) {
+    /// Tells the peers that were sent our bitfield that we have a new
+    /// piece, unless they have it too.
+    fn broadcast_have(&mut self, piece_index: u32) {
+        let peers: Vec<Peer> = self
+            .peers
+            .iter()
+            .filter(|(_, state)| {
+                matches!(
+                    state.connection,
+                    ConnectionState::BitfieldSent | ConnectionState::Ready
+                )
+            })
+            .filter(|(peer, _)| {
+                self.peers_bitfield
+                    .get(peer)
+                    .and_then(|peer_bitfield| peer_bitfield.get(piece_index as usize))
+                    != Some(true)
+            })
+            .map(|(peer, _)| *peer)
+            .collect();
+
+        for peer in peers {
+            self.send_message(peer, Message::Have(Have::new(piece_index)));
+        }
+    }
+
+    /// Lets the tracker know right away that we became a seeder. If the
+    /// `started` event was not received yet, it is sent instead, with nothing
+    /// left to download.
+    fn download_completed(&mut self) {
+        log::info!("Download completed");
+
+        if self.tracker_event.is_none() {
+            self.tracker_event = Some(Event::Completed);
+        }
+        self.tracker_scheduler.announce_now();
+    }
+
+    fn ban_peer(&mut self, peer: Peer) {
+        log::warn!(
+            "Banning {:?}, it sent {} pieces failing their hash check.",
+            peer,
+            self.hash_failures.strikes(&peer)
+        );
+
+        self.drop_peer(peer);
+    }
+
+    /// Choked peers may only request their allowed fast pieces. Peers
+    /// supporting the fast extension are told when a request is dropped, the
+    /// others are dropped themselves when asking for a block the torrent does
+    /// not have.
+    fn handle_request(&mut self, peer: Peer, request: Request) {
         log::debug!("Handling request");
-        match message {
-            Message::Request(request) => {
-                if self.is_piece_on_disk(request.piece_index()) {
-                    self.send_piece(peer, request)
-                }
-            }
-            Message::Have(_) => {
-                log::warn!(
-                    "Received {:?}, but support for Have messages is not yet implemented.",
-                    message
-                );
+        if !self.is_valid_request(&request) {
+            log::warn!("Invalid request {:?} from {:?}", request, peer);
+            match self.fast_peers.contains_key(&peer) {
+                true => self.send_reject_request_message(peer, request),
+                false => self.drop_peer(peer),
             }
-            Message::NotInterested(_) => {
-                log::warn!(
-                    "Received {:?} but support for NotInterested messages is not yet implemented.",
-                    message
-                );
+            return;
+        }
+
+        let piece_index = request.piece_index();
+        let is_unchoked = self.peers.get(&peer).is_some_and(|state| !state.am_choking);
+        let is_allowed_fast = self
+            .fast_peers
+            .get(&peer)
+            .is_some_and(|allowed_fast_set| allowed_fast_set.contains(&piece_index));
+
+        if (is_unchoked || is_allowed_fast) && self.is_piece_on_disk(piece_index) {
+            match self.send_piece(peer, request) {
+                Ok(()) => return,
+                Err(e) => log::warn!("Could not read the block of {:?}: {:?}", request, e),
             }
-            _ => log::warn!(
-                "Unexpected message {:?}, waiting for Request, Have, or Not Interested messages",
-                message
-            ),
+        }
+        if self.fast_peers.contains_key(&peer) {
+            self.send_reject_request_message(peer, request);
+        } else {
+            log::debug!("Dropping request {:?} from {:?}", request, peer);
         }
     }
 
-    fn send_interested_messages(&mut self) {
-        log::debug!("peers_bitfield {:?}", self.peers_bitfield);
-        self.peers_bitfield
-            .clone()
-            .into_iter()
-            .for_each(|(peer, peer_bitfield)| {
-                let interesting_pieces = self.interesting_pieces(peer_bitfield);
-                if interesting_pieces.any() {
-                    self.send_interested_message(peer.clone());
-                    self.seeder_peers
-                        .insert(peer, MyLeecherState::InterestedAndChoked);
-                    log::info!("Send interested messages, peer: {:?}", peer);
-                }
-            })
+    /// Whether the block lies within a piece of the torrent and is no larger
+    /// than a block.
+    fn is_valid_request(&self, request: &Request) -> bool {
+        let piece_index = request.piece_index();
+        let length = request.piece_length();
+        piece_index < self.torrent.number_of_pieces()
+            && length > 0
+            && length as usize <= BlockReaderWriter::BIT_TORRENT_BLOCK_SIZE
+            && request.begin_offset() as u64 + length as u64
+                <= torrent::expected_piece_length(piece_index, &self.torrent)
+    }
+
+    /// The rejected block is requested again, possibly from another peer.
+    fn handle_reject_request(&mut self, peer: Peer, message: RejectRequest) {
+        log::debug!(
+            "{:?} rejected our request for block 0x{:x} of piece {}",
+            peer,
+            message.begin_offset(),
+            message.piece_index()
+        );
+        self.block_requests
+            .rejected(peer, message.piece_index(), message.begin_offset());
+    }
+
+    /// Requests are answered as soon as they are received, the block was
+    /// already sent.
+    fn handle_cancel(&mut self, peer: Peer, message: Cancel) {
+        log::debug!(
+            "{:?} cancelled its request for block 0x{:x} of piece {}, already sent",
+            peer,
+            message.begin_offset(),
+            message.piece_index()
+        );
+    }
+
+    fn handle_suggest_piece(&mut self, peer: Peer, message: SuggestPiece) {
+        self.suggested_pieces.suggest(peer, message.piece_index());
+    }
+
+    /// Pieces are only requested once unchoked, so the allowed fast set of
+    /// a peer is not used.
+    fn handle_allowed_fast(&mut self, peer: Peer, message: AllowedFast) {
+        log::debug!(
+            "{:?} allows us to request piece {} while choked",
+            peer,
+            message.piece_index()
+        );
+    }
+
+    /// The allowed fast set is only defined for IPv4 peers.
+    fn allowed_fast_set(&self, peer: Peer) -> Vec<u32> {
+        match peer.ip() {
+            IpAddr::V4(ip) => AllowedFast::allowed_fast_set(
+                ip,
+                self.torrent.info_hash(),
+                self.torrent.number_of_pieces(),
+                AllowedFast::ALLOWED_FAST_SET_SIZE,
+            ),
+            IpAddr::V6(_) => Vec::new(),
+        }
     }
 
     fn begin_peer_connections(&mut self) {
         let peers = self.peers_to_connect();
 
         for peer in peers {
-            if peer.socket_address() == Self::CLIENT_IP_PORT {
-                log::warn!("Skipping connection to ourselves.");
-                continue;
-            }
             //send handshake
+            self.reconnect_backoff.connecting(peer, Instant::now());
             if let Ok(_) = self.connect(peer) {
                 self.send_handshake_message(peer);
 
                 //update peer state
-                self.seeder_peers
-                    .insert(peer, MyLeecherState::WaitingHandshake);
-                self.leecher_peers
-                    .insert(peer, MySeederState::WaitingHandshake);
+                self.set_connection_state(peer, ConnectionState::WaitingHandshake);
 
                 log::debug!("Handshake sent to peer {:?}", peer);
             }
@@ -455,39 +1290,130 @@ impl StateMachine {
         for id in 1..=3 {
             let address = format!("127.0.0.1:200{}", id);
             let peer = Peer::from_socket_address(address.parse::<SocketAddr>().unwrap());
-            let initial_state = MyLeecherState::Unconnected;
+            let initial_state = PeerState::new(ConnectionState::Unconnected);
 
-            self.seeder_peers.insert(peer, initial_state);
+            self.peers.insert(peer, initial_state);
         }
     }
 
-    fn connect_to_tracker(&mut self) {
-        if self.mock_peers {
-            self.mock_peers();
-        } else {
-            loop {
-                match self.send_tracker_request() {
-                    Ok(response) => {
-                        if !(self.is_file_on_disk()) {
-                            self.fill_peer_list(response.peers()).unwrap();
-                        }
+    /// Hands the announce over to the tracker thread, which answers in
+    /// `handle_tracker_response`.
+    fn announce(&mut self) {
+        let request = self.tracker_request(self.tracker_event.clone());
+        self.tracker_announcer.announce(request);
+        self.announcing = true;
+    }
 
-                        break;
+    fn handle_tracker_response(
+        &mut self,
+        request: TrackerRequest,
+        response: Result<TrackerResponse, Error>,
+    ) {
+        self.announcing = false;
+        match response {
+            Ok(response) => {
+                log::info!(
+                    "Tracker reports {:?} seeders and {:?} leechers, next announce in {:?} seconds",
+                    response.complete(),
+                    response.incomplete(),
+                    response.interval()
+                );
+                self.tracker_scheduler
+                    .announce_succeeded(response.interval(), response.min_interval());
+                // the download may have completed while the announce was sent
+                let completed_meanwhile = request.left() > 0 && self.is_file_on_disk();
+                self.tracker_event = match (request.event(), &self.tracker_event) {
+                    (Some(Event::Started), Some(Event::Started)) if completed_meanwhile => {
+                        Some(Event::Completed)
                     }
-                    Err(e) => {
-                        log::error!("{:?}", e);
-                        thread::sleep(Duration::from_secs(1))
+                    (Some(Event::Started), Some(Event::Started))
+                    | (Some(Event::Completed), Some(Event::Completed)) => None,
+                    (_, tracker_event) => tracker_event.clone(),
+                };
+                if completed_meanwhile {
+                    self.tracker_scheduler.announce_now();
+                }
+
+                if !(self.is_file_on_disk()) {
+                    if let Err(e) = self.fill_peer_list(response.peers()) {
+                        log::warn!("{:?}", e);
                     }
                 }
             }
+            Err(_) => self.tracker_scheduler.announce_failed(),
         }
     }
 
+    /// The tracker thread is gone along with the announce in flight, which is
+    /// retried as a failed one by a new thread.
+    fn restart_tracker_announcer(&mut self) {
+        log::error!("Thread TrackerAnnouncer stopped, restarting it");
+        self.tracker_announcer = TrackerAnnouncer::new(TrackerList::from_torrent(&self.torrent));
+        self.announcing = false;
+        self.tracker_scheduler.announce_failed();
+    }
+
+    /// Sends the `stopped` event, unless no tracker ever knew about us, and
+    /// waits for it to be answered for a while.
+    fn disconnect_from_tracker(&mut self) {
+        if matches!(self.tracker_event, Some(Event::Started)) && !self.announcing {
+            return;
+        }
+
+        let request = self.tracker_request(Some(Event::Stopped));
+        self.tracker_announcer.announce(request);
+        let deadline = Instant::now() + TrackerAnnouncer::STOPPED_TIMEOUT;
+        // the announce in flight is answered first
+        while let Ok((request, response)) =
+            self.tracker_announcer.announced().recv_deadline(deadline)
+        {
+            if matches!(request.event(), Some(Event::Stopped)) {
+                if let Err(e) = response {
+                    log::warn!("Could not announce that we are stopping: {:?}", e);
+                }
+                return;
+            }
+        }
+        log::warn!("The trackers did not answer that we are stopping in time");
+    }
+
+    /// The tracker thread wakes the state machine up once the announce in
+    /// flight is answered.
+    fn time_until_next_announce(&self) -> Duration {
+        if self.mock_peers || self.announcing {
+            TrackerScheduler::DEFAULT_INTERVAL
+        } else {
+            self.tracker_scheduler.time_until_announce()
+        }
+    }
+
+    /// Until the next announce, peer exchange, choker round, reconnection
+    /// or request timeout
+    fn time_until_next_wake_up(&self) -> Duration {
+        let now = Instant::now();
+        [
+            self.reconnect_backoff.time_until_next_attempt(now),
+            self.block_requests.time_until_next_timeout(now),
+        ]
+        .into_iter()
+        .flatten()
+        .fold(
+            self.time_until_next_announce()
+                .min(PeerExchange::INTERVAL)
+                .min(self.choker.time_until_next_round(now)),
+            Duration::min,
+        )
+    }
+
+    /// Unconnected peers, once their reconnection is due
     fn peers_to_connect(&self) -> Vec<Peer> {
+        let now = Instant::now();
         let mut peers = Vec::new();
-        for (peer, download_state) in self.seeder_peers.iter() {
-            match download_state {
-                MyLeecherState::Unconnected => peers.push(*peer),
+        for (peer, state) in self.peers.iter() {
+            match state.connection {
+                ConnectionState::Unconnected if self.reconnect_backoff.is_due(peer, now) => {
+                    peers.push(*peer)
+                }
                 _ => (),
             }
         }
@@ -495,33 +1421,69 @@ impl StateMachine {
         peers
     }
 
-    // Operation
-    // peer_bitfield AND !my_bitfield
-    fn is_peer_still_interesting(&self, peer: Peer) -> bool {
-        let mut my_bitfield = self.bitfield.clone();
-        my_bitfield.negate();
+    fn send_handshake_message(&mut self, peer: Peer) {
+        let mut handshake =
+            Handshake::with_extension_protocol(self.torrent.info_hash(), self.client_id)
+                .advertise_fast_extension();
+        if self.dht.is_some() {
+            handshake = handshake.advertise_dht();
+        }
+        self.send_message(peer, Message::Handshake(handshake));
+    }
 
-        let mut peer_bitfield = self.peers_bitfield.get(&peer).unwrap().clone();
-        peer_bitfield.and(&my_bitfield);
+    fn send_port_message(&mut self, peer: Peer) {
+        if let Some(dht) = &self.dht {
+            let port = Port::new(dht.local_address().port());
+            self.send_message(peer, Message::Port(port));
+        }
+    }
 
-        peer_bitfield.any()
+    fn send_extension_handshake(&mut self, peer: Peer) {
+        let registry = ExtensionRegistry::new(Self::local_extensions(&self.torrent));
+        let handshake = ExtensionHandshake::new(
+            registry.local_extensions().clone(),
+            Some(CLIENT_VERSION.to_string()),
+            None,
+            Some(peer.ip()),
+            None,
+        )
+        .with_listen_port(Self::CLIENT_PORT);
+        let extended = Extended::new(Extended::HANDSHAKE_ID, handshake.into_bytes());
+        self.send_message(peer, Message::Extended(extended));
     }
 
-    fn finish_download_with_peer(&mut self, peer: Peer) {
-        log::info!("{:?} sent all the pieces we needed from it.", peer);
-        self.send_not_interested_message(peer);
-        self.seeder_peers
-            .insert(peer, MyLeecherState::NotInterestedAndUnchoked);
+    /// Peers supporting the fast extension get Have All or Have None instead
+    /// of a full or empty bitfield.
+    fn send_bitfield_message(&mut self, peer: Peer) {
+        let message = if !self.fast_peers.contains_key(&peer) {
+            Message::Bitfield(Bitfield::new(self.bitfield.clone()))
+        } else if self.bitfield.all() {
+            Message::HaveAll(HaveAll::new())
+        } else if self.bitfield.none() {
+            Message::HaveNone(HaveNone::new())
+        } else {
+            Message::Bitfield(Bitfield::new(self.bitfield.clone()))
+        };
+        self.send_message(peer, message);
     }
 
-    fn send_handshake_message(&mut self, peer: Peer) {
-        let handshake = Handshake::new(self.torrent.info_hash(), self.client_id);
-        self.send_message(peer, Message::Handshake(handshake));
+    /// Only the allowed fast pieces we have are advertised.
+    fn send_allowed_fast_messages(&mut self, peer: Peer) {
+        let allowed_fast_set = self.fast_peers.get(&peer).cloned().unwrap_or_default();
+        for piece_index in allowed_fast_set {
+            if self.bitfield.get(piece_index as usize) == Some(true) {
+                self.send_message(peer, Message::AllowedFast(AllowedFast::new(piece_index)));
+            }
+        }
     }
 
-    fn send_bitfield_message(&mut self, peer: Peer) {
-        let my_bitfield = Bitfield::new(self.bitfield.clone());
-        self.send_message(peer, Message::Bitfield(my_bitfield));
+    fn send_reject_request_message(&self, peer: Peer, request: Request) {
+        let message = RejectRequest::new(
+            request.piece_index(),
+            request.begin_offset(),
+            request.piece_length(),
+        );
+        self.send_message(peer, Message::RejectRequest(message));
     }
 
     fn send_interested_message(&mut self, peer: Peer) {
@@ -534,22 +1496,40 @@ impl StateMachine {
         self.send_message(peer, Message::NotInterested(message));
     }
 
+    fn send_cancel_message(&mut self, peer: Peer, block: Block) {
+        log::debug!(
+            "Cancelling block 0x{:x} of piece {} with {:?}",
+            block.offset(),
+            block.piece_index(),
+            peer
+        );
+        let cancel = Cancel::new(block.piece_index(), block.offset(), block.length());
+        self.send_message(peer, Message::Cancel(cancel));
+    }
+
+    fn send_choke_message(&mut self, peer: Peer) {
+        let message = Choke::new();
+        self.send_message(peer, Message::Choke(message));
+    }
+
     fn send_unchoke_message(&mut self, peer: Peer) {
         let message = Unchoke::new();
         self.send_message(peer, Message::Unchoke(message));
     }
 
-    fn send_piece(&self, peer: Peer, request: Request) {
+    /// The block is read from disk, cut to the requested length.
+    fn send_piece(&mut self, peer: Peer, request: Request) -> Result<(), Error> {
         let piece_index = request.piece_index();
         let piece_offset = request.begin_offset();
 
-        let data = self
-            .block_reader_writer
-            .read(piece_index, piece_offset)
-            .unwrap();
+        let mut data = self.block_reader_writer.read(piece_index, piece_offset)?;
+        data.truncate(request.piece_length() as usize);
 
+        self.choker.record_uploaded(peer, data.len() as u64);
         let piece = Piece::new(piece_index, piece_offset, data);
         self.send_message(peer, Message::Piece(piece));
+
+        Ok(())
     }
 
     fn is_piece_on_disk(&self, piece_index: u32) -> bool {
@@ -574,44 +1554,33 @@ impl StateMachine {
         self.tcp_handler.connect(peer)
     }
 
-    fn send_tracker_request(&mut self) -> Result<TrackerResponse, Error> {
-        // I think for now it's ok to assume that we either have the whole file or
-        // nothing at all when the client starts. We should read from disk to check what
-        // is the case and fill `left_to_download` with 0 or torrent_size. Based on that,
-        // we can decide whether or not we'll iterate the list of peers sent by the tracker
-        // to find a peer that has the file we want (if we want a file). If we are seeding,
-        // we don't need to do that, and we just need to wait for a handshake instead. The
-        // TcpHandler module also listen for connections and the Handshake will be received
-        // in the function `run` naturally.
-        let torrent = &self.torrent;
-        let left_to_download = if self.is_file_on_disk() {
-            0
-        } else {
-            torrent.total_length_in_bytes()
-        };
-
-        let tracker_request =
-            TrackerRequest::from_torrent(torrent, self.client_id(), left_to_download);
-        let tracker_address = TrackerAddress::from_torrent(&self.torrent)?;
-        log::debug!("Sending tracker request {:?}", tracker_request);
-
-        let response = TrackerRequest::send_request(tracker_request, tracker_address);
-        log::debug!("Tracker response: {:?}", response);
+    fn tracker_request(&self, event: Option<Event>) -> TrackerRequest {
+        TrackerRequest::from_torrent(
+            &self.torrent,
+            self.client_id(),
+            self.transfer_statistics.uploaded(),
+            self.transfer_statistics.downloaded(),
+            self.left_to_download(),
+            event,
+        )
+    }
 
-        response
+    /// Number of bytes of the pieces we do not have yet.
+    fn left_to_download(&self) -> u64 {
+        (0..self.torrent.number_of_pieces())
+            .filter(|piece_index| !self.bitfield[*piece_index as usize])
+            .map(|piece_index| torrent::expected_piece_length(piece_index, &self.torrent))
+            .sum()
     }
 
+    /// Adds the peers returned by the tracker that we do not know yet.
     fn fill_peer_list(&mut self, tracker_peer_list: Option<&Vec<Peer>>) -> Result<(), Error> {
         match tracker_peer_list {
             Some(peers) => {
                 for peer in peers {
-                    if peer.socket_address() == Self::CLIENT_IP_PORT {
-                        log::warn!("Not adding ourselves to the peer list.");
-                        continue;
-                    }
-                    self.seeder_peers.insert(*peer, MyLeecherState::Unconnected);
+                    self.add_peer(*peer);
                 }
-                log::debug!("Seeding peers: {:?}", self.seeder_peers);
+                log::debug!("Peers: {:?}", self.peers);
             }
 
             None => {
@@ -622,9 +1591,86 @@ impl StateMachine {
         Ok(())
     }
 
-    fn update_all_my_leecher_states(&mut self, updated_state: MyLeecherState) {
-        self.seeder_peers.values_mut().for_each(|state| {
-            *state = updated_state.clone();
-        });
+    /// Adds a peer to connect to, returns whether it was not known yet.
+    fn add_peer(&mut self, peer: Peer) -> bool {
+        if self.own_addresses.contains(&peer) {
+            log::debug!("Not adding our own address {:?} to the peer list.", peer);
+            return false;
+        }
+        if self.hash_failures.is_banned(&peer) {
+            log::debug!("Not adding banned peer {:?} to the peer list.", peer);
+            return false;
+        }
+        if self.peers.contains_key(&peer) {
+            return false;
+        }
+        self.peers
+            .insert(peer, PeerState::new(ConnectionState::Unconnected));
+
+        true
+    }
+}
+
+#[cfg(test)]
+impl StateMachine {
+    /// State machine without an event loop, the commands for the peers are
+    /// returned instead.
+    pub(crate) fn without_network(
+        torrent: Torrent,
+        working_directory: &PathBuf,
+    ) -> (Self, Receiver<event_loop::Command>) {
+        let (command_sender, commands) = crossbeam_channel::unbounded();
+        let (_, message_receiver) = crossbeam_channel::unbounded();
+        let state_machine = Self::with_tcp_handler(
+            torrent,
+            working_directory,
+            false,
+            TcpHandler::detached(command_sender),
+            message_receiver,
+            TransferStatistics::new(),
+        );
+
+        (state_machine, commands)
+    }
+
+    /// Handles a message as if received from the peer.
+    pub(crate) fn receive(&mut self, peer: Peer, message: Message) {
+        self.handle_messsage(peer, message);
+    }
+
+    /// Requests blocks from the peers that unchoked us.
+    pub(crate) fn download(&mut self) {
+        self.handle_current_downloads();
+    }
+
+    pub(crate) fn peer_state(&self, peer: &Peer) -> Option<&PeerState> {
+        self.peers.get(peer)
+    }
+
+    pub(crate) fn outstanding_blocks(&self, peer: &Peer) -> usize {
+        self.block_requests.outstanding(peer)
+    }
+
+    pub(crate) fn peers_having(&self, piece_index: u32) -> u32 {
+        self.piece_availability.peers_having(piece_index)
+    }
+
+    pub(crate) fn has_piece(&self, piece_index: u32) -> bool {
+        self.is_piece_on_disk(piece_index)
+    }
+
+    /// Adds a peer to connect to, as if returned by a tracker.
+    pub(crate) fn add(&mut self, peer: Peer) -> bool {
+        self.add_peer(peer)
+    }
+
+    /// Sends the peer exchange messages due.
+    pub(crate) fn exchange(&mut self) {
+        self.exchange_peers();
+    }
+
+    /// Connected peers, as advertised to the other peers
+    pub(crate) fn advertised_peers(&self) -> HashMap<Peer, u8> {
+        self.connected_peers()
     }
 }
@@ -0,0 +1,311 @@
+use {
+    crate::http::Peer,
+    std::{
+        collections::{HashMap, HashSet, VecDeque},
+        time::{Duration, Instant},
+    },
+};
+
+/// Block of a piece, as requested from a peer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Block {
+    piece_index: u32,
+    offset: u32,
+    length: u32,
+}
+
+impl Block {
+    pub fn new(piece_index: u32, offset: u32, length: u32) -> Self {
+        Self {
+            piece_index,
+            offset,
+            length,
+        }
+    }
+
+    pub fn piece_index(&self) -> u32 {
+        self.piece_index
+    }
+
+    pub fn offset(&self) -> u32 {
+        self.offset
+    }
+
+    pub fn length(&self) -> u32 {
+        self.length
+    }
+}
+
+#[derive(Debug)]
+struct PieceBlocks {
+    /// blocks to request, the ones given back first
+    unrequested: VecDeque<Block>,
+    /// offsets of the blocks received
+    received: HashSet<u32>,
+    blocks: usize,
+}
+
+/// Keeps several pieces in flight, and up to a queue depth of block requests
+/// outstanding with each peer. The blocks of the requests that time out, are
+/// rejected, or are lost with a peer go back to the pool, to be requested
+/// from any peer having the piece. In endgame, the last blocks are requested
+/// from several peers at once.
+#[derive(Debug)]
+pub struct BlockRequests {
+    queue_depth: usize,
+    pieces: HashMap<u32, PieceBlocks>,
+    /// blocks requested from each peer, with the time of the request
+    outstanding: HashMap<Peer, HashMap<Block, Instant>>,
+}
+
+impl BlockRequests {
+    pub const DEFAULT_QUEUE_DEPTH: usize = 32;
+    pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+    pub fn new(queue_depth: usize) -> Self {
+        Self {
+            queue_depth: queue_depth.max(1),
+            pieces: HashMap::new(),
+            outstanding: HashMap::new(),
+        }
+    }
+
+    /// Requests outstanding with a peer at most, `reqq` being the number it
+    /// said it accepts in its extension handshake.
+    pub fn queue_depth(&self, reqq: Option<u32>) -> usize {
+        match reqq {
+            Some(reqq) => self.queue_depth.min(reqq as usize).max(1),
+            None => self.queue_depth,
+        }
+    }
+
+    pub fn outstanding(&self, peer: &Peer) -> usize {
+        self.outstanding.get(peer).map_or(0, |blocks| blocks.len())
+    }
+
+    pub fn is_piece_started(&self, piece_index: u32) -> bool {
+        self.pieces.contains_key(&piece_index)
+    }
+
+    /// Pieces started and not complete yet
+    pub fn pieces_in_flight(&self) -> Vec<u32> {
+        let mut pieces: Vec<u32> = self.pieces.keys().copied().collect();
+        pieces.sort_unstable();
+        pieces
+    }
+
+    /// Pieces with blocks waiting to be requested
+    pub fn pieces_with_unrequested_blocks(&self) -> Vec<u32> {
+        let mut pieces: Vec<u32> = self
+            .pieces
+            .iter()
+            .filter(|(_, piece)| !piece.unrequested.is_empty())
+            .map(|(piece_index, _)| *piece_index)
+            .collect();
+        pieces.sort_unstable();
+        pieces
+    }
+
+    pub fn start_piece(&mut self, piece_index: u32, blocks: Vec<Block>) {
+        self.pieces
+            .entry(piece_index)
+            .or_insert_with(|| PieceBlocks {
+                blocks: blocks.len(),
+                unrequested: blocks.into(),
+                received: HashSet::new(),
+            });
+    }
+
+    /// Takes blocks of the piece to request from `peer`, until it has
+    /// `queue_depth` requests outstanding.
+    pub fn request_blocks(
+        &mut self,
+        peer: Peer,
+        piece_index: u32,
+        queue_depth: usize,
+        now: Instant,
+    ) -> Vec<Block> {
+        let piece = match self.pieces.get_mut(&piece_index) {
+            Some(piece) => piece,
+            None => return Vec::new(),
+        };
+        let outstanding = self.outstanding.entry(peer).or_default();
+
+        let mut blocks = Vec::new();
+        while outstanding.len() < queue_depth {
+            match piece.unrequested.pop_front() {
+                Some(block) => {
+                    outstanding.insert(block, now);
+                    blocks.push(block);
+                }
+                None => break,
+            }
+        }
+        blocks
+    }
+
+    /// Endgame: takes the blocks of the piece requested from other peers
+    /// only, to request them from `peer` too, until it has `queue_depth`
+    /// requests outstanding. The oldest requests are duplicated first.
+    pub fn request_duplicates(
+        &mut self,
+        peer: Peer,
+        piece_index: u32,
+        queue_depth: usize,
+        now: Instant,
+    ) -> Vec<Block> {
+        if !self.pieces.contains_key(&piece_index) {
+            return Vec::new();
+        }
+        let mut candidates: Vec<(Block, Instant)> = self
+            .outstanding
+            .iter()
+            .filter(|(other_peer, _)| **other_peer != peer)
+            .flat_map(|(_, blocks)| blocks.iter())
+            .filter(|(block, _)| block.piece_index == piece_index)
+            .map(|(block, requested_at)| (*block, *requested_at))
+            .collect();
+        candidates.sort_by_key(|(block, requested_at)| (*requested_at, block.offset));
+
+        let outstanding = self.outstanding.entry(peer).or_default();
+        let mut blocks = Vec::new();
+        for (block, _) in candidates {
+            if outstanding.len() >= queue_depth {
+                break;
+            }
+            if outstanding.insert(block, now).is_none() {
+                blocks.push(block);
+            }
+        }
+        blocks
+    }
+
+    /// Whether the block is wanted: only the blocks outstanding with `peer`
+    /// are, with the offset and length they were requested with. The wanted
+    /// ones come with the other requests for the block, to be cancelled.
+    pub fn received(&mut self, peer: Peer, block: Block) -> Option<Vec<(Peer, Block)>> {
+        let is_outstanding = self
+            .outstanding
+            .get(&peer)
+            .is_some_and(|blocks| blocks.contains_key(&block));
+        let piece = match self.pieces.get_mut(&block.piece_index) {
+            Some(piece) if is_outstanding => piece,
+            _ => return None,
+        };
+        if !piece.received.insert(block.offset) {
+            return None;
+        }
+        piece
+            .unrequested
+            .retain(|unrequested| *unrequested != block);
+
+        // the block may also have been requested from other peers, in endgame
+        let mut duplicates = Vec::new();
+        for (other_peer, blocks) in self.outstanding.iter_mut() {
+            if blocks.remove(&block).is_some() && *other_peer != peer {
+                duplicates.push((*other_peer, block));
+            }
+        }
+
+        Some(duplicates)
+    }
+
+    pub fn is_piece_complete(&self, piece_index: u32) -> bool {
+        self.pieces
+            .get(&piece_index)
+            .is_some_and(|piece| piece.received.len() == piece.blocks)
+    }
+
+    /// Forgets a piece, once complete or abandoned, so that it can be started
+    /// again if it failed its hash check or could not be written.
+    pub fn finish_piece(&mut self, piece_index: u32) {
+        self.pieces.remove(&piece_index);
+        for blocks in self.outstanding.values_mut() {
+            blocks.retain(|block, _| block.piece_index != piece_index);
+        }
+    }
+
+    /// The peer will not send the block.
+    pub fn rejected(&mut self, peer: Peer, piece_index: u32, offset: u32) {
+        let block = self.outstanding.get(&peer).and_then(|blocks| {
+            blocks
+                .keys()
+                .find(|block| block.piece_index == piece_index && block.offset == offset)
+                .copied()
+        });
+        if let Some(block) = block {
+            self.give_back(peer, block);
+        }
+    }
+
+    /// Gives back every block requested from the peer, which choked us or
+    /// disconnected.
+    pub fn release_peer(&mut self, peer: &Peer) {
+        let blocks: Vec<Block> = self
+            .outstanding
+            .remove(peer)
+            .map(|blocks| blocks.into_keys().collect())
+            .unwrap_or_default();
+        for block in blocks {
+            self.return_to_pool(block);
+        }
+    }
+
+    /// Gives back the blocks requested for longer than `REQUEST_TIMEOUT`,
+    /// and returns the peers that did not send them.
+    pub fn expire(&mut self, now: Instant) -> HashSet<Peer> {
+        let expired: Vec<(Peer, Block)> = self
+            .outstanding
+            .iter()
+            .flat_map(|(peer, blocks)| {
+                blocks
+                    .iter()
+                    .filter(|(_, requested_at)| {
+                        now.duration_since(**requested_at) >= Self::REQUEST_TIMEOUT
+                    })
+                    .map(|(block, _)| (*peer, *block))
+            })
+            .collect();
+
+        let mut slow_peers = HashSet::new();
+        for (peer, block) in expired {
+            self.give_back(peer, block);
+            slow_peers.insert(peer);
+        }
+        slow_peers
+    }
+
+    /// Until the oldest outstanding request times out
+    pub fn time_until_next_timeout(&self, now: Instant) -> Option<Duration> {
+        self.outstanding
+            .values()
+            .flat_map(|blocks| blocks.values())
+            .min()
+            .map(|requested_at| {
+                (*requested_at + Self::REQUEST_TIMEOUT).saturating_duration_since(now)
+            })
+    }
+
+    fn give_back(&mut self, peer: Peer, block: Block) {
+        if let Some(blocks) = self.outstanding.get_mut(&peer) {
+            blocks.remove(&block);
+        }
+        self.return_to_pool(block);
+    }
+
+    /// Blocks still requested from another peer stay with it.
+    fn return_to_pool(&mut self, block: Block) {
+        let is_outstanding = self
+            .outstanding
+            .values()
+            .any(|blocks| blocks.contains_key(&block));
+        if let Some(piece) = self.pieces.get_mut(&block.piece_index) {
+            if !is_outstanding
+                && !piece.received.contains(&block.offset)
+                && !piece.unrequested.contains(&block)
+            {
+                piece.unrequested.push_front(block);
+            }
+        }
+    }
+}
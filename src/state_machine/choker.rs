@@ -0,0 +1,136 @@
+use {
+    crate::http::Peer,
+    rand::seq::SliceRandom,
+    std::{
+        collections::{HashMap, HashSet},
+        time::{Duration, Instant},
+    },
+};
+
+/// Tit-for-tat choking (BEP 3). Every round, the interested peers we
+/// download from the fastest, or upload to the fastest once seeding, are
+/// unchoked. One more interested peer is unchoked optimistically, so that
+/// new peers get a chance to show their rate.
+#[derive(Debug)]
+pub struct Choker {
+    next_round: Instant,
+    next_optimistic_unchoke: Instant,
+    optimistic_unchoke: Option<Peer>,
+    connected_at: HashMap<Peer, Instant>,
+    /// bytes of the pieces received from and sent to each peer this round
+    downloaded: HashMap<Peer, u64>,
+    uploaded: HashMap<Peer, u64>,
+}
+
+impl Choker {
+    pub const ROUND_INTERVAL: Duration = Duration::from_secs(10);
+    pub const OPTIMISTIC_UNCHOKE_INTERVAL: Duration = Duration::from_secs(30);
+    /// Peers unchoked for their rate, the optimistic unchoke comes on top
+    pub const UNCHOKE_SLOTS: usize = 4;
+    /// Peers connected for less than this are more likely to be
+    /// optimistically unchoked, they have no rate to compete with yet
+    pub const NEW_PEER_PERIOD: Duration = Duration::from_secs(60);
+    pub const NEW_PEER_WEIGHT: u32 = 3;
+
+    /// The first round is due right away.
+    pub fn new(now: Instant) -> Self {
+        Self {
+            next_round: now,
+            next_optimistic_unchoke: now,
+            optimistic_unchoke: None,
+            connected_at: HashMap::new(),
+            downloaded: HashMap::new(),
+            uploaded: HashMap::new(),
+        }
+    }
+
+    pub fn add_peer(&mut self, peer: Peer, now: Instant) {
+        self.connected_at.entry(peer).or_insert(now);
+    }
+
+    pub fn remove_peer(&mut self, peer: &Peer) {
+        self.connected_at.remove(peer);
+        self.downloaded.remove(peer);
+        self.uploaded.remove(peer);
+        if self.optimistic_unchoke == Some(*peer) {
+            self.optimistic_unchoke = None;
+        }
+    }
+
+    pub fn record_downloaded(&mut self, peer: Peer, bytes: u64) {
+        *self.downloaded.entry(peer).or_insert(0) += bytes;
+    }
+
+    pub fn record_uploaded(&mut self, peer: Peer, bytes: u64) {
+        *self.uploaded.entry(peer).or_insert(0) += bytes;
+    }
+
+    pub fn is_round_due(&self, now: Instant) -> bool {
+        now >= self.next_round
+    }
+
+    pub fn time_until_next_round(&self, now: Instant) -> Duration {
+        self.next_round.saturating_duration_since(now)
+    }
+
+    /// Peer optimistically unchoked until the next rotation
+    pub fn optimistic_unchoke(&self) -> Option<Peer> {
+        self.optimistic_unchoke
+    }
+
+    /// Peers to unchoke among the `interested` ones, the others are to be
+    /// choked. The rates are measured again from here.
+    pub fn run_round(&mut self, interested: &[Peer], seeding: bool, now: Instant) -> HashSet<Peer> {
+        let rates = match seeding {
+            true => &self.uploaded,
+            false => &self.downloaded,
+        };
+        let mut ranked = interested.to_vec();
+        ranked.sort_by_key(|peer| std::cmp::Reverse(rates.get(peer).copied().unwrap_or(0)));
+        let mut unchoked: HashSet<Peer> = ranked.into_iter().take(Self::UNCHOKE_SLOTS).collect();
+
+        let optimistic_is_gone = self
+            .optimistic_unchoke
+            .is_none_or(|peer| !interested.contains(&peer) || unchoked.contains(&peer));
+        if now >= self.next_optimistic_unchoke || optimistic_is_gone {
+            self.optimistic_unchoke = self.pick_optimistic_unchoke(interested, &unchoked, now);
+            self.next_optimistic_unchoke = now + Self::OPTIMISTIC_UNCHOKE_INTERVAL;
+        }
+        if let Some(peer) = self.optimistic_unchoke {
+            unchoked.insert(peer);
+        }
+
+        self.downloaded.clear();
+        self.uploaded.clear();
+        self.next_round = now + Self::ROUND_INTERVAL;
+
+        unchoked
+    }
+
+    /// Random choked peer, new peers weighing more
+    fn pick_optimistic_unchoke(
+        &self,
+        interested: &[Peer],
+        unchoked: &HashSet<Peer>,
+        now: Instant,
+    ) -> Option<Peer> {
+        let candidates: Vec<Peer> = interested
+            .iter()
+            .filter(|peer| !unchoked.contains(peer))
+            .copied()
+            .collect();
+
+        candidates
+            .choose_weighted(&mut rand::thread_rng(), |peer| {
+                let is_new = self.connected_at.get(peer).is_none_or(|connected_at| {
+                    now.duration_since(*connected_at) < Self::NEW_PEER_PERIOD
+                });
+                match is_new {
+                    true => Self::NEW_PEER_WEIGHT,
+                    false => 1,
+                }
+            })
+            .ok()
+            .copied()
+    }
+}
@@ -0,0 +1,674 @@
+use {
+    crate::{
+        error::Error,
+        http::Peer,
+        pwp::Message,
+        state_machine::{
+            negotiators::Negotiators, peer_event::PeerEvent,
+            transfer_statistics::TransferStatistics,
+        },
+        tcp::{EncryptionPolicy, TcpSession},
+        utp::{UtpSocket, UtpStream},
+    },
+    crossbeam_channel::{Receiver, Sender},
+    mio::{
+        event::Event,
+        net::{TcpListener, TcpStream},
+        unix::SourceFd,
+        Events, Interest, Poll, Token, Waker,
+    },
+    std::{collections::HashMap, io, net::SocketAddr, sync::Arc, time::Duration},
+};
+
+/// Requests to the event loop, from the `TcpHandler` and from the threads
+/// negotiating the connections. The outcome of a negotiation names the
+/// connection by its token, and is ignored once that connection was closed.
+#[derive(Debug)]
+pub enum Command {
+    Send(Peer, Message),
+    Connect(Peer),
+    /// Connect over TCP, uTP was tried
+    ConnectTcp(Token),
+    Established(Token, Box<TcpSession>),
+    Failed(Token, Error),
+    /// Connection initiated by a peer over uTP
+    AcceptUtp(UtpStream),
+    /// Close the connection, without telling the state machine
+    Disconnect(Peer),
+    /// Connect to the peers over uTP first, through this socket
+    UseUtp(Arc<UtpSocket>),
+}
+
+#[derive(Debug)]
+enum Connection {
+    /// TCP connection in progress
+    Connecting(TcpStream),
+    /// The encryption or uTP is negotiated by another thread
+    Negotiating {
+        /// the peer initiated the connection, the state machine does not
+        /// know about it yet
+        accepted: bool,
+    },
+    Established {
+        session: Box<TcpSession>,
+        /// the peer is not read while it does not read what we send
+        paused: bool,
+    },
+}
+
+/// Single thread doing the input and output of every peer connection, as
+/// their sockets become ready. Messages sent to a connection that is not
+/// established yet are sent once it is. The state machine is told about the
+/// connections it asked for that failed, and about every established
+/// connection that was closed.
+#[derive(Debug)]
+pub struct EventLoop {
+    poll: Poll,
+    listener: Option<TcpListener>,
+    commands: Receiver<Command>,
+    command_sender: Sender<Command>,
+    waker: Arc<Waker>,
+    message_sender: Sender<(Peer, PeerEvent)>,
+    transfer_statistics: TransferStatistics,
+    info_hash: [u8; 20],
+    encryption: EncryptionPolicy,
+    utp_socket: Option<Arc<UtpSocket>>,
+    negotiators: Negotiators,
+    connections: HashMap<Token, (Peer, Connection)>,
+    tokens: HashMap<Peer, Token>,
+    /// messages of the connections not established yet
+    pending: HashMap<Peer, Vec<Message>>,
+    next_token: usize,
+}
+
+impl EventLoop {
+    const LISTENER: Token = Token(0);
+    const WAKER: Token = Token(1);
+    const FIRST_CONNECTION: usize = 2;
+    /// Reading a peer is paused above this many bytes waiting to be sent to
+    /// it, and resumed below the low mark.
+    pub const WRITE_BUFFER_HIGH_MARK: usize = 1024 * 1024;
+    pub const WRITE_BUFFER_LOW_MARK: usize = 256 * 1024;
+    /// uTP connections are also checked this often, in case a notification
+    /// was missed
+    const UTP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+    const MAX_EVENTS: usize = 1024;
+    /// Connections open or being opened at most, past which the connections
+    /// asked for fail and the ones initiated by peers are refused
+    pub const MAX_CONNECTIONS: usize = 200;
+
+    /// The returned sender and waker queue the commands of the loop.
+    pub fn new(
+        listener: Option<std::net::TcpListener>,
+        message_sender: Sender<(Peer, PeerEvent)>,
+        transfer_statistics: TransferStatistics,
+        info_hash: [u8; 20],
+        encryption: EncryptionPolicy,
+    ) -> Result<Self, Error> {
+        let poll = Poll::new().map_err(|_| Error::FailedToCreateEventLoop)?;
+        let waker =
+            Waker::new(poll.registry(), Self::WAKER).map_err(|_| Error::FailedToCreateEventLoop)?;
+
+        let mut listener = listener.map(TcpListener::from_std);
+        if let Some(listener) = &mut listener {
+            poll.registry()
+                .register(listener, Self::LISTENER, Interest::READABLE)
+                .map_err(|_| Error::FailedToCreateEventLoop)?;
+        }
+        let (command_sender, commands) = crossbeam_channel::unbounded();
+        let waker = Arc::new(waker);
+        let negotiators = Negotiators::new(command_sender.clone(), waker.clone());
+
+        Ok(Self {
+            poll,
+            listener,
+            commands,
+            command_sender,
+            waker,
+            message_sender,
+            transfer_statistics,
+            info_hash,
+            encryption,
+            utp_socket: None,
+            negotiators,
+            connections: HashMap::new(),
+            tokens: HashMap::new(),
+            pending: HashMap::new(),
+            next_token: Self::FIRST_CONNECTION,
+        })
+    }
+
+    pub fn command_sender(&self) -> Sender<Command> {
+        self.command_sender.clone()
+    }
+
+    pub fn waker(&self) -> Arc<Waker> {
+        self.waker.clone()
+    }
+
+    pub fn run(mut self) {
+        log::info!("Thread EventLoop started.");
+        let mut events = Events::with_capacity(Self::MAX_EVENTS);
+
+        loop {
+            let timeout = match self.has_utp_sessions() {
+                true => Some(Self::UTP_POLL_INTERVAL),
+                false => None,
+            };
+            if let Err(e) = self.poll.poll(&mut events, timeout) {
+                if e.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                log::error!("Event loop failed: {:?}", e);
+                break;
+            }
+
+            for event in events.iter() {
+                match event.token() {
+                    Self::LISTENER => self.accept_connections(),
+                    Self::WAKER => (),
+                    token => self.handle_event(token, event),
+                }
+            }
+            while let Ok(command) = self.commands.try_recv() {
+                self.handle_command(command);
+            }
+            self.poll_utp_sessions();
+        }
+
+        log::info!("Thread EventLoop exited.");
+    }
+
+    fn handle_command(&mut self, command: Command) {
+        match command {
+            Command::Send(peer, message) => self.send(peer, message),
+            Command::Connect(peer) => self.connect(peer),
+            Command::ConnectTcp(token) => {
+                if let Some((peer, _)) = self.negotiating(token) {
+                    self.connect_tcp(peer);
+                }
+            }
+            Command::Established(token, session) => self.establish(token, session),
+            Command::Failed(token, error) => self.fail(token, error),
+            Command::AcceptUtp(stream) => self.accept_utp(stream),
+            Command::Disconnect(peer) => self.close(peer),
+            Command::UseUtp(utp_socket) => self.use_utp(utp_socket),
+        }
+    }
+
+    /// Also wakes up when the uTP connections receive packets.
+    fn use_utp(&mut self, utp_socket: Arc<UtpSocket>) {
+        let waker = self.waker();
+        utp_socket.set_notifier(move || {
+            let _ = waker.wake();
+        });
+
+        self.utp_socket = Some(utp_socket);
+    }
+
+    fn send(&mut self, peer: Peer, message: Message) {
+        let token = match self.tokens.get(&peer) {
+            Some(token) => *token,
+            None => {
+                log::debug!("Connection with {:?} was closed.", peer);
+                return;
+            }
+        };
+        let session = match self.connections.get_mut(&token) {
+            Some((_, Connection::Established { session, .. })) => session,
+            _ => {
+                self.pending.entry(peer).or_default().push(message);
+                return;
+            }
+        };
+
+        let uploaded_bytes = match &message {
+            Message::Piece(piece) => piece.data().len() as u64,
+            _ => 0,
+        };
+        match session.send(message) {
+            Ok(_) => self.transfer_statistics.add_uploaded(uploaded_bytes),
+            Err(e) => {
+                log::warn!("Connection with {:?} is broken: {:?}", peer, e);
+                self.disconnect(peer, PeerEvent::Error(Error::FailedToSendMessage));
+                return;
+            }
+        }
+        self.apply_backpressure(token);
+    }
+
+    /// Tries uTP first when it is enabled.
+    fn connect(&mut self, peer: Peer) {
+        if self.tokens.contains_key(&peer) {
+            return;
+        }
+        if self.connections.len() >= Self::MAX_CONNECTIONS {
+            log::debug!("Too many connections to connect to {:?}", peer);
+            let _ = self
+                .message_sender
+                .send((peer, PeerEvent::Error(Error::TooManyConnections)));
+            return;
+        }
+        let utp_socket = match &self.utp_socket {
+            Some(utp_socket) => utp_socket.clone(),
+            None => return self.connect_tcp(peer),
+        };
+
+        self.insert_connection(peer, Connection::Negotiating { accepted: false });
+        let token = self.token_of(peer);
+        let (info_hash, encryption) = (self.info_hash, self.encryption);
+        let queued = self.negotiators.negotiate(move || {
+            match TcpSession::with_utp(&utp_socket, peer, info_hash, encryption) {
+                Ok(session) => {
+                    log::debug!("Connected to {:?} over uTP", peer);
+                    Command::Established(token, Box::new(session))
+                }
+                Err(_) => Command::ConnectTcp(token),
+            }
+        });
+        if !queued {
+            self.connect_tcp(peer);
+        }
+    }
+
+    /// Starts a non-blocking connection, completed in `handle_event`.
+    fn connect_tcp(&mut self, peer: Peer) {
+        if self.is_established(peer) {
+            return;
+        }
+        let address = SocketAddr::new(peer.ip(), peer.port());
+        let mut stream = match TcpStream::connect(address) {
+            Ok(stream) => stream,
+            Err(e) => {
+                log::debug!("Could not connect to {:?}: {:?}", peer, e);
+                self.disconnect(peer, PeerEvent::Error(Error::FailedToConnectToPeer));
+                return;
+            }
+        };
+
+        let token = self.token_of(peer);
+        let registration = self.poll.registry().register(
+            &mut stream,
+            token,
+            Interest::READABLE | Interest::WRITABLE,
+        );
+        match registration {
+            Ok(()) => self.insert_connection(peer, Connection::Connecting(stream)),
+            Err(e) => {
+                log::warn!("Could not watch the connection to {:?}: {:?}", peer, e);
+                self.disconnect(peer, PeerEvent::Error(Error::FailedToConnectToPeer));
+            }
+        }
+    }
+
+    fn handle_event(&mut self, token: Token, event: &Event) {
+        let peer = match self.connections.get(&token) {
+            Some((peer, _)) => *peer,
+            None => return,
+        };
+
+        match self.connections.get_mut(&token) {
+            Some((_, Connection::Connecting(stream))) => {
+                if event.is_error() || stream.take_error().ok().flatten().is_some() {
+                    log::debug!("Could not connect to {:?}", peer);
+                    self.disconnect(peer, PeerEvent::Error(Error::FailedToConnectToPeer));
+                    return;
+                }
+                // the connection is still in progress until it has a peer
+                if stream.peer_addr().is_ok() {
+                    self.negotiate_tcp(token, peer);
+                }
+            }
+            Some((_, Connection::Established { .. })) => {
+                if event.is_writable() {
+                    self.flush(token, peer);
+                }
+                if event.is_readable() || event.is_read_closed() {
+                    self.receive(token, peer);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Hands the connected stream over to a thread negotiating the
+    /// encryption, which may take several round trips.
+    fn negotiate_tcp(&mut self, token: Token, peer: Peer) {
+        let mut stream = match self.connections.get_mut(&token) {
+            Some((_, connection)) => {
+                match std::mem::replace(connection, Connection::Negotiating { accepted: false }) {
+                    Connection::Connecting(stream) => stream,
+                    _ => return,
+                }
+            }
+            None => return,
+        };
+        let _ = self.poll.registry().deregister(&mut stream);
+        let stream = std::net::TcpStream::from(stream);
+
+        if !self.encryption.allows_encryption() {
+            match TcpSession::from_transport(stream) {
+                Ok(session) => self.establish(token, Box::new(session)),
+                Err(e) => self.fail(token, e),
+            }
+            return;
+        }
+
+        let (info_hash, encryption) = (self.info_hash, self.encryption);
+        let queued = self.negotiators.negotiate(move || {
+            match TcpSession::with_connected_stream(stream, peer, info_hash, encryption) {
+                Ok(session) => Command::Established(token, Box::new(session)),
+                Err(e) => Command::Failed(token, e),
+            }
+        });
+        if !queued {
+            self.fail(token, Error::TooManyConnections);
+        }
+    }
+
+    /// The peer of the connection still being negotiated, and whether it
+    /// initiated it.
+    fn negotiating(&self, token: Token) -> Option<(Peer, bool)> {
+        match self.connections.get(&token) {
+            Some((peer, Connection::Negotiating { accepted })) => Some((*peer, *accepted)),
+            _ => None,
+        }
+    }
+
+    /// The state machine only hears about the connections it asked for.
+    fn fail(&mut self, token: Token, error: Error) {
+        match self.negotiating(token) {
+            Some((peer, true)) => {
+                log::warn!("Refused the connection of {:?}: {:?}", peer, error);
+                self.close(peer);
+            }
+            Some((peer, false)) => {
+                log::debug!("Could not connect to {:?}: {:?}", peer, error);
+                self.disconnect(peer, PeerEvent::Error(error));
+            }
+            None => (),
+        }
+    }
+
+    /// Watches the socket of the session, and sends it the messages queued
+    /// while it was being established. The session of a connection closed
+    /// meanwhile is dropped.
+    fn establish(&mut self, token: Token, session: Box<TcpSession>) {
+        let peer = match self.negotiating(token) {
+            Some((peer, _)) => peer,
+            None => {
+                log::debug!("Connection closed while negotiating, dropping its session");
+                return;
+            }
+        };
+        log::debug!(
+            "Connection with {:?} established, encrypted: {}",
+            peer,
+            session.is_encrypted()
+        );
+
+        if let Some(fd) = session.raw_fd() {
+            let registration = self.poll.registry().register(
+                &mut SourceFd(&fd),
+                token,
+                Interest::READABLE | Interest::WRITABLE,
+            );
+            if let Err(e) = registration {
+                log::warn!("Could not watch the connection with {:?}: {:?}", peer, e);
+                self.disconnect(peer, PeerEvent::Error(Error::FailedToConnectToPeer));
+                return;
+            }
+        }
+        self.insert_connection(
+            peer,
+            Connection::Established {
+                session,
+                paused: false,
+            },
+        );
+
+        for message in self.pending.remove(&peer).unwrap_or_default() {
+            self.send(peer, message);
+        }
+        // bytes may have arrived with the end of the negotiation
+        self.receive(token, peer);
+    }
+
+    /// Forwards the complete messages of the peer to the state machine.
+    fn receive(&mut self, token: Token, peer: Peer) {
+        let session = match self.connections.get_mut(&token) {
+            Some((
+                _,
+                Connection::Established {
+                    session,
+                    paused: false,
+                },
+            )) => session,
+            _ => return,
+        };
+
+        loop {
+            match session.receive() {
+                Ok(Some(message)) => {
+                    if let Message::Piece(piece) = &message {
+                        self.transfer_statistics
+                            .add_downloaded(piece.data().len() as u64);
+                    }
+                    if self
+                        .message_sender
+                        .send((peer, PeerEvent::Message(message)))
+                        .is_err()
+                    {
+                        return;
+                    }
+                }
+                Ok(None) => return,
+                Err(Error::PeerClosedConnection) => {
+                    log::info!("{:?} closed the connection", peer);
+                    self.disconnect(peer, PeerEvent::Disconnected);
+                    return;
+                }
+                Err(e) => {
+                    log::warn!("Closing the connection with {:?}: {:?}", peer, e);
+                    self.disconnect(peer, PeerEvent::Error(e));
+                    return;
+                }
+            }
+        }
+    }
+
+    fn flush(&mut self, token: Token, peer: Peer) {
+        if let Some((_, Connection::Established { session, .. })) = self.connections.get_mut(&token)
+        {
+            if let Err(e) = session.flush() {
+                log::warn!("Connection with {:?} is broken: {:?}", peer, e);
+                self.disconnect(peer, PeerEvent::Error(Error::FailedToSendMessage));
+                return;
+            }
+        }
+        self.apply_backpressure(token);
+    }
+
+    /// Stops reading a peer that does not read what we send, until most of
+    /// it was sent.
+    fn apply_backpressure(&mut self, token: Token) {
+        let (peer, session, paused) = match self.connections.get_mut(&token) {
+            Some((peer, Connection::Established { session, paused })) => (*peer, session, paused),
+            _ => return,
+        };
+
+        let pending = session.pending_send_length();
+        let interest = if !*paused && pending > Self::WRITE_BUFFER_HIGH_MARK {
+            log::debug!("Pausing {:?}, {} bytes waiting to be sent", peer, pending);
+            *paused = true;
+            Interest::WRITABLE
+        } else if *paused && pending < Self::WRITE_BUFFER_LOW_MARK {
+            log::debug!("Resuming {:?}", peer);
+            *paused = false;
+            Interest::READABLE | Interest::WRITABLE
+        } else {
+            return;
+        };
+
+        if let Some(fd) = session.raw_fd() {
+            let _ = self
+                .poll
+                .registry()
+                .reregister(&mut SourceFd(&fd), token, interest);
+        }
+    }
+
+    /// uTP sessions have no socket to watch, they are checked on every
+    /// wake up.
+    fn poll_utp_sessions(&mut self) {
+        let utp_sessions: Vec<(Token, Peer)> = self
+            .connections
+            .iter()
+            .filter_map(|(token, (peer, connection))| match connection {
+                Connection::Established { session, .. } if session.raw_fd().is_none() => {
+                    Some((*token, *peer))
+                }
+                _ => None,
+            })
+            .collect();
+
+        for (token, peer) in utp_sessions {
+            self.flush(token, peer);
+            self.receive(token, peer);
+        }
+    }
+
+    fn has_utp_sessions(&self) -> bool {
+        self.connections.values().any(|(_, connection)| {
+            matches!(connection, Connection::Established { session, .. } if session.raw_fd().is_none())
+        })
+    }
+
+    /// Accepted connections negotiate their encryption in the negotiation
+    /// threads.
+    fn accept_connections(&mut self) {
+        loop {
+            let listener = match &self.listener {
+                Some(listener) => listener,
+                None => return,
+            };
+            let (stream, address) = match listener.accept() {
+                Ok(connection) => connection,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return,
+                Err(e) => {
+                    log::warn!("Could not accept a connection: {:?}", e);
+                    return;
+                }
+            };
+            let peer = Peer::from_socket_address(address);
+            log::info!("Peer {} initiated a connection.", address);
+            let token = match self.accept(peer) {
+                Some(token) => token,
+                None => continue,
+            };
+
+            let stream = std::net::TcpStream::from(stream);
+            let (info_hash, encryption) = (self.info_hash, self.encryption);
+            let queued = self.negotiators.negotiate(move || {
+                let session = stream
+                    .set_nonblocking(false)
+                    .map_err(|_| Error::FailedToSetSocketAsNonBlocking)
+                    .and_then(|_| TcpSession::accept(stream, info_hash, encryption));
+                match session {
+                    Ok(session) => Command::Established(token, Box::new(session)),
+                    Err(e) => Command::Failed(token, e),
+                }
+            });
+            if !queued {
+                self.close(peer);
+            }
+        }
+    }
+
+    fn accept_utp(&mut self, stream: UtpStream) {
+        let peer = Peer::from_socket_address(stream.peer_addr());
+        let token = match self.accept(peer) {
+            Some(token) => token,
+            None => return,
+        };
+
+        let (info_hash, encryption) = (self.info_hash, self.encryption);
+        let queued = self.negotiators.negotiate(move || {
+            match TcpSession::accept(stream, info_hash, encryption) {
+                Ok(session) => Command::Established(token, Box::new(session)),
+                Err(e) => Command::Failed(token, e),
+            }
+        });
+        if !queued {
+            self.close(peer);
+        }
+    }
+
+    /// Token of the connection initiated by a peer, unless there are too
+    /// many connections or one with the same address already.
+    fn accept(&mut self, peer: Peer) -> Option<Token> {
+        if self.connections.len() >= Self::MAX_CONNECTIONS {
+            log::warn!("Too many connections, refusing the one of {:?}", peer);
+            return None;
+        }
+        if self.tokens.contains_key(&peer) {
+            log::debug!("Already connected to {:?}, refusing its connection", peer);
+            return None;
+        }
+        self.insert_connection(peer, Connection::Negotiating { accepted: true });
+
+        Some(self.token_of(peer))
+    }
+
+    fn is_established(&self, peer: Peer) -> bool {
+        let connection = self
+            .tokens
+            .get(&peer)
+            .and_then(|token| self.connections.get(token));
+
+        matches!(connection, Some((_, Connection::Established { .. })))
+    }
+
+    fn token_of(&mut self, peer: Peer) -> Token {
+        if let Some(token) = self.tokens.get(&peer) {
+            return *token;
+        }
+        let token = Token(self.next_token);
+        self.next_token += 1;
+        self.tokens.insert(peer, token);
+
+        token
+    }
+
+    fn insert_connection(&mut self, peer: Peer, connection: Connection) {
+        let token = self.token_of(peer);
+        self.connections.insert(token, (peer, connection));
+    }
+
+    /// Closes the connection with `peer`, letting the state machine know
+    /// why.
+    fn disconnect(&mut self, peer: Peer, event: PeerEvent) {
+        self.close(peer);
+        let _ = self.message_sender.send((peer, event));
+    }
+
+    fn close(&mut self, peer: Peer) {
+        self.pending.remove(&peer);
+        let token = match self.tokens.remove(&peer) {
+            Some(token) => token,
+            None => return,
+        };
+
+        match self.connections.remove(&token) {
+            Some((_, Connection::Connecting(mut stream))) => {
+                let _ = self.poll.registry().deregister(&mut stream);
+            }
+            Some((_, Connection::Established { session, .. })) => {
+                if let Some(fd) = session.raw_fd() {
+                    let _ = self.poll.registry().deregister(&mut SourceFd(&fd));
+                }
+            }
+            _ => (),
+        }
+    }
+}
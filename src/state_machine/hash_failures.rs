@@ -0,0 +1,72 @@
+use {
+    crate::http::Peer,
+    std::collections::{HashMap, HashSet},
+};
+
+/// Keeps track of the peers that sent blocks of each piece being downloaded,
+/// so that the peers involved in a piece failing its hash check can be
+/// blamed, and banned once they are involved in too many of them.
+#[derive(Debug)]
+pub struct HashFailures {
+    contributors: HashMap<u32, HashSet<Peer>>,
+    strikes: HashMap<Peer, usize>,
+    banned_peers: HashSet<Peer>,
+    max_strikes: usize,
+}
+
+impl HashFailures {
+    pub const DEFAULT_MAX_STRIKES: usize = 2;
+
+    pub fn new(max_strikes: usize) -> Self {
+        Self {
+            contributors: HashMap::new(),
+            strikes: HashMap::new(),
+            banned_peers: HashSet::new(),
+            max_strikes,
+        }
+    }
+
+    /// Remembers that `peer` sent a block of the piece.
+    pub fn record_block(&mut self, piece_index: u32, peer: Peer) {
+        self.contributors
+            .entry(piece_index)
+            .or_default()
+            .insert(peer);
+    }
+
+    pub fn piece_verified(&mut self, piece_index: u32) {
+        self.contributors.remove(&piece_index);
+    }
+
+    /// The blocks received of the piece are discarded, they will all be
+    /// downloaded again.
+    pub fn piece_abandoned(&mut self, piece_index: u32) {
+        self.contributors.remove(&piece_index);
+    }
+
+    /// Blames every contributor of a corrupted piece and returns the peers
+    /// that reached the maximum number of strikes with this failure.
+    pub fn piece_failed(&mut self, piece_index: u32) -> Vec<Peer> {
+        let contributors = self.contributors.remove(&piece_index).unwrap_or_default();
+        let mut newly_banned = Vec::new();
+
+        for peer in contributors {
+            let strikes = self.strikes.entry(peer).or_insert(0);
+            *strikes += 1;
+
+            if *strikes >= self.max_strikes && self.banned_peers.insert(peer) {
+                newly_banned.push(peer);
+            }
+        }
+
+        newly_banned
+    }
+
+    pub fn strikes(&self, peer: &Peer) -> usize {
+        self.strikes.get(peer).copied().unwrap_or(0)
+    }
+
+    pub fn is_banned(&self, peer: &Peer) -> bool {
+        self.banned_peers.contains(peer)
+    }
+}
@@ -17,3 +17,6 @@ pub fn generate_random_identity() -> [u8; 20] {
 
     id
 }
+
+/// Client name and version sent in the extension handshake (`v`)
+pub const CLIENT_VERSION: &str = concat!("Torrust ", env!("CARGO_PKG_VERSION"));
@@ -0,0 +1,55 @@
+use {
+    crate::state_machine::event_loop::Command,
+    crossbeam_channel::{Sender, TrySendError},
+    mio::Waker,
+    std::{sync::Arc, thread},
+};
+
+type Negotiation = Box<dyn FnOnce() -> Command + Send>;
+
+/// Fixed number of threads negotiating the encryption and the uTP
+/// connections, which take several round trips and may wait for a slow peer.
+/// The command each negotiation returns is queued to the event loop. When
+/// every thread is busy and `QUEUE_LENGTH` negotiations are waiting, the new
+/// ones are refused.
+#[derive(Debug)]
+pub struct Negotiators {
+    negotiations: Sender<Negotiation>,
+}
+
+impl Negotiators {
+    pub const THREADS: usize = 8;
+    pub const QUEUE_LENGTH: usize = 64;
+
+    pub fn new(command_sender: Sender<Command>, waker: Arc<Waker>) -> Self {
+        let (negotiations, receiver) =
+            crossbeam_channel::bounded::<Negotiation>(Self::QUEUE_LENGTH);
+
+        for _ in 0..Self::THREADS {
+            let (receiver, command_sender, waker) =
+                (receiver.clone(), command_sender.clone(), waker.clone());
+            thread::spawn(move || {
+                while let Ok(negotiation) = receiver.recv() {
+                    if command_sender.send(negotiation()).is_err() {
+                        break;
+                    }
+                    let _ = waker.wake();
+                }
+            });
+        }
+
+        Self { negotiations }
+    }
+
+    /// Whether the negotiation was queued.
+    pub fn negotiate(&self, negotiation: impl FnOnce() -> Command + Send + 'static) -> bool {
+        match self.negotiations.try_send(Box::new(negotiation)) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                log::warn!("Every negotiation thread is busy, refusing the connection");
+                false
+            }
+            Err(TrySendError::Disconnected(_)) => false,
+        }
+    }
+}
@@ -0,0 +1,12 @@
+use crate::{error::Error, pwp::Message};
+
+/// What the network side of the StateMachine reports about a peer
+#[derive(Debug)]
+pub enum PeerEvent {
+    Message(Message),
+    /// The peer closed the connection
+    Disconnected,
+    /// The connection could not be established, or was closed after the
+    /// peer sent invalid data or stopped taking what we send
+    Error(Error),
+}
@@ -0,0 +1,98 @@
+use {
+    crate::{http::Peer, pwp::PexMessage},
+    std::{
+        collections::{HashMap, HashSet},
+        time::{Duration, Instant},
+    },
+};
+
+/// Rate limits the peer exchange (BEP 11) with each peer, and remembers the
+/// connected peers already advertised to it, so that only the changes are
+/// sent.
+#[derive(Debug)]
+pub struct PeerExchange {
+    interval: Duration,
+    advertised: HashMap<Peer, HashSet<Peer>>,
+    last_sent: HashMap<Peer, Instant>,
+    last_received: HashMap<Peer, Instant>,
+}
+
+impl PeerExchange {
+    /// Messages are sent at most once a minute
+    pub const INTERVAL: Duration = Duration::from_secs(60);
+
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            advertised: HashMap::new(),
+            last_sent: HashMap::new(),
+            last_received: HashMap::new(),
+        }
+    }
+
+    /// Message to send to `peer` if one is due and something changed, given
+    /// the currently connected peers and their flags.
+    pub fn message_for(
+        &mut self,
+        peer: Peer,
+        connected_peers: &HashMap<Peer, u8>,
+        now: Instant,
+    ) -> Option<PexMessage> {
+        if let Some(last_sent) = self.last_sent.get(&peer) {
+            if now.duration_since(*last_sent) < self.interval {
+                return None;
+            }
+        }
+
+        let advertised = self.advertised.entry(peer).or_default();
+        let added: Vec<(Peer, u8)> = connected_peers
+            .iter()
+            .filter(|(connected, _)| **connected != peer && !advertised.contains(connected))
+            .take(PexMessage::MAX_PEERS)
+            .map(|(connected, flags)| (*connected, *flags))
+            .collect();
+        let dropped: Vec<Peer> = advertised
+            .iter()
+            .filter(|advertised| !connected_peers.contains_key(advertised))
+            .take(PexMessage::MAX_PEERS)
+            .copied()
+            .collect();
+
+        added.iter().for_each(|(peer, _)| {
+            advertised.insert(*peer);
+        });
+        dropped.iter().for_each(|peer| {
+            advertised.remove(peer);
+        });
+
+        let message = PexMessage::new(added, dropped);
+        if message.is_empty() {
+            return None;
+        }
+        self.last_sent.insert(peer, now);
+
+        Some(message)
+    }
+
+    /// Whether a message received from `peer` should be used. Messages
+    /// following the previous one too closely are dropped, with some slack
+    /// for the timers of the peer.
+    pub fn accept(&mut self, peer: Peer, now: Instant) -> bool {
+        if let Some(last_received) = self.last_received.get(&peer) {
+            if now.duration_since(*last_received) < self.interval / 2 {
+                return false;
+            }
+        }
+        self.last_received.insert(peer, now);
+
+        true
+    }
+
+    /// Forgets a disconnected peer, which is sent every connected peer again
+    /// if it comes back.
+    pub fn remove_peer(&mut self, peer: &Peer) {
+        self.advertised.remove(peer);
+        self.last_sent.remove(peer);
+        self.last_received.remove(peer);
+    }
+}
@@ -0,0 +1,61 @@
+use {
+    crate::pwp::{ExtensionHandshake, ExtensionRegistry},
+    std::net::IpAddr,
+};
+
+/// What a peer told about itself in its extension handshakes (BEP 10).
+#[derive(Debug, Clone)]
+pub struct PeerExtensions {
+    registry: ExtensionRegistry,
+    client_version: Option<String>,
+    reqq: Option<u32>,
+    yourip: Option<IpAddr>,
+    listen_port: Option<u16>,
+}
+
+impl PeerExtensions {
+    pub fn new(registry: ExtensionRegistry) -> Self {
+        Self {
+            registry,
+            client_version: None,
+            reqq: None,
+            yourip: None,
+            listen_port: None,
+        }
+    }
+
+    /// Later handshakes only update the fields they carry.
+    pub fn update(&mut self, handshake: &ExtensionHandshake) {
+        self.registry.update_remote(handshake);
+        if let Some(client_version) = handshake.client_version() {
+            self.client_version = Some(client_version.clone());
+        }
+        self.reqq = handshake.reqq().or(self.reqq);
+        self.yourip = handshake.yourip().or(self.yourip);
+        self.listen_port = handshake.listen_port().or(self.listen_port);
+    }
+
+    pub fn registry(&self) -> &ExtensionRegistry {
+        &self.registry
+    }
+
+    /// Client name and version of the peer, such as `uTorrent 1.2`
+    pub fn client_version(&self) -> Option<&String> {
+        self.client_version.as_ref()
+    }
+
+    /// Number of outstanding requests the peer accepts
+    pub fn reqq(&self) -> Option<u32> {
+        self.reqq
+    }
+
+    /// Our address, as seen by the peer
+    pub fn yourip(&self) -> Option<IpAddr> {
+        self.yourip
+    }
+
+    /// Port the peer accepts connections on
+    pub fn listen_port(&self) -> Option<u16> {
+        self.listen_port
+    }
+}
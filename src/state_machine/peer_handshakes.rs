@@ -0,0 +1,50 @@
+use {
+    crate::{error::Error, http::Peer, pwp::Handshake},
+    std::collections::HashMap,
+};
+
+/// Handshakes of the connected peers, with their peer id and the reserved
+/// bits telling the extensions they support.
+#[derive(Debug)]
+pub struct PeerHandshakes {
+    info_hash: [u8; 20],
+    client_id: [u8; 20],
+    handshakes: HashMap<Peer, Handshake>,
+}
+
+impl PeerHandshakes {
+    pub fn new(info_hash: [u8; 20], client_id: [u8; 20]) -> Self {
+        Self {
+            info_hash,
+            client_id,
+            handshakes: HashMap::new(),
+        }
+    }
+
+    /// Refuses the handshakes for another torrent, our own handshake when we
+    /// connected to ourselves, and a second connection with the same peer.
+    pub fn validate(&self, peer: Peer, handshake: &Handshake) -> Result<(), Error> {
+        if handshake.info_hash() != self.info_hash {
+            return Err(Error::HandshakeInfoHashMismatch);
+        }
+        if handshake.peer_id() == self.client_id {
+            return Err(Error::ConnectedToOurselves);
+        }
+        let is_duplicate = self.handshakes.iter().any(|(other, other_handshake)| {
+            *other != peer && other_handshake.peer_id() == handshake.peer_id()
+        });
+        if is_duplicate {
+            return Err(Error::DuplicatePeerConnection);
+        }
+
+        Ok(())
+    }
+
+    pub fn insert(&mut self, peer: Peer, handshake: Handshake) {
+        self.handshakes.insert(peer, handshake);
+    }
+
+    pub fn remove(&mut self, peer: &Peer) {
+        self.handshakes.remove(peer);
+    }
+}
@@ -0,0 +1,90 @@
+use {
+    crate::http::Peer,
+    std::{
+        collections::HashMap,
+        time::{Duration, Instant},
+    },
+};
+
+#[derive(Debug)]
+struct Attempts {
+    failures: u32,
+    next_attempt: Instant,
+}
+
+/// Decides when the peers we connected to are connected to again once the
+/// connection failed or was closed, backing off while they keep failing.
+/// Peers that connected to us are not tracked, their port is not the one
+/// they listen on.
+#[derive(Debug, Default)]
+pub struct ReconnectBackoff {
+    peers: HashMap<Peer, Attempts>,
+}
+
+impl ReconnectBackoff {
+    /// Delay before the first reconnection, doubled after each failure
+    pub const RETRY_DELAY: Duration = Duration::from_secs(15);
+    pub const MAX_RETRY_DELAY: Duration = Duration::from_secs(10 * 60);
+    /// Peers failing more often in a row are given up on
+    pub const MAX_FAILURES: u32 = 5;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Remembers that we are connecting to `peer`.
+    pub fn connecting(&mut self, peer: Peer, now: Instant) {
+        self.peers.entry(peer).or_insert(Attempts {
+            failures: 0,
+            next_attempt: now,
+        });
+    }
+
+    /// The peer answered the handshake, its next failure is retried quickly.
+    pub fn connected(&mut self, peer: Peer) {
+        if let Some(attempts) = self.peers.get_mut(&peer) {
+            attempts.failures = 0;
+        }
+    }
+
+    /// Schedules the next connection to `peer`, and returns whether there is
+    /// one. Peers we did not connect to, or that failed too many times, are
+    /// forgotten.
+    pub fn failed(&mut self, peer: Peer, now: Instant) -> bool {
+        let attempts = match self.peers.get_mut(&peer) {
+            Some(attempts) => attempts,
+            None => return false,
+        };
+        attempts.failures += 1;
+        if attempts.failures > Self::MAX_FAILURES {
+            self.peers.remove(&peer);
+            return false;
+        }
+
+        let delay = Self::RETRY_DELAY
+            .saturating_mul(2u32.saturating_pow(attempts.failures - 1))
+            .min(Self::MAX_RETRY_DELAY);
+        attempts.next_attempt = now + delay;
+
+        true
+    }
+
+    pub fn forget(&mut self, peer: &Peer) {
+        self.peers.remove(peer);
+    }
+
+    pub fn is_due(&self, peer: &Peer, now: Instant) -> bool {
+        self.peers
+            .get(peer)
+            .is_none_or(|attempts| attempts.next_attempt <= now)
+    }
+
+    /// Time until the next scheduled reconnection, if any
+    pub fn time_until_next_attempt(&self, now: Instant) -> Option<Duration> {
+        self.peers
+            .values()
+            .filter(|attempts| attempts.next_attempt > now)
+            .map(|attempts| attempts.next_attempt - now)
+            .min()
+    }
+}
@@ -1,138 +1,153 @@
 use {
     crate::{
-        adaptative_wait::AdaptativeWait,
         error::Error,
         http::Peer,
         pwp::Message,
-        state_machine::{StateMachine, Wait},
-        tcp::TcpSession,
+        state_machine::{
+            event_loop::{Command, EventLoop},
+            peer_event::PeerEvent,
+            transfer_statistics::TransferStatistics,
+            StateMachine,
+        },
+        tcp::EncryptionPolicy,
+        utp::UtpSocket,
     },
-    crossbeam_channel::{Receiver, Sender},
+    crossbeam_channel::Sender,
+    mio::Waker,
     std::{
-        collections::HashMap,
-        net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener},
-        sync::{Arc, Mutex},
+        net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener},
+        sync::Arc,
         thread,
-        time::Duration,
     },
 };
 
+/// Network side of the StateMachine: the connections with the peers are
+/// driven by an `EventLoop` thread, which this handler sends commands to.
 #[derive(Debug)]
 pub struct TcpHandler {
-    peers: Arc<Mutex<HashMap<Peer, TcpSession>>>,
-    tcp_sender: Sender<(Peer, Message)>,
+    command_sender: Sender<Command>,
+    waker: Arc<Waker>,
 }
 
 impl TcpHandler {
-    pub fn new(message_sender: Sender<(Peer, Message)>) -> Self {
-        let (tcp_sender, tcp_receiver) = crossbeam_channel::unbounded();
-        let peers = Arc::new(Mutex::new(HashMap::new()));
-        let peers_ref = peers.clone();
+    /// Starts the event loop, which has 3 functions:
+    ///    - Accept connections from other peers attempting to leech from us.
+    ///    - Send Messages from the StateMachine to the Peers.
+    ///    - Send Messages received from the Peers to the StateMachine, and
+    ///      let it know when a connection is closed.
+    pub fn new(
+        message_sender: Sender<(Peer, PeerEvent)>,
+        transfer_statistics: TransferStatistics,
+        info_hash: [u8; 20],
+        encryption: EncryptionPolicy,
+    ) -> Result<Self, Error> {
+        let listener = match TcpHandler::bind_listener() {
+            Ok(listener) => Some(listener),
+            Err(e) => {
+                log::warn!("Not accepting connections from the peers: {:?}", e);
+                None
+            }
+        };
+        let event_loop = EventLoop::new(
+            listener,
+            message_sender,
+            transfer_statistics,
+            info_hash,
+            encryption,
+        )?;
+        let command_sender = event_loop.command_sender();
+        let waker = event_loop.waker();
+        thread::spawn(move || event_loop.run());
+
+        Ok(Self {
+            command_sender,
+            waker,
+        })
+    }
+
+    /// Handler without an event loop, whose commands are left in the channel
+    /// of `command_sender`.
+    #[cfg(test)]
+    pub fn detached(command_sender: Sender<Command>) -> Self {
+        let poll = mio::Poll::new().unwrap();
+        let waker = Waker::new(poll.registry(), mio::Token(0)).unwrap();
+
+        Self {
+            command_sender,
+            waker: Arc::new(waker),
+        }
+    }
 
-        let adaptative_wait = AdaptativeWait::new(64, Duration::from_millis(100));
+    /// Also connects to the peers and accepts their connections over uTP.
+    pub fn with_utp(self, utp_socket: Arc<UtpSocket>) -> Self {
+        let (command_sender, waker) = (self.command_sender.clone(), self.waker.clone());
+        let socket_ref = utp_socket.clone();
         thread::spawn(move || {
-            TcpHandler::run(
-                peers_ref,
-                message_sender.clone(),
-                tcp_receiver,
-                adaptative_wait,
-            )
+            TcpHandler::utp_connection_listener(command_sender, waker, socket_ref)
         });
 
-        Self { peers, tcp_sender }
+        self.command(Command::UseUtp(utp_socket));
+        self
     }
 
-    /// Connect to a Peer and insert it in the hashmap of Peers.
+    /// Starts connecting to a Peer, over uTP first when it is enabled. The
+    /// messages sent meanwhile are sent once it is connected.
     pub fn connect(&mut self, peer: Peer) -> Result<(), Error> {
-        let tcp_session = TcpSession::connect(peer.clone())?;
-        self.peers.lock().unwrap().insert(peer, tcp_session);
+        self.command(Command::Connect(peer));
 
         Ok(())
     }
 
-    pub fn send(&self, message: (Peer, Message)) {
-        self.tcp_sender.send(message).unwrap();
+    /// Closes the connection with a Peer. Messages it sent before may still
+    /// be received.
+    pub fn disconnect(&self, peer: Peer) {
+        self.command(Command::Disconnect(peer));
     }
 
-    /// Starts the TcpHandler, a module that has 3 functions:
-    ///    - Accept connections from other peers attempting to leech from us.
-    ///    - Send Messages from the StateMachine to the Peers through TCP.
-    ///    - Send Messages received from the Peers through TCP to the state machine.
-    pub fn run(
-        peers: Arc<Mutex<HashMap<Peer, TcpSession>>>,
-        message_sender: Sender<(Peer, Message)>,
-        tcp_receiver: Receiver<(Peer, Message)>,
-        mut wait_mechanism: impl Wait,
-    ) {
-        log::info!("Thread TcpHandler started.");
-
-        let peers_ref = peers.clone();
-        thread::spawn(move || TcpHandler::connection_listener(peers_ref));
-        let peers_ref = peers.clone();
-        thread::spawn(move || TcpHandler::tcp_sender(peers_ref, tcp_receiver));
-
-        loop {
-            let mut messages_to_send = Vec::new();
-            // Send messages received through TCP to the state machine
-            if let Ok(ref mut peers) = peers.try_lock() {
-                for (peer, ref mut session) in peers.iter_mut() {
-                    loop {
-                        match session.receive() {
-                            Ok(Some(message)) => messages_to_send.push((*peer, message)),
-                            Ok(None) => break,
-                            Err(_) => panic!("Unexpected TCP data."),
-                        }
-                    }
-                }
-            }
+    pub fn send(&self, message: (Peer, Message)) {
+        self.command(Command::Send(message.0, message.1));
+    }
 
-            // Make sure to call send only after dropping the lock, otherwise we may deadlock.
-            messages_to_send
-                .into_iter()
-                .for_each(|(peer, message)| message_sender.send((peer, message)).unwrap());
+    fn command(&self, command: Command) {
+        self.command_sender.send(command).unwrap();
+        let _ = self.waker.wake();
+    }
 
-            wait_mechanism.wait();
-        }
+    /// Listens on every IPv6 address, which also accepts IPv4 connections on
+    /// dual-stack hosts, or on every IPv4 address when IPv6 is not available.
+    fn bind_listener() -> Result<TcpListener, Error> {
+        let ipv6_address =
+            SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), StateMachine::CLIENT_PORT);
+        let ipv4_address =
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), StateMachine::CLIENT_PORT);
+
+        let listener = TcpListener::bind(ipv6_address)
+            .or_else(|_| TcpListener::bind(ipv4_address))
+            .map_err(|_| Error::FailedToCreateTcpListener)?;
+        listener
+            .set_nonblocking(true)
+            .map_err(|_| Error::FailedToCreateTcpListener)?;
+
+        Ok(listener)
     }
 
-    /// Continously send messages received from the StateMachine to the peers
-    fn tcp_sender(
-        peers: Arc<Mutex<HashMap<Peer, TcpSession>>>,
-        tcp_receiver: Receiver<(Peer, Message)>,
+    /// Continously accepts the connections over uTP, handing them to the
+    /// event loop, which negotiates their encryption.
+    fn utp_connection_listener(
+        command_sender: Sender<Command>,
+        waker: Arc<Waker>,
+        utp_socket: Arc<UtpSocket>,
     ) {
-        log::info!("Thread TcpSender started.");
+        log::info!("Thread UtpConnectionListener started.");
 
-        while let Ok((peer, message)) = tcp_receiver.recv() {
-            let result = peers.lock().unwrap().get(&peer).unwrap().send(message);
-            if let Err(_) = result {
-                log::warn!("Connection with {:?} is broken.", peer);
+        while let Ok(stream) = utp_socket.accept() {
+            log::info!("Peer {} initiated a uTP connection.", stream.peer_addr());
+            if command_sender.send(Command::AcceptUtp(stream)).is_err() {
+                break;
             }
+            let _ = waker.wake();
         }
 
-        log::info!("Thread TcpSender exited.");
-    }
-
-    /// Continously listen for external connections. A connection of this
-    /// kind happens when a peer wants a file that we are seeding. The
-    /// functionn accepts connections and insert the respective TcpSession
-    /// into the Peers hashmap.
-    fn connection_listener(peers: Arc<Mutex<HashMap<Peer, TcpSession>>>) {
-        log::info!("Thread ConnectionListener started.");
-
-        let client_ip = IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0));
-        let socket_address = SocketAddr::new(client_ip, StateMachine::CLIENT_PORT);
-        let tcp_listener = TcpListener::bind(socket_address).unwrap();
-
-        for stream in tcp_listener.incoming() {
-            let stream = stream.unwrap();
-            let address = stream.peer_addr().unwrap();
-            let peer = Peer::from_socket_address(address);
-
-            log::info!("Peer {} initiated a connection.", address);
-            let tcp_session = TcpSession::from_stream(stream).unwrap();
-            peers.lock().unwrap().insert(peer, tcp_session);
-        }
-
-        log::info!("Thread ConnectionListener exited.");
+        log::info!("Thread UtpConnectionListener exited.");
     }
 }
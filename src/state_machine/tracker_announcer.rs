@@ -0,0 +1,78 @@
+use {
+    crate::{
+        http::{TrackerAddress, TrackerList, TrackerRequest, TrackerResponse},
+        udp::UdpTrackers,
+        Error,
+    },
+    crossbeam_channel::{Receiver, Sender},
+    std::{thread, time::Duration},
+};
+
+pub type Announced = (TrackerRequest, Result<TrackerResponse, Error>);
+
+/// Thread announcing to the trackers of the torrent, so that the trackers
+/// slow to answer or unreachable do not hold up the state machine. Each
+/// outcome comes back with the request it answers, in the order of the
+/// requests.
+#[derive(Debug)]
+pub struct TrackerAnnouncer {
+    requests: Sender<TrackerRequest>,
+    announced: Receiver<Announced>,
+}
+
+impl TrackerAnnouncer {
+    /// How long the `stopped` event may delay shutting down
+    pub const STOPPED_TIMEOUT: Duration = Duration::from_secs(10);
+
+    pub fn new(tracker_list: TrackerList) -> Self {
+        let (requests, request_receiver) = crossbeam_channel::unbounded();
+        let (announced_sender, announced) = crossbeam_channel::unbounded();
+        thread::spawn(move || Self::run(tracker_list, request_receiver, announced_sender));
+
+        Self {
+            requests,
+            announced,
+        }
+    }
+
+    pub fn announce(&self, request: TrackerRequest) {
+        let _ = self.requests.send(request);
+    }
+
+    pub fn announced(&self) -> Receiver<Announced> {
+        self.announced.clone()
+    }
+
+    /// Runs until the announcer is dropped.
+    fn run(
+        mut tracker_list: TrackerList,
+        requests: Receiver<TrackerRequest>,
+        announced: Sender<Announced>,
+    ) {
+        log::info!("Thread TrackerAnnouncer started.");
+
+        let mut udp_trackers = UdpTrackers::new();
+        while let Ok(request) = requests.recv() {
+            log::debug!("Sending tracker request {:?}", request);
+            let response = tracker_list.announce(|announce| {
+                log::debug!("Announcing to {}", announce);
+                let tracker_address = TrackerAddress::from_announce(announce)?;
+                TrackerRequest::send_request(request.clone(), tracker_address, &mut udp_trackers)
+            });
+            log::debug!("Tracker response: {:?}", response);
+
+            if let Err(e) = &response {
+                log::error!(
+                    "No tracker of {:?} answered the announce: {:?}",
+                    tracker_list.tiers(),
+                    e
+                );
+            }
+            if announced.send((request, response)).is_err() {
+                break;
+            }
+        }
+
+        log::info!("Thread TrackerAnnouncer exited.");
+    }
+}
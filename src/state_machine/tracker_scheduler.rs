@@ -0,0 +1,69 @@
+use std::time::{Duration, Instant};
+
+/// Decides when the tracker has to be announced to again, following the
+/// interval it asked for, and backing off while no tracker answers.
+#[derive(Debug)]
+pub struct TrackerScheduler {
+    next_announce: Instant,
+    failed_announces: u32,
+}
+
+impl TrackerScheduler {
+    /// Used when the tracker does not send an interval
+    pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(30 * 60);
+    /// Delay before the first retry, doubled after each failed announce
+    pub const RETRY_DELAY: Duration = Duration::from_secs(15);
+
+    /// The first announce is due right away.
+    pub fn new() -> Self {
+        Self {
+            next_announce: Instant::now(),
+            failed_announces: 0,
+        }
+    }
+
+    pub fn is_announce_due(&self) -> bool {
+        self.time_until_announce().is_zero()
+    }
+
+    pub fn time_until_announce(&self) -> Duration {
+        self.next_announce.saturating_duration_since(Instant::now())
+    }
+
+    /// Used for the announces that should not wait for the interval, such as
+    /// the `completed` event.
+    pub fn announce_now(&mut self) {
+        self.next_announce = Instant::now();
+    }
+
+    /// Schedules the next announce after `interval` seconds, but never sooner
+    /// than `min_interval` seconds when the tracker sent one.
+    pub fn announce_succeeded(&mut self, interval: Option<usize>, min_interval: Option<usize>) {
+        let interval = interval
+            .map(|interval| Duration::from_secs(interval as u64))
+            .unwrap_or(Self::DEFAULT_INTERVAL);
+        let min_interval = min_interval
+            .map(|min_interval| Duration::from_secs(min_interval as u64))
+            .unwrap_or_default();
+
+        self.failed_announces = 0;
+        self.next_announce = Instant::now() + interval.max(min_interval);
+    }
+
+    /// Schedules a retry, waiting twice as long as the previous one up to the
+    /// default interval.
+    pub fn announce_failed(&mut self) {
+        let delay = Self::RETRY_DELAY
+            .saturating_mul(2u32.saturating_pow(self.failed_announces))
+            .min(Self::DEFAULT_INTERVAL);
+
+        self.failed_announces = self.failed_announces.saturating_add(1);
+        self.next_announce = Instant::now() + delay;
+    }
+}
+
+impl Default for TrackerScheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,35 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+/// Payload bytes transferred with peers during this session, as reported to
+/// the trackers. Only the data of piece messages is counted, as BEP 3 asks.
+/// Clones share the same counters, so that the TCP threads can update them.
+#[derive(Debug, Clone, Default)]
+pub struct TransferStatistics {
+    uploaded: Arc<AtomicU64>,
+    downloaded: Arc<AtomicU64>,
+}
+
+impl TransferStatistics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn uploaded(&self) -> u64 {
+        self.uploaded.load(Ordering::Relaxed)
+    }
+
+    pub fn downloaded(&self) -> u64 {
+        self.downloaded.load(Ordering::Relaxed)
+    }
+
+    pub fn add_uploaded(&self, bytes: u64) {
+        self.uploaded.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn add_downloaded(&self, bytes: u64) {
+        self.downloaded.fetch_add(bytes, Ordering::Relaxed);
+    }
+}
@@ -1,4 +0,0 @@
-pub trait Wait {
-    fn wait(&mut self);
-    fn reset(&mut self);
-}
@@ -1,5 +1,14 @@
 mod tcp_session;
 pub use tcp_session::TcpSession;
 
-mod message_parser;
-pub use message_parser::MessageParser;
+mod encryption_policy;
+pub use encryption_policy::EncryptionPolicy;
+
+mod mse_handshake;
+pub use mse_handshake::MseHandshake;
+
+mod rc4;
+pub use rc4::Rc4;
+
+mod transport;
+pub use transport::Transport;
@@ -0,0 +1,22 @@
+use clap::ValueEnum;
+
+/// Whether the connections with the peers are encrypted (MSE/PE)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum EncryptionPolicy {
+    /// Plaintext connections only
+    Disabled,
+    /// Encrypted connections, falling back to plaintext
+    Prefer,
+    /// Encrypted connections only
+    Require,
+}
+
+impl EncryptionPolicy {
+    pub fn allows_plaintext(self) -> bool {
+        self != EncryptionPolicy::Require
+    }
+
+    pub fn allows_encryption(self) -> bool {
+        self != EncryptionPolicy::Disabled
+    }
+}
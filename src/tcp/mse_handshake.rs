@@ -0,0 +1,349 @@
+use {
+    crate::{
+        tcp::{EncryptionPolicy, Rc4, Transport},
+        Error,
+    },
+    num_bigint::BigUint,
+    rand::Rng,
+    sha1::{Digest, Sha1},
+    std::time::Duration,
+};
+
+/// Message Stream Encryption key exchange (MSE/PE). Both sides agree on a
+/// Diffie-Hellman secret, then on obfuscating the rest of the connection
+/// with RC4 or leaving it in plaintext.
+#[derive(Debug)]
+pub struct MseHandshake {
+    encryptor: Option<Rc4>,
+    decryptor: Option<Rc4>,
+    /// bytes of the connection received along with the handshake, decrypted
+    payload: Vec<u8>,
+}
+
+impl MseHandshake {
+    /// 768 bits safe prime of the Diffie-Hellman key exchange
+    const PRIME: &'static [u8] =
+        b"FFFFFFFFFFFFFFFFC90FDAA22168C234C4C6628B80DC1CD129024E088A67CC74\
+        020BBEA63B139B22514A08798E3404DDEF9519B3CD3A431B302B0A6DF25F1437\
+        4FE1356D6D51C245E485B576625E7EC6F44C42E9A63A36210000000000090563";
+    const GENERATOR: u32 = 2;
+    const PUBLIC_KEY_LENGTH: usize = 96;
+    const PRIVATE_KEY_LENGTH: usize = 20;
+    /// Verification constant, sent encrypted to check the keys
+    const VC: [u8; 8] = [0; 8];
+    pub const CRYPTO_PLAINTEXT: u32 = 0x01;
+    pub const CRYPTO_RC4: u32 = 0x02;
+    const MAX_PADDING_LENGTH: usize = 512;
+    /// The first bytes of the RC4 keystream are dropped
+    const DISCARDED_KEYSTREAM_LENGTH: usize = 1024;
+    pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Key exchange of the side opening the connection to the peer
+    /// downloading or seeding `info_hash`.
+    pub fn initiate(
+        stream: &mut dyn Transport,
+        info_hash: [u8; 20],
+        policy: EncryptionPolicy,
+    ) -> Result<Self, Error> {
+        Self::set_timeout(stream)?;
+        let private_key = Self::private_key();
+        let mut message = Self::public_key(&private_key);
+        message.extend(Self::padding());
+        Self::write(stream, &message)?;
+
+        let mut received = Vec::new();
+        Self::read_at_least(stream, &mut received, Self::PUBLIC_KEY_LENGTH)?;
+        let secret = Self::shared_secret(&received[..Self::PUBLIC_KEY_LENGTH], &private_key);
+        let mut encryptor = Self::cipher(b"keyA", &secret, &info_hash);
+        let mut decryptor = Self::cipher(b"keyB", &secret, &info_hash);
+
+        let crypto_provide = match policy {
+            EncryptionPolicy::Require => Self::CRYPTO_RC4,
+            _ => Self::CRYPTO_RC4 | Self::CRYPTO_PLAINTEXT,
+        };
+        let mut message = Self::hash(&[b"req1", &secret]).to_vec();
+        message.extend(Self::xor(
+            Self::hash(&[b"req2", &info_hash]),
+            Self::hash(&[b"req3", &secret]),
+        ));
+        let mut encrypted = Self::VC.to_vec();
+        encrypted.extend(crypto_provide.to_be_bytes());
+        encrypted.extend(0u16.to_be_bytes()); // no padding
+        encrypted.extend(0u16.to_be_bytes()); // no initial payload
+        encryptor.apply_keystream(&mut encrypted);
+        message.extend(encrypted);
+        Self::write(stream, &message)?;
+
+        let mut encrypted_vc = Self::VC;
+        decryptor.apply_keystream(&mut encrypted_vc);
+        let mut offset = Self::synchronize(
+            stream,
+            &mut received,
+            Self::PUBLIC_KEY_LENGTH,
+            &encrypted_vc,
+        )?;
+
+        let fields = Self::read_decrypted(stream, &mut received, &mut offset, 6, &mut decryptor)?;
+        let crypto_select = u32::from_be_bytes(fields[0..4].try_into().unwrap());
+        let padding_length = u16::from_be_bytes(fields[4..6].try_into().unwrap()) as usize;
+        if padding_length > Self::MAX_PADDING_LENGTH {
+            return Err(Error::EncryptionSynchronizationFailed);
+        }
+        Self::read_decrypted(
+            stream,
+            &mut received,
+            &mut offset,
+            padding_length,
+            &mut decryptor,
+        )?;
+
+        let is_provided = crypto_select.count_ones() == 1 && crypto_select & crypto_provide != 0;
+        if !is_provided {
+            return Err(Error::NoCommonEncryptionMethod);
+        }
+        Ok(Self::negotiated(
+            crypto_select,
+            encryptor,
+            decryptor,
+            received.split_off(offset),
+        ))
+    }
+
+    /// Key exchange of the side accepting the connection, for the torrent
+    /// `info_hash`. The peer chooses among the methods allowed by `policy`,
+    /// RC4 being preferred.
+    pub fn accept(
+        stream: &mut dyn Transport,
+        info_hash: [u8; 20],
+        policy: EncryptionPolicy,
+    ) -> Result<Self, Error> {
+        Self::set_timeout(stream)?;
+        let mut received = Vec::new();
+        Self::read_at_least(stream, &mut received, Self::PUBLIC_KEY_LENGTH)?;
+
+        let private_key = Self::private_key();
+        let secret = Self::shared_secret(&received[..Self::PUBLIC_KEY_LENGTH], &private_key);
+        let mut message = Self::public_key(&private_key);
+        message.extend(Self::padding());
+        Self::write(stream, &message)?;
+
+        let req1 = Self::hash(&[b"req1", &secret]);
+        let mut offset = Self::synchronize(stream, &mut received, Self::PUBLIC_KEY_LENGTH, &req1)?;
+        Self::read_at_least(stream, &mut received, offset + 20)?;
+        let expected_skey_hash = Self::xor(
+            Self::hash(&[b"req2", &info_hash]),
+            Self::hash(&[b"req3", &secret]),
+        );
+        if received[offset..offset + 20] != expected_skey_hash {
+            return Err(Error::UnknownEncryptedInfoHash);
+        }
+        offset += 20;
+
+        let mut encryptor = Self::cipher(b"keyB", &secret, &info_hash);
+        let mut decryptor = Self::cipher(b"keyA", &secret, &info_hash);
+        let fields = Self::read_decrypted(stream, &mut received, &mut offset, 14, &mut decryptor)?;
+        if fields[0..8] != Self::VC {
+            return Err(Error::EncryptionSynchronizationFailed);
+        }
+        let crypto_provide = u32::from_be_bytes(fields[8..12].try_into().unwrap());
+        let padding_length = u16::from_be_bytes(fields[12..14].try_into().unwrap()) as usize;
+        if padding_length > Self::MAX_PADDING_LENGTH {
+            return Err(Error::EncryptionSynchronizationFailed);
+        }
+        let padding_and_length = Self::read_decrypted(
+            stream,
+            &mut received,
+            &mut offset,
+            padding_length + 2,
+            &mut decryptor,
+        )?;
+        let initial_payload_length =
+            u16::from_be_bytes(padding_and_length[padding_length..].try_into().unwrap()) as usize;
+        let mut payload = Self::read_decrypted(
+            stream,
+            &mut received,
+            &mut offset,
+            initial_payload_length,
+            &mut decryptor,
+        )?;
+
+        let crypto_select = if crypto_provide & Self::CRYPTO_RC4 != 0 && policy.allows_encryption()
+        {
+            Self::CRYPTO_RC4
+        } else if crypto_provide & Self::CRYPTO_PLAINTEXT != 0 && policy.allows_plaintext() {
+            Self::CRYPTO_PLAINTEXT
+        } else {
+            return Err(Error::NoCommonEncryptionMethod);
+        };
+        let mut encrypted = Self::VC.to_vec();
+        encrypted.extend(crypto_select.to_be_bytes());
+        encrypted.extend(0u16.to_be_bytes()); // no padding
+        encryptor.apply_keystream(&mut encrypted);
+        Self::write(stream, &encrypted)?;
+
+        let mut handshake = Self::negotiated(
+            crypto_select,
+            encryptor,
+            decryptor,
+            received.split_off(offset),
+        );
+        payload.append(&mut handshake.payload);
+        handshake.payload = payload;
+
+        Ok(handshake)
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.encryptor.is_some()
+    }
+
+    /// Ciphers of the outgoing and incoming bytes, none in plaintext, and
+    /// the payload already received.
+    pub fn into_parts(self) -> (Option<Rc4>, Option<Rc4>, Vec<u8>) {
+        (self.encryptor, self.decryptor, self.payload)
+    }
+
+    fn negotiated(
+        crypto_select: u32,
+        encryptor: Rc4,
+        mut decryptor: Rc4,
+        mut payload: Vec<u8>,
+    ) -> Self {
+        if crypto_select != Self::CRYPTO_RC4 {
+            return Self {
+                encryptor: None,
+                decryptor: None,
+                payload,
+            };
+        }
+
+        decryptor.apply_keystream(&mut payload);
+        Self {
+            encryptor: Some(encryptor),
+            decryptor: Some(decryptor),
+            payload,
+        }
+    }
+
+    fn private_key() -> BigUint {
+        BigUint::from_bytes_be(&rand::random::<[u8; Self::PRIVATE_KEY_LENGTH]>())
+    }
+
+    fn prime() -> BigUint {
+        BigUint::parse_bytes(Self::PRIME, 16).unwrap()
+    }
+
+    fn public_key(private_key: &BigUint) -> Vec<u8> {
+        let public_key = BigUint::from(Self::GENERATOR).modpow(private_key, &Self::prime());
+        Self::to_key_bytes(&public_key)
+    }
+
+    fn shared_secret(their_public_key: &[u8], private_key: &BigUint) -> Vec<u8> {
+        let secret = BigUint::from_bytes_be(their_public_key).modpow(private_key, &Self::prime());
+        Self::to_key_bytes(&secret)
+    }
+
+    /// Big endian, left padded to the length of the prime
+    fn to_key_bytes(number: &BigUint) -> Vec<u8> {
+        let bytes = number.to_bytes_be();
+        let mut key = vec![0u8; Self::PUBLIC_KEY_LENGTH - bytes.len()];
+        key.extend(bytes);
+
+        key
+    }
+
+    fn padding() -> Vec<u8> {
+        let length = rand::thread_rng().gen_range(0..=Self::MAX_PADDING_LENGTH);
+        (0..length).map(|_| rand::random()).collect()
+    }
+
+    fn hash(parts: &[&[u8]]) -> [u8; 20] {
+        let mut hasher = Sha1::new();
+        parts.iter().for_each(|part| hasher.update(part));
+
+        hasher.finalize().into()
+    }
+
+    fn xor(mut left: [u8; 20], right: [u8; 20]) -> [u8; 20] {
+        left.iter_mut()
+            .zip(right.iter())
+            .for_each(|(left, right)| *left ^= right);
+
+        left
+    }
+
+    fn cipher(name: &[u8], secret: &[u8], info_hash: &[u8; 20]) -> Rc4 {
+        let mut cipher = Rc4::new(&Self::hash(&[name, secret, info_hash]));
+        cipher.discard(Self::DISCARDED_KEYSTREAM_LENGTH);
+
+        cipher
+    }
+
+    fn set_timeout(stream: &dyn Transport) -> Result<(), Error> {
+        stream
+            .set_read_timeout(Some(Self::TIMEOUT))
+            .map_err(|_| Error::EncryptionHandshakeFailed)
+    }
+
+    fn write(stream: &mut dyn Transport, bytes: &[u8]) -> Result<(), Error> {
+        stream
+            .write_all(bytes)
+            .map_err(|_| Error::EncryptionHandshakeFailed)
+    }
+
+    fn read_at_least(
+        stream: &mut dyn Transport,
+        received: &mut Vec<u8>,
+        length: usize,
+    ) -> Result<(), Error> {
+        let mut buffer = [0u8; 1024];
+        while received.len() < length {
+            match stream.read(&mut buffer) {
+                Ok(0) | Err(_) => return Err(Error::EncryptionHandshakeFailed),
+                Ok(read_bytes) => received.extend_from_slice(&buffer[..read_bytes]),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the offset following `pattern`, which has to be found within
+    /// the padding that starts at `from`.
+    fn synchronize(
+        stream: &mut dyn Transport,
+        received: &mut Vec<u8>,
+        from: usize,
+        pattern: &[u8],
+    ) -> Result<usize, Error> {
+        let limit = from + Self::MAX_PADDING_LENGTH + pattern.len();
+        loop {
+            let end = received.len().min(limit);
+            if let Some(position) = received[from..end]
+                .windows(pattern.len())
+                .position(|window| window == pattern)
+            {
+                return Ok(from + position + pattern.len());
+            }
+            if received.len() >= limit {
+                return Err(Error::EncryptionSynchronizationFailed);
+            }
+            Self::read_at_least(stream, received, received.len() + 1)?;
+        }
+    }
+
+    /// Decrypts the next `length` bytes, waiting for them if needed.
+    fn read_decrypted(
+        stream: &mut dyn Transport,
+        received: &mut Vec<u8>,
+        offset: &mut usize,
+        length: usize,
+        decryptor: &mut Rc4,
+    ) -> Result<Vec<u8>, Error> {
+        Self::read_at_least(stream, received, *offset + length)?;
+        let mut bytes = received[*offset..*offset + length].to_vec();
+        decryptor.apply_keystream(&mut bytes);
+        *offset += length;
+
+        Ok(bytes)
+    }
+}
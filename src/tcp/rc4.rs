@@ -0,0 +1,41 @@
+/// RC4 stream cipher, used to obfuscate the connections (MSE/PE).
+#[derive(Debug, Clone)]
+pub struct Rc4 {
+    state: [u8; 256],
+    i: u8,
+    j: u8,
+}
+
+impl Rc4 {
+    pub fn new(key: &[u8]) -> Self {
+        let mut state = [0u8; 256];
+        state
+            .iter_mut()
+            .enumerate()
+            .for_each(|(index, value)| *value = index as u8);
+
+        let mut j: u8 = 0;
+        for i in 0..256 {
+            j = j.wrapping_add(state[i]).wrapping_add(key[i % key.len()]);
+            state.swap(i, j as usize);
+        }
+
+        Self { state, i: 0, j: 0 }
+    }
+
+    /// Encrypts or decrypts `bytes` in place.
+    pub fn apply_keystream(&mut self, bytes: &mut [u8]) {
+        for byte in bytes.iter_mut() {
+            self.i = self.i.wrapping_add(1);
+            self.j = self.j.wrapping_add(self.state[self.i as usize]);
+            self.state.swap(self.i as usize, self.j as usize);
+            let index = self.state[self.i as usize].wrapping_add(self.state[self.j as usize]);
+            *byte ^= self.state[index as usize];
+        }
+    }
+
+    /// Skips the first bytes of the keystream, which leak the key.
+    pub fn discard(&mut self, length: usize) {
+        self.apply_keystream(&mut vec![0u8; length]);
+    }
+}
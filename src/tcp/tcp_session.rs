@@ -1,10 +1,9 @@
 use crate::{
-    pwp::{identity_first_message_type_of, Handshake},
-    KeepAlive,
+    pwp::{Handshake, MessageDecoder},
+    tcp::{EncryptionPolicy, MseHandshake, Rc4, Transport},
+    utp::UtpSocket,
 };
 
-use super::MessageParser;
-
 use {
     crate::{
         http::Peer,
@@ -12,33 +11,213 @@ use {
         Error,
     },
     std::{
+        collections::VecDeque,
         io::{self, prelude::*},
-        net::TcpStream,
-        time::Duration,
+        net::{SocketAddr, TcpStream},
+        os::fd::RawFd,
+        thread,
+        time::{Duration, Instant},
     },
 };
 
 #[derive(Debug)]
 pub struct TcpSession {
-    stream: TcpStream,
+    stream: Box<dyn Transport>,
+    /// ciphers of an encrypted connection, for the sent and received bytes
+    encryptor: Option<Rc4>,
+    decryptor: Option<Rc4>,
+    /// received bytes, decrypted, that were not decoded yet
+    decoder: MessageDecoder,
+    /// bytes of the sent messages, encrypted, that the socket did not take yet
+    outgoing: VecDeque<u8>,
+    /// the peer closed the connection
+    closed: bool,
 }
 
 impl TcpSession {
-    pub fn from_stream(mut stream: TcpStream) -> Result<Self, Error> {
-        Self::set_stream_parameters(&mut stream)?;
+    pub fn from_transport(transport: impl Transport + 'static) -> Result<Self, Error> {
+        let stream: Box<dyn Transport> = Box::new(transport);
+        Self::set_stream_parameters(stream.as_ref())?;
 
-        Ok(Self { stream })
+        Ok(Self {
+            stream,
+            encryptor: None,
+            decryptor: None,
+            decoder: MessageDecoder::new(),
+            outgoing: VecDeque::new(),
+            closed: false,
+        })
     }
 
+    #[cfg(test)]
     pub fn connect(peer: Peer) -> Result<Self, Error> {
         let address = peer.socket_address();
-        let mut stream = TcpStream::connect(address).map_err(|_| Error::FailedToConnectToPeer)?;
-        Self::set_stream_parameters(&mut stream)?;
+        let stream = TcpStream::connect(address).map_err(|_| Error::FailedToConnectToPeer)?;
+
+        Self::from_transport(stream)
+    }
+
+    /// Connects to a peer of the torrent `info_hash`, encrypting the
+    /// connection as allowed by `policy`. When encryption is only preferred,
+    /// a peer failing the key exchange is connected to again in plaintext.
+    #[cfg(test)]
+    pub fn with_encryption(
+        peer: Peer,
+        info_hash: [u8; 20],
+        policy: EncryptionPolicy,
+    ) -> Result<Self, Error> {
+        if !policy.allows_encryption() {
+            return Self::connect(peer);
+        }
+
+        Self::establish(
+            || TcpStream::connect(peer.socket_address()).map_err(|_| Error::FailedToConnectToPeer),
+            peer,
+            info_hash,
+            policy,
+        )
+    }
+
+    /// Same as `with_encryption`, for a connection to `peer` that is already
+    /// open. It is opened again for the plaintext fallback.
+    pub fn with_connected_stream(
+        stream: TcpStream,
+        peer: Peer,
+        info_hash: [u8; 20],
+        policy: EncryptionPolicy,
+    ) -> Result<Self, Error> {
+        stream
+            .set_nonblocking(false)
+            .map_err(|_| Error::FailedToSetSocketAsNonBlocking)?;
+        let mut connected_stream = Some(stream);
+
+        Self::establish(
+            || match connected_stream.take() {
+                Some(stream) => Ok(stream),
+                None => TcpStream::connect_timeout(
+                    &SocketAddr::new(peer.ip(), peer.port()),
+                    MseHandshake::TIMEOUT,
+                )
+                .map_err(|_| Error::FailedToConnectToPeer),
+            },
+            peer,
+            info_hash,
+            policy,
+        )
+    }
+
+    /// Same as `with_encryption`, over a uTP connection of `socket`.
+    pub fn with_utp(
+        socket: &UtpSocket,
+        peer: Peer,
+        info_hash: [u8; 20],
+        policy: EncryptionPolicy,
+    ) -> Result<Self, Error> {
+        Self::establish(
+            || {
+                let address = SocketAddr::new(peer.ip(), peer.port());
+                socket.connect(address, UtpSocket::CONNECT_TIMEOUT)
+            },
+            peer,
+            info_hash,
+            policy,
+        )
+    }
+
+    /// Opens the connection with `connect`, a second time for the plaintext
+    /// fallback.
+    fn establish<T: Transport + 'static>(
+        mut connect: impl FnMut() -> Result<T, Error>,
+        peer: Peer,
+        info_hash: [u8; 20],
+        policy: EncryptionPolicy,
+    ) -> Result<Self, Error> {
+        if !policy.allows_encryption() {
+            return Self::from_transport(connect()?);
+        }
+
+        let encrypted = connect().and_then(|mut stream| {
+            let handshake = MseHandshake::initiate(&mut stream, info_hash, policy)?;
+            Self::from_mse_handshake(stream, handshake)
+        });
+        match encrypted {
+            Err(e) if policy.allows_plaintext() => {
+                log::debug!(
+                    "Encrypted connection with {:?} failed: {:?}, trying plaintext",
+                    peer,
+                    e
+                );
+                Self::from_transport(connect()?)
+            }
+            session => session,
+        }
+    }
+
+    /// Session of a connection initiated by a peer, which starts either with
+    /// a plaintext BitTorrent handshake or with an encryption key exchange.
+    pub fn accept(
+        mut stream: impl Transport + 'static,
+        info_hash: [u8; 20],
+        policy: EncryptionPolicy,
+    ) -> Result<Self, Error> {
+        if Self::starts_with_plaintext_handshake(&stream)? {
+            if !policy.allows_plaintext() {
+                return Err(Error::PlaintextConnectionRefused);
+            }
+            return Self::from_transport(stream);
+        }
+
+        if !policy.allows_encryption() {
+            return Err(Error::EncryptedConnectionRefused);
+        }
+        let handshake = MseHandshake::accept(&mut stream, info_hash, policy)?;
+
+        Self::from_mse_handshake(stream, handshake)
+    }
+
+    fn from_mse_handshake(
+        stream: impl Transport + 'static,
+        handshake: MseHandshake,
+    ) -> Result<Self, Error> {
+        log::debug!("Encryption negotiated: {}", handshake.is_encrypted());
+        let (encryptor, decryptor, payload) = handshake.into_parts();
+        let mut session = Self::from_transport(stream)?;
+        session.encryptor = encryptor;
+        session.decryptor = decryptor;
+        session.decoder.feed(&payload);
 
-        Ok(Self { stream })
+        Ok(session)
     }
 
-    fn set_stream_parameters(stream: &mut TcpStream) -> Result<(), Error> {
+    /// Waits for the length and the protocol name of a handshake.
+    fn starts_with_plaintext_handshake(stream: &dyn Transport) -> Result<bool, Error> {
+        let mut first_bytes =
+            [0u8; 1 + Handshake::BITTORRENT_VERSION_1_PROTOCOL_NAME_LENGTH as usize];
+        let deadline = Instant::now() + MseHandshake::TIMEOUT;
+        stream
+            .set_read_timeout(Some(MseHandshake::TIMEOUT))
+            .map_err(|_| Error::FailedToPeekData)?;
+
+        loop {
+            let peeked_bytes = stream
+                .peek(&mut first_bytes)
+                .map_err(|_| Error::FailedToPeekData)?;
+            if peeked_bytes == 0 {
+                return Err(Error::FailedToPeekData);
+            }
+            if peeked_bytes == first_bytes.len() || Instant::now() > deadline {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        Ok(
+            first_bytes[0] == Handshake::BITTORRENT_VERSION_1_PROTOCOL_NAME_LENGTH
+                && first_bytes[1..] == *Handshake::BITTORRENT_VERSION_1_PROTOCOL_NAME.as_bytes(),
+        )
+    }
+
+    fn set_stream_parameters(stream: &dyn Transport) -> Result<(), Error> {
         stream
             .set_nonblocking(true)
             .map_err(|_| Error::FailedToSetSocketAsNonBlocking)?;
@@ -54,85 +233,90 @@ impl TcpSession {
         Ok(())
     }
 
-    pub fn stream(&self) -> &TcpStream {
-        &self.stream
+    pub fn is_encrypted(&self) -> bool {
+        self.encryptor.is_some()
     }
 
-    /// Returns the number of bytes sent
-    pub fn send(&self, bittorrent_message: impl IntoBytes) -> Result<usize, io::Error> {
-        self.stream().write(&(bittorrent_message.into_bytes()))
+    /// Socket of the connection, to wait for its readiness. None for uTP
+    /// connections.
+    pub fn raw_fd(&self) -> Option<RawFd> {
+        self.stream.raw_fd()
     }
 
-    pub fn read_buffer(&self, size: usize) -> Result<Vec<u8>, Error> {
-        let mut bytes = vec![0u8; size];
-        let bytes_peeked = self
-            .stream()
-            .peek(&mut bytes)
-            .map_err(|_| Error::FailedToPeekData)?;
-
-        if bytes_peeked == size {
-            self.stream()
-                .read(&mut bytes)
-                .map_err(|_| Error::FailedToReadFromSocket)?;
-
-            Ok(bytes)
-        } else {
-            Err(Error::NotEnoughBytesToRead)
+    /// Queues the message and sends what the socket takes right away.
+    /// Returns the number of bytes of the message.
+    pub fn send(&mut self, bittorrent_message: impl IntoBytes) -> Result<usize, io::Error> {
+        let mut bytes = bittorrent_message.into_bytes();
+        if let Some(encryptor) = &mut self.encryptor {
+            encryptor.apply_keystream(&mut bytes);
         }
+        self.outgoing.extend(&bytes);
+        self.flush()?;
+
+        Ok(bytes.len())
     }
 
-    /// Write the received bytes in the buffer
-    /// Returns the received BitTorrent message or None (if there is no data in the buffer)
-    pub fn receive(&mut self) -> Result<Option<Message>, Error> {
-        // check if it is a handshake
-        // PWP message are all starting with a 4 bytes representing the message length
-        let mut zero_to_third_read_bytes: [u8; 4] = [0; 4];
-        let number_of_bytes_read = match self.stream.peek(&mut zero_to_third_read_bytes) {
-            Ok(read_bytes) => read_bytes,
-            Err(_) => return Ok(None),
-        };
-        if number_of_bytes_read == 0 {
-            return Ok(None);
+    /// Sends the queued bytes until the socket would block.
+    pub fn flush(&mut self) -> Result<(), io::Error> {
+        while !self.outgoing.is_empty() {
+            match self.stream.write(self.outgoing.as_slices().0) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(written_bytes) => {
+                    self.outgoing.drain(..written_bytes);
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => return Err(e),
+            }
         }
 
-        // Keep alive (PWP protocol) handling
-        if zero_to_third_read_bytes == [0, 0, 0, 0] {
-            // Keep alive message case
-            return Ok(Some(Message::KeepAlive(KeepAlive::new())));
-        }
+        Ok(())
+    }
 
-        // Handshake handling
-        let mut zero_to_fourth_read_bytes: [u8; 5] = [0; 5];
-        let number_of_bytes_read = match self.stream.peek(&mut zero_to_fourth_read_bytes) {
-            Ok(read_bytes) => read_bytes,
-            Err(_) => return Ok(None),
-        };
+    /// Number of bytes queued and not sent yet
+    pub fn pending_send_length(&self) -> usize {
+        self.outgoing.len()
+    }
 
-        if number_of_bytes_read == 0 {
-            return Ok(None);
+    /// Moves the bytes available on the socket to the decoder, decrypting
+    /// them.
+    fn fill_received_buffer(&mut self) {
+        let mut buffer = [0u8; 16 * 1024];
+        loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => {
+                    self.closed = true;
+                    return;
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return,
+                Err(_) => {
+                    self.closed = true;
+                    return;
+                }
+                Ok(read_bytes) => {
+                    let bytes = &mut buffer[..read_bytes];
+                    if let Some(decryptor) = &mut self.decryptor {
+                        decryptor.apply_keystream(bytes);
+                    }
+                    self.decoder.feed(bytes);
+                }
+            }
         }
+    }
 
-        let mut handshake_protocol_name = Handshake::BITTORRENT_VERSION_1_PROTOCOL_NAME.chars();
-        let expected_four_first_byte_of_handshake = [
-            Handshake::BITTORRENT_VERSION_1_PROTOCOL_NAME_LENGTH,
-            handshake_protocol_name.next().unwrap() as u8,
-            handshake_protocol_name.next().unwrap() as u8,
-            handshake_protocol_name.next().unwrap() as u8,
-            handshake_protocol_name.next().unwrap() as u8,
-        ];
-        if zero_to_fourth_read_bytes == expected_four_first_byte_of_handshake {
-            return MessageParser::parse_handshake_message(&self);
+    /// Returns the next received BitTorrent message, or None until one is
+    /// complete. Fails once the messages sent before the peer closed the
+    /// connection were returned.
+    pub fn receive(&mut self) -> Result<Option<Message>, Error> {
+        if let Some(message) = self.decoder.decode()? {
+            return Ok(Some(message));
         }
+        self.fill_received_buffer();
 
-        // PWP messages
-        // if not handshake, it is probably a Peer Wire Protocol message
-        match identity_first_message_type_of(&zero_to_fourth_read_bytes) {
-            Ok(message) => MessageParser::parse_message(&self, message),
-            Err(error) => {
-                log::error!("Unexpected TCP data: {:?}", zero_to_fourth_read_bytes);
-
-                Err(error)
-            }
+        match self.decoder.decode()? {
+            None if self.closed => Err(Error::PeerClosedConnection),
+            message => Ok(message),
         }
     }
 }
@@ -0,0 +1,51 @@
+use std::{
+    fmt::Debug,
+    io::{self, Read, Write},
+    net::TcpStream,
+    os::fd::{AsRawFd, RawFd},
+    time::Duration,
+};
+
+/// Byte stream carrying a session with a peer, over TCP or uTP.
+pub trait Transport: Read + Write + Send + Debug {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()>;
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+
+    fn set_nodelay(&self, nodelay: bool) -> io::Result<()>;
+
+    /// Copies the first received bytes without consuming them.
+    fn peek(&self, bytes: &mut [u8]) -> io::Result<usize>;
+
+    /// Socket to wait on for readiness, None when the transport is not a
+    /// socket of the system.
+    fn raw_fd(&self) -> Option<RawFd>;
+}
+
+impl Transport for TcpStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        TcpStream::set_nonblocking(self, nonblocking)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_write_timeout(self, timeout)
+    }
+
+    fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+        TcpStream::set_nodelay(self, nodelay)
+    }
+
+    fn peek(&self, bytes: &mut [u8]) -> io::Result<usize> {
+        TcpStream::peek(self, bytes)
+    }
+
+    fn raw_fd(&self) -> Option<RawFd> {
+        Some(self.as_raw_fd())
+    }
+}
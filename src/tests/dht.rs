@@ -0,0 +1,261 @@
+#[cfg(test)]
+mod test {
+    use crate::{
+        dht::{
+            DhtNode, KrpcMessage, KrpcQuery, KrpcResponse, NodeId, NodeInfo, RoutingTable,
+            TokenSecret,
+        },
+        http::Peer,
+        pwp::{FromBytes, IntoBytes},
+        Error,
+    };
+    use std::{
+        env, fs,
+        net::{IpAddr, Ipv4Addr, SocketAddr},
+        time::{Duration, Instant},
+    };
+
+    const QUERYING_NODE: &[u8; 20] = b"abcdefghij0123456789";
+    const QUERIED_NODE: &[u8; 20] = b"mnopqrstuvwxyz123456";
+
+    fn node(id: [u8; 20], port: u16) -> NodeInfo {
+        NodeInfo::new(NodeId::new(id), SocketAddr::from(([127, 0, 0, 1], port)))
+    }
+
+    /// Id sharing exactly `prefix_length` leading bits with the zero id
+    fn id_with_prefix(prefix_length: usize, last_byte: u8) -> [u8; 20] {
+        let mut id = [0u8; 20];
+        id[prefix_length / 8] = 0x80 >> (prefix_length % 8);
+        id[19] |= last_byte;
+        id
+    }
+
+    fn assert_round_trip(message: KrpcMessage, bencode: &[u8]) {
+        assert_eq!(message.clone().into_bytes(), bencode);
+        assert_eq!(KrpcMessage::from_bytes(bencode).unwrap().0, message);
+    }
+
+    #[test]
+    fn encode_queries_as_in_the_specification() {
+        let sender = NodeId::new(*QUERYING_NODE);
+        let query = |query| KrpcMessage::Query {
+            transaction_id: b"aa".to_vec(),
+            sender,
+            query,
+        };
+
+        assert_round_trip(
+            query(KrpcQuery::Ping),
+            b"d1:ad2:id20:abcdefghij0123456789e1:q4:ping1:t2:aa1:y1:qe",
+        );
+        assert_round_trip(
+            query(KrpcQuery::FindNode {
+                target: NodeId::new(*QUERIED_NODE),
+            }),
+            b"d1:ad2:id20:abcdefghij01234567896:target20:mnopqrstuvwxyz123456e\
+              1:q9:find_node1:t2:aa1:y1:qe",
+        );
+        assert_round_trip(
+            query(KrpcQuery::GetPeers {
+                info_hash: *QUERIED_NODE,
+            }),
+            b"d1:ad2:id20:abcdefghij01234567899:info_hash20:mnopqrstuvwxyz123456e\
+              1:q9:get_peers1:t2:aa1:y1:qe",
+        );
+        assert_round_trip(
+            query(KrpcQuery::AnnouncePeer {
+                info_hash: *QUERIED_NODE,
+                port: 6881,
+                implied_port: true,
+                token: b"aoeusnth".to_vec(),
+            }),
+            b"d1:ad2:id20:abcdefghij012345678912:implied_porti1e\
+              9:info_hash20:mnopqrstuvwxyz1234564:porti6881e5:token8:aoeusnthe\
+              1:q13:announce_peer1:t2:aa1:y1:qe",
+        );
+    }
+
+    #[test]
+    fn encode_responses_and_errors() {
+        assert_round_trip(
+            KrpcMessage::Response {
+                transaction_id: b"aa".to_vec(),
+                response: KrpcResponse::empty(NodeId::new(*QUERIED_NODE)),
+            },
+            b"d1:rd2:id20:mnopqrstuvwxyz123456e1:t2:aa1:y1:re",
+        );
+        assert_round_trip(
+            KrpcMessage::Error {
+                transaction_id: b"aa".to_vec(),
+                code: 201,
+                message: "A Generic Error Ocurred".to_string(),
+            },
+            b"d1:eli201e23:A Generic Error Ocurrede1:t2:aa1:y1:ee",
+        );
+
+        let peer = Peer::from_socket_address(SocketAddr::from(([10, 0, 0, 1], 6881)));
+        let response = KrpcMessage::Response {
+            transaction_id: b"aa".to_vec(),
+            response: KrpcResponse::new(
+                NodeId::new(*QUERIED_NODE),
+                vec![node(*QUERYING_NODE, 6882)],
+                vec![peer],
+                Some(b"aoeusnth".to_vec()),
+            ),
+        };
+        let bytes = response.clone().into_bytes();
+        assert_eq!(KrpcMessage::from_bytes(&bytes).unwrap().0, response);
+    }
+
+    #[test]
+    fn reject_unknown_query() {
+        let bencode = b"d1:ad2:id20:abcdefghij0123456789e1:q4:vote1:t2:aa1:y1:qe";
+        assert!(KrpcMessage::from_bytes(bencode).is_err());
+    }
+
+    #[test]
+    fn routing_table_keeps_k_nodes_per_bucket() {
+        let mut routing_table = RoutingTable::new(NodeId::new([0; 20]));
+        let start = Instant::now();
+
+        for index in 0..RoutingTable::K as u8 {
+            assert!(routing_table.insert(node(id_with_prefix(0, index), 1000), start));
+        }
+        assert!(!routing_table.insert(node(id_with_prefix(0, 0xFF), 1000), start));
+        assert!(routing_table.insert(node(id_with_prefix(5, 1), 1000), start));
+        assert!(!routing_table.insert(node([0; 20], 1000), start));
+        assert_eq!(routing_table.len(), RoutingTable::K + 1);
+
+        // the least recently seen node is replaced once questionable
+        let later = start + RoutingTable::QUESTIONABLE_AFTER;
+        assert!(routing_table.insert(node(id_with_prefix(0, 0xFF), 1000), later));
+        assert_eq!(routing_table.len(), RoutingTable::K + 1);
+        assert!(!routing_table
+            .nodes()
+            .contains(&node(id_with_prefix(0, 0), 1000)));
+    }
+
+    #[test]
+    fn routing_table_returns_the_closest_nodes() {
+        let mut routing_table = RoutingTable::new(NodeId::new([0; 20]));
+        let now = Instant::now();
+        for prefix_length in [1, 3, 8, 40] {
+            routing_table.insert(node(id_with_prefix(prefix_length, 0), 1000), now);
+        }
+
+        let target = NodeId::new(id_with_prefix(8, 1));
+        let closest: Vec<NodeId> = routing_table
+            .closest(&target, 2)
+            .iter()
+            .map(NodeInfo::id)
+            .collect();
+        assert_eq!(
+            closest,
+            vec![
+                NodeId::new(id_with_prefix(8, 0)),
+                NodeId::new(id_with_prefix(40, 0))
+            ]
+        );
+    }
+
+    #[test]
+    fn routing_table_persists_across_runs() {
+        let path = env::temp_dir().join(format!("torrust_dht_{}", rand::random::<u32>()));
+        let mut routing_table = RoutingTable::new(NodeId::random());
+        routing_table.insert(node(*QUERYING_NODE, 6881), Instant::now());
+        routing_table.insert(node(*QUERIED_NODE, 6882), Instant::now());
+
+        routing_table.save(&path).unwrap();
+        let loaded = RoutingTable::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.own_id(), routing_table.own_id());
+        let mut nodes = loaded.nodes();
+        nodes.sort_by_key(|node| node.address().port());
+        assert_eq!(
+            nodes,
+            vec![node(*QUERYING_NODE, 6881), node(*QUERIED_NODE, 6882)]
+        );
+    }
+
+    #[test]
+    fn tokens_are_valid_for_the_same_address_until_the_secret_changes_twice() {
+        let start = Instant::now();
+        let mut secret = TokenSecret::new(start);
+        let ip = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        let other_ip = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+
+        let token = secret.token(ip, start);
+        assert!(secret.is_valid(&token, ip, start));
+        assert!(!secret.is_valid(&token, other_ip, start));
+        assert!(secret.is_valid(&token, ip, start + TokenSecret::LIFETIME));
+        assert!(!secret.is_valid(&token, ip, start + TokenSecret::LIFETIME * 2));
+    }
+
+    fn start_cluster(size: usize) -> Vec<DhtNode> {
+        let address = SocketAddr::from(([127, 0, 0, 1], 0));
+        let nodes: Vec<DhtNode> = (0..size)
+            .map(|_| {
+                let routing_table = RoutingTable::new(NodeId::random());
+                DhtNode::with_timeout(address, routing_table, Duration::from_millis(500)).unwrap()
+            })
+            .collect();
+
+        let bootstrap_node = [nodes[0].local_address()];
+        for node in &nodes[1..] {
+            assert!(node.bootstrap(&bootstrap_node) > 0);
+        }
+
+        nodes
+    }
+
+    #[test]
+    fn cluster_nodes_find_each_other() {
+        let nodes = start_cluster(5);
+
+        let found = nodes[4]
+            .find_node(nodes[0].local_address(), nodes[1].id())
+            .unwrap();
+        assert!(found
+            .iter()
+            .any(|node| node.id() == nodes[1].id() && node.address() == nodes[1].local_address()));
+        assert!(nodes[0].routing_table().len() >= 4);
+    }
+
+    #[test]
+    fn cluster_nodes_find_announced_peers() {
+        let nodes = start_cluster(5);
+        let info_hash = *QUERIED_NODE;
+
+        assert!(nodes[1].announce(info_hash, 6881).is_empty());
+        let peers = nodes[3].announce(info_hash, 7000);
+
+        let announced = Peer::from_socket_address(SocketAddr::from(([127, 0, 0, 1], 6881)));
+        assert_eq!(peers, vec![announced]);
+    }
+
+    #[test]
+    fn announce_with_bad_token_is_refused() {
+        let nodes = start_cluster(2);
+
+        let result = nodes[1].announce_peer(
+            nodes[0].local_address(),
+            *QUERIED_NODE,
+            6881,
+            b"forged".to_vec(),
+        );
+        assert!(matches!(result, Err(Error::DhtNodeReturnedAnError)));
+    }
+
+    #[test]
+    fn query_to_silent_node_times_out() {
+        let silent = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let routing_table = RoutingTable::new(NodeId::random());
+        let address = SocketAddr::from(([127, 0, 0, 1], 0));
+        let node =
+            DhtNode::with_timeout(address, routing_table, Duration::from_millis(100)).unwrap();
+
+        let result = node.get_peers(silent.local_addr().unwrap(), *QUERIED_NODE);
+        assert!(matches!(result, Err(Error::DhtNodeDidNotAnswer)));
+    }
+}
@@ -1,8 +1,12 @@
 #[cfg(test)]
 mod tests {
-    use crate::tests::pwp::unittest;
-    use crate::{BlockReaderWriter, Torrent};
-    use std::{fs, path::Path};
+    use crate::tests::{
+        pwp::unittest,
+        torrent::test::{multi_file_torrent_bencode, single_file_torrent_bencode},
+    };
+    use crate::{file_management::is_piece_valid, BlockReaderWriter, Torrent};
+    use bendy::decoding::Decoder;
+    use std::{env, fs, path::Path};
 
     #[test]
     fn read_and_write_pieces() {
@@ -11,7 +15,8 @@ mod tests {
         let filesize = block_length * 6 + extra_bytes;
 
         let filename = Path::new("with_offset.jpg");
-        let file = BlockReaderWriter::new(filename, block_length * 2, filesize as usize).unwrap();
+        let file =
+            BlockReaderWriter::new(filename, block_length as u64 * 2, filesize as u64).unwrap();
 
         let piece_1_block_1 = vec![0xBB; block_length as usize];
         file.write(1, 0, &piece_1_block_1).unwrap();
@@ -88,7 +93,7 @@ mod tests {
         let size = torrent.total_length_in_bytes();
         let jpg_file = Path::new("samples/upload/venon.jpg");
 
-        BlockReaderWriter::new(jpg_file, piece_length, size as usize).unwrap()
+        BlockReaderWriter::new(jpg_file, piece_length, size).unwrap()
     }
 
     #[test]
@@ -105,4 +110,115 @@ mod tests {
         let offset = BlockReaderWriter::calculate_offset(1, 32 * 1024, 16384);
         assert_eq!(offset, 49152);
     }
+
+    #[test]
+    fn read_and_write_blocks_across_files() {
+        let block_length = BlockReaderWriter::BIT_TORRENT_BLOCK_SIZE as u32;
+        let bencode = multi_file_torrent_bencode(block_length * 2);
+        let torrent = Torrent::from_bencode(&mut Decoder::new(&bencode)).unwrap();
+
+        let working_directory = env::temp_dir().join("torrust_read_and_write_blocks_across_files");
+        let _ = fs::remove_dir_all(&working_directory);
+        fs::create_dir_all(&working_directory).unwrap();
+        let file = BlockReaderWriter::from_torrent(&torrent, &working_directory).unwrap();
+
+        // The first file is 20000 bytes long, so the second block of the
+        // first piece spans both files.
+        let piece_0_block_1 = vec![0xAA; block_length as usize];
+        file.write(0, 0, &piece_0_block_1).unwrap();
+        let piece_0_block_2 = vec![0xBB; block_length as usize];
+        file.write(0, block_length, &piece_0_block_2).unwrap();
+        let piece_1_block_1 = vec![0xCC; block_length as usize];
+        file.write(1, 0, &piece_1_block_1).unwrap();
+        let last_block = vec![0xDD; 50000 - 3 * block_length as usize];
+        file.write(1, block_length, &last_block).unwrap();
+
+        assert_eq!(file.read(0, 0).unwrap(), piece_0_block_1);
+        assert_eq!(file.read(0, block_length).unwrap(), piece_0_block_2);
+        assert_eq!(file.read(1, 0).unwrap(), piece_1_block_1);
+        assert_eq!(file.read(1, block_length).unwrap(), last_block);
+
+        let root = working_directory.join("release");
+        let first_file = fs::read(root.join("first.bin")).unwrap();
+        let second_file = fs::read(root.join("nested").join("second.bin")).unwrap();
+        assert_eq!(first_file.len(), 20000);
+        assert_eq!(second_file.len(), 30000);
+        assert_eq!(first_file[block_length as usize], 0xBB);
+        assert_eq!(second_file[0], 0xBB);
+        assert_eq!(second_file[29999], 0xDD);
+
+        assert!(file.write(1, block_length * 2, &[0xEE]).is_err());
+        assert!(file.read(2, 0).is_err());
+
+        fs::remove_dir_all(&working_directory).unwrap();
+    }
+
+    #[test]
+    fn read_and_write_blocks_beyond_four_gigabytes() {
+        let block_length = BlockReaderWriter::BIT_TORRENT_BLOCK_SIZE as u32;
+        let total_length = 5 * 1024 * 1024 * 1024 + 1000;
+        let bencode = single_file_torrent_bencode("sparse.img", total_length, 1024 * 1024);
+        let torrent = Torrent::from_bencode(&mut Decoder::new(&bencode)).unwrap();
+
+        let working_directory = env::temp_dir().join("torrust_read_and_write_beyond_4_gib");
+        let _ = fs::remove_dir_all(&working_directory);
+        fs::create_dir_all(&working_directory).unwrap();
+        let file = BlockReaderWriter::from_torrent(&torrent, &working_directory).unwrap();
+
+        // Piece 4096 begins exactly at 4 GiB
+        let block_at_four_gigabytes = vec![0xAA; block_length as usize];
+        file.write(4096, block_length, &block_at_four_gigabytes)
+            .unwrap();
+        let last_block = vec![0xBB; 1000];
+        file.write(5120, 0, &last_block).unwrap();
+
+        assert_eq!(
+            file.read(4096, block_length).unwrap(),
+            block_at_four_gigabytes
+        );
+        assert_eq!(file.read(5120, 0).unwrap(), last_block);
+        assert!(file.write(5120, 1000, &[0xCC]).is_err());
+
+        let filepath = working_directory.join("sparse.img");
+        assert_eq!(fs::metadata(filepath).unwrap().len(), total_length);
+
+        fs::remove_dir_all(&working_directory).unwrap();
+    }
+
+    #[test]
+    fn calculate_offset_beyond_four_gigabytes() {
+        let offset = BlockReaderWriter::calculate_offset(4096, 1024 * 1024, 0);
+        assert_eq!(offset, 4 * 1024 * 1024 * 1024);
+
+        let offset = BlockReaderWriter::calculate_offset(u32::MAX, 1024 * 1024, 16384);
+        assert_eq!(offset, u32::MAX as u64 * 1024 * 1024 + 16384);
+    }
+
+    #[test]
+    fn verify_piece_hashes() {
+        let torrent = Torrent::from_file(Path::new("samples/upload/iceberg.jpg.torrent")).unwrap();
+        let working_directory = env::temp_dir().join("torrust_verify_piece_hashes");
+        let _ = fs::remove_dir_all(&working_directory);
+        fs::create_dir_all(&working_directory).unwrap();
+        fs::copy(
+            "scripts/multi-client/iceberg.jpg",
+            working_directory.join("iceberg.jpg"),
+        )
+        .unwrap();
+
+        let file = BlockReaderWriter::from_torrent(&torrent, &working_directory).unwrap();
+        for piece_index in 0..torrent.number_of_pieces() {
+            assert!(is_piece_valid(&torrent, &file, piece_index));
+        }
+
+        let mut corrupted_block = file.read(3, 0).unwrap();
+        corrupted_block[42] ^= 0xFF;
+        file.write(3, 0, &corrupted_block).unwrap();
+
+        assert!(!is_piece_valid(&torrent, &file, 3));
+        assert!(is_piece_valid(&torrent, &file, 2));
+        assert!(!is_piece_valid(&torrent, &file, torrent.number_of_pieces()));
+
+        fs::remove_dir_all(&working_directory).unwrap();
+    }
 }
@@ -1,6 +1,13 @@
 #[cfg(test)]
 mod test {
-    use crate::http::{Event, TrackerRequest, TrackerResponse};
+    use crate::{
+        http::{
+            Event, Peer, ScrapeRequest, ScrapeResponse, ScrapeStatistics, TrackerAddress,
+            TrackerList, TrackerRequest, TrackerResponse,
+        },
+        Error,
+    };
+    use std::net::SocketAddr;
 
     static INFO_ID: [u8; 20] = [
         0x06, 0x71, 0x33, 0xAC, 0xE5, 0xDD, 0x0C, 0x50, 0x27, 0xB9, 0x9D, 0xE5, 0xD4, 0xBA, 0x51,
@@ -17,9 +24,10 @@ mod test {
 
     #[test]
     fn request_into_url() {
+        let tracker = tracker_address();
         let url_builder = |event| {
             TrackerRequest::new(INFO_ID, PEER_ID, 6882, 0, 0, 356639, true, Some(event))
-                .into_url(TRACKER_HOSTNAME, TRACKER_PORT)
+                .into_url(&tracker)
                 .unwrap()
         };
 
@@ -42,6 +50,35 @@ mod test {
                                     &port=6882&uploaded=0&downloaded=0&left=356639&compact=1&event=completed");
     }
 
+    #[test]
+    fn request_into_url_keeps_the_announce_path_and_query() {
+        let url = |announce: &str| {
+            let tracker = TrackerAddress::from_announce(announce).unwrap();
+            TrackerRequest::new(INFO_ID, PEER_ID, 6882, 0, 0, 0, true, None)
+                .into_url(&tracker)
+                .unwrap()
+        };
+
+        let with_path = url("https://example.org/a/0123456789abcdef/announce");
+        assert_eq!(with_path.scheme(), "https");
+        assert_eq!(with_path.path(), "/a/0123456789abcdef/announce");
+
+        let with_query = url("http://example.org:2710/announce.php?passkey=0123456789abcdef");
+        assert_eq!(with_query.path(), "/announce.php");
+        assert!(with_query
+            .query()
+            .unwrap()
+            .starts_with("passkey=0123456789abcdef&info_hash="));
+    }
+
+    fn tracker_address() -> TrackerAddress {
+        TrackerAddress::from_announce(&format!(
+            "http://{}:{}/announce",
+            TRACKER_HOSTNAME, TRACKER_PORT
+        ))
+        .unwrap()
+    }
+
     // This test is ignored because it requires a tracker running on 127.0.0.1:6969.
     // It actually does not test anything, since the response has to be analysed
     // manually and also depends on the file being served. I'm only keeping this
@@ -52,7 +89,7 @@ mod test {
     fn tracker_http_request() {
         let event = Event::Started;
         let request = TrackerRequest::new(INFO_ID, PEER_ID, 6882, 0, 0, 356639, true, Some(event));
-        let url = request.into_url(TRACKER_HOSTNAME, TRACKER_PORT).unwrap();
+        let url = request.into_url(&tracker_address()).unwrap();
 
         let mut response = reqwest::blocking::get(url).unwrap();
         let mut bencode = Vec::new();
@@ -61,4 +98,195 @@ mod test {
         let parsed_response = TrackerResponse::from_bencode(&bencode);
         println!("{:?}", parsed_response);
     }
+
+    fn tiers(tiers: &[&[&str]]) -> Vec<Vec<String>> {
+        tiers
+            .iter()
+            .map(|tier| tier.iter().map(|tracker| tracker.to_string()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn fall_back_to_next_tier_and_promote_tracker() {
+        let mut tracker_list = TrackerList::new(tiers(&[&["a"], &["b", "c", "d"]]));
+        let mut tried = Vec::new();
+
+        let response = tracker_list.announce(|tracker| {
+            tried.push(tracker.to_string());
+            match tracker {
+                "a" => Err(Error::TrackerConnectionNotPossible),
+                "b" => Ok(TrackerResponse::from_failure(
+                    "unregistered torrent".to_string(),
+                )),
+                _ => Ok(TrackerResponse::new(1800, 0, 0, vec![])),
+            }
+        });
+
+        assert_eq!(response.unwrap().interval(), Some(1800));
+        assert_eq!(tried, vec!["a", "b", "c"]);
+        assert_eq!(tracker_list.tiers(), &tiers(&[&["a"], &["c", "b", "d"]]));
+    }
+
+    #[test]
+    fn fail_when_every_tracker_fails() {
+        let mut tracker_list = TrackerList::new(tiers(&[&["a"], &["b"]]));
+
+        let response = tracker_list.announce(|tracker| match tracker {
+            "a" => Ok(TrackerResponse::from_failure(
+                "unregistered torrent".to_string(),
+            )),
+            _ => Err(Error::TrackerConnectionNotPossible),
+        });
+
+        assert!(matches!(response, Err(Error::TrackerConnectionNotPossible)));
+        assert_eq!(tracker_list.tiers(), &tiers(&[&["a"], &["b"]]));
+    }
+
+    #[test]
+    fn parse_min_interval() {
+        let bencode =
+            b"d8:completei2e10:incompletei1e8:intervali1800e12:min intervali900e5:peers0:e";
+        let response = TrackerResponse::from_bencode(bencode).unwrap();
+
+        assert_eq!(response.interval(), Some(1800));
+        assert_eq!(response.min_interval(), Some(900));
+        assert_eq!(response.peers(), Some(&vec![]));
+    }
+
+    #[test]
+    fn scrape_path_follows_the_announce_convention() {
+        let scrape_path = |announce: &str| {
+            TrackerAddress::from_announce(announce)
+                .unwrap()
+                .scrape_path()
+        };
+
+        assert_eq!(
+            scrape_path("http://example.org/announce").unwrap(),
+            "/scrape"
+        );
+        assert_eq!(
+            scrape_path("http://example.org/x/announce.php").unwrap(),
+            "/x/scrape.php"
+        );
+        assert_eq!(
+            scrape_path("http://example.org/announce?key=abc").unwrap(),
+            "/scrape"
+        );
+        assert!(matches!(
+            scrape_path("http://example.org/a"),
+            Err(Error::TrackerDoesNotSupportScrape)
+        ));
+        assert!(matches!(
+            scrape_path("http://example.org/announce/x"),
+            Err(Error::TrackerDoesNotSupportScrape)
+        ));
+    }
+
+    #[test]
+    fn scrape_request_into_url() {
+        let tracker = TrackerAddress::from_announce("https://example.org/announce").unwrap();
+        let url = ScrapeRequest::new(vec![INFO_ID, [0x41; 20]])
+            .into_url(&tracker)
+            .unwrap();
+
+        assert_eq!(
+            url.as_str(),
+            "https://example.org/scrape?\
+             info_hash=%06q3%AC%E5%DD%0CP%27%B9%9D%E5%D4%BAQ%28%28%20%8D%5B\
+             &info_hash=AAAAAAAAAAAAAAAAAAAA"
+        );
+    }
+
+    #[test]
+    fn parse_scrape_response() {
+        let mut bencode = b"d5:filesd20:".to_vec();
+        bencode.extend(INFO_ID);
+        bencode.extend(b"d8:completei5e10:downloadedi50e10:incompletei10e4:name3:fooee");
+        bencode.extend(b"8:intervali1800ee");
+        let response = ScrapeResponse::from_bencode(&bencode).unwrap();
+
+        assert_eq!(response.failure(), None);
+        assert_eq!(
+            response.statistics(&INFO_ID),
+            Some(&ScrapeStatistics::new(5, 50, 10))
+        );
+        assert_eq!(response.statistics(&PEER_ID), None);
+    }
+
+    #[test]
+    fn parse_scrape_failure() {
+        let response = ScrapeResponse::from_bencode(b"d14:failure reason9:forbiddene").unwrap();
+
+        assert_eq!(response.failure(), Some(&"forbidden".to_string()));
+        assert_eq!(response.statistics(&INFO_ID), None);
+    }
+
+    fn peer(address: &str) -> Peer {
+        Peer::from_socket_address(address.parse::<SocketAddr>().unwrap())
+    }
+
+    #[test]
+    fn parse_dictionary_peer_list() {
+        let bencode = b"d8:intervali1800e5:peersld2:ip8:10.0.0.17:peer id20:\
+                        AAAAAAAAAAAAAAAAAAAA4:porti6881eed2:ip11:2001:db8::24:porti6882eed\
+                        2:ip9:localhost4:porti6883eeee";
+        let response = TrackerResponse::from_bencode(bencode).unwrap();
+
+        let peers = response.peers().unwrap();
+        assert_eq!(peers.len(), 3);
+        assert_eq!(peers[0], peer("10.0.0.1:6881"));
+        assert_eq!(peers[1], peer("[2001:db8::2]:6882"));
+        assert_eq!(
+            peers[2].socket_address().rsplit_once(':').unwrap().1,
+            "6883"
+        );
+    }
+
+    #[test]
+    fn parse_compact_ipv6_peers() {
+        let mut bencode = b"d8:intervali1800e5:peers6:".to_vec();
+        bencode.extend([10, 0, 0, 1, 0x1A, 0xE1]);
+        bencode.extend(b"6:peers618:");
+        bencode.extend([
+            0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x02,
+        ]);
+        bencode.extend([0x1A, 0xE2]);
+        bencode.extend(b"e");
+        let response = TrackerResponse::from_bencode(&bencode).unwrap();
+
+        assert_eq!(
+            response.peers(),
+            Some(&vec![peer("10.0.0.1:6881"), peer("[2001:db8::2]:6882")])
+        );
+    }
+
+    #[test]
+    fn reject_dictionary_peer_without_port() {
+        let bencode = b"d8:intervali1800e5:peersld2:ip8:10.0.0.1eee";
+
+        assert!(matches!(
+            TrackerResponse::from_bencode(bencode),
+            Err(Error::InvalidPeerPort)
+        ));
+    }
+
+    #[test]
+    fn ipv4_mapped_peers_are_stored_as_ipv4() {
+        assert_eq!(peer("[::ffff:10.0.0.1]:6881"), peer("10.0.0.1:6881"));
+        assert_eq!(
+            peer("[::ffff:10.0.0.1]:6881").socket_address(),
+            "10.0.0.1:6881"
+        );
+    }
+
+    #[test]
+    fn ipv6_tracker_url() {
+        let tracker = TrackerAddress::from_announce("http://[2001:db8::1]:6969/announce").unwrap();
+        let request = TrackerRequest::new(INFO_ID, PEER_ID, 6882, 0, 0, 0, true, None);
+        let url = request.into_url(&tracker).unwrap();
+
+        assert_eq!(url.host_str(), Some("[2001:db8::1]"));
+        assert_eq!(url.port(), Some(6969));
+    }
 }
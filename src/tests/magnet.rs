@@ -0,0 +1,238 @@
+#[cfg(test)]
+mod test {
+    use crate::{
+        http::Peer,
+        magnet::{MagnetLink, MetadataFetcher, MetadataMessage},
+        pwp::{Extended, ExtensionHandshake, FromBytes, Handshake, IntoBytes},
+        Error, Torrent,
+    };
+    use bendy::decoding::{Decoder, Object};
+    use std::{
+        collections::BTreeMap,
+        fs,
+        io::{Read, Write},
+        net::{TcpListener, TcpStream},
+        thread,
+        time::Duration,
+    };
+
+    const ICEBERG_INFO_HASH: [u8; 20] = [
+        0x06, 0x71, 0x33, 0xac, 0xe5, 0xdd, 0x0c, 0x50, 0x27, 0xb9, 0x9d, 0xe5, 0xd4, 0xba, 0x51,
+        0x28, 0x28, 0x20, 0x8d, 0x5b,
+    ];
+    const PEER_ID: [u8; 20] = [0x2d; 20];
+    /// `ut_metadata` id assigned by the stand-in peer
+    const PEER_UT_METADATA: u8 = 3;
+
+    fn iceberg_info_dictionary() -> Vec<u8> {
+        let bencode = fs::read("samples/upload/iceberg.jpg.torrent").unwrap();
+        let mut decoder = Decoder::new(&bencode);
+        let mut torrent = match decoder.next_object().unwrap() {
+            Some(Object::Dict(torrent)) => torrent,
+            _ => panic!("torrent is not a dictionary"),
+        };
+
+        while let Ok(Some(pair)) = torrent.next_pair() {
+            if let (b"info", Object::Dict(info)) = pair {
+                return info.into_raw().unwrap().to_vec();
+            }
+        }
+        panic!("torrent has no info dictionary")
+    }
+
+    fn iceberg_magnet_link() -> MagnetLink {
+        MagnetLink::new(ICEBERG_INFO_HASH, None, vec![])
+    }
+
+    /// Peer serving `metadata` through `ut_metadata` on loopback.
+    fn start_stand_in_peer(metadata: Vec<u8>) -> Peer {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let peer = Peer::from_socket_address(listener.local_addr().unwrap());
+
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            serve_metadata(&mut stream, &metadata);
+        });
+
+        peer
+    }
+
+    fn read_message(stream: &mut TcpStream) -> Vec<u8> {
+        let mut length = [0u8; 4];
+        stream.read_exact(&mut length).unwrap();
+        let mut message = length.to_vec();
+        message.resize(4 + u32::from_be_bytes(length) as usize, 0);
+        stream.read_exact(&mut message[4..]).unwrap();
+
+        message
+    }
+
+    fn serve_metadata(stream: &mut TcpStream, metadata: &[u8]) {
+        let mut handshake = [0u8; Handshake::HANDSHAKE_VERSION_1_MESSAGE_LENGTH];
+        stream.read_exact(&mut handshake).unwrap();
+        let handshake = Handshake::from_bytes(&handshake).unwrap().0;
+        assert!(handshake.supports_extension_protocol());
+        let answer = Handshake::with_extension_protocol(handshake.info_hash(), [0x41; 20]);
+        stream.write_all(&answer.into_bytes()).unwrap();
+
+        // messages the fetcher has to skip
+        stream.write_all(&[0, 0, 0, 0]).unwrap();
+        stream.write_all(&[0, 0, 0, 2, 5, 0xFF]).unwrap();
+
+        let extension_handshake = Extended::from_bytes(&read_message(stream)).unwrap().0;
+        let their_handshake = ExtensionHandshake::from_bytes(extension_handshake.payload())
+            .unwrap()
+            .0;
+        let their_ut_metadata = their_handshake.extensions()["ut_metadata"];
+        let our_extensions = BTreeMap::from([("ut_metadata".to_string(), PEER_UT_METADATA)]);
+        let our_handshake = ExtensionHandshake::new(
+            our_extensions,
+            None,
+            None,
+            None,
+            Some(metadata.len() as u64),
+        );
+        let message = Extended::new(Extended::HANDSHAKE_ID, our_handshake.into_bytes());
+        stream.write_all(&message.into_bytes()).unwrap();
+
+        while let Ok(request) = Extended::from_bytes(&read_message(stream)) {
+            let request = request.0;
+            assert_eq!(request.extended_message_id(), PEER_UT_METADATA);
+            let piece = match MetadataMessage::from_bytes(request.payload()).unwrap().0 {
+                MetadataMessage::Request { piece } => piece,
+                other => panic!("unexpected {:?}", other),
+            };
+
+            let begin = piece as usize * MetadataMessage::PIECE_SIZE;
+            let end = (begin + MetadataMessage::PIECE_SIZE).min(metadata.len());
+            let data = MetadataMessage::Data {
+                piece,
+                total_size: metadata.len() as u64,
+                data: metadata[begin..end].to_vec(),
+            };
+            let message = Extended::new(their_ut_metadata, data.into_bytes());
+            stream.write_all(&message.into_bytes()).unwrap();
+
+            if end == metadata.len() {
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn parse_hex_magnet_link() {
+        let link = MagnetLink::parse(
+            "magnet:?xt=urn:btih:067133ace5dd0c5027b99de5d4ba512828208d5b&dn=iceberg.jpg\
+             &tr=http%3A%2F%2F127.0.0.1%3A6969%2Fannounce&tr=udp%3A%2F%2Ftracker.example.org%3A1337",
+        )
+        .unwrap();
+
+        assert_eq!(link.info_hash(), ICEBERG_INFO_HASH);
+        assert_eq!(link.display_name(), Some(&"iceberg.jpg".to_string()));
+        assert_eq!(
+            link.announce_tiers(),
+            vec![
+                vec!["http://127.0.0.1:6969/announce".to_string()],
+                vec!["udp://tracker.example.org:1337".to_string()]
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_base32_magnet_link() {
+        let link =
+            MagnetLink::parse("magnet:?xt=urn:btih:AZYTHLHF3UGFAJ5ZTXS5JOSRFAUCBDK3").unwrap();
+
+        assert_eq!(link.info_hash(), ICEBERG_INFO_HASH);
+        assert_eq!(link.display_name(), None);
+        assert!(link.trackers().is_empty());
+    }
+
+    #[test]
+    fn reject_invalid_magnet_links() {
+        let parse = |link: &str| MagnetLink::parse(link);
+
+        assert!(matches!(
+            parse("http://example.org"),
+            Err(Error::InvalidMagnetLink)
+        ));
+        assert!(matches!(
+            parse("magnet:?dn=iceberg.jpg"),
+            Err(Error::MagnetLinkWithoutInfoHash)
+        ));
+        assert!(matches!(
+            parse("magnet:?xt=urn:btih:067133ace5dd0c5027b99de5d4ba512828208d5"),
+            Err(Error::InvalidMagnetLinkInfoHash)
+        ));
+        assert!(matches!(
+            parse("magnet:?xt=urn:btih:zz7133ace5dd0c5027b99de5d4ba512828208d5b"),
+            Err(Error::InvalidMagnetLinkInfoHash)
+        ));
+    }
+
+    #[test]
+    fn metadata_messages_round_trip() {
+        let messages = vec![
+            MetadataMessage::Request { piece: 2 },
+            MetadataMessage::Data {
+                piece: 1,
+                total_size: 16390,
+                data: b"d4:infoe".to_vec(),
+            },
+            MetadataMessage::Reject { piece: 0 },
+        ];
+
+        for message in messages {
+            let bytes = message.clone().into_bytes();
+            assert_eq!(MetadataMessage::from_bytes(&bytes).unwrap().0, message);
+        }
+        assert_eq!(
+            MetadataMessage::Request { piece: 2 }.into_bytes(),
+            b"d8:msg_typei0e5:piecei2ee"
+        );
+    }
+
+    #[test]
+    fn fetch_metadata_from_peer() {
+        let info_dictionary = iceberg_info_dictionary();
+        let peer = start_stand_in_peer(info_dictionary.clone());
+        let fetcher =
+            MetadataFetcher::with_timeout(iceberg_magnet_link(), PEER_ID, Duration::from_secs(5));
+
+        assert_eq!(fetcher.fetch_from(peer).unwrap(), info_dictionary);
+    }
+
+    #[test]
+    fn reject_metadata_not_matching_info_hash() {
+        let mut info_dictionary = iceberg_info_dictionary();
+        let last = info_dictionary.len() - 2;
+        info_dictionary[last] ^= 0xFF;
+        let peer = start_stand_in_peer(info_dictionary);
+        let fetcher =
+            MetadataFetcher::with_timeout(iceberg_magnet_link(), PEER_ID, Duration::from_secs(5));
+
+        assert!(matches!(
+            fetcher.fetch_from(peer),
+            Err(Error::MetadataDoesNotMatchInfoHash)
+        ));
+    }
+
+    #[test]
+    fn torrent_rebuilt_from_metadata() {
+        let link = MagnetLink::new(
+            ICEBERG_INFO_HASH,
+            None,
+            vec!["http://127.0.0.1:6969/announce".to_string()],
+        );
+        let bencode = link.torrent_bencode(&iceberg_info_dictionary());
+        let torrent = Torrent::from_bencode(&mut Decoder::new(&bencode)).unwrap();
+
+        assert_eq!(torrent.info_hash(), ICEBERG_INFO_HASH);
+        assert_eq!(torrent.name(), "iceberg.jpg");
+        assert_eq!(torrent.announce(), "http://127.0.0.1:6969/announce");
+        assert_eq!(
+            torrent.announce_tiers(),
+            vec![vec!["http://127.0.0.1:6969/announce".to_string()]]
+        );
+    }
+}
@@ -21,3 +21,15 @@ pub mod pieces_selection;
 
 #[cfg(test)]
 pub mod rarest_pieces_selection;
+
+#[cfg(test)]
+pub mod udp;
+
+#[cfg(test)]
+pub mod magnet;
+
+#[cfg(test)]
+pub mod dht;
+
+#[cfg(test)]
+pub mod utp;
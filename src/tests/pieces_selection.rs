@@ -9,7 +9,10 @@ pub mod unittest {
 
     use crate::{
         http::Peer,
-        pieces_selection::{simple_selection::SimpleSelector, PieceSelection, PiecesSelection},
+        pieces_selection::{
+            simple_selection::SimpleSelector, PieceAvailability, PieceSelection, PiecesSelection,
+            SuggestedPieces,
+        },
     };
 
     #[test]
@@ -195,4 +198,95 @@ pub mod unittest {
             }
         }
     }
+
+    #[test]
+    pub fn suggested_pieces_are_selected_first() {
+        let seeder = Peer::from_socket_address(SocketAddr::new(
+            IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+            6999,
+        ));
+        let leecher = Peer::from_socket_address(SocketAddr::new(
+            IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2)),
+            6999,
+        ));
+        let mut peers_bitfields: HashMap<Peer, BitVec> = HashMap::new();
+        peers_bitfields.insert(seeder, BitVec::from_elem(4, true));
+        peers_bitfields.insert(leecher, BitVec::from_bytes(&[0b0100_0000]));
+        let mut mybitfield = BitVec::from_elem(4, false);
+        mybitfield.set(2, true);
+
+        let mut suggested_pieces = SuggestedPieces::new();
+        suggested_pieces.suggest(seeder, 3);
+        // pieces we have or the peer does not have are not prioritized
+        suggested_pieces.suggest(seeder, 2);
+        suggested_pieces.suggest(leecher, 0);
+
+        let selection = vec![
+            PieceSelection::new(0, seeder),
+            PieceSelection::new(1, leecher),
+            PieceSelection::new(3, seeder),
+        ];
+        let prioritized = suggested_pieces.prioritize(selection, &mybitfield, &peers_bitfields);
+
+        let pieces: Vec<(u32, Peer)> = prioritized
+            .iter()
+            .map(|selection| (selection.piece_id(), selection.peer()))
+            .collect();
+        assert_eq!(pieces, vec![(3, seeder), (0, seeder), (1, leecher)]);
+    }
+
+    #[test]
+    pub fn only_latest_suggestions_are_kept() {
+        let seeder = Peer::from_socket_address(SocketAddr::new(
+            IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+            6999,
+        ));
+        let pieces = SuggestedPieces::MAX_SUGGESTIONS_PER_PEER as u32 + 1;
+        let mut peers_bitfields: HashMap<Peer, BitVec> = HashMap::new();
+        peers_bitfields.insert(seeder, BitVec::from_elem(pieces as usize, true));
+        let mybitfield = BitVec::from_elem(pieces as usize, false);
+
+        let mut suggested_pieces = SuggestedPieces::new();
+        (0..pieces).for_each(|piece_id| suggested_pieces.suggest(seeder, piece_id));
+        let prioritized = suggested_pieces.prioritize(Vec::new(), &mybitfield, &peers_bitfields);
+
+        assert_eq!(prioritized.len(), SuggestedPieces::MAX_SUGGESTIONS_PER_PEER);
+        assert_eq!(prioritized[0].piece_id(), 1);
+    }
+
+    #[test]
+    pub fn piece_availability_follows_the_peers() {
+        let mut availability = PieceAvailability::new(4);
+        let seeder_bitfield = BitVec::from_elem(4, true);
+        // with the spare bits of the last byte
+        let leecher_bitfield = BitVec::from_fn(8, |piece| piece == 1 || piece == 7);
+
+        availability.add_bitfield(&seeder_bitfield);
+        availability.add_bitfield(&leecher_bitfield);
+        availability.add_piece(2);
+        assert_eq!(availability.peers_having(0), 1);
+        assert_eq!(availability.peers_having(1), 2);
+        assert_eq!(availability.peers_having(2), 2);
+
+        availability.remove_bitfield(&leecher_bitfield);
+        assert_eq!(availability.peers_having(1), 1);
+        assert_eq!(availability.peers_having(4), 0);
+    }
+
+    #[test]
+    pub fn rarest_pieces_are_selected_first() {
+        let mut availability = PieceAvailability::new(4);
+        availability.add_bitfield(&BitVec::from_elem(4, true));
+        availability.add_bitfield(&BitVec::from_fn(4, |piece| piece != 2));
+        availability.add_bitfield(&BitVec::from_fn(4, |piece| piece == 0));
+
+        let seeder_bitfield = BitVec::from_elem(4, true);
+        let pieces = availability.rarest_first(&seeder_bitfield, |_| true);
+        assert_eq!(pieces[0], 2);
+        assert_eq!(pieces[3], 0);
+
+        let pieces = availability.rarest_first(&seeder_bitfield, |piece| piece != 2);
+        assert_eq!(pieces.len(), 3);
+        assert_eq!(pieces[0..2].iter().sum::<u32>(), 4);
+    }
 }
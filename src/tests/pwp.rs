@@ -1,14 +1,23 @@
 #[cfg(test)]
 pub mod unittest {
     use crate::{
+        http::Peer,
         pwp::{
-            from_bytes, Bitfield, FromBytes, Handshake, Have, Interested, IntoBytes,
-            MandatoryBitTorrentMessageFields, MessageType, NotInterested, Piece, Request, Unchoke,
+            from_bytes, AllowedFast, Bitfield, ExtensionHandshake, ExtensionRegistry, FromBytes,
+            Handshake, Have, HaveAll, HaveNone, Interested, IntoBytes,
+            MandatoryBitTorrentMessageFields, Message, MessageDecoder, MessageType, NotInterested,
+            PexMessage, Piece, RejectRequest, Request, SuggestPiece, Unchoke,
         },
-        Cancel, Choke, KeepAlive, Port,
+        Cancel, Choke, Error, Extended, KeepAlive, Port,
     };
     use bit_vec::BitVec;
-    use std::{fs::File, io::Read, path::Path};
+    use std::{
+        collections::BTreeMap,
+        fs::File,
+        io::Read,
+        net::{IpAddr, Ipv4Addr, SocketAddr},
+        path::Path,
+    };
 
     const INFO_ID: [u8; 20] = [
         0x06, 0x71, 0x33, 0xac, 0xe5, 0xdd, 0x0c, 0x50, 0x27, 0xb9, 0x9d, 0xe5, 0xd4, 0xba, 0x51,
@@ -421,4 +430,299 @@ pub mod unittest {
         let message_type_to_test = from_bytes::identity_first_message_type_of(&bytes).unwrap();
         assert_eq!(message_type_to_test, MessageType::Port);
     }
+
+    #[test]
+    pub fn extended_message_into_bytes() {
+        let extended_message = Extended::new(3, b"de".to_vec());
+        let expected_bytes = [0, 0, 0, 4, 20, 3, b'd', b'e'];
+
+        assert_eq!(extended_message.into_bytes(), expected_bytes);
+    }
+
+    #[test]
+    pub fn extended_message_from_bytes() {
+        let bytes = [0, 0, 0, 4, 20, 0, b'd', b'e', 0xFF];
+        let (extended_to_test, size) = Extended::from_bytes(&bytes).unwrap();
+
+        assert_eq!(size, 8);
+        assert_eq!(extended_to_test.message_length(), 4);
+        assert_eq!(extended_to_test.message_type(), MessageType::Extended.id());
+        assert_eq!(
+            extended_to_test.extended_message_id(),
+            Extended::HANDSHAKE_ID
+        );
+        assert_eq!(extended_to_test.payload(), &b"de".to_vec());
+    }
+
+    #[test]
+    pub fn handshake_advertises_extension_protocol() {
+        let handshake = Handshake::with_extension_protocol(INFO_ID, PEER_ID);
+        assert_eq!(handshake.reserved(), [0, 0, 0, 0, 0, 0x10, 0, 0]);
+
+        let bytes = handshake.into_bytes();
+        let handshake_to_test = Handshake::from_bytes(&bytes).unwrap().0;
+        assert!(handshake_to_test.supports_extension_protocol());
+        assert!(!Handshake::new(INFO_ID, PEER_ID).supports_extension_protocol());
+    }
+
+    #[test]
+    pub fn extension_handshake_round_trip() {
+        let extensions = BTreeMap::from([("ut_metadata".to_string(), 1)]);
+        let handshake = ExtensionHandshake::new(
+            extensions,
+            Some("Torrust".to_string()),
+            Some(250),
+            Some(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))),
+            Some(31235),
+        )
+        .with_listen_port(6881);
+        let bytes = handshake.clone().into_bytes();
+
+        assert_eq!(
+            bytes,
+            b"d1:md11:ut_metadatai1ee13:metadata_sizei31235e1:pi6881e4:reqqi250e1:v7:Torrust6:yourip4:\x7f\x00\x00\x01e"
+        );
+        assert_eq!(ExtensionHandshake::from_bytes(&bytes).unwrap().0, handshake);
+    }
+
+    #[test]
+    pub fn extension_handshake_ignores_unknown_fields() {
+        let bytes = b"d1:md6:ut_pexi2ee1:pi70000e1:v5:Other6:yourip3:abc1:xi1ee";
+        let handshake = ExtensionHandshake::from_bytes(bytes).unwrap().0;
+
+        assert_eq!(handshake.extensions()["ut_pex"], 2);
+        assert_eq!(handshake.listen_port(), None);
+        assert_eq!(handshake.client_version(), Some(&"Other".to_string()));
+        assert_eq!(handshake.reqq(), None);
+        assert_eq!(handshake.yourip(), None);
+    }
+
+    #[test]
+    pub fn extension_registry_maps_local_and_remote_ids() {
+        let mut registry = ExtensionRegistry::new(&["ut_metadata", "ut_pex"]);
+        assert_eq!(registry.local_id("ut_metadata"), Some(1));
+        assert_eq!(registry.local_id("ut_pex"), Some(2));
+        assert_eq!(registry.extension_name(2), Some("ut_pex"));
+        assert_eq!(registry.remote_id("ut_metadata"), None);
+
+        let extensions =
+            BTreeMap::from([("ut_metadata".to_string(), 3), ("ut_pex".to_string(), 7)]);
+        registry.update_remote(&ExtensionHandshake::new(extensions, None, None, None, None));
+        assert_eq!(registry.remote_id("ut_metadata"), Some(3));
+        assert_eq!(registry.remote_id("ut_pex"), Some(7));
+
+        // an id of 0 disables the extension
+        let extensions = BTreeMap::from([("ut_pex".to_string(), 0)]);
+        registry.update_remote(&ExtensionHandshake::new(extensions, None, None, None, None));
+        assert_eq!(registry.remote_id("ut_pex"), None);
+        assert_eq!(registry.remote_id("ut_metadata"), Some(3));
+    }
+
+    #[test]
+    pub fn pex_message_round_trip() {
+        let ipv4 = Peer::from_socket_address("10.0.0.1:6881".parse::<SocketAddr>().unwrap());
+        let ipv6 = Peer::from_socket_address("[2001:db8::1]:6881".parse::<SocketAddr>().unwrap());
+        let dropped = Peer::from_socket_address("10.0.0.2:256".parse::<SocketAddr>().unwrap());
+        let message = PexMessage::new(
+            vec![(ipv4, PexMessage::SEED_FLAG), (ipv6, 0)],
+            vec![dropped],
+        );
+        let bytes = message.clone().into_bytes();
+
+        assert!(bytes.starts_with(b"d5:added6:\x0a\x00\x00\x01\x1a\xe17:added.f1:\x02"));
+        assert!(bytes.ends_with(b"7:dropped6:\x0a\x00\x00\x02\x01\x008:dropped60:e"));
+        assert_eq!(PexMessage::from_bytes(&bytes).unwrap().0, message);
+    }
+
+    #[test]
+    pub fn pex_message_without_flags() {
+        let bytes = b"d5:added6:\x0a\x00\x00\x01\x1a\xe1e";
+        let message = PexMessage::from_bytes(bytes).unwrap().0;
+
+        assert_eq!(message.added().len(), 1);
+        assert_eq!(message.added()[0].1, 0);
+        assert!(message.dropped().is_empty());
+    }
+
+    #[test]
+    pub fn pex_message_with_truncated_peers_is_invalid() {
+        assert!(PexMessage::from_bytes(b"d5:added5:\x0a\x00\x00\x01\x1ae").is_err());
+    }
+
+    #[test]
+    pub fn handshake_advertises_dht() {
+        let handshake = Handshake::with_extension_protocol(INFO_ID, PEER_ID).advertise_dht();
+        assert_eq!(handshake.reserved(), [0, 0, 0, 0, 0, 0x10, 0, 0x01]);
+
+        let handshake_to_test = Handshake::from_bytes(&handshake.into_bytes()).unwrap().0;
+        assert!(handshake_to_test.supports_dht());
+        assert!(!Handshake::new(INFO_ID, PEER_ID).supports_dht());
+    }
+
+    #[test]
+    pub fn fast_extension_messages_into_bytes() {
+        assert_eq!(
+            SuggestPiece::new(3).into_bytes(),
+            [0, 0, 0, 5, 13, 0, 0, 0, 3]
+        );
+        assert_eq!(HaveAll::new().into_bytes(), [0, 0, 0, 1, 14]);
+        assert_eq!(HaveNone::new().into_bytes(), [0, 0, 0, 1, 15]);
+        assert_eq!(
+            RejectRequest::new(1, 0x4000, 0x4000).into_bytes(),
+            [0, 0, 0, 13, 16, 0, 0, 0, 1, 0, 0, 0x40, 0, 0, 0, 0x40, 0]
+        );
+        assert_eq!(
+            AllowedFast::new(7).into_bytes(),
+            [0, 0, 0, 5, 17, 0, 0, 0, 7]
+        );
+    }
+
+    #[test]
+    pub fn fast_extension_messages_from_bytes() {
+        let suggest_piece = SuggestPiece::from_bytes(&[0, 0, 0, 5, 13, 0, 0, 0, 3]).unwrap();
+        assert_eq!(suggest_piece.0.piece_index(), 3);
+        assert_eq!(suggest_piece.1, 9);
+
+        assert_eq!(HaveAll::from_bytes(&[0, 0, 0, 1, 14]).unwrap().1, 5);
+        assert_eq!(HaveNone::from_bytes(&[0, 0, 0, 1, 15]).unwrap().1, 5);
+        assert!(HaveAll::from_bytes(&[0, 0, 0, 1, 15]).is_err());
+
+        let bytes = [0, 0, 0, 13, 16, 0, 0, 0, 1, 0, 0, 0x40, 0, 0, 0, 0x40, 0];
+        let reject_request = RejectRequest::from_bytes(&bytes).unwrap().0;
+        assert_eq!(
+            reject_request.message_type(),
+            MessageType::RejectRequest.id()
+        );
+        assert_eq!(reject_request.piece_index(), 1);
+        assert_eq!(reject_request.begin_offset(), 0x4000);
+        assert_eq!(reject_request.piece_length(), 0x4000);
+
+        let allowed_fast = AllowedFast::from_bytes(&[0, 0, 0, 5, 17, 0, 0, 0, 7]).unwrap();
+        assert_eq!(allowed_fast.0.piece_index(), 7);
+    }
+
+    #[test]
+    pub fn identify_fast_extension_message_types_from_bytes() {
+        let message_types = [
+            (13, MessageType::SuggestPiece),
+            (14, MessageType::HaveAll),
+            (15, MessageType::HaveNone),
+            (16, MessageType::RejectRequest),
+            (17, MessageType::AllowedFast),
+        ];
+
+        for (id, expected) in message_types {
+            let bytes = [0, 0, 0, 1, id];
+            let message_type_to_test = from_bytes::identity_first_message_type_of(&bytes).unwrap();
+            assert_eq!(message_type_to_test, expected);
+        }
+    }
+
+    #[test]
+    pub fn handshake_advertises_fast_extension() {
+        let handshake = Handshake::new(INFO_ID, PEER_ID).advertise_fast_extension();
+        assert_eq!(handshake.reserved(), [0, 0, 0, 0, 0, 0, 0, 0x04]);
+
+        let handshake_to_test = Handshake::from_bytes(&handshake.into_bytes()).unwrap().0;
+        assert!(handshake_to_test.supports_fast_extension());
+        assert!(!Handshake::new(INFO_ID, PEER_ID).supports_fast_extension());
+    }
+
+    /// Example of BEP 6
+    #[test]
+    pub fn allowed_fast_set_of_bep_6() {
+        let ip = Ipv4Addr::new(80, 4, 4, 200);
+        let info_hash = [0xaa; 20];
+
+        assert_eq!(
+            AllowedFast::allowed_fast_set(ip, info_hash, 1313, 7),
+            vec![1059, 431, 808, 1217, 287, 376, 1188]
+        );
+        assert_eq!(
+            AllowedFast::allowed_fast_set(ip, info_hash, 1313, 9),
+            vec![1059, 431, 808, 1217, 287, 376, 1188, 353, 508]
+        );
+        // the same set for the whole /24 network
+        assert_eq!(
+            AllowedFast::allowed_fast_set(Ipv4Addr::new(80, 4, 4, 1), info_hash, 1313, 7),
+            vec![1059, 431, 808, 1217, 287, 376, 1188]
+        );
+    }
+
+    #[test]
+    pub fn allowed_fast_set_is_capped_by_the_number_of_pieces() {
+        let mut allowed_fast_set =
+            AllowedFast::allowed_fast_set(Ipv4Addr::new(80, 4, 4, 200), [0xaa; 20], 3, 10);
+        allowed_fast_set.sort();
+
+        assert_eq!(allowed_fast_set, vec![0, 1, 2]);
+    }
+
+    #[test]
+    pub fn decoder_yields_a_piece_fed_byte_by_byte() {
+        let block: Vec<u8> = (0..0x8000).map(|index| index as u8).collect();
+        let bytes = Piece::new(3, 0x4000, block.clone()).into_bytes();
+        let mut decoder = MessageDecoder::new();
+
+        for byte in &bytes[..bytes.len() - 1] {
+            decoder.feed(&[*byte]);
+            assert!(decoder.decode().unwrap().is_none());
+        }
+        decoder.feed(&bytes[bytes.len() - 1..]);
+
+        match decoder.decode().unwrap() {
+            Some(Message::Piece(piece)) => assert_eq!(*piece.data(), block),
+            message => panic!("unexpected {:?}", message),
+        }
+        assert_eq!(decoder.buffered_length(), 0);
+    }
+
+    #[test]
+    pub fn decoder_splits_a_chunk_of_several_messages() {
+        let mut bytes = Handshake::new([0xaa; 20], [0xbb; 20]).into_bytes();
+        bytes.extend(KeepAlive::new().into_bytes());
+        bytes.extend(KeepAlive::new().into_bytes());
+        bytes.extend(Have::new(7).into_bytes());
+        bytes.extend(&Interested::new().into_bytes()[..3]);
+        let mut decoder = MessageDecoder::new();
+        decoder.feed(&bytes);
+
+        assert!(matches!(decoder.decode(), Ok(Some(Message::Handshake(_)))));
+        assert!(matches!(decoder.decode(), Ok(Some(Message::KeepAlive(_)))));
+        assert!(matches!(decoder.decode(), Ok(Some(Message::KeepAlive(_)))));
+        match decoder.decode() {
+            Ok(Some(Message::Have(have))) => assert_eq!(have.piece_index(), 7),
+            message => panic!("unexpected {:?}", message),
+        }
+        assert!(matches!(decoder.decode(), Ok(None)));
+        assert_eq!(decoder.buffered_length(), 3);
+    }
+
+    #[test]
+    pub fn decoder_skips_unknown_message_ids() {
+        let mut decoder = MessageDecoder::new();
+        decoder.feed(&[0, 0, 0, 3, 42, 0xff, 0xff]);
+        decoder.feed(&Unchoke::new().into_bytes());
+
+        assert!(matches!(decoder.decode(), Ok(Some(Message::Unchoke(_)))));
+        assert!(matches!(decoder.decode(), Ok(None)));
+    }
+
+    #[test]
+    pub fn decoder_rejects_oversize_messages() {
+        let mut decoder = MessageDecoder::new();
+        decoder.feed(&(MessageDecoder::MAX_MESSAGE_LENGTH + 1).to_be_bytes());
+        decoder.feed(&[MessageType::Piece.id()]);
+
+        assert!(matches!(decoder.decode(), Err(Error::MessageTooLong)));
+    }
+
+    #[test]
+    pub fn decoder_rejects_malformed_messages() {
+        let mut decoder = MessageDecoder::new();
+        // a have message without piece index
+        decoder.feed(&[0, 0, 0, 1, MessageType::Have.id()]);
+
+        assert!(decoder.decode().is_err());
+    }
 }
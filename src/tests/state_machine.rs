@@ -1,6 +1,49 @@
 #[cfg(test)]
 pub mod tests {
-    use crate::state_machine::identity::{generate_random_identity, CLIENT_VERSION_ID};
+    use crate::{
+        dht::{DhtNode, NodeId, RoutingTable},
+        http::{Event, Peer, TrackerList, TrackerRequest},
+        pwp::{
+            Bitfield, Choke, Extended, ExtensionHandshake, FromBytes, Handshake, Have, HaveAll,
+            Interested, IntoBytes, Message, NotInterested, PexMessage, Piece, Request, Unchoke,
+        },
+        state_machine::{
+            block_requests::{Block, BlockRequests},
+            choker::Choker,
+            event_loop::{Command, EventLoop},
+            hash_failures::HashFailures,
+            identity::{generate_random_identity, CLIENT_VERSION_ID},
+            peer_event::PeerEvent,
+            peer_exchange::PeerExchange,
+            peer_handshakes::PeerHandshakes,
+            reconnect_backoff::ReconnectBackoff,
+            tracker_announcer::TrackerAnnouncer,
+            tracker_scheduler::TrackerScheduler,
+            transfer_statistics::TransferStatistics,
+            ConnectionState, StateMachine,
+        },
+        tcp::{EncryptionPolicy, TcpSession},
+        tests::torrent::test::private_torrent_bencode,
+        torrent::Torrent,
+        Error,
+    };
+    use bendy::decoding::Decoder;
+    use bit_vec::BitVec;
+    use crossbeam_channel::{Receiver, Sender};
+    use mio::{Token, Waker};
+    use std::{
+        collections::{BTreeMap, HashMap, HashSet},
+        env, fs,
+        net::{SocketAddr, TcpListener},
+        path::Path,
+        sync::Arc,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    fn peer(port: u16) -> Peer {
+        Peer::from_socket_address(SocketAddr::from(([127, 0, 0, 1], port)))
+    }
 
     #[test]
     pub fn generate_peer_id() {
@@ -19,4 +62,956 @@ pub mod tests {
             )
         }
     }
+
+    #[test]
+    pub fn verified_piece_does_not_blame_contributors() {
+        let mut hash_failures = HashFailures::new(1);
+        hash_failures.record_block(0, peer(2001));
+        hash_failures.piece_verified(0);
+
+        assert!(hash_failures.piece_failed(0).is_empty());
+        assert_eq!(hash_failures.strikes(&peer(2001)), 0);
+        assert!(!hash_failures.is_banned(&peer(2001)));
+    }
+
+    #[test]
+    pub fn repeat_offenders_are_banned() {
+        let mut hash_failures = HashFailures::new(2);
+
+        hash_failures.record_block(0, peer(2001));
+        hash_failures.record_block(0, peer(2002));
+        hash_failures.record_block(0, peer(2001));
+        assert!(hash_failures.piece_failed(0).is_empty());
+        assert_eq!(hash_failures.strikes(&peer(2001)), 1);
+        assert_eq!(hash_failures.strikes(&peer(2002)), 1);
+
+        hash_failures.record_block(1, peer(2001));
+        hash_failures.record_block(2, peer(2002));
+        hash_failures.piece_verified(2);
+        assert_eq!(hash_failures.piece_failed(1), vec![peer(2001)]);
+
+        assert!(hash_failures.is_banned(&peer(2001)));
+        assert!(!hash_failures.is_banned(&peer(2002)));
+
+        hash_failures.record_block(3, peer(2001));
+        assert!(hash_failures.piece_failed(3).is_empty());
+        assert_eq!(hash_failures.strikes(&peer(2001)), 3);
+    }
+
+    #[test]
+    pub fn first_announce_is_due_right_away() {
+        let mut scheduler = TrackerScheduler::new();
+        assert!(scheduler.is_announce_due());
+
+        scheduler.announce_succeeded(Some(1800), None);
+        assert!(!scheduler.is_announce_due());
+        assert!(scheduler.time_until_announce() > Duration::from_secs(1790));
+
+        scheduler.announce_now();
+        assert!(scheduler.is_announce_due());
+    }
+
+    #[test]
+    pub fn min_interval_takes_precedence_over_a_shorter_interval() {
+        let mut scheduler = TrackerScheduler::new();
+
+        scheduler.announce_succeeded(Some(60), Some(300));
+        assert!(scheduler.time_until_announce() > Duration::from_secs(290));
+
+        scheduler.announce_succeeded(None, None);
+        assert!(scheduler.time_until_announce() > Duration::from_secs(29 * 60));
+    }
+
+    #[test]
+    pub fn failed_announces_back_off() {
+        let mut scheduler = TrackerScheduler::new();
+
+        scheduler.announce_failed();
+        assert!(scheduler.time_until_announce() <= TrackerScheduler::RETRY_DELAY);
+        scheduler.announce_failed();
+        assert!(scheduler.time_until_announce() > TrackerScheduler::RETRY_DELAY);
+
+        for _ in 0..20 {
+            scheduler.announce_failed();
+        }
+        assert!(scheduler.time_until_announce() <= TrackerScheduler::DEFAULT_INTERVAL);
+        assert!(scheduler.time_until_announce() > TrackerScheduler::DEFAULT_INTERVAL / 2);
+
+        scheduler.announce_succeeded(Some(10), None);
+        scheduler.announce_failed();
+        assert!(scheduler.time_until_announce() <= TrackerScheduler::RETRY_DELAY);
+    }
+
+    #[test]
+    pub fn announces_are_answered_over_a_channel() {
+        let tracker_list = TrackerList::new(vec![vec!["invalid announce".to_string()]]);
+        let announcer = TrackerAnnouncer::new(tracker_list);
+        let request = TrackerRequest::new(
+            [0xaa; 20],
+            [0x01; 20],
+            6881,
+            0,
+            0,
+            10,
+            true,
+            Some(Event::Started),
+        );
+
+        announcer.announce(request);
+        match announcer.announced().recv_timeout(Duration::from_secs(5)) {
+            Ok((request, response)) => {
+                assert!(matches!(request.event(), Some(Event::Started)));
+                assert!(response.is_err());
+            }
+            Err(e) => panic!("no answer: {:?}", e),
+        }
+    }
+
+    #[test]
+    pub fn transfer_statistics_are_shared_between_clones() {
+        let statistics = TransferStatistics::new();
+        let tcp_statistics = statistics.clone();
+
+        tcp_statistics.add_downloaded(16384);
+        tcp_statistics.add_downloaded(1000);
+        tcp_statistics.add_uploaded(16384);
+
+        assert_eq!(statistics.downloaded(), 17384);
+        assert_eq!(statistics.uploaded(), 16384);
+    }
+
+    #[test]
+    pub fn peer_exchange_sends_only_changes() {
+        let mut exchange = PeerExchange::new(Duration::from_secs(60));
+        let start = Instant::now();
+        let mut connected = HashMap::from([(peer(1), 0), (peer(2), 0x02)]);
+
+        let message = exchange.message_for(peer(1), &connected, start).unwrap();
+        assert_eq!(message.added(), &vec![(peer(2), 0x02)]);
+        assert!(message.dropped().is_empty());
+
+        // not due yet
+        connected.insert(peer(3), 0);
+        assert!(exchange
+            .message_for(peer(1), &connected, start + Duration::from_secs(30))
+            .is_none());
+
+        connected.remove(&peer(2));
+        let message = exchange
+            .message_for(peer(1), &connected, start + Duration::from_secs(60))
+            .unwrap();
+        assert_eq!(message.added(), &vec![(peer(3), 0)]);
+        assert_eq!(message.dropped(), &vec![peer(2)]);
+
+        // nothing changed
+        assert!(exchange
+            .message_for(peer(1), &connected, start + Duration::from_secs(120))
+            .is_none());
+    }
+
+    #[test]
+    pub fn peer_exchange_drops_messages_received_too_often() {
+        let mut exchange = PeerExchange::new(Duration::from_secs(60));
+        let start = Instant::now();
+
+        assert!(exchange.accept(peer(1), start));
+        assert!(!exchange.accept(peer(1), start + Duration::from_secs(10)));
+        assert!(exchange.accept(peer(2), start + Duration::from_secs(10)));
+        assert!(exchange.accept(peer(1), start + Duration::from_secs(55)));
+    }
+
+    #[test]
+    pub fn handshakes_for_another_torrent_are_refused() {
+        let handshakes = PeerHandshakes::new([0xaa; 20], [0x01; 20]);
+
+        assert!(handshakes
+            .validate(peer(1), &Handshake::new([0xaa; 20], [0x02; 20]))
+            .is_ok());
+        assert!(matches!(
+            handshakes.validate(peer(1), &Handshake::new([0xbb; 20], [0x02; 20])),
+            Err(Error::HandshakeInfoHashMismatch)
+        ));
+    }
+
+    #[test]
+    pub fn connections_to_ourselves_are_detected() {
+        let handshakes = PeerHandshakes::new([0xaa; 20], [0x01; 20]);
+
+        assert!(matches!(
+            handshakes.validate(peer(1), &Handshake::new([0xaa; 20], [0x01; 20])),
+            Err(Error::ConnectedToOurselves)
+        ));
+    }
+
+    #[test]
+    pub fn second_connection_with_a_peer_id_is_refused() {
+        let mut handshakes = PeerHandshakes::new([0xaa; 20], [0x01; 20]);
+        let handshake = Handshake::new([0xaa; 20], [0x02; 20]);
+        handshakes.insert(peer(1), Handshake::new([0xaa; 20], [0x02; 20]));
+
+        // the same connection may handshake again
+        assert!(handshakes.validate(peer(1), &handshake).is_ok());
+        assert!(matches!(
+            handshakes.validate(peer(2), &handshake),
+            Err(Error::DuplicatePeerConnection)
+        ));
+
+        handshakes.remove(&peer(1));
+        assert!(handshakes.validate(peer(2), &handshake).is_ok());
+    }
+
+    #[test]
+    pub fn reconnections_back_off() {
+        let mut backoff = ReconnectBackoff::new();
+        let start = Instant::now();
+
+        // peers that connected to us are not connected to again
+        assert!(!backoff.failed(peer(1), start));
+        assert!(backoff.is_due(&peer(1), start));
+
+        backoff.connecting(peer(2), start);
+        assert!(backoff.failed(peer(2), start));
+        assert!(!backoff.is_due(&peer(2), start));
+        assert!(backoff.is_due(&peer(2), start + ReconnectBackoff::RETRY_DELAY));
+        assert_eq!(
+            backoff.time_until_next_attempt(start),
+            Some(ReconnectBackoff::RETRY_DELAY)
+        );
+
+        assert!(backoff.failed(peer(2), start));
+        assert!(!backoff.is_due(&peer(2), start + ReconnectBackoff::RETRY_DELAY));
+
+        backoff.connected(peer(2));
+        assert!(backoff.failed(peer(2), start));
+        assert!(backoff.is_due(&peer(2), start + ReconnectBackoff::RETRY_DELAY));
+    }
+
+    fn blocks(piece_index: u32, count: u32) -> Vec<Block> {
+        (0..count)
+            .map(|block| Block::new(piece_index, block * 0x4000, 0x4000))
+            .collect()
+    }
+
+    #[test]
+    pub fn block_requests_are_pipelined_up_to_the_queue_depth() {
+        let now = Instant::now();
+        let mut requests = BlockRequests::new(4);
+        assert_eq!(requests.queue_depth(None), 4);
+        assert_eq!(requests.queue_depth(Some(2)), 2);
+
+        requests.start_piece(0, blocks(0, 3));
+        requests.start_piece(1, blocks(1, 3));
+        assert_eq!(requests.request_blocks(peer(1), 0, 4, now), blocks(0, 3));
+        assert_eq!(requests.request_blocks(peer(1), 1, 4, now), blocks(1, 1));
+        assert_eq!(requests.outstanding(&peer(1)), 4);
+        assert_eq!(requests.pieces_with_unrequested_blocks(), vec![1]);
+
+        // the queue is refilled as the blocks arrive
+        assert!(requests
+            .received(peer(1), Block::new(0, 0, 0x4000))
+            .is_some());
+        assert!(requests
+            .received(peer(1), Block::new(0, 0, 0x4000))
+            .is_none());
+        assert!(requests
+            .received(peer(2), Block::new(1, 0x4000, 0x4000))
+            .is_none());
+        assert_eq!(requests.request_blocks(peer(1), 1, 4, now).len(), 1);
+
+        assert!(requests
+            .received(peer(1), Block::new(0, 0x4000, 0x4000))
+            .is_some());
+        assert!(requests
+            .received(peer(1), Block::new(0, 0x8000, 0x4000))
+            .is_some());
+        assert!(requests.is_piece_complete(0));
+        requests.finish_piece(0);
+        assert!(!requests.is_piece_started(0));
+        assert_eq!(requests.outstanding(&peer(1)), 2);
+    }
+
+    #[test]
+    pub fn only_the_requested_blocks_are_accepted() {
+        let now = Instant::now();
+        let mut requests = BlockRequests::new(BlockRequests::DEFAULT_QUEUE_DEPTH);
+        requests.start_piece(0, blocks(0, 2));
+        requests.request_blocks(peer(1), 0, 2, now);
+
+        // offsets and lengths other than the requested ones
+        for offset in [1, 2, 3, 0x2000] {
+            assert!(requests
+                .received(peer(1), Block::new(0, offset, 0x4000))
+                .is_none());
+        }
+        assert!(requests
+            .received(peer(1), Block::new(0, 0, 0x4001))
+            .is_none());
+        assert!(requests
+            .received(peer(1), Block::new(0, 0x4000, 0x100000))
+            .is_none());
+        assert!(!requests.is_piece_complete(0));
+        assert_eq!(requests.outstanding(&peer(1)), 2);
+
+        assert!(requests
+            .received(peer(1), Block::new(0, 0, 0x4000))
+            .is_some());
+        assert!(requests
+            .received(peer(1), Block::new(0, 0x4000, 0x4000))
+            .is_some());
+        assert!(requests.is_piece_complete(0));
+    }
+
+    #[test]
+    pub fn unanswered_block_requests_go_back_to_the_pool() {
+        let start = Instant::now();
+        let mut requests = BlockRequests::new(BlockRequests::DEFAULT_QUEUE_DEPTH);
+        requests.start_piece(0, blocks(0, 2));
+        requests.request_blocks(peer(1), 0, 1, start);
+        assert_eq!(
+            requests.time_until_next_timeout(start),
+            Some(BlockRequests::REQUEST_TIMEOUT)
+        );
+
+        assert!(requests.expire(start).is_empty());
+        let timeout = start + BlockRequests::REQUEST_TIMEOUT;
+        assert_eq!(requests.expire(timeout), HashSet::from([peer(1)]));
+        assert_eq!(requests.outstanding(&peer(1)), 0);
+
+        // the block that timed out is requested first, from another peer
+        assert_eq!(
+            requests.request_blocks(peer(2), 0, 1, timeout),
+            blocks(0, 1)
+        );
+        // the slow peer is not expected to send it anymore
+        assert!(requests
+            .received(peer(1), Block::new(0, 0, 0x4000))
+            .is_none());
+        assert!(requests
+            .received(peer(2), Block::new(0, 0, 0x4000))
+            .is_some());
+
+        requests.request_blocks(peer(2), 0, 1, timeout);
+        requests.rejected(peer(2), 0, 0x4000);
+        assert_eq!(requests.pieces_with_unrequested_blocks(), vec![0]);
+        requests.request_blocks(peer(3), 0, 1, timeout);
+        requests.release_peer(&peer(3));
+        assert_eq!(requests.outstanding(&peer(3)), 0);
+        assert_eq!(requests.pieces_with_unrequested_blocks(), vec![0]);
+        assert_eq!(requests.time_until_next_timeout(timeout), None);
+    }
+
+    #[test]
+    pub fn endgame_requests_the_last_blocks_from_every_peer() {
+        let now = Instant::now();
+        let mut requests = BlockRequests::new(BlockRequests::DEFAULT_QUEUE_DEPTH);
+        requests.start_piece(0, blocks(0, 2));
+        requests.request_blocks(peer(1), 0, 1, now);
+        requests.request_blocks(peer(2), 0, 1, now);
+        assert!(requests.pieces_with_unrequested_blocks().is_empty());
+        assert_eq!(requests.pieces_in_flight(), vec![0]);
+
+        assert_eq!(
+            requests.request_duplicates(peer(3), 0, 1, now),
+            blocks(0, 1)
+        );
+        assert_eq!(requests.request_duplicates(peer(3), 0, 2, now).len(), 1);
+        assert!(requests.request_duplicates(peer(3), 0, 2, now).is_empty());
+
+        // the first peer to send a block wins, the others are cancelled
+        let duplicates = requests
+            .received(peer(3), Block::new(0, 0, 0x4000))
+            .unwrap();
+        assert_eq!(duplicates, vec![(peer(1), blocks(0, 1)[0])]);
+        assert_eq!(requests.outstanding(&peer(1)), 0);
+        assert_eq!(requests.outstanding(&peer(3)), 1);
+
+        // blocks still requested from another peer are not given back
+        requests.release_peer(&peer(3));
+        assert!(requests.pieces_with_unrequested_blocks().is_empty());
+        let duplicates = requests
+            .received(peer(2), Block::new(0, 0x4000, 0x4000))
+            .unwrap();
+        assert!(duplicates.is_empty());
+        assert!(requests.is_piece_complete(0));
+    }
+
+    #[test]
+    pub fn fastest_peers_are_unchoked() {
+        let start = Instant::now();
+        let mut choker = Choker::new(start);
+        let interested: Vec<Peer> = (1..=6).map(peer).collect();
+        for (bytes, peer) in interested.iter().enumerate() {
+            choker.add_peer(*peer, start);
+            choker.record_downloaded(*peer, bytes as u64);
+            choker.record_uploaded(*peer, 10 - bytes as u64);
+        }
+        assert!(choker.is_round_due(start));
+
+        let unchoked = choker.run_round(&interested, false, start);
+        let optimistic_unchoke = choker.optimistic_unchoke().unwrap();
+        assert!([peer(1), peer(2)].contains(&optimistic_unchoke));
+        let mut expected: HashSet<Peer> = (3..=6).map(peer).collect();
+        expected.insert(optimistic_unchoke);
+        assert_eq!(unchoked, expected);
+
+        assert!(!choker.is_round_due(start));
+        assert_eq!(choker.time_until_next_round(start), Choker::ROUND_INTERVAL);
+    }
+
+    #[test]
+    pub fn seeders_unchoke_the_peers_they_upload_to_the_fastest() {
+        let start = Instant::now();
+        let mut choker = Choker::new(start);
+        let interested: Vec<Peer> = (1..=5).map(peer).collect();
+        for (bytes, peer) in interested.iter().enumerate() {
+            choker.add_peer(*peer, start);
+            choker.record_uploaded(*peer, 10 - bytes as u64);
+        }
+
+        let unchoked = choker.run_round(&interested, true, start);
+        assert_eq!(choker.optimistic_unchoke(), Some(peer(5)));
+        assert_eq!(unchoked, interested.iter().copied().collect());
+
+        // the optimistic unchoke lasts for more than a round
+        let next_round = start + Choker::ROUND_INTERVAL;
+        choker.record_uploaded(peer(6), 100);
+        let interested: Vec<Peer> = (1..=6).map(peer).collect();
+        let unchoked = choker.run_round(&interested, true, next_round);
+        assert_eq!(choker.optimistic_unchoke(), Some(peer(5)));
+        assert!(unchoked.contains(&peer(6)) && unchoked.contains(&peer(5)));
+        assert_eq!(unchoked.len(), Choker::UNCHOKE_SLOTS + 1);
+    }
+
+    #[test]
+    pub fn failing_peers_are_given_up_on() {
+        let mut backoff = ReconnectBackoff::new();
+        let start = Instant::now();
+        backoff.connecting(peer(1), start);
+
+        for _ in 0..ReconnectBackoff::MAX_FAILURES {
+            assert!(backoff.failed(peer(1), start));
+        }
+        assert!(
+            backoff.time_until_next_attempt(start).unwrap() <= ReconnectBackoff::MAX_RETRY_DELAY
+        );
+        assert!(!backoff.failed(peer(1), start));
+        assert_eq!(backoff.time_until_next_attempt(start), None);
+    }
+
+    /// Commands and waker of the event loop, with what it reports
+    type EventLoopHandles = (
+        Sender<Command>,
+        Arc<Waker>,
+        Receiver<(Peer, PeerEvent)>,
+        TransferStatistics,
+    );
+
+    fn start_event_loop() -> EventLoopHandles {
+        let (message_sender, message_receiver) = crossbeam_channel::unbounded();
+        let transfer_statistics = TransferStatistics::new();
+        let event_loop = EventLoop::new(
+            None,
+            message_sender,
+            transfer_statistics.clone(),
+            [0xaa; 20],
+            EncryptionPolicy::Disabled,
+        )
+        .unwrap();
+        let (commands, waker) = (event_loop.command_sender(), event_loop.waker());
+        thread::spawn(move || event_loop.run());
+
+        (commands, waker, message_receiver, transfer_statistics)
+    }
+
+    #[test]
+    pub fn event_loop_exchanges_messages_with_a_peer() {
+        let (commands, waker, message_receiver, transfer_statistics) = start_event_loop();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let remote_peer = Peer::from_socket_address(listener.local_addr().unwrap());
+        // sent before the connection is established
+        commands.send(Command::Connect(remote_peer)).unwrap();
+        commands
+            .send(Command::Send(
+                remote_peer,
+                Message::Handshake(Handshake::new([0xaa; 20], [0xbb; 20])),
+            ))
+            .unwrap();
+        waker.wake().unwrap();
+
+        let (stream, _) = listener.accept().unwrap();
+        let mut remote =
+            TcpSession::accept(stream, [0xaa; 20], EncryptionPolicy::Disabled).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let received = loop {
+            assert!(Instant::now() < deadline, "no message received");
+            if let Some(message) = remote.receive().unwrap() {
+                break message;
+            }
+            thread::sleep(Duration::from_millis(10));
+        };
+        assert!(matches!(received, Message::Handshake(_)));
+
+        remote.send(Piece::new(0, 0, vec![7; 100])).unwrap();
+        match message_receiver.recv_timeout(Duration::from_secs(5)) {
+            Ok((peer, PeerEvent::Message(Message::Piece(piece)))) => {
+                assert_eq!(peer, remote_peer);
+                assert_eq!(piece.data().len(), 100);
+            }
+            event => panic!("unexpected {:?}", event),
+        }
+        assert_eq!(transfer_statistics.downloaded(), 100);
+
+        drop(remote);
+        match message_receiver.recv_timeout(Duration::from_secs(5)) {
+            Ok((peer, PeerEvent::Disconnected)) => assert_eq!(peer, remote_peer),
+            event => panic!("unexpected {:?}", event),
+        }
+    }
+
+    #[test]
+    pub fn sessions_of_closed_connections_are_dropped() {
+        let (commands, waker, message_receiver, _) = start_event_loop();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (remote_stream, _) = listener.accept().unwrap();
+        let mut remote = TcpSession::from_transport(remote_stream).unwrap();
+
+        // negotiated for a connection the event loop does not have anymore
+        let session = TcpSession::from_transport(stream).unwrap();
+        commands
+            .send(Command::Established(Token(1000), Box::new(session)))
+            .unwrap();
+        waker.wake().unwrap();
+
+        remote.send(Piece::new(0, 0, vec![7; 100])).unwrap();
+        assert!(message_receiver
+            .recv_timeout(Duration::from_millis(500))
+            .is_err());
+    }
+
+    #[test]
+    pub fn event_loop_reports_failed_connections() {
+        let (commands, waker, message_receiver, _) = start_event_loop();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let unreachable_peer = Peer::from_socket_address(listener.local_addr().unwrap());
+        drop(listener);
+
+        commands.send(Command::Connect(unreachable_peer)).unwrap();
+        waker.wake().unwrap();
+
+        match message_receiver.recv_timeout(Duration::from_secs(5)) {
+            Ok((peer, PeerEvent::Error(Error::FailedToConnectToPeer))) => {
+                assert_eq!(peer, unreachable_peer)
+            }
+            event => panic!("unexpected {:?}", event),
+        }
+    }
+
+    fn iceberg_torrent() -> Torrent {
+        Torrent::from_file(Path::new("samples/upload/iceberg.jpg.torrent")).unwrap()
+    }
+
+    /// Downloads iceberg.jpg to an empty directory.
+    fn downloading_state_machine() -> (StateMachine, Receiver<Command>) {
+        let working_directory =
+            env::temp_dir().join(format!("torrust_state_machine_{}", rand::random::<u32>()));
+        fs::create_dir_all(&working_directory).unwrap();
+
+        StateMachine::without_network(iceberg_torrent(), &working_directory)
+    }
+
+    /// Seeds iceberg.jpg.
+    fn seeding_state_machine() -> (StateMachine, Receiver<Command>) {
+        let working_directory =
+            env::temp_dir().join(format!("torrust_state_machine_{}", rand::random::<u32>()));
+        fs::create_dir_all(&working_directory).unwrap();
+        fs::copy(
+            "scripts/multi-client/iceberg.jpg",
+            working_directory.join(iceberg_torrent().name()),
+        )
+        .unwrap();
+
+        StateMachine::without_network(iceberg_torrent(), &working_directory)
+    }
+
+    /// Private torrent of four pieces
+    fn private_torrent() -> Torrent {
+        let bencode = private_torrent_bencode("private.bin", 4 * 16384, 16384);
+        Torrent::from_bencode(&mut Decoder::new(&bencode)).unwrap()
+    }
+
+    /// Downloads the private torrent to an empty directory.
+    fn private_state_machine() -> (StateMachine, Receiver<Command>) {
+        let working_directory =
+            env::temp_dir().join(format!("torrust_state_machine_{}", rand::random::<u32>()));
+        fs::create_dir_all(&working_directory).unwrap();
+
+        StateMachine::without_network(private_torrent(), &working_directory)
+    }
+
+    /// Connects a peer, which does not send its bitfield yet.
+    fn connect_peer(state_machine: &mut StateMachine, peer: Peer) {
+        let handshake = Handshake::new(iceberg_torrent().info_hash(), [peer.port() as u8; 20]);
+        state_machine.receive(peer, Message::Handshake(handshake));
+    }
+
+    /// Bitfield of whole bytes, as sent over the wire
+    fn bitfield_of(pieces: &[u32]) -> Message {
+        let number_of_pieces = iceberg_torrent().number_of_pieces() as usize;
+        let mut bitfield = BitVec::from_elem(number_of_pieces.div_ceil(8) * 8, false);
+        for piece_index in pieces {
+            bitfield.set(*piece_index as usize, true);
+        }
+        Message::Bitfield(Bitfield::new(bitfield))
+    }
+
+    /// Messages sent to the peers since the last call
+    fn sent_messages(commands: &Receiver<Command>) -> Vec<(Peer, Message)> {
+        commands
+            .try_iter()
+            .filter_map(|command| match command {
+                Command::Send(peer, message) => Some((peer, message)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Answers the block requests among `sent` with the blocks of
+    /// iceberg.jpg.
+    fn answer_requests(state_machine: &mut StateMachine, sent: &[(Peer, Message)]) {
+        let file = fs::read("scripts/multi-client/iceberg.jpg").unwrap();
+        let piece_length = iceberg_torrent().piece_length_in_bytes() as usize;
+        for (peer, message) in sent {
+            if let Message::Request(request) = message {
+                let start =
+                    request.piece_index() as usize * piece_length + request.begin_offset() as usize;
+                let data = file[start..start + request.piece_length() as usize].to_vec();
+                let piece = Piece::new(request.piece_index(), request.begin_offset(), data);
+                state_machine.receive(*peer, Message::Piece(piece));
+            }
+        }
+    }
+
+    #[test]
+    pub fn messages_before_the_bitfield_imply_an_empty_one() {
+        let (mut state_machine, commands) = downloading_state_machine();
+        for port in [1, 2, 3] {
+            connect_peer(&mut state_machine, peer(port));
+            assert_eq!(
+                state_machine.peer_state(&peer(port)).unwrap().connection,
+                ConnectionState::BitfieldSent
+            );
+        }
+
+        state_machine.receive(peer(1), Message::Have(Have::new(3)));
+        state_machine.receive(peer(2), Message::Choke(Choke::new()));
+        state_machine.receive(peer(3), Message::NotInterested(NotInterested::new()));
+
+        for port in [1, 2, 3] {
+            let state = state_machine.peer_state(&peer(port)).unwrap();
+            assert_eq!(state.connection, ConnectionState::Ready);
+            assert!(state.peer_choking);
+            assert!(!state.peer_interested);
+            assert_eq!(state.am_interested, port == 1);
+        }
+        assert_eq!(state_machine.peers_having(3), 1);
+
+        let sent = sent_messages(&commands);
+        let interested: Vec<Peer> = sent
+            .iter()
+            .filter(|(_, message)| matches!(message, Message::Interested(_)))
+            .map(|(peer, _)| *peer)
+            .collect();
+        assert_eq!(interested, vec![peer(1)]);
+        assert!(!sent
+            .iter()
+            .any(|(_, message)| matches!(message, Message::NotInterested(_))));
+    }
+
+    #[test]
+    pub fn choking_and_interest_change_once_ready() {
+        let (mut state_machine, commands) = downloading_state_machine();
+        connect_peer(&mut state_machine, peer(1));
+        state_machine.receive(peer(1), bitfield_of(&[]));
+        assert!(!state_machine.peer_state(&peer(1)).unwrap().am_interested);
+
+        state_machine.receive(peer(1), Message::Have(Have::new(0)));
+        state_machine.receive(peer(1), Message::Interested(Interested::new()));
+        let state = state_machine.peer_state(&peer(1)).unwrap();
+        assert!(state.am_interested);
+        assert!(state.peer_interested);
+        // a slot was free
+        assert!(!state.am_choking);
+        let sent = sent_messages(&commands);
+        assert!(sent
+            .iter()
+            .any(|(_, message)| matches!(message, Message::Interested(_))));
+        assert!(sent
+            .iter()
+            .any(|(_, message)| matches!(message, Message::Unchoke(_))));
+
+        state_machine.receive(peer(1), Message::NotInterested(NotInterested::new()));
+        assert!(!state_machine.peer_state(&peer(1)).unwrap().peer_interested);
+
+        // the blocks requested are not coming once choked
+        state_machine.receive(peer(1), Message::Unchoke(Unchoke::new()));
+        state_machine.download();
+        assert_eq!(state_machine.outstanding_blocks(&peer(1)), 2);
+        state_machine.receive(peer(1), Message::Choke(Choke::new()));
+        assert!(state_machine.peer_state(&peer(1)).unwrap().peer_choking);
+        assert_eq!(state_machine.outstanding_blocks(&peer(1)), 0);
+
+        // once its only piece is downloaded, the peer is not interesting
+        state_machine.receive(peer(1), Message::Unchoke(Unchoke::new()));
+        state_machine.download();
+        answer_requests(&mut state_machine, &sent_messages(&commands));
+        assert!(state_machine.has_piece(0));
+        assert!(!state_machine.peer_state(&peer(1)).unwrap().am_interested);
+        assert!(sent_messages(&commands)
+            .iter()
+            .any(|(_, message)| matches!(message, Message::NotInterested(_))));
+    }
+
+    #[test]
+    pub fn banned_peers_are_disconnected() {
+        let (mut state_machine, commands) = downloading_state_machine();
+        connect_peer(&mut state_machine, peer(1));
+        state_machine.receive(peer(1), bitfield_of(&[0, 1]));
+        state_machine.receive(peer(1), Message::Unchoke(Unchoke::new()));
+        state_machine.download();
+
+        // both pieces fail their hash check
+        for (peer, message) in sent_messages(&commands) {
+            if let Message::Request(request) = message {
+                let data = vec![0; request.piece_length() as usize];
+                let piece = Piece::new(request.piece_index(), request.begin_offset(), data);
+                state_machine.receive(peer, Message::Piece(piece));
+            }
+        }
+
+        assert!(state_machine.peer_state(&peer(1)).is_none());
+        assert!(commands
+            .try_iter()
+            .any(|command| matches!(command, Command::Disconnect(banned) if banned == peer(1))));
+    }
+
+    #[test]
+    pub fn peers_are_advertised_at_their_listen_port() {
+        let (mut state_machine, _commands) = downloading_state_machine();
+        // dialed
+        assert!(state_machine.add(peer(1)));
+        state_machine.download();
+        connect_peer(&mut state_machine, peer(1));
+        // connected to us, from a port they do not listen on
+        connect_peer(&mut state_machine, peer(2));
+        connect_peer(&mut state_machine, peer(3));
+        let handshake = ExtensionHandshake::new(Default::default(), None, None, None, None)
+            .with_listen_port(7000);
+        let extended = Extended::new(Extended::HANDSHAKE_ID, handshake.into_bytes());
+        state_machine.receive(peer(3), Message::Extended(extended));
+
+        let advertised: HashSet<Peer> = state_machine.advertised_peers().into_keys().collect();
+        assert_eq!(advertised, HashSet::from([peer(1), peer(7000)]));
+    }
+
+    #[test]
+    pub fn verified_pieces_are_announced_to_the_peers_missing_them() {
+        let (mut state_machine, commands) = downloading_state_machine();
+        for port in [1, 2, 3, 4] {
+            connect_peer(&mut state_machine, peer(port));
+        }
+        state_machine.receive(peer(1), bitfield_of(&[0]));
+        state_machine.receive(peer(2), bitfield_of(&[]));
+        state_machine.receive(peer(3), bitfield_of(&[0, 1]));
+        // peer(4) did not send its bitfield yet
+        assert_eq!(state_machine.peers_having(0), 2);
+        assert_eq!(state_machine.peers_having(1), 1);
+
+        state_machine.receive(peer(1), Message::Unchoke(Unchoke::new()));
+        state_machine.download();
+        answer_requests(&mut state_machine, &sent_messages(&commands));
+        assert!(state_machine.has_piece(0));
+
+        let mut have_receivers: Vec<Peer> = sent_messages(&commands)
+            .into_iter()
+            .filter(
+                |(_, message)| matches!(message, Message::Have(have) if have.piece_index() == 0),
+            )
+            .map(|(peer, _)| peer)
+            .collect();
+        have_receivers.sort_by_key(|peer| peer.port());
+        assert_eq!(have_receivers, vec![peer(2), peer(4)]);
+
+        // the availability follows the Have messages of the peers
+        state_machine.receive(peer(2), Message::Have(Have::new(0)));
+        state_machine.receive(peer(2), Message::Have(Have::new(0)));
+        state_machine.receive(peer(4), Message::Have(Have::new(1)));
+        assert_eq!(state_machine.peers_having(0), 3);
+        assert_eq!(state_machine.peers_having(1), 2);
+    }
+
+    #[test]
+    pub fn endgame_requests_the_last_blocks_from_every_unchoking_peer() {
+        let (mut state_machine, commands) = downloading_state_machine();
+        let pieces: Vec<u32> = (0..iceberg_torrent().number_of_pieces()).collect();
+        for port in [1, 2] {
+            connect_peer(&mut state_machine, peer(port));
+            state_machine.receive(peer(port), bitfield_of(&pieces));
+            state_machine.receive(peer(port), Message::Unchoke(Unchoke::new()));
+        }
+        sent_messages(&commands);
+        // the whole torrent fits in the request queue of the first peer
+        state_machine.download();
+
+        let sent = sent_messages(&commands);
+        let requests_to = |port| {
+            sent.iter()
+                .filter(|(to, message)| *to == peer(port) && matches!(message, Message::Request(_)))
+                .count()
+        };
+        let blocks = 2 * pieces.len();
+        assert_eq!(requests_to(1), blocks);
+        assert_eq!(requests_to(2), blocks);
+
+        // the blocks received from one peer are cancelled with the other
+        let first_peer = sent[0].0;
+        let (from_first, _): (Vec<_>, Vec<_>) =
+            sent.into_iter().partition(|(to, _)| *to == first_peer);
+        answer_requests(&mut state_machine, &from_first);
+        assert!(pieces
+            .iter()
+            .all(|piece_index| state_machine.has_piece(*piece_index)));
+        let cancels = sent_messages(&commands)
+            .into_iter()
+            .filter(|(to, message)| *to != first_peer && matches!(message, Message::Cancel(_)))
+            .count();
+        assert_eq!(cancels, blocks);
+    }
+
+    #[test]
+    pub fn bitfield_padding_is_not_pieces() {
+        let (mut state_machine, commands) = downloading_state_machine();
+        let number_of_pieces = iceberg_torrent().number_of_pieces();
+        connect_peer(&mut state_machine, peer(1));
+        state_machine.receive(peer(1), Message::HaveAll(HaveAll::new()));
+        connect_peer(&mut state_machine, peer(2));
+        // padding bits set by the peer
+        let padded_length = (number_of_pieces as usize).div_ceil(8) * 8;
+        state_machine.receive(
+            peer(2),
+            Message::Bitfield(Bitfield::new(BitVec::from_elem(padded_length, true))),
+        );
+        state_machine.receive(peer(2), Message::Have(Have::new(number_of_pieces)));
+
+        assert_eq!(state_machine.peers_having(number_of_pieces - 1), 2);
+        assert_eq!(state_machine.peers_having(number_of_pieces), 0);
+
+        state_machine.receive(peer(1), Message::Unchoke(Unchoke::new()));
+        state_machine.download();
+        answer_requests(&mut state_machine, &sent_messages(&commands));
+        assert!((0..number_of_pieces).all(|piece_index| state_machine.has_piece(piece_index)));
+        for port in [1, 2] {
+            assert!(!state_machine.peer_state(&peer(port)).unwrap().am_interested);
+        }
+    }
+
+    #[test]
+    pub fn private_torrent_is_left_out_of_the_dht() {
+        let (state_machine, commands) = private_state_machine();
+        let routing_table = RoutingTable::new(NodeId::new([0; 20]));
+        let dht = DhtNode::bind("127.0.0.1:0".parse().unwrap(), routing_table).unwrap();
+        let mut state_machine = state_machine.with_dht(Arc::new(dht), vec![]);
+
+        let handshake = Handshake::new(private_torrent().info_hash(), [1; 20]).advertise_dht();
+        state_machine.receive(peer(1), Message::Handshake(handshake));
+
+        let sent = sent_messages(&commands);
+        assert!(sent.iter().any(|(_, message)| matches!(
+            message,
+            Message::Handshake(handshake) if !handshake.supports_dht()
+        )));
+        assert!(!sent
+            .iter()
+            .any(|(_, message)| matches!(message, Message::Port(_))));
+    }
+
+    #[test]
+    pub fn private_torrent_does_without_peer_exchange() {
+        let (mut state_machine, commands) = private_state_machine();
+        let info_hash = private_torrent().info_hash();
+        for port in [1, 2] {
+            let handshake = Handshake::with_extension_protocol(info_hash, [port as u8; 20]);
+            state_machine.receive(peer(port), Message::Handshake(handshake));
+        }
+        let extensions = BTreeMap::from([(PexMessage::EXTENSION_NAME.to_string(), 1)]);
+        let handshake = ExtensionHandshake::new(extensions, None, None, None, None);
+        let extended = Extended::new(Extended::HANDSHAKE_ID, handshake.into_bytes());
+        state_machine.receive(peer(1), Message::Extended(extended));
+        let pex = PexMessage::new(vec![(peer(9), 0)], vec![]);
+        state_machine.receive(
+            peer(1),
+            Message::Extended(Extended::new(1, pex.into_bytes())),
+        );
+        state_machine.exchange();
+
+        let sent = sent_messages(&commands);
+        let extended: Vec<&Extended> = sent
+            .iter()
+            .filter_map(|(_, message)| match message {
+                Message::Extended(extended) => Some(extended),
+                _ => None,
+            })
+            .collect();
+        // only our extension handshakes, without the peer exchange
+        assert_eq!(extended.len(), 2);
+        for extended in extended {
+            assert_eq!(extended.extended_message_id(), Extended::HANDSHAKE_ID);
+            let (handshake, _) = ExtensionHandshake::from_bytes(extended.payload()).unwrap();
+            assert!(!handshake
+                .extensions()
+                .contains_key(PexMessage::EXTENSION_NAME));
+        }
+        assert!(state_machine.add(peer(9)));
+    }
+
+    #[test]
+    pub fn invalid_requests_are_rejected() {
+        let (mut state_machine, commands) = seeding_state_machine();
+        let info_hash = iceberg_torrent().info_hash();
+        let handshake = Handshake::new(info_hash, [1; 20]).advertise_fast_extension();
+        state_machine.receive(peer(1), Message::Handshake(handshake));
+        connect_peer(&mut state_machine, peer(2));
+        let allowed_fast = sent_messages(&commands)
+            .into_iter()
+            .find_map(|(_, message)| match message {
+                Message::AllowedFast(allowed_fast) => Some(allowed_fast.piece_index()),
+                _ => None,
+            })
+            .unwrap();
+        let piece_length = iceberg_torrent().piece_length_in_bytes() as u32;
+
+        let request = Request::new(allowed_fast, 0, 100);
+        state_machine.receive(peer(1), Message::Request(request));
+        match sent_messages(&commands).as_slice() {
+            [(_, Message::Piece(piece))] => assert_eq!(piece.data().len(), 100),
+            sent => panic!("Expected the block, sent {:?}", sent),
+        }
+
+        let number_of_pieces = iceberg_torrent().number_of_pieces();
+        for request in [
+            Request::new(number_of_pieces, 0, 100),
+            Request::new(allowed_fast, piece_length - 100, 16384),
+            Request::new(allowed_fast, 0, 32768),
+        ] {
+            state_machine.receive(peer(1), Message::Request(request));
+            let sent = sent_messages(&commands);
+            assert!(matches!(sent.as_slice(), [(_, Message::RejectRequest(_))]));
+        }
+        assert!(state_machine.peer_state(&peer(1)).is_some());
+
+        let request = Request::new(number_of_pieces, 0, 100);
+        state_machine.receive(peer(2), Message::Request(request));
+        assert!(state_machine.peer_state(&peer(2)).is_none());
+    }
 }
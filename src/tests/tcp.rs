@@ -4,21 +4,21 @@ pub mod user_case {
         http::Peer,
         pwp::{
             Bitfield, Handshake, Interested, MandatoryBitTorrentMessageFields, Message,
-            MessageType, Request,
+            MessageType, Piece, Request,
         },
-        tcp::TcpSession,
+        tcp::{EncryptionPolicy, Rc4, TcpSession},
         tests::pwp::unittest::{path_build_to_pwp_message, read_bytes_from},
-        BlockReaderWriter, Torrent,
+        BlockReaderWriter, Error, KeepAlive, Torrent,
     };
     use core::panic;
     use std::{
         fs::File,
         io::{self, Read},
-        net::{IpAddr, Ipv4Addr, SocketAddr},
+        net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, TcpStream},
         path::Path,
         process::{Child, Command},
-        thread::sleep,
-        time::Duration,
+        thread::{self, sleep, JoinHandle},
+        time::{Duration, Instant},
     };
 
     use bendy::decoding::Decoder;
@@ -624,4 +624,147 @@ pub mod user_case {
             }
         };
     }
+
+    const INFO_HASH: [u8; 20] = [0xaa; 20];
+
+    /// Accepts one connection on loopback with the `policy` of the listener
+    fn accept_one(policy: EncryptionPolicy) -> (Peer, JoinHandle<Result<TcpSession, Error>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let peer = Peer::from_socket_address(listener.local_addr().unwrap());
+        let listener_thread = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            TcpSession::accept(stream, INFO_HASH, policy)
+        });
+
+        (peer, listener_thread)
+    }
+
+    fn wait_message(tcp_session: &mut TcpSession) -> Message {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            if let Some(message) = tcp_session.receive().unwrap() {
+                return message;
+            }
+            sleep(Duration::from_millis(10));
+        }
+        panic!("no message received")
+    }
+
+    #[test]
+    pub fn rc4_test_vector() {
+        let mut bytes = b"Plaintext".to_vec();
+        Rc4::new(b"Key").apply_keystream(&mut bytes);
+
+        assert_eq!(
+            bytes,
+            [0xBB, 0xF3, 0x16, 0xE8, 0xD9, 0x40, 0xAF, 0x0A, 0xD3]
+        );
+    }
+
+    #[test]
+    pub fn encrypted_session_exchanges_messages() {
+        let (peer, listener_thread) = accept_one(EncryptionPolicy::Prefer);
+        let mut client =
+            TcpSession::with_encryption(peer, INFO_HASH, EncryptionPolicy::Require).unwrap();
+        let mut server = listener_thread.join().unwrap().unwrap();
+        assert!(client.is_encrypted());
+        assert!(server.is_encrypted());
+
+        client.send(Handshake::new(INFO_HASH, PEER_ID)).unwrap();
+        client.send(KeepAlive::new()).unwrap();
+        client.send(Interested::new()).unwrap();
+        match wait_message(&mut server) {
+            Message::Handshake(handshake) => assert_eq!(handshake.info_hash(), INFO_HASH),
+            message => panic!("unexpected {:?}", message),
+        }
+        assert!(matches!(wait_message(&mut server), Message::KeepAlive(_)));
+        assert!(matches!(wait_message(&mut server), Message::Interested(_)));
+
+        server.send(Request::new(1, 0x4000, 0x4000)).unwrap();
+        match wait_message(&mut client) {
+            Message::Request(request) => assert_eq!(request.begin_offset(), 0x4000),
+            message => panic!("unexpected {:?}", message),
+        }
+    }
+
+    #[test]
+    pub fn plaintext_handshake_accepted_when_encryption_is_preferred() {
+        let (peer, listener_thread) = accept_one(EncryptionPolicy::Prefer);
+        let mut client = TcpSession::connect(peer).unwrap();
+        client.send(Handshake::new(INFO_HASH, PEER_ID)).unwrap();
+        let mut server = listener_thread.join().unwrap().unwrap();
+
+        assert!(!server.is_encrypted());
+        assert!(matches!(wait_message(&mut server), Message::Handshake(_)));
+    }
+
+    #[test]
+    pub fn plaintext_handshake_refused_when_encryption_is_required() {
+        let (peer, listener_thread) = accept_one(EncryptionPolicy::Require);
+        let mut client = TcpSession::connect(peer).unwrap();
+        client.send(Handshake::new(INFO_HASH, PEER_ID)).unwrap();
+
+        assert!(matches!(
+            listener_thread.join().unwrap(),
+            Err(Error::PlaintextConnectionRefused)
+        ));
+    }
+
+    #[test]
+    pub fn encrypted_handshake_for_another_torrent_is_refused() {
+        let (peer, listener_thread) = accept_one(EncryptionPolicy::Require);
+        let client = TcpSession::with_encryption(peer, [0xbb; 20], EncryptionPolicy::Require);
+
+        assert!(client.is_err());
+        assert!(matches!(
+            listener_thread.join().unwrap(),
+            Err(Error::UnknownEncryptedInfoHash)
+        ));
+    }
+
+    #[test]
+    pub fn session_queues_what_the_socket_does_not_take() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client =
+            TcpSession::from_transport(TcpStream::connect(listener.local_addr().unwrap()).unwrap())
+                .unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+
+        // far more than the socket buffers, while the peer does not read
+        for piece_index in 0..32 {
+            client
+                .send(Piece::new(piece_index, 0, vec![0; 1 << 20]))
+                .unwrap();
+        }
+        assert!(client.pending_send_length() > 0);
+
+        let reader = thread::spawn(move || {
+            let mut bytes = Vec::new();
+            server.read_to_end(&mut bytes).unwrap();
+            bytes.len()
+        });
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while client.pending_send_length() > 0 {
+            assert!(Instant::now() < deadline, "queued bytes not sent");
+            client.flush().unwrap();
+            sleep(Duration::from_millis(1));
+        }
+        drop(client);
+
+        assert_eq!(reader.join().unwrap(), 32 * (13 + (1 << 20)));
+    }
+
+    #[test]
+    pub fn session_fails_once_the_peer_closed_the_connection() {
+        let (peer, listener_thread) = accept_one(EncryptionPolicy::Prefer);
+        let mut client = TcpSession::connect(peer).unwrap();
+        client.send(Handshake::new(INFO_HASH, PEER_ID)).unwrap();
+        client.send(Interested::new()).unwrap();
+        let mut server = listener_thread.join().unwrap().unwrap();
+        drop(client);
+
+        assert!(matches!(wait_message(&mut server), Message::Handshake(_)));
+        assert!(matches!(wait_message(&mut server), Message::Interested(_)));
+        assert!(matches!(server.receive(), Err(Error::PeerClosedConnection)));
+    }
 }
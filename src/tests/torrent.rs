@@ -1,6 +1,6 @@
 #[cfg(test)]
 pub mod test {
-    use crate::{Error, Torrent};
+    use crate::{torrent, Error, Torrent};
     use bendy::decoding::Decoder;
     use std::{fs::File, io::Read, path::Path};
 
@@ -31,4 +31,223 @@ pub mod test {
             Err(e) => Err(e),
         }
     }
+
+    /// Bencoded multi-file torrent with two files of 20000 and 30000 bytes,
+    /// the second one being in a subdirectory.
+    pub fn multi_file_torrent_bencode(piece_length: u32) -> Vec<u8> {
+        let number_of_pieces = 50000_usize.div_ceil(piece_length as usize);
+        let mut bencode = format!(
+            "d8:announce30:http://127.0.0.1:6969/announce4:infod5:filesl\
+             d6:lengthi20000e4:pathl9:first.bineed6:lengthi30000e6:md5sum32:\
+             0123456789abcdef0123456789abcdef4:pathl6:nested10:second.bineee\
+             4:name7:release12:piece lengthi{}e6:pieces{}:",
+            piece_length,
+            number_of_pieces * 20
+        )
+        .into_bytes();
+        bencode.extend(vec![0xAB; number_of_pieces * 20]);
+        bencode.extend(b"ee");
+
+        bencode
+    }
+
+    /// Bencoded single-file torrent whose piece hashes are all zeros.
+    pub fn single_file_torrent_bencode(name: &str, length: u64, piece_length: u64) -> Vec<u8> {
+        let number_of_pieces = length.div_ceil(piece_length) as usize;
+        let mut bencode = format!(
+            "d8:announce30:http://127.0.0.1:6969/announce4:infod6:lengthi{}e\
+             4:name{}:{}12:piece lengthi{}e6:pieces{}:",
+            length,
+            name.len(),
+            name,
+            piece_length,
+            number_of_pieces * 20
+        )
+        .into_bytes();
+        bencode.extend(vec![0x00; number_of_pieces * 20]);
+        bencode.extend(b"ee");
+
+        bencode
+    }
+
+    #[test]
+    pub fn parse_multi_file_torrent() {
+        let bencode = multi_file_torrent_bencode(32 * 1024);
+        let mut bencode_decoder = Decoder::new(&bencode);
+        let torrent = Torrent::from_bencode(&mut bencode_decoder).unwrap();
+
+        assert!(torrent.is_multi_file());
+        assert_eq!(torrent.name(), "release");
+        assert_eq!(torrent.total_length_in_bytes(), 50000);
+        assert_eq!(torrent.number_of_pieces(), 2);
+
+        let files = torrent.files();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].path(), &vec!["first.bin".to_string()]);
+        assert_eq!(files[0].length(), 20000);
+        assert_eq!(files[0].md5sum(), None);
+        assert_eq!(
+            files[1].relative_path(),
+            Path::new("nested").join("second.bin")
+        );
+        assert_eq!(files[1].length(), 30000);
+        assert_eq!(
+            files[1].md5sum().map(|sum| sum.as_str()),
+            Some("0123456789abcdef0123456789abcdef")
+        );
+    }
+
+    #[test]
+    pub fn single_file_torrent_has_one_file() {
+        let filepath = Path::new("samples/upload/iceberg.jpg.torrent");
+        let torrent = Torrent::from_file(filepath).unwrap();
+
+        assert!(!torrent.is_multi_file());
+        assert_eq!(torrent.files().len(), 1);
+        assert_eq!(torrent.files()[0].path(), &vec!["iceberg.jpg".to_string()]);
+        assert_eq!(torrent.files()[0].length(), torrent.total_length_in_bytes());
+    }
+
+    #[test]
+    pub fn reject_path_escaping_the_torrent_directory() {
+        let bencode = b"d4:infod5:filesld6:lengthi10e4:pathl2:..6:passwdeee\
+                        4:name7:release12:piece lengthi16384e6:pieces20:\
+                        aaaaaaaaaaaaaaaaaaaaee";
+        let mut bencode_decoder = Decoder::new(bencode);
+
+        assert!(matches!(
+            Torrent::from_bencode(&mut bencode_decoder),
+            Err(Error::InvalidFilePathComponent)
+        ));
+    }
+
+    #[test]
+    pub fn reject_name_escaping_the_working_directory() {
+        for name in ["..", "../passwd", "/etc/passwd", "..\\passwd"] {
+            let bencode = format!(
+                "d4:infod6:lengthi10e4:name{}:{}12:piece lengthi16384e\
+                 6:pieces20:aaaaaaaaaaaaaaaaaaaaee",
+                name.len(),
+                name
+            );
+            let mut bencode_decoder = Decoder::new(bencode.as_bytes());
+
+            assert!(matches!(
+                Torrent::from_bencode(&mut bencode_decoder),
+                Err(Error::InvalidFilePathComponent)
+            ));
+        }
+    }
+
+    #[test]
+    pub fn reject_torrent_without_length() {
+        let bencode = b"d4:infod4:name7:release12:piece lengthi16384e\
+                        6:pieces20:aaaaaaaaaaaaaaaaaaaaee";
+        let mut bencode_decoder = Decoder::new(bencode);
+
+        assert!(matches!(
+            Torrent::from_bencode(&mut bencode_decoder),
+            Err(Error::TotalPiecesLengthNotFoundDuringParsing)
+        ));
+    }
+
+    #[test]
+    pub fn parse_torrent_larger_than_four_gigabytes() {
+        let total_length = 5 * 1024 * 1024 * 1024 + 1000;
+        let bencode = single_file_torrent_bencode("sparse.img", total_length, 1024 * 1024);
+        let mut bencode_decoder = Decoder::new(&bencode);
+        let torrent = Torrent::from_bencode(&mut bencode_decoder).unwrap();
+
+        assert_eq!(torrent.total_length_in_bytes(), total_length);
+        assert_eq!(torrent.number_of_pieces(), 5121);
+        assert_eq!(torrent::expected_piece_length(5119, &torrent), 1024 * 1024);
+        assert_eq!(torrent::expected_piece_length(5120, &torrent), 1000);
+        assert_eq!(torrent::expected_blocks_in_piece(5119, &torrent), 64);
+        assert_eq!(torrent::expected_blocks_in_piece(5120, &torrent), 1);
+        assert_eq!(torrent::expected_block_length(5119, 63, &torrent), 16384);
+        assert_eq!(torrent::expected_block_length(5120, 0, &torrent), 1000);
+    }
+
+    #[test]
+    pub fn last_block_length_when_pieces_are_aligned() {
+        let bencode = single_file_torrent_bencode("aligned.bin", 4 * 32 * 1024, 32 * 1024);
+        let mut bencode_decoder = Decoder::new(&bencode);
+        let torrent = Torrent::from_bencode(&mut bencode_decoder).unwrap();
+
+        assert_eq!(torrent::expected_blocks_in_piece(3, &torrent), 2);
+        assert_eq!(torrent::expected_block_length(3, 1, &torrent), 16384);
+    }
+
+    #[test]
+    pub fn parse_announce_list_tiers() {
+        let bencode = b"d8:announce21:http://a.org/announce13:announce-listll\
+                        21:http://a.org/announce21:http://b.org/announceele\
+                        l25:udp://c.org:1337/announceee4:infod6:lengthi10e\
+                        4:name4:file12:piece lengthi16384e6:pieces20:aaaaaaaaaaaaaaaaaaaaee";
+        let mut bencode_decoder = Decoder::new(bencode);
+        let torrent = Torrent::from_bencode(&mut bencode_decoder).unwrap();
+
+        assert_eq!(
+            torrent.announce_tiers(),
+            vec![
+                vec![
+                    "http://a.org/announce".to_string(),
+                    "http://b.org/announce".to_string()
+                ],
+                vec!["udp://c.org:1337/announce".to_string()]
+            ]
+        );
+    }
+
+    #[test]
+    pub fn announce_is_the_only_tier_without_announce_list() {
+        let filepath = Path::new("samples/upload/iceberg.jpg.torrent");
+        let torrent = Torrent::from_file(filepath).unwrap();
+
+        assert_eq!(
+            torrent.announce_tiers(),
+            vec![vec!["http://127.0.0.1:6969/announce".to_string()]]
+        );
+    }
+
+    /// Bencoded single-file torrent with the private flag, see BEP 27.
+    pub fn private_torrent_bencode(name: &str, length: u64, piece_length: u64) -> Vec<u8> {
+        let mut bencode = single_file_torrent_bencode(name, length, piece_length);
+        bencode.truncate(bencode.len() - 2);
+        bencode.extend(b"7:privatei1eee");
+
+        bencode
+    }
+
+    #[test]
+    pub fn parse_private_flag() {
+        let bencode = private_torrent_bencode("file", 10, 16384);
+        let mut bencode_decoder = Decoder::new(&bencode);
+        let torrent = Torrent::from_bencode(&mut bencode_decoder).unwrap();
+        assert!(torrent.is_private());
+
+        let bencode = single_file_torrent_bencode("file", 10, 16384);
+        let mut bencode_decoder = Decoder::new(&bencode);
+        let torrent = Torrent::from_bencode(&mut bencode_decoder).unwrap();
+        assert!(!torrent.is_private());
+    }
+
+    #[test]
+    pub fn parse_dht_nodes() {
+        let bencode = b"d4:infod6:lengthi10e4:name4:file12:piece lengthi16384e\
+                        6:pieces20:aaaaaaaaaaaaaaaaaaaae5:nodesl\
+                        l9:127.0.0.1i6881ee\
+                        l6:brokene\
+                        l11:router.testi51413eeee";
+        let mut bencode_decoder = Decoder::new(bencode);
+        let torrent = Torrent::from_bencode(&mut bencode_decoder).unwrap();
+
+        assert_eq!(
+            torrent.nodes(),
+            &vec![
+                ("127.0.0.1".to_string(), 6881),
+                ("router.test".to_string(), 51413)
+            ]
+        );
+    }
 }
@@ -0,0 +1,295 @@
+#[cfg(test)]
+mod test {
+    use crate::{
+        http::{Event, Peer, ScrapeStatistics, TrackerAddress, TrackerProtocol, TrackerRequest},
+        pwp::IntoBytes,
+        udp::{UdpTracker, UdpTrackerRequest, UdpTrackers},
+        Error,
+    };
+    use reqwest::Url;
+    use std::{
+        net::{SocketAddr, UdpSocket},
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        thread,
+        time::Duration,
+    };
+
+    const INFO_HASH: [u8; 20] = [0x06; 20];
+    const PEER_ID: [u8; 20] = [0x2d; 20];
+    const CONNECTION_ID: u64 = 0xC0FFEE;
+
+    /// Minimal UDP tracker answering on loopback, used instead of a real one.
+    struct StandInTracker {
+        address: TrackerAddress,
+        connects: Arc<AtomicUsize>,
+        packets: Arc<AtomicUsize>,
+    }
+
+    #[derive(Clone, Copy)]
+    struct StandInBehaviour {
+        /// number of packets received before the tracker starts answering
+        ignored_packets: usize,
+        /// answer announces with an error action
+        refuse_announces: bool,
+    }
+
+    impl StandInTracker {
+        fn start(behaviour: StandInBehaviour) -> Self {
+            let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+            socket
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            let url = format!("udp://{}", socket.local_addr().unwrap());
+            let address = TrackerAddress::from_url(Url::parse(&url).unwrap()).unwrap();
+
+            let connects = Arc::new(AtomicUsize::new(0));
+            let packets = Arc::new(AtomicUsize::new(0));
+            let (connects_ref, packets_ref) = (connects.clone(), packets.clone());
+            thread::spawn(move || Self::serve(socket, behaviour, connects_ref, packets_ref));
+
+            Self {
+                address,
+                connects,
+                packets,
+            }
+        }
+
+        fn serve(
+            socket: UdpSocket,
+            behaviour: StandInBehaviour,
+            connects: Arc<AtomicUsize>,
+            packets: Arc<AtomicUsize>,
+        ) {
+            let mut buffer = [0u8; 2048];
+
+            while let Ok((length, client)) = socket.recv_from(&mut buffer) {
+                let received_packets = packets.fetch_add(1, Ordering::SeqCst) + 1;
+                if received_packets <= behaviour.ignored_packets {
+                    continue;
+                }
+
+                let request = &buffer[..length];
+                let action = u32::from_be_bytes(request[8..12].try_into().unwrap());
+                let transaction_id = &request[12..16];
+
+                let mut response = Vec::new();
+                match action {
+                    UdpTrackerRequest::CONNECT_ACTION => {
+                        connects.fetch_add(1, Ordering::SeqCst);
+                        response.extend(0u32.to_be_bytes());
+                        response.extend(transaction_id);
+                        response.extend(CONNECTION_ID.to_be_bytes());
+                    }
+                    UdpTrackerRequest::ANNOUNCE_ACTION if behaviour.refuse_announces => {
+                        response.extend(3u32.to_be_bytes());
+                        response.extend(transaction_id);
+                        response.extend(b"unregistered torrent");
+                    }
+                    UdpTrackerRequest::ANNOUNCE_ACTION => {
+                        assert_eq!(request[0..8], CONNECTION_ID.to_be_bytes());
+                        assert_eq!(length, 98);
+                        response.extend(1u32.to_be_bytes());
+                        response.extend(transaction_id);
+                        response.extend(1800u32.to_be_bytes());
+                        response.extend(2u32.to_be_bytes());
+                        response.extend(1u32.to_be_bytes());
+                        response.extend([10, 0, 0, 1, 0x1A, 0xE1]);
+                        response.extend([10, 0, 0, 2, 0x1A, 0xE2]);
+                    }
+                    UdpTrackerRequest::SCRAPE_ACTION => {
+                        response.extend(2u32.to_be_bytes());
+                        response.extend(transaction_id);
+                        for (index, _) in request[16..].chunks(20).enumerate() {
+                            response.extend((5 + index as u32).to_be_bytes());
+                            response.extend(10u32.to_be_bytes());
+                            response.extend(3u32.to_be_bytes());
+                        }
+                    }
+                    _ => continue,
+                }
+
+                socket.send_to(&response, client).unwrap();
+            }
+        }
+    }
+
+    fn announce_request() -> TrackerRequest {
+        TrackerRequest::new(
+            INFO_HASH,
+            PEER_ID,
+            6882,
+            100,
+            200,
+            300,
+            true,
+            Some(Event::Started),
+        )
+    }
+
+    fn peer(address: &str) -> Peer {
+        Peer::from_socket_address(address.parse::<SocketAddr>().unwrap())
+    }
+
+    #[test]
+    fn announce_to_udp_tracker() {
+        let tracker = StandInTracker::start(StandInBehaviour {
+            ignored_packets: 0,
+            refuse_announces: false,
+        });
+        let mut client = UdpTracker::new(&tracker.address).unwrap();
+
+        let response = client.announce(&announce_request()).unwrap();
+        assert_eq!(response.interval(), Some(1800));
+        assert_eq!(response.incomplete(), Some(2));
+        assert_eq!(response.complete(), Some(1));
+        assert_eq!(
+            response.peers(),
+            Some(&vec![peer("10.0.0.1:6881"), peer("10.0.0.2:6882")])
+        );
+    }
+
+    #[test]
+    fn connection_id_is_reused() {
+        let tracker = StandInTracker::start(StandInBehaviour {
+            ignored_packets: 0,
+            refuse_announces: false,
+        });
+        let mut client = UdpTracker::new(&tracker.address).unwrap();
+
+        client.announce(&announce_request()).unwrap();
+        client.announce(&announce_request()).unwrap();
+        client.scrape(&[INFO_HASH]).unwrap();
+
+        assert_eq!(tracker.connects.load(Ordering::SeqCst), 1);
+        assert_eq!(tracker.packets.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn connection_id_is_reused_across_announces() {
+        let tracker = StandInTracker::start(StandInBehaviour {
+            ignored_packets: 0,
+            refuse_announces: false,
+        });
+        let mut udp_trackers = UdpTrackers::new();
+
+        for _ in 0..2 {
+            let response = TrackerRequest::send_request(
+                announce_request(),
+                tracker.address.clone(),
+                &mut udp_trackers,
+            )
+            .unwrap();
+            assert_eq!(response.interval(), Some(1800));
+        }
+
+        assert_eq!(tracker.connects.load(Ordering::SeqCst), 1);
+        assert_eq!(tracker.packets.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn retransmit_lost_requests() {
+        let tracker = StandInTracker::start(StandInBehaviour {
+            ignored_packets: 2,
+            refuse_announces: false,
+        });
+        let mut client =
+            UdpTracker::with_timeout(&tracker.address, Duration::from_millis(50), 3).unwrap();
+
+        assert!(client.announce(&announce_request()).is_ok());
+        assert_eq!(tracker.connects.load(Ordering::SeqCst), 1);
+        assert_eq!(tracker.packets.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn give_up_when_tracker_does_not_answer() {
+        let tracker = StandInTracker::start(StandInBehaviour {
+            ignored_packets: usize::MAX,
+            refuse_announces: false,
+        });
+        let mut client =
+            UdpTracker::with_timeout(&tracker.address, Duration::from_millis(20), 2).unwrap();
+
+        assert!(matches!(
+            client.announce(&announce_request()),
+            Err(Error::UdpTrackerDidNotAnswer)
+        ));
+        assert_eq!(tracker.packets.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn tracker_error_action_is_a_failure() {
+        let tracker = StandInTracker::start(StandInBehaviour {
+            ignored_packets: 0,
+            refuse_announces: true,
+        });
+        let mut client = UdpTracker::new(&tracker.address).unwrap();
+
+        let response = client.announce(&announce_request()).unwrap();
+        assert_eq!(
+            response.failure(),
+            Some(&"unregistered torrent".to_string())
+        );
+        assert_eq!(response.peers(), None);
+    }
+
+    #[test]
+    fn scrape_udp_tracker() {
+        let tracker = StandInTracker::start(StandInBehaviour {
+            ignored_packets: 0,
+            refuse_announces: false,
+        });
+        let mut client = UdpTracker::new(&tracker.address).unwrap();
+
+        let statistics = client.scrape(&[INFO_HASH, [0x07; 20]]).unwrap();
+        assert_eq!(
+            statistics,
+            vec![
+                ScrapeStatistics::new(5, 10, 3),
+                ScrapeStatistics::new(6, 10, 3)
+            ]
+        );
+    }
+
+    #[test]
+    fn announce_request_into_bytes() {
+        let bytes =
+            UdpTrackerRequest::announce(CONNECTION_ID, 42, 7, &announce_request()).into_bytes();
+
+        assert_eq!(bytes.len(), 98);
+        assert_eq!(bytes[0..8], CONNECTION_ID.to_be_bytes());
+        assert_eq!(bytes[8..12], 1u32.to_be_bytes());
+        assert_eq!(bytes[12..16], 42u32.to_be_bytes());
+        assert_eq!(bytes[16..36], INFO_HASH);
+        assert_eq!(bytes[36..56], PEER_ID);
+        assert_eq!(bytes[56..64], 200u64.to_be_bytes());
+        assert_eq!(bytes[64..72], 300u64.to_be_bytes());
+        assert_eq!(bytes[72..80], 100u64.to_be_bytes());
+        assert_eq!(bytes[80..84], 2u32.to_be_bytes());
+        assert_eq!(bytes[88..92], 7u32.to_be_bytes());
+        assert_eq!(bytes[92..96], (-1i32).to_be_bytes());
+        assert_eq!(bytes[96..98], 6882u16.to_be_bytes());
+    }
+
+    #[test]
+    fn tracker_address_ports() {
+        let address = |url: &str| TrackerAddress::from_url(Url::parse(url).unwrap());
+
+        let udp = address("udp://tracker.example.org:1337/announce").unwrap();
+        assert_eq!(udp.protocol(), TrackerProtocol::Udp);
+        assert_eq!(udp.port(), 1337);
+        assert!(matches!(
+            address("udp://tracker.example.org/announce"),
+            Err(Error::TrackerPortNotProvided)
+        ));
+
+        assert_eq!(address("http://example.org/announce").unwrap().port(), 80);
+        assert_eq!(address("https://example.org/announce").unwrap().port(), 443);
+        assert!(matches!(
+            address("wss://example.org/announce"),
+            Err(Error::UnsupportedTrackerProtocol)
+        ));
+    }
+}
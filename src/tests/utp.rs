@@ -0,0 +1,256 @@
+#[cfg(test)]
+mod test {
+    use crate::{
+        http::Peer,
+        pwp::{FromBytes, Handshake, IntoBytes, Message, Piece},
+        tcp::{EncryptionPolicy, TcpSession},
+        utp::{
+            Ledbat, SimulatedLink, UtpConnection, UtpConnectionState, UtpPacket, UtpPacketType,
+            UtpSocket, UtpStream,
+        },
+        Error,
+    };
+    use std::{
+        io::{Read, Write},
+        net::SocketAddr,
+        thread::{self, sleep},
+        time::{Duration, Instant},
+    };
+
+    const INFO_HASH: [u8; 20] = [0xaa; 20];
+    const PEER_ID: [u8; 20] = [0x2d; 20];
+
+    fn loopback_socket(link: SimulatedLink) -> (UtpSocket, SocketAddr) {
+        let socket = UtpSocket::with_link(SocketAddr::from(([127, 0, 0, 1], 0)), link).unwrap();
+        let address = socket.local_addr().unwrap();
+        (socket, address)
+    }
+
+    /// Connected streams of a client and a server sending through `link`
+    fn connected_streams(link: SimulatedLink) -> (UtpSocket, UtpStream, UtpSocket, UtpStream) {
+        let (server_socket, server_address) = loopback_socket(link);
+        let (client_socket, _) = loopback_socket(link);
+        let client = client_socket
+            .connect(server_address, Duration::from_secs(10))
+            .unwrap();
+        let server = server_socket.accept().unwrap();
+
+        (client_socket, client, server_socket, server)
+    }
+
+    fn sample_data(length: usize) -> Vec<u8> {
+        (0..length).map(|index| (index * 7 % 251) as u8).collect()
+    }
+
+    fn read_to_length(stream: &mut UtpStream, length: usize) -> Vec<u8> {
+        let mut received = Vec::new();
+        let mut buffer = [0u8; 4096];
+        while received.len() < length {
+            match stream.read(&mut buffer).unwrap() {
+                0 => break,
+                read_bytes => received.extend_from_slice(&buffer[..read_bytes]),
+            }
+        }
+        received
+    }
+
+    #[test]
+    fn packet_header_layout() {
+        let mut packet = UtpPacket::new(UtpPacketType::Data, 0x1234, 7, 6, vec![0xff; 3]);
+        packet.stamp(0x01020304, 0x05060708, 0x00100000);
+        let bytes = packet.clone().into_bytes();
+
+        assert_eq!(bytes.len(), UtpPacket::HEADER_LENGTH + 3);
+        assert_eq!(bytes[0], 0x01);
+        assert_eq!(&bytes[2..4], [0x12, 0x34]);
+        assert_eq!(&bytes[4..8], [1, 2, 3, 4]);
+        assert_eq!(&bytes[16..20], [0, 7, 0, 6]);
+        assert_eq!(UtpPacket::from_bytes(&bytes).unwrap().0, packet);
+    }
+
+    #[test]
+    fn packet_extensions_are_skipped() {
+        let mut bytes = UtpPacket::new(UtpPacketType::State, 1, 2, 3, vec![]).into_bytes();
+        bytes[1] = 1; // selective ack
+        bytes.extend([0, 4, 0xff, 0xff, 0xff, 0xff]);
+        let (packet, _) = UtpPacket::from_bytes(&bytes).unwrap();
+
+        assert_eq!(packet.packet_type(), UtpPacketType::State);
+        assert!(packet.payload().is_empty());
+    }
+
+    #[test]
+    fn invalid_packets_are_refused() {
+        let mut bytes = UtpPacket::new(UtpPacketType::Syn, 1, 1, 0, vec![]).into_bytes();
+        assert!(UtpPacket::from_bytes(&bytes[..10]).is_err());
+
+        bytes[0] = 0x52; // unknown type 5
+        assert!(matches!(
+            UtpPacket::from_bytes(&bytes),
+            Err(Error::InvalidUtpPacket)
+        ));
+    }
+
+    #[test]
+    fn ledbat_grows_below_target_and_shrinks_above() {
+        let now = Instant::now();
+        let mut ledbat = Ledbat::new();
+        ledbat.on_ack(1400, 50_000, now);
+        let window = ledbat.window();
+        ledbat.on_ack(1400, 50_000, now);
+        assert!(ledbat.window() > window);
+
+        let window = ledbat.window();
+        ledbat.on_ack(1400, 50_000 + 2 * Ledbat::TARGET, now);
+        assert!(ledbat.window() < window);
+        assert_eq!(ledbat.base_delay(), Some(50_000));
+    }
+
+    #[test]
+    fn ledbat_backs_off_on_loss_and_timeout() {
+        let now = Instant::now();
+        let mut ledbat = Ledbat::new();
+        for _ in 0..100 {
+            ledbat.on_ack(1400, 10_000, now);
+        }
+        let window = ledbat.window();
+
+        ledbat.on_loss();
+        assert_eq!(ledbat.window(), window / 2);
+        ledbat.on_timeout();
+        assert_eq!(ledbat.window(), Ledbat::MIN_WINDOW);
+    }
+
+    #[test]
+    fn connection_handshake_without_socket() {
+        let now = Instant::now();
+        let mut client = UtpConnection::connect(100, now);
+        let syn = client.poll(now).remove(0);
+        assert_eq!(syn.packet_type(), UtpPacketType::Syn);
+        assert_eq!(syn.connection_id(), 100);
+
+        let mut server = UtpConnection::accept(&syn, now);
+        assert_eq!(server.receive_id(), 101);
+        let state = server.poll(now).remove(0);
+        assert_eq!(state.packet_type(), UtpPacketType::State);
+        assert_eq!(state.connection_id(), 100);
+
+        client.handle_packet(state, now);
+        assert_eq!(client.state(), UtpConnectionState::Connected);
+        client.write(b"hello");
+        let data = client.poll(now).remove(0);
+        assert_eq!(data.connection_id(), 101);
+        server.handle_packet(data, now);
+
+        let mut buffer = [0u8; 16];
+        assert_eq!(server.read(&mut buffer), 5);
+        assert_eq!(&buffer[..5], b"hello");
+    }
+
+    #[test]
+    fn unanswered_packets_fail_the_connection() {
+        let start = Instant::now();
+        let mut client = UtpConnection::connect(100, start);
+        let mut now = start;
+        for _ in 0..UtpConnection::MAX_TRANSMISSIONS + 1 {
+            client.poll(now);
+            now += UtpConnection::MAX_TIMEOUT;
+        }
+
+        assert_eq!(client.state(), UtpConnectionState::Failed);
+    }
+
+    #[test]
+    fn loopback_transfer_both_ways() {
+        let (_client_socket, mut client, _server_socket, mut server) =
+            connected_streams(SimulatedLink::default());
+        let data = sample_data(200_000);
+
+        let sent = data.clone();
+        let sender = thread::spawn(move || {
+            client.write_all(&sent).unwrap();
+            client
+        });
+        assert_eq!(read_to_length(&mut server, data.len()), data);
+
+        let mut client = sender.join().unwrap();
+        server.write_all(b"thanks").unwrap();
+        assert_eq!(read_to_length(&mut client, 6), b"thanks");
+    }
+
+    #[test]
+    fn transfer_with_loss_and_delay_keeps_the_data_intact() {
+        let link = SimulatedLink::new(0.1, Duration::from_millis(20));
+        let (_client_socket, mut client, _server_socket, mut server) = connected_streams(link);
+        let data = sample_data(100_000);
+
+        let sent = data.clone();
+        let sender = thread::spawn(move || client.write_all(&sent).unwrap());
+        assert_eq!(read_to_length(&mut server, data.len()), data);
+        sender.join().unwrap();
+    }
+
+    #[test]
+    fn closed_stream_reads_end_of_file() {
+        let (_client_socket, mut client, _server_socket, mut server) =
+            connected_streams(SimulatedLink::default());
+        client.write_all(b"bye").unwrap();
+        drop(client);
+
+        assert_eq!(read_to_length(&mut server, 10), b"bye");
+        assert_eq!(server.read(&mut [0u8; 4]).unwrap(), 0);
+    }
+
+    #[test]
+    fn connect_to_silent_address_times_out() {
+        let silent_socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let (client_socket, _) = loopback_socket(SimulatedLink::default());
+
+        let start = Instant::now();
+        let result = client_socket.connect(
+            silent_socket.local_addr().unwrap(),
+            Duration::from_millis(300),
+        );
+
+        assert!(matches!(result, Err(Error::UtpConnectionFailed)));
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn encrypted_session_over_utp() {
+        let (server_socket, server_address) = loopback_socket(SimulatedLink::default());
+        let (client_socket, _) = loopback_socket(SimulatedLink::default());
+        let listener_thread = thread::spawn(move || {
+            let stream = server_socket.accept().unwrap();
+            let session = TcpSession::accept(stream, INFO_HASH, EncryptionPolicy::Require);
+            (server_socket, session)
+        });
+
+        let peer = Peer::from_socket_address(server_address);
+        let mut client =
+            TcpSession::with_utp(&client_socket, peer, INFO_HASH, EncryptionPolicy::Require)
+                .unwrap();
+        let (_server_socket, server) = listener_thread.join().unwrap();
+        let mut server = server.unwrap();
+        assert!(client.is_encrypted());
+
+        client.send(Handshake::new(INFO_HASH, PEER_ID)).unwrap();
+        server.send(Piece::new(0, 0, sample_data(0x4000))).unwrap();
+        assert!(matches!(wait_message(&mut server), Message::Handshake(_)));
+        match wait_message(&mut client) {
+            Message::Piece(piece) => assert_eq!(*piece.data(), sample_data(0x4000)),
+            message => panic!("unexpected {:?}", message),
+        }
+    }
+
+    fn wait_message(tcp_session: &mut TcpSession) -> Message {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            if let Some(message) = tcp_session.receive().unwrap() {
+                return message;
+            }
+            sleep(Duration::from_millis(10));
+        }
+        panic!("no message received")
+    }
+}
@@ -4,22 +4,35 @@ use bendy::decoding::{Decoder, DictDecoder, Object};
 use sha1::{Digest, Sha1};
 use std::{fs::File, io::Read, path::Path, str::FromStr};
 
+mod torrent_file;
+pub use torrent_file::TorrentFile;
+
 #[derive(Debug)]
 pub struct Torrent {
     /// URL of the tracker
     announce: String,
+    /// tiers of tracker URLs, see BEP 12
+    announce_list: Vec<Vec<String>>,
+    /// DHT nodes to bootstrap from, as host and port, see BEP 5
+    nodes: Vec<(String, u16)>,
     /// number of bytes in each piece
-    piece_length_in_bytes: u32,
+    piece_length_in_bytes: u64,
     /// pieces number calculted with total_length_in_bytes and piece_length_in_bytes
     number_of_pieces: u32,
-    /// length of file
-    total_length_in_bytes: u32,
-    /// the filename
+    /// length of file, or sum of the lengths of all files
+    total_length_in_bytes: u64,
+    /// the filename, or the root directory name of a multi-file torrent
     name: String,
+    /// the files described by the torrent, a single one for single-file torrents
+    files: Vec<TorrentFile>,
+    /// whether the info dictionary used the multi-file layout
+    is_multi_file: bool,
     /// a 160-bit (20-byte)
     info_hash: [u8; 20],
     /// The hash of each piece
     piece_hashes: Vec<[u8; 20]>,
+    /// whether the peers only come from the trackers, see BEP 27
+    private: bool,
 }
 
 impl Torrent {
@@ -27,7 +40,21 @@ impl Torrent {
         &self.announce
     }
 
-    pub fn piece_length_in_bytes(&self) -> u32 {
+    /// Tiers of tracker URLs, falling back to the single `announce` URL when
+    /// the torrent has no `announce-list`.
+    pub fn announce_tiers(&self) -> Vec<Vec<String>> {
+        if self.announce_list.is_empty() {
+            vec![vec![self.announce.clone()]]
+        } else {
+            self.announce_list.clone()
+        }
+    }
+
+    pub fn nodes(&self) -> &Vec<(String, u16)> {
+        &self.nodes
+    }
+
+    pub fn piece_length_in_bytes(&self) -> u64 {
         self.piece_length_in_bytes
     }
 
@@ -35,7 +62,7 @@ impl Torrent {
         self.number_of_pieces
     }
 
-    pub fn total_length_in_bytes(&self) -> u32 {
+    pub fn total_length_in_bytes(&self) -> u64 {
         self.total_length_in_bytes
     }
 
@@ -43,10 +70,22 @@ impl Torrent {
         &self.name
     }
 
+    pub fn files(&self) -> &Vec<TorrentFile> {
+        &self.files
+    }
+
+    pub fn is_multi_file(&self) -> bool {
+        self.is_multi_file
+    }
+
     pub fn info_hash(&self) -> [u8; 20] {
         self.info_hash.clone()
     }
 
+    pub fn is_private(&self) -> bool {
+        self.private
+    }
+
     pub fn piece_hashes(&self) -> Vec<[u8; 20]> {
         self.piece_hashes.clone()
     }
@@ -62,6 +101,27 @@ impl Torrent {
                         _ => return Err(Error::BencodeObjectHasUnexpectedType),
                     }
                 }
+                "announce-list" => match pair.1 {
+                    Object::List(mut tiers) => {
+                        while let Ok(Some(tier)) = tiers.next_object() {
+                            let tier = Self::decode_announce_tier(tier)?;
+                            if !tier.is_empty() {
+                                self.announce_list.push(tier);
+                            }
+                        }
+                    }
+                    _ => return Err(Error::BencodeObjectHasUnexpectedType),
+                },
+                "nodes" => match pair.1 {
+                    Object::List(mut nodes) => {
+                        while let Ok(Some(node)) = nodes.next_object() {
+                            if let Some(node) = Self::decode_node(node) {
+                                self.nodes.push(node);
+                            }
+                        }
+                    }
+                    _ => return Err(Error::BencodeObjectHasUnexpectedType),
+                },
                 "info" => match pair.1 {
                     Object::Dict(mut info_dict) => {
                         self.decode_dict(&mut info_dict)?;
@@ -82,25 +142,42 @@ impl Torrent {
                 },
                 "piece length" => {
                     self.piece_length_in_bytes = match pair.1 {
-                        Object::Integer(string) => u32::from_str(string)
+                        Object::Integer(string) => u64::from_str(string)
                             .map_err(|_| Error::PieceLengthStringCannotBeConvertedToInteger)?,
                         _ => return Err(Error::BencodeObjectHasUnexpectedType),
                     }
                 }
                 "length" => {
                     self.total_length_in_bytes = match pair.1 {
-                        Object::Integer(string) => u32::from_str(string)
+                        Object::Integer(string) => u64::from_str(string)
                             .map_err(|_| Error::LengthStringCannotBeConvertedToInteger)?,
                         _ => return Err(Error::BencodeObjectHasUnexpectedType),
                     }
                 }
+                // the file of a single-file torrent, or the root directory
+                // of a multi-file one, is named after it
                 "name" => {
-                    self.name = match pair.1 {
+                    let name = match pair.1 {
                         Object::Bytes(byte) => String::from_utf8(byte.to_vec())
                             .map_err(|_| Error::NameBytesCannotBeConvertedToString)?,
                         _ => return Err(Error::BencodeObjectHasUnexpectedType),
-                    }
+                    };
+                    self.name = TorrentFile::validate_path_component(name)?;
                 }
+                "files" => match pair.1 {
+                    Object::List(mut files_list) => {
+                        while let Ok(Some(file)) = files_list.next_object() {
+                            match file {
+                                Object::Dict(mut file_dict) => {
+                                    self.files.push(TorrentFile::from_bencode(&mut file_dict)?)
+                                }
+                                _ => return Err(Error::BencodeObjectHasUnexpectedType),
+                            }
+                        }
+                        self.is_multi_file = true;
+                    }
+                    _ => return Err(Error::BencodeObjectHasUnexpectedType),
+                },
                 "pieces" => match pair.1 {
                     Object::Bytes(bytes) => {
                         const HASH_LENGTH: usize = 20;
@@ -112,12 +189,57 @@ impl Torrent {
                     }
                     _ => return Err(Error::BencodeObjectHasUnexpectedType),
                 },
+                "private" => {
+                    self.private = match pair.1 {
+                        Object::Integer(integer) => integer == "1",
+                        _ => return Err(Error::BencodeObjectHasUnexpectedType),
+                    }
+                }
                 other => log::debug!("Skipping field {} from torrent file.", other),
             }
         }
         Ok(())
     }
 
+    fn decode_announce_tier(tier: Object) -> Result<Vec<String>, Error> {
+        let mut urls = Vec::new();
+        let mut tier = match tier {
+            Object::List(tier) => tier,
+            _ => return Err(Error::BencodeObjectHasUnexpectedType),
+        };
+
+        while let Ok(Some(url)) = tier.next_object() {
+            match url {
+                Object::Bytes(bytes) => urls.push(
+                    String::from_utf8(bytes.to_vec())
+                        .map_err(|_| Error::AnnounceBytesCannotBeConvertedToString)?,
+                ),
+                _ => return Err(Error::BencodeObjectHasUnexpectedType),
+            }
+        }
+
+        Ok(urls)
+    }
+
+    /// A node is a `[host, port]` list, malformed ones are skipped.
+    fn decode_node(node: Object) -> Option<(String, u16)> {
+        let mut node = match node {
+            Object::List(node) => node,
+            _ => return None,
+        };
+
+        let host = match node.next_object() {
+            Ok(Some(Object::Bytes(host))) => String::from_utf8(host.to_vec()).ok()?,
+            _ => return None,
+        };
+        let port = match node.next_object() {
+            Ok(Some(Object::Integer(port))) => u16::from_str(port).ok()?,
+            _ => return None,
+        };
+
+        Some((host, port))
+    }
+
     pub fn from_file(filepath: &Path) -> Result<Torrent, Error> {
         let mut file = File::open(filepath).map_err(|_| Error::FailedToOpenTorrentFile)?;
         let mut buffer = Vec::new();
@@ -133,12 +255,17 @@ impl Torrent {
     pub fn from_bencode(bencode_decoder: &mut Decoder) -> Result<Torrent, Error> {
         let mut torrent_result = Torrent {
             announce: String::from(""),
+            announce_list: vec![],
+            nodes: vec![],
             piece_length_in_bytes: 0,
             number_of_pieces: 0,
             total_length_in_bytes: 0,
             name: String::from(""),
+            files: vec![],
+            is_multi_file: false,
             info_hash: [0; 20],
             piece_hashes: vec![],
+            private: false,
         };
 
         let maybe_bencode_object = bencode_decoder
@@ -153,47 +280,61 @@ impl Torrent {
             _ => (),
         };
 
-        torrent_result.number_of_pieces = div_ceil(
+        if torrent_result.is_multi_file {
+            torrent_result.total_length_in_bytes =
+                torrent_result.files.iter().map(|file| file.length()).sum();
+        } else {
+            let file = TorrentFile::new(
+                vec![torrent_result.name.clone()],
+                torrent_result.total_length_in_bytes,
+                None,
+            );
+            torrent_result.files.push(file);
+        }
+
+        if torrent_result.piece_length_in_bytes() == 0 {
+            return Err(Error::SinglePieceLengthNotFoundDuringParsing);
+        }
+        if torrent_result.total_length_in_bytes() == 0 {
+            return Err(Error::TotalPiecesLengthNotFoundDuringParsing);
+        }
+
+        torrent_result.number_of_pieces = u32::try_from(div_ceil(
             torrent_result.total_length_in_bytes(),
             torrent_result.piece_length_in_bytes(),
-        );
+        ))
+        .map_err(|_| Error::TooManyPiecesInTorrent)?;
 
         Ok(torrent_result)
     }
 }
 
-pub fn div_ceil(a: u32, b: u32) -> u32 {
+pub fn div_ceil(a: u64, b: u64) -> u64 {
     a / b + if a % b == 0 { 0 } else { 1 }
 }
 
-pub fn expected_blocks_in_piece(piece_index: u32, torrent: &Torrent) -> usize {
-    let torrent_length = torrent.total_length_in_bytes();
+/// Length of a piece in bytes, only the last piece may be shorter than the
+/// piece length announced in the torrent.
+pub fn expected_piece_length(piece_index: u32, torrent: &Torrent) -> u64 {
     let piece_length = torrent.piece_length_in_bytes();
+    let piece_begin = piece_index as u64 * piece_length;
 
-    if torrent_length % piece_length == 0 {
-        piece_length as usize / BlockReaderWriter::BIT_TORRENT_BLOCK_SIZE
-    } else {
-        if piece_index == torrent.number_of_pieces() - 1 {
-            let last_piece_size = torrent_length % torrent.piece_length_in_bytes();
-            div_ceil(
-                last_piece_size,
-                BlockReaderWriter::BIT_TORRENT_BLOCK_SIZE as u32,
-            ) as usize
-        } else {
-            piece_length as usize / BlockReaderWriter::BIT_TORRENT_BLOCK_SIZE
-        }
-    }
+    piece_length.min(torrent.total_length_in_bytes().saturating_sub(piece_begin))
 }
 
-pub fn expected_block_length(piece_index: u32, block_index: u32, torrent: &Torrent) -> u32 {
-    let blocks_per_piece = expected_blocks_in_piece(piece_index, torrent) as u32;
+pub fn expected_blocks_in_piece(piece_index: u32, torrent: &Torrent) -> usize {
+    let piece_length = expected_piece_length(piece_index, torrent);
 
-    let is_last_piece = piece_index == torrent.number_of_pieces() - 1;
-    let is_last_block = block_index == blocks_per_piece - 1;
+    div_ceil(
+        piece_length,
+        BlockReaderWriter::BIT_TORRENT_BLOCK_SIZE as u64,
+    ) as usize
+}
 
-    if is_last_piece && is_last_block {
-        torrent.total_length_in_bytes() % BlockReaderWriter::BIT_TORRENT_BLOCK_SIZE as u32
-    } else {
-        BlockReaderWriter::BIT_TORRENT_BLOCK_SIZE as u32
-    }
+pub fn expected_block_length(piece_index: u32, block_index: u32, torrent: &Torrent) -> u32 {
+    let piece_length = expected_piece_length(piece_index, torrent);
+    let block_begin = block_index as u64 * BlockReaderWriter::BIT_TORRENT_BLOCK_SIZE as u64;
+    let remaining_bytes = piece_length.saturating_sub(block_begin);
+
+    remaining_bytes.min(BlockReaderWriter::BIT_TORRENT_BLOCK_SIZE as u64) as u32
 }
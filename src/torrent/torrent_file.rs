@@ -0,0 +1,105 @@
+use crate::Error;
+use bendy::decoding::{DictDecoder, Object};
+use std::{path::PathBuf, str::FromStr};
+
+/// A file described in the info dictionary. Single-file torrents are modeled
+/// as a torrent containing exactly one of them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TorrentFile {
+    /// path components, relative to the torrent root directory
+    path: Vec<String>,
+    /// length of the file in bytes
+    length: u64,
+    /// optional 32-character hexadecimal MD5 sum of the file
+    md5sum: Option<String>,
+}
+
+impl TorrentFile {
+    pub fn new(path: Vec<String>, length: u64, md5sum: Option<String>) -> Self {
+        Self {
+            path,
+            length,
+            md5sum,
+        }
+    }
+
+    pub fn path(&self) -> &Vec<String> {
+        &self.path
+    }
+
+    pub fn length(&self) -> u64 {
+        self.length
+    }
+
+    pub fn md5sum(&self) -> Option<&String> {
+        self.md5sum.as_ref()
+    }
+
+    /// Path of the file relative to the torrent root directory
+    pub fn relative_path(&self) -> PathBuf {
+        self.path.iter().collect()
+    }
+
+    /// Decodes one entry of the `files` list of a multi-file info dictionary.
+    pub fn from_bencode(dict: &mut DictDecoder) -> Result<Self, Error> {
+        let mut path = Vec::new();
+        let mut length = None;
+        let mut md5sum = None;
+
+        while let Ok(Some(pair)) = dict.next_pair() {
+            match pair {
+                (b"length", Object::Integer(string)) => {
+                    length = Some(
+                        u64::from_str(string)
+                            .map_err(|_| Error::LengthStringCannotBeConvertedToInteger)?,
+                    );
+                }
+                (b"path", Object::List(mut list)) => {
+                    while let Ok(Some(component)) = list.next_object() {
+                        let component = match component {
+                            Object::Bytes(bytes) => String::from_utf8(bytes.to_vec())
+                                .map_err(|_| Error::NameBytesCannotBeConvertedToString)?,
+                            _ => return Err(Error::BencodeObjectHasUnexpectedType),
+                        };
+                        path.push(Self::validate_path_component(component)?);
+                    }
+                }
+                (b"md5sum", Object::Bytes(bytes)) => {
+                    md5sum = Some(
+                        String::from_utf8(bytes.to_vec())
+                            .map_err(|_| Error::BencodeObjectHasUnexpectedType)?,
+                    );
+                }
+                (b"length" | b"path" | b"md5sum", _) => {
+                    return Err(Error::BencodeObjectHasUnexpectedType)
+                }
+                (other, _) => log::debug!(
+                    "Skipping field {} from files list.",
+                    String::from_utf8_lossy(other)
+                ),
+            }
+        }
+
+        if path.is_empty() {
+            return Err(Error::FilePathNotFoundDuringParsing);
+        }
+        let length = length.ok_or(Error::TotalPiecesLengthNotFoundDuringParsing)?;
+
+        Ok(Self::new(path, length, md5sum))
+    }
+
+    /// Rejects components that would escape the torrent root directory.
+    pub(crate) fn validate_path_component(component: String) -> Result<String, Error> {
+        let is_invalid = component.is_empty()
+            || component == "."
+            || component == ".."
+            || component.contains('/')
+            || component.contains('\\');
+
+        if is_invalid {
+            Err(Error::InvalidFilePathComponent)
+        } else {
+            Ok(component)
+        }
+    }
+}
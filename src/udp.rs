@@ -0,0 +1,9 @@
+mod udp_tracker;
+mod udp_tracker_request;
+mod udp_tracker_response;
+mod udp_trackers;
+
+pub use udp_tracker::UdpTracker;
+pub use udp_tracker_request::UdpTrackerRequest;
+pub use udp_tracker_response::UdpTrackerResponse;
+pub use udp_trackers::UdpTrackers;
@@ -0,0 +1,187 @@
+use {
+    crate::{
+        http::{ScrapeStatistics, TrackerAddress, TrackerRequest, TrackerResponse},
+        pwp::{FromBytes, IntoBytes},
+        udp::{UdpTrackerRequest, UdpTrackerResponse},
+        Error,
+    },
+    std::{
+        net::{Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket},
+        time::{Duration, Instant},
+    },
+};
+
+/// Client of the UDP tracker protocol (BEP 15). The connection id obtained
+/// from the tracker is cached and reused until it expires.
+#[derive(Debug)]
+pub struct UdpTracker {
+    socket: UdpSocket,
+    connection: Option<(u64, Instant)>,
+    base_timeout: Duration,
+    max_retransmissions: u32,
+    key: u32,
+}
+
+impl UdpTracker {
+    /// A request is retransmitted after `15 * 2^n` seconds, n going up to 8
+    pub const BASE_TIMEOUT: Duration = Duration::from_secs(15);
+    pub const MAX_RETRANSMISSIONS: u32 = 8;
+    /// Retransmissions allowed when other trackers can be tried instead
+    pub const MAX_RETRANSMISSIONS_BEFORE_FALLBACK: u32 = 2;
+    /// Clients may use a connection id for one minute after receiving it
+    pub const CONNECTION_ID_LIFETIME: Duration = Duration::from_secs(60);
+    const MAX_PACKET_SIZE: usize = 2048;
+
+    pub fn new(tracker: &TrackerAddress) -> Result<Self, Error> {
+        Self::with_timeout(tracker, Self::BASE_TIMEOUT, Self::MAX_RETRANSMISSIONS)
+    }
+
+    pub fn with_timeout(
+        tracker: &TrackerAddress,
+        base_timeout: Duration,
+        max_retransmissions: u32,
+    ) -> Result<Self, Error> {
+        // IPv6 hosts keep the brackets of their URL
+        let host = tracker.host().trim_start_matches('[').trim_end_matches(']');
+        let address = (host, tracker.port())
+            .to_socket_addrs()
+            .map_err(|_| Error::FailedToResolveTrackerAddress)?
+            .next()
+            .ok_or(Error::FailedToResolveTrackerAddress)?;
+
+        let local_address = match address {
+            SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+            SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+        };
+        let socket = UdpSocket::bind(local_address).map_err(|_| Error::FailedToBindUdpSocket)?;
+        socket
+            .connect(address)
+            .map_err(|_| Error::FailedToBindUdpSocket)?;
+
+        Ok(Self {
+            socket,
+            connection: None,
+            base_timeout,
+            max_retransmissions,
+            key: rand::random(),
+        })
+    }
+
+    pub fn announce(&mut self, request: &TrackerRequest) -> Result<TrackerResponse, Error> {
+        let connection_id = self.connection_id()?;
+        let announce =
+            UdpTrackerRequest::announce(connection_id, rand::random(), self.key, request);
+
+        match self.send_request(announce)? {
+            UdpTrackerResponse::Announce { response, .. } => Ok(response),
+            UdpTrackerResponse::Error { message, .. } => Ok(TrackerResponse::from_failure(message)),
+            _ => Err(Error::UnexpectedUdpTrackerAction),
+        }
+    }
+
+    pub fn scrape(&mut self, info_hashes: &[[u8; 20]]) -> Result<Vec<ScrapeStatistics>, Error> {
+        let mut statistics = Vec::new();
+
+        for info_hashes in info_hashes.chunks(UdpTrackerRequest::MAX_SCRAPED_TORRENTS) {
+            let scrape = UdpTrackerRequest::Scrape {
+                connection_id: self.connection_id()?,
+                transaction_id: rand::random(),
+                info_hashes: info_hashes.to_vec(),
+            };
+
+            match self.send_request(scrape)? {
+                UdpTrackerResponse::Scrape {
+                    statistics: chunk_statistics,
+                    ..
+                } => statistics.extend(chunk_statistics),
+                UdpTrackerResponse::Error { message, .. } => {
+                    log::error!("UDP tracker scrape failed: {}", message);
+                    return Err(Error::UdpTrackerReturnedAnError);
+                }
+                _ => return Err(Error::UnexpectedUdpTrackerAction),
+            }
+        }
+
+        Ok(statistics)
+    }
+
+    /// Returns the cached connection id, or connects to the tracker again if
+    /// there is none or if it expired.
+    fn connection_id(&mut self) -> Result<u64, Error> {
+        if let Some((connection_id, received_at)) = self.connection {
+            if received_at.elapsed() < Self::CONNECTION_ID_LIFETIME {
+                return Ok(connection_id);
+            }
+        }
+
+        let connect = UdpTrackerRequest::Connect {
+            transaction_id: rand::random(),
+        };
+        match self.send_request(connect)? {
+            UdpTrackerResponse::Connect { connection_id, .. } => {
+                self.connection = Some((connection_id, Instant::now()));
+                Ok(connection_id)
+            }
+            UdpTrackerResponse::Error { message, .. } => {
+                log::error!("UDP tracker refused the connection: {}", message);
+                Err(Error::UdpTrackerReturnedAnError)
+            }
+            _ => Err(Error::UnexpectedUdpTrackerAction),
+        }
+    }
+
+    /// Sends a request and waits for the response carrying the same
+    /// transaction id, retransmitting it with an exponential backoff.
+    fn send_request(&self, request: UdpTrackerRequest) -> Result<UdpTrackerResponse, Error> {
+        let transaction_id = request.transaction_id();
+        let packet = request.into_bytes();
+
+        for retransmission in 0..=self.max_retransmissions {
+            self.socket
+                .send(&packet)
+                .map_err(|_| Error::FailedToSendUdpPacket)?;
+
+            let timeout = self.base_timeout * 2u32.pow(retransmission);
+            if let Some(response) = self.receive_response(transaction_id, timeout) {
+                return Ok(response);
+            }
+
+            log::debug!(
+                "UDP tracker did not answer within {:?}, retransmitting.",
+                timeout
+            );
+        }
+
+        Err(Error::UdpTrackerDidNotAnswer)
+    }
+
+    fn receive_response(
+        &self,
+        transaction_id: u32,
+        timeout: Duration,
+    ) -> Option<UdpTrackerResponse> {
+        let deadline = Instant::now() + timeout;
+        let mut buffer = [0u8; Self::MAX_PACKET_SIZE];
+
+        loop {
+            let remaining_time = deadline.saturating_duration_since(Instant::now());
+            if remaining_time.is_zero() {
+                return None;
+            }
+
+            self.socket.set_read_timeout(Some(remaining_time)).ok()?;
+            let received_bytes = match self.socket.recv(&mut buffer) {
+                Ok(received_bytes) => received_bytes,
+                Err(_) => return None,
+            };
+
+            match UdpTrackerResponse::from_bytes(&buffer[..received_bytes]) {
+                Ok((response, _)) if response.transaction_id() == transaction_id => {
+                    return Some(response)
+                }
+                Ok(_) => log::debug!("Dropping UDP tracker response of another transaction."),
+                Err(error) => log::warn!("Dropping invalid UDP tracker response: {:?}", error),
+            }
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use crate::{
+    http::{Event, TrackerRequest},
+    pwp::IntoBytes,
+};
+
+/// Requests of the UDP tracker protocol, see BEP 15
+#[derive(Debug)]
+pub enum UdpTrackerRequest {
+    /// connect: <protocol_id=0x41727101980><action=0><transaction_id>
+    Connect { transaction_id: u32 },
+    /// announce: <connection_id><action=1><transaction_id><info_hash><peer_id>
+    /// <downloaded><left><uploaded><event><ip=0><key><num_want=-1><port>
+    Announce {
+        connection_id: u64,
+        transaction_id: u32,
+        info_hash: [u8; 20],
+        peer_id: [u8; 20],
+        downloaded: u64,
+        left: u64,
+        uploaded: u64,
+        event: u32,
+        key: u32,
+        port: u16,
+    },
+    /// scrape: <connection_id><action=2><transaction_id><info_hash>...
+    Scrape {
+        connection_id: u64,
+        transaction_id: u32,
+        info_hashes: Vec<[u8; 20]>,
+    },
+}
+
+impl UdpTrackerRequest {
+    pub const PROTOCOL_ID: u64 = 0x41727101980;
+    pub const CONNECT_ACTION: u32 = 0;
+    pub const ANNOUNCE_ACTION: u32 = 1;
+    pub const SCRAPE_ACTION: u32 = 2;
+    pub const ERROR_ACTION: u32 = 3;
+    /// Trackers only accept up to about 74 info hashes in a single scrape
+    pub const MAX_SCRAPED_TORRENTS: usize = 74;
+
+    pub fn announce(
+        connection_id: u64,
+        transaction_id: u32,
+        key: u32,
+        request: &TrackerRequest,
+    ) -> Self {
+        UdpTrackerRequest::Announce {
+            connection_id,
+            transaction_id,
+            info_hash: request.info_hash(),
+            peer_id: request.peer_id(),
+            downloaded: request.downloaded(),
+            left: request.left(),
+            uploaded: request.uploaded(),
+            event: Event::udp_id(request.event()),
+            key,
+            port: request.port(),
+        }
+    }
+
+    pub fn transaction_id(&self) -> u32 {
+        match self {
+            UdpTrackerRequest::Connect { transaction_id }
+            | UdpTrackerRequest::Announce { transaction_id, .. }
+            | UdpTrackerRequest::Scrape { transaction_id, .. } => *transaction_id,
+        }
+    }
+}
+
+impl IntoBytes for UdpTrackerRequest {
+    fn into_bytes(self) -> Vec<u8> {
+        let mut serialized_message = Vec::new();
+
+        match self {
+            UdpTrackerRequest::Connect { transaction_id } => {
+                serialized_message.extend(Self::PROTOCOL_ID.to_be_bytes());
+                serialized_message.extend(Self::CONNECT_ACTION.to_be_bytes());
+                serialized_message.extend(transaction_id.to_be_bytes());
+            }
+            UdpTrackerRequest::Announce {
+                connection_id,
+                transaction_id,
+                info_hash,
+                peer_id,
+                downloaded,
+                left,
+                uploaded,
+                event,
+                key,
+                port,
+            } => {
+                serialized_message.extend(connection_id.to_be_bytes());
+                serialized_message.extend(Self::ANNOUNCE_ACTION.to_be_bytes());
+                serialized_message.extend(transaction_id.to_be_bytes());
+                serialized_message.extend(info_hash);
+                serialized_message.extend(peer_id);
+                serialized_message.extend(downloaded.to_be_bytes());
+                serialized_message.extend(left.to_be_bytes());
+                serialized_message.extend(uploaded.to_be_bytes());
+                serialized_message.extend(event.to_be_bytes());
+                // let the tracker use the source address of the packet
+                serialized_message.extend(0u32.to_be_bytes());
+                serialized_message.extend(key.to_be_bytes());
+                // let the tracker choose the number of peers
+                serialized_message.extend((-1i32).to_be_bytes());
+                serialized_message.extend(port.to_be_bytes());
+            }
+            UdpTrackerRequest::Scrape {
+                connection_id,
+                transaction_id,
+                info_hashes,
+            } => {
+                serialized_message.extend(connection_id.to_be_bytes());
+                serialized_message.extend(Self::SCRAPE_ACTION.to_be_bytes());
+                serialized_message.extend(transaction_id.to_be_bytes());
+                info_hashes
+                    .iter()
+                    .for_each(|info_hash| serialized_message.extend(info_hash));
+            }
+        }
+
+        serialized_message
+    }
+}
@@ -0,0 +1,111 @@
+use crate::{
+    http::{ScrapeStatistics, TrackerResponse},
+    pwp::FromBytes,
+    udp::UdpTrackerRequest,
+    Error,
+};
+
+/// Responses of the UDP tracker protocol, see BEP 15
+#[derive(Debug)]
+pub enum UdpTrackerResponse {
+    /// connect: <action=0><transaction_id><connection_id>
+    Connect {
+        transaction_id: u32,
+        connection_id: u64,
+    },
+    /// announce: <action=1><transaction_id><interval><leechers><seeders>
+    /// followed by compact IPv4 peers
+    Announce {
+        transaction_id: u32,
+        response: TrackerResponse,
+    },
+    /// scrape: <action=2><transaction_id> followed by
+    /// <seeders><completed><leechers> for each requested info hash
+    Scrape {
+        transaction_id: u32,
+        statistics: Vec<ScrapeStatistics>,
+    },
+    /// error: <action=3><transaction_id><message>
+    Error {
+        transaction_id: u32,
+        message: String,
+    },
+}
+
+impl UdpTrackerResponse {
+    const HEADER_LENGTH: usize = 8;
+
+    pub fn transaction_id(&self) -> u32 {
+        match self {
+            UdpTrackerResponse::Connect { transaction_id, .. }
+            | UdpTrackerResponse::Announce { transaction_id, .. }
+            | UdpTrackerResponse::Scrape { transaction_id, .. }
+            | UdpTrackerResponse::Error { transaction_id, .. } => *transaction_id,
+        }
+    }
+
+    fn read_u32(bytes: &[u8], offset: usize) -> Result<u32, Error> {
+        let field = bytes
+            .get(offset..offset + 4)
+            .ok_or(Error::UdpTrackerResponseTooShort)?;
+
+        Ok(u32::from_be_bytes(field.try_into().unwrap()))
+    }
+
+    fn read_u64(bytes: &[u8], offset: usize) -> Result<u64, Error> {
+        let field = bytes
+            .get(offset..offset + 8)
+            .ok_or(Error::UdpTrackerResponseTooShort)?;
+
+        Ok(u64::from_be_bytes(field.try_into().unwrap()))
+    }
+}
+
+impl FromBytes for UdpTrackerResponse {
+    fn from_bytes(bytes: &[u8]) -> Result<(Self, usize), Error> {
+        let action = Self::read_u32(bytes, 0)?;
+        let transaction_id = Self::read_u32(bytes, 4)?;
+
+        let response = match action {
+            UdpTrackerRequest::CONNECT_ACTION => UdpTrackerResponse::Connect {
+                transaction_id,
+                connection_id: Self::read_u64(bytes, 8)?,
+            },
+            UdpTrackerRequest::ANNOUNCE_ACTION => {
+                let interval = Self::read_u32(bytes, 8)? as usize;
+                let leechers = Self::read_u32(bytes, 12)? as usize;
+                let seeders = Self::read_u32(bytes, 16)? as usize;
+                let peers = TrackerResponse::parse_compact_peers(&bytes[20..]);
+
+                UdpTrackerResponse::Announce {
+                    transaction_id,
+                    response: TrackerResponse::new(interval, seeders, leechers, peers),
+                }
+            }
+            UdpTrackerRequest::SCRAPE_ACTION => {
+                let statistics = bytes[Self::HEADER_LENGTH..]
+                    .chunks_exact(12)
+                    .map(|chunk| {
+                        let seeders = Self::read_u32(chunk, 0).unwrap() as usize;
+                        let completed = Self::read_u32(chunk, 4).unwrap() as usize;
+                        let leechers = Self::read_u32(chunk, 8).unwrap() as usize;
+
+                        ScrapeStatistics::new(seeders, completed, leechers)
+                    })
+                    .collect();
+
+                UdpTrackerResponse::Scrape {
+                    transaction_id,
+                    statistics,
+                }
+            }
+            UdpTrackerRequest::ERROR_ACTION => UdpTrackerResponse::Error {
+                transaction_id,
+                message: String::from_utf8_lossy(&bytes[Self::HEADER_LENGTH..]).to_string(),
+            },
+            _ => return Err(Error::UnexpectedUdpTrackerAction),
+        };
+
+        Ok((response, bytes.len()))
+    }
+}
@@ -0,0 +1,33 @@
+use {
+    crate::{http::TrackerAddress, udp::UdpTracker, Error},
+    std::collections::{hash_map::Entry, HashMap},
+};
+
+/// One client per UDP tracker, kept across the announces so that the
+/// connection id and the key of each tracker are reused (BEP 15).
+#[derive(Debug, Default)]
+pub struct UdpTrackers {
+    trackers: HashMap<(String, u16), UdpTracker>,
+}
+
+impl UdpTrackers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The client of the tracker, created on first use. It gives up early
+    /// enough for the next tracker of the list to be tried.
+    pub fn tracker(&mut self, tracker: &TrackerAddress) -> Result<&mut UdpTracker, Error> {
+        match self
+            .trackers
+            .entry((tracker.host().to_string(), tracker.port()))
+        {
+            Entry::Occupied(entry) => Ok(entry.into_mut()),
+            Entry::Vacant(entry) => Ok(entry.insert(UdpTracker::with_timeout(
+                tracker,
+                UdpTracker::BASE_TIMEOUT,
+                UdpTracker::MAX_RETRANSMISSIONS_BEFORE_FALLBACK,
+            )?)),
+        }
+    }
+}
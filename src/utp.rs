@@ -0,0 +1,15 @@
+mod ledbat;
+mod simulated_link;
+mod utp_connection;
+mod utp_packet;
+mod utp_packet_type;
+mod utp_socket;
+mod utp_stream;
+
+pub use ledbat::Ledbat;
+pub use simulated_link::SimulatedLink;
+pub use utp_connection::{UtpConnection, UtpConnectionState};
+pub use utp_packet::UtpPacket;
+pub use utp_packet_type::UtpPacketType;
+pub use utp_socket::{UtpConnectionHandle, UtpSocket};
+pub use utp_stream::UtpStream;
@@ -0,0 +1,86 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+/// LEDBAT congestion control (RFC 6817): the window grows while the delay
+/// added by the queues on the path stays under a target, and shrinks above
+/// it, so that the transfers give way to the other traffic of the link.
+#[derive(Debug, Clone)]
+pub struct Ledbat {
+    /// bytes allowed in flight
+    window: usize,
+    /// minimum one-way delay of each interval, the oldest first
+    base_delays: VecDeque<(Instant, u32)>,
+}
+
+impl Ledbat {
+    /// Queuing delay aimed for, in microseconds
+    pub const TARGET: u32 = 100_000;
+    /// Maximum growth of the window in a round trip, in bytes
+    pub const MAX_WINDOW_INCREASE: usize = 3000;
+    pub const MIN_WINDOW: usize = 1500;
+    pub const INITIAL_WINDOW: usize = 2 * Self::MIN_WINDOW;
+    /// The base delay is the minimum seen during the last intervals
+    const BASE_DELAY_INTERVAL: Duration = Duration::from_secs(60);
+    const BASE_DELAY_INTERVALS: usize = 2;
+
+    pub fn new() -> Self {
+        Self {
+            window: Self::INITIAL_WINDOW,
+            base_delays: VecDeque::new(),
+        }
+    }
+
+    pub fn window(&self) -> usize {
+        self.window
+    }
+
+    /// Lowest one-way delay measured, which is assumed to have crossed
+    /// empty queues. It includes the offset between the clocks of the peers.
+    pub fn base_delay(&self) -> Option<u32> {
+        self.base_delays.iter().map(|&(_, delay)| delay).min()
+    }
+
+    /// `bytes_acked` were acknowledged by a packet that measured a one-way
+    /// `delay`, in microseconds.
+    pub fn on_ack(&mut self, bytes_acked: usize, delay: u32, now: Instant) {
+        self.update_base_delay(delay, now);
+        let queuing_delay = delay.saturating_sub(self.base_delay().unwrap_or(delay));
+
+        let off_target = (Self::TARGET as f64 - queuing_delay as f64) / Self::TARGET as f64;
+        let increase =
+            off_target * bytes_acked as f64 * Self::MAX_WINDOW_INCREASE as f64 / self.window as f64;
+        let window = (self.window as f64 + increase).max(Self::MIN_WINDOW as f64);
+
+        self.window = window as usize;
+    }
+
+    pub fn on_loss(&mut self) {
+        self.window = (self.window / 2).max(Self::MIN_WINDOW);
+    }
+
+    pub fn on_timeout(&mut self) {
+        self.window = Self::MIN_WINDOW;
+    }
+
+    fn update_base_delay(&mut self, delay: u32, now: Instant) {
+        match self.base_delays.back_mut() {
+            Some((start, base_delay)) if now.duration_since(*start) < Self::BASE_DELAY_INTERVAL => {
+                *base_delay = (*base_delay).min(delay);
+            }
+            _ => {
+                self.base_delays.push_back((now, delay));
+                if self.base_delays.len() > Self::BASE_DELAY_INTERVALS {
+                    self.base_delays.pop_front();
+                }
+            }
+        }
+    }
+}
+
+impl Default for Ledbat {
+    fn default() -> Self {
+        Self::new()
+    }
+}
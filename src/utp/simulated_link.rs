@@ -0,0 +1,27 @@
+use {rand::Rng, std::time::Duration};
+
+/// Degradation applied to the packets sent by a uTP socket, to exercise the
+/// retransmissions and the congestion control on loopback.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct SimulatedLink {
+    /// probability for a packet to be dropped, between 0 and 1
+    loss_rate: f64,
+    /// added to the sending of every packet
+    delay: Duration,
+}
+
+impl SimulatedLink {
+    /// Only the tests degrade the links
+    #[cfg(test)]
+    pub fn new(loss_rate: f64, delay: Duration) -> Self {
+        Self { loss_rate, delay }
+    }
+
+    pub fn delay(&self) -> Duration {
+        self.delay
+    }
+
+    pub fn drops_packet(&self) -> bool {
+        self.loss_rate > 0.0 && rand::thread_rng().gen_bool(self.loss_rate.min(1.0))
+    }
+}
@@ -0,0 +1,437 @@
+use {
+    crate::utp::{Ledbat, UtpPacket, UtpPacketType},
+    std::{
+        collections::{HashMap, VecDeque},
+        time::{Duration, Instant},
+    },
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UtpConnectionState {
+    SynSent,
+    Connected,
+    /// reset by the peer, or no answer after several retransmissions
+    Failed,
+}
+
+/// Packet sent and not acknowledged yet
+#[derive(Debug, Clone)]
+struct InFlightPacket {
+    packet_type: UtpPacketType,
+    seq_nr: u16,
+    payload: Vec<u8>,
+    /// None when the packet waits for a (re)transmission
+    sent_at: Option<Instant>,
+    transmissions: u32,
+}
+
+/// One side of a uTP connection (BEP 29). It only reacts to the received
+/// packets and to the time given to `poll`, which returns the packets to
+/// send: the socket does the networking.
+#[derive(Debug)]
+pub struct UtpConnection {
+    state: UtpConnectionState,
+    /// connection id of the received packets
+    receive_id: u16,
+    /// connection id of the sent packets
+    send_id: u16,
+    /// sequence number of the next packet sent
+    seq_nr: u16,
+    /// last packet received in order
+    ack_nr: u16,
+    in_flight: VecDeque<InFlightPacket>,
+    /// bytes written but not sent yet
+    send_buffer: VecDeque<u8>,
+    /// bytes received in order but not read yet
+    receive_buffer: VecDeque<u8>,
+    out_of_order: HashMap<u16, UtpPacket>,
+    ledbat: Ledbat,
+    /// bytes the peer can still receive
+    peer_window: u32,
+    rtt: Option<Duration>,
+    rtt_variance: Duration,
+    timeout: Duration,
+    /// consecutive acks of the same packet
+    duplicate_acks: u32,
+    /// delay measured on the last packet received, sent back to the peer
+    reply_delay: u32,
+    ack_pending: bool,
+    close_requested: bool,
+    fin_sent: bool,
+    fin_received: bool,
+    /// reference of the timestamps
+    epoch: Instant,
+}
+
+impl UtpConnection {
+    pub const PACKET_PAYLOAD_SIZE: usize = 1400;
+    pub const RECEIVE_WINDOW: usize = 1024 * 1024;
+    pub const SEND_BUFFER_SIZE: usize = 1024 * 1024;
+    pub const INITIAL_TIMEOUT: Duration = Duration::from_secs(1);
+    pub const MIN_TIMEOUT: Duration = Duration::from_millis(500);
+    pub const MAX_TIMEOUT: Duration = Duration::from_secs(16);
+    /// The connection fails when a packet was sent that many times
+    pub const MAX_TRANSMISSIONS: u32 = 6;
+    const DUPLICATE_ACKS_BEFORE_RESEND: u32 = 3;
+
+    fn with_ids(receive_id: u16, send_id: u16, seq_nr: u16, now: Instant) -> Self {
+        Self {
+            state: UtpConnectionState::SynSent,
+            receive_id,
+            send_id,
+            seq_nr,
+            ack_nr: 0,
+            in_flight: VecDeque::new(),
+            send_buffer: VecDeque::new(),
+            receive_buffer: VecDeque::new(),
+            out_of_order: HashMap::new(),
+            ledbat: Ledbat::new(),
+            peer_window: Self::RECEIVE_WINDOW as u32,
+            rtt: None,
+            rtt_variance: Duration::ZERO,
+            timeout: Self::INITIAL_TIMEOUT,
+            duplicate_acks: 0,
+            reply_delay: 0,
+            ack_pending: false,
+            close_requested: false,
+            fin_sent: false,
+            fin_received: false,
+            epoch: now,
+        }
+    }
+
+    /// Connection we initiate, the SYN is sent by the next `poll`.
+    pub fn connect(receive_id: u16, now: Instant) -> Self {
+        let mut connection = Self::with_ids(receive_id, receive_id.wrapping_add(1), 1, now);
+        connection.queue_packet(UtpPacketType::Syn, Vec::new());
+
+        connection
+    }
+
+    /// Connection initiated by the peer with `syn`, acknowledged by the next
+    /// `poll`.
+    pub fn accept(syn: &UtpPacket, now: Instant) -> Self {
+        let mut connection = Self::with_ids(
+            syn.connection_id().wrapping_add(1),
+            syn.connection_id(),
+            rand::random(),
+            now,
+        );
+        connection.state = UtpConnectionState::Connected;
+        connection.ack_nr = syn.seq_nr();
+        connection.receive_packet_timing(syn, now);
+        connection.ack_pending = true;
+
+        connection
+    }
+
+    pub fn state(&self) -> UtpConnectionState {
+        self.state
+    }
+
+    pub fn receive_id(&self) -> u16 {
+        self.receive_id
+    }
+
+    /// The peer closed the connection and every byte it sent was read.
+    pub fn is_finished(&self) -> bool {
+        self.fin_received && self.receive_buffer.is_empty()
+    }
+
+    /// Nothing is left to exchange, the connection can be forgotten.
+    pub fn is_closed(&self) -> bool {
+        self.state == UtpConnectionState::Failed
+            || (self.fin_sent && self.fin_received && self.in_flight.is_empty())
+    }
+
+    /// Our FIN was acknowledged.
+    pub fn is_shut_down(&self) -> bool {
+        self.fin_sent && self.in_flight.is_empty()
+    }
+
+    pub fn handle_packet(&mut self, packet: UtpPacket, now: Instant) {
+        if self.state == UtpConnectionState::Failed {
+            return;
+        }
+        if packet.packet_type() == UtpPacketType::Reset {
+            self.state = UtpConnectionState::Failed;
+            return;
+        }
+        self.receive_packet_timing(&packet, now);
+
+        if packet.packet_type() == UtpPacketType::Syn {
+            // our acknowledgement of the SYN was lost
+            self.ack_pending = true;
+            return;
+        }
+        if self.state == UtpConnectionState::SynSent {
+            if packet.packet_type() != UtpPacketType::State {
+                return;
+            }
+            self.state = UtpConnectionState::Connected;
+            self.ack_nr = packet.seq_nr().wrapping_sub(1);
+        }
+
+        self.handle_ack(&packet, now);
+
+        if matches!(
+            packet.packet_type(),
+            UtpPacketType::Data | UtpPacketType::Fin
+        ) {
+            self.ack_pending = true;
+            if Self::is_after(packet.seq_nr(), self.ack_nr) && !self.fin_received {
+                self.out_of_order.insert(packet.seq_nr(), packet);
+                self.deliver_in_order();
+            }
+        }
+    }
+
+    /// Returns the number of bytes accepted, 0 when the send buffer is full.
+    pub fn write(&mut self, bytes: &[u8]) -> usize {
+        let accepted = bytes
+            .len()
+            .min(Self::SEND_BUFFER_SIZE - self.send_buffer.len());
+        self.send_buffer.extend(&bytes[..accepted]);
+
+        accepted
+    }
+
+    pub fn read(&mut self, bytes: &mut [u8]) -> usize {
+        let read_bytes = self.peek(bytes);
+        self.receive_buffer.drain(..read_bytes);
+
+        read_bytes
+    }
+
+    pub fn peek(&self, bytes: &mut [u8]) -> usize {
+        let peeked_bytes = bytes.len().min(self.receive_buffer.len());
+        self.receive_buffer
+            .iter()
+            .take(peeked_bytes)
+            .zip(bytes.iter_mut())
+            .for_each(|(received, byte)| *byte = *received);
+
+        peeked_bytes
+    }
+
+    /// The FIN is sent once every written byte was sent.
+    pub fn close(&mut self) {
+        self.close_requested = true;
+    }
+
+    /// Returns the packets to send at `now`: retransmissions, new data as
+    /// allowed by the windows, and acknowledgements.
+    pub fn poll(&mut self, now: Instant) -> Vec<UtpPacket> {
+        if self.state == UtpConnectionState::Failed {
+            return Vec::new();
+        }
+        self.check_timeout(now);
+        if self.state == UtpConnectionState::Failed {
+            return Vec::new();
+        }
+
+        if self.state == UtpConnectionState::Connected {
+            while !self.send_buffer.is_empty() && self.can_send(Self::PACKET_PAYLOAD_SIZE) {
+                let length = self.send_buffer.len().min(Self::PACKET_PAYLOAD_SIZE);
+                let payload = self.send_buffer.drain(..length).collect();
+                self.queue_packet(UtpPacketType::Data, payload);
+            }
+            if self.close_requested && !self.fin_sent && self.send_buffer.is_empty() {
+                self.queue_packet(UtpPacketType::Fin, Vec::new());
+                self.fin_sent = true;
+            }
+        }
+
+        let mut packets = Vec::new();
+        let mut bytes_in_flight = self.bytes_in_flight();
+        for index in 0..self.in_flight.len() {
+            let length = self.in_flight[index].payload.len();
+            if self.in_flight[index].sent_at.is_some() {
+                continue;
+            }
+            if index > 0 && bytes_in_flight + length > self.send_window() {
+                break;
+            }
+            bytes_in_flight += length;
+
+            let in_flight = &mut self.in_flight[index];
+            in_flight.sent_at = Some(now);
+            in_flight.transmissions += 1;
+            let (packet_type, seq_nr, payload) = (
+                in_flight.packet_type,
+                in_flight.seq_nr,
+                in_flight.payload.clone(),
+            );
+            packets.push(self.packet(packet_type, seq_nr, payload, now));
+        }
+
+        if self.ack_pending && packets.is_empty() && self.state == UtpConnectionState::Connected {
+            packets.push(self.packet(UtpPacketType::State, self.seq_nr, Vec::new(), now));
+        }
+        if !packets.is_empty() {
+            self.ack_pending = false;
+        }
+
+        packets
+    }
+
+    fn queue_packet(&mut self, packet_type: UtpPacketType, payload: Vec<u8>) {
+        self.in_flight.push_back(InFlightPacket {
+            packet_type,
+            seq_nr: self.seq_nr,
+            payload,
+            sent_at: None,
+            transmissions: 0,
+        });
+        self.seq_nr = self.seq_nr.wrapping_add(1);
+    }
+
+    fn packet(
+        &self,
+        packet_type: UtpPacketType,
+        seq_nr: u16,
+        payload: Vec<u8>,
+        now: Instant,
+    ) -> UtpPacket {
+        // The SYN announces the id of the packets we receive
+        let connection_id = match packet_type {
+            UtpPacketType::Syn => self.receive_id,
+            _ => self.send_id,
+        };
+        let mut packet = UtpPacket::new(packet_type, connection_id, seq_nr, self.ack_nr, payload);
+        let receive_window = Self::RECEIVE_WINDOW.saturating_sub(self.receive_buffer.len());
+        packet.stamp(self.timestamp(now), self.reply_delay, receive_window as u32);
+
+        packet
+    }
+
+    /// Microseconds since the connection was created, on 32 bits.
+    fn timestamp(&self, now: Instant) -> u32 {
+        now.duration_since(self.epoch).as_micros() as u32
+    }
+
+    fn receive_packet_timing(&mut self, packet: &UtpPacket, now: Instant) {
+        self.reply_delay = self.timestamp(now).wrapping_sub(packet.timestamp());
+        self.peer_window = packet.window_size();
+    }
+
+    fn handle_ack(&mut self, packet: &UtpPacket, now: Instant) {
+        let mut bytes_acked = 0;
+        let mut acked_packets = 0;
+        while let Some(in_flight) = self.in_flight.front() {
+            if in_flight.sent_at.is_none() && in_flight.transmissions == 0 {
+                break;
+            }
+            if Self::is_after(in_flight.seq_nr, packet.ack_nr()) {
+                break;
+            }
+            let in_flight = self.in_flight.pop_front().unwrap();
+            if let (1, Some(sent_at)) = (in_flight.transmissions, in_flight.sent_at) {
+                self.update_timeout(now.duration_since(sent_at));
+            }
+            bytes_acked += in_flight.payload.len();
+            acked_packets += 1;
+        }
+
+        if acked_packets > 0 {
+            self.duplicate_acks = 0;
+            if packet.timestamp_difference() != 0 {
+                self.ledbat
+                    .on_ack(bytes_acked, packet.timestamp_difference(), now);
+            }
+        } else if packet.packet_type() == UtpPacketType::State
+            && !self.in_flight.is_empty()
+            && packet.ack_nr() == self.in_flight[0].seq_nr.wrapping_sub(1)
+        {
+            self.duplicate_acks += 1;
+            if self.duplicate_acks == Self::DUPLICATE_ACKS_BEFORE_RESEND {
+                log::debug!("uTP packet {} lost, resending", self.in_flight[0].seq_nr);
+                self.in_flight[0].sent_at = None;
+                self.ledbat.on_loss();
+            }
+        }
+    }
+
+    /// Round trip estimation of TCP (RFC 6298).
+    fn update_timeout(&mut self, rtt: Duration) {
+        match self.rtt {
+            None => {
+                self.rtt = Some(rtt);
+                self.rtt_variance = rtt / 2;
+            }
+            Some(smoothed_rtt) => {
+                let deviation = smoothed_rtt.abs_diff(rtt);
+                self.rtt_variance = (self.rtt_variance * 3 + deviation) / 4;
+                self.rtt = Some((smoothed_rtt * 7 + rtt) / 8);
+            }
+        }
+        self.timeout =
+            (self.rtt.unwrap() + self.rtt_variance * 4).clamp(Self::MIN_TIMEOUT, Self::MAX_TIMEOUT);
+    }
+
+    /// Resends everything in flight when the oldest packet is not
+    /// acknowledged in time.
+    fn check_timeout(&mut self, now: Instant) {
+        let (sent_at, transmissions) = match self.in_flight.front() {
+            Some(InFlightPacket {
+                sent_at: Some(sent_at),
+                transmissions,
+                ..
+            }) => (*sent_at, *transmissions),
+            _ => return,
+        };
+        if now.duration_since(sent_at) < self.timeout {
+            return;
+        }
+
+        if transmissions >= Self::MAX_TRANSMISSIONS {
+            log::debug!("uTP connection {} timed out", self.receive_id);
+            self.state = UtpConnectionState::Failed;
+            return;
+        }
+        self.timeout = (self.timeout * 2).min(Self::MAX_TIMEOUT);
+        self.ledbat.on_timeout();
+        self.in_flight
+            .iter_mut()
+            .for_each(|in_flight| in_flight.sent_at = None);
+    }
+
+    fn deliver_in_order(&mut self) {
+        while let Some(packet) = self.out_of_order.remove(&self.ack_nr.wrapping_add(1)) {
+            self.ack_nr = packet.seq_nr();
+            if packet.packet_type() == UtpPacketType::Fin {
+                self.fin_received = true;
+                self.out_of_order.clear();
+                return;
+            }
+            self.receive_buffer.extend(packet.payload());
+        }
+    }
+
+    fn bytes_in_flight(&self) -> usize {
+        self.in_flight
+            .iter()
+            .filter(|in_flight| in_flight.sent_at.is_some())
+            .map(|in_flight| in_flight.payload.len())
+            .sum()
+    }
+
+    fn send_window(&self) -> usize {
+        self.ledbat.window().min(self.peer_window as usize)
+    }
+
+    /// One packet is always allowed in flight, whatever the windows.
+    fn can_send(&self, length: usize) -> bool {
+        let queued: usize = self
+            .in_flight
+            .iter()
+            .map(|in_flight| in_flight.payload.len())
+            .sum();
+        queued == 0 || queued + length <= self.send_window()
+    }
+
+    /// Whether `seq_nr` comes after `other`, the sequence numbers wrapping.
+    fn is_after(seq_nr: u16, other: u16) -> bool {
+        (seq_nr.wrapping_sub(other) as i16) > 0
+    }
+}
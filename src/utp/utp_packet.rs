@@ -0,0 +1,147 @@
+use crate::{
+    pwp::{FromBytes, IntoBytes},
+    utp::UtpPacketType,
+    Error,
+};
+
+/// uTP packet (BEP 29):
+/// <type|ver><extension><connection_id><timestamp_microseconds>
+/// <timestamp_difference_microseconds><wnd_size><seq_nr><ack_nr><payload>
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UtpPacket {
+    packet_type: UtpPacketType,
+    connection_id: u16,
+    /// sending time, in microseconds
+    timestamp: u32,
+    /// delay of the last packet received from the other side, in microseconds
+    timestamp_difference: u32,
+    /// bytes the sender can still receive
+    window_size: u32,
+    seq_nr: u16,
+    ack_nr: u16,
+    payload: Vec<u8>,
+}
+
+impl UtpPacket {
+    pub const HEADER_LENGTH: usize = 20;
+    pub const VERSION: u8 = 1;
+
+    pub fn new(
+        packet_type: UtpPacketType,
+        connection_id: u16,
+        seq_nr: u16,
+        ack_nr: u16,
+        payload: Vec<u8>,
+    ) -> Self {
+        Self {
+            packet_type,
+            connection_id,
+            timestamp: 0,
+            timestamp_difference: 0,
+            window_size: 0,
+            seq_nr,
+            ack_nr,
+            payload,
+        }
+    }
+
+    /// Sets the fields filled in right before sending the packet.
+    pub fn stamp(&mut self, timestamp: u32, timestamp_difference: u32, window_size: u32) {
+        self.timestamp = timestamp;
+        self.timestamp_difference = timestamp_difference;
+        self.window_size = window_size;
+    }
+
+    pub fn packet_type(&self) -> UtpPacketType {
+        self.packet_type
+    }
+
+    pub fn connection_id(&self) -> u16 {
+        self.connection_id
+    }
+
+    pub fn timestamp(&self) -> u32 {
+        self.timestamp
+    }
+
+    pub fn timestamp_difference(&self) -> u32 {
+        self.timestamp_difference
+    }
+
+    pub fn window_size(&self) -> u32 {
+        self.window_size
+    }
+
+    pub fn seq_nr(&self) -> u16 {
+        self.seq_nr
+    }
+
+    pub fn ack_nr(&self) -> u16 {
+        self.ack_nr
+    }
+
+    pub fn payload(&self) -> &Vec<u8> {
+        &self.payload
+    }
+}
+
+impl IntoBytes for UtpPacket {
+    fn into_bytes(self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(Self::HEADER_LENGTH + self.payload.len());
+
+        bytes.push(self.packet_type.id() << 4 | Self::VERSION);
+        bytes.push(0); // no extension
+        bytes.extend(self.connection_id.to_be_bytes());
+        bytes.extend(self.timestamp.to_be_bytes());
+        bytes.extend(self.timestamp_difference.to_be_bytes());
+        bytes.extend(self.window_size.to_be_bytes());
+        bytes.extend(self.seq_nr.to_be_bytes());
+        bytes.extend(self.ack_nr.to_be_bytes());
+        bytes.extend(self.payload);
+
+        bytes
+    }
+}
+
+impl FromBytes for UtpPacket {
+    /// Extensions, such as selective acks, are skipped.
+    fn from_bytes(bytes: &[u8]) -> Result<(Self, usize), Error> {
+        if bytes.len() < Self::HEADER_LENGTH {
+            return Err(Error::InvalidUtpPacket);
+        }
+        if bytes[0] & 0x0F != Self::VERSION {
+            return Err(Error::InvalidUtpPacket);
+        }
+        let packet_type = UtpPacketType::from_id(bytes[0] >> 4)?;
+        let u16_at = |offset: usize| u16::from_be_bytes([bytes[offset], bytes[offset + 1]]);
+        let u32_at =
+            |offset: usize| u32::from_be_bytes(bytes[offset..offset + 4].try_into().unwrap());
+
+        let mut extension = bytes[1];
+        let mut offset = Self::HEADER_LENGTH;
+        while extension != 0 {
+            if bytes.len() < offset + 2 {
+                return Err(Error::InvalidUtpPacket);
+            }
+            extension = bytes[offset];
+            offset += 2 + bytes[offset + 1] as usize;
+        }
+        if bytes.len() < offset {
+            return Err(Error::InvalidUtpPacket);
+        }
+
+        Ok((
+            Self {
+                packet_type,
+                connection_id: u16_at(2),
+                timestamp: u32_at(4),
+                timestamp_difference: u32_at(8),
+                window_size: u32_at(12),
+                seq_nr: u16_at(16),
+                ack_nr: u16_at(18),
+                payload: bytes[offset..].to_vec(),
+            },
+            bytes.len(),
+        ))
+    }
+}
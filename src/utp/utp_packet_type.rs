@@ -0,0 +1,39 @@
+use crate::Error;
+
+/// Type of a uTP packet, the high nibble of its first byte
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UtpPacketType {
+    /// regular data packet, carrying a payload
+    Data,
+    /// last packet of the connection
+    Fin,
+    /// acknowledgement, without payload
+    State,
+    /// forced termination of the connection
+    Reset,
+    /// connection request
+    Syn,
+}
+
+impl UtpPacketType {
+    pub fn id(self) -> u8 {
+        match self {
+            UtpPacketType::Data => 0,
+            UtpPacketType::Fin => 1,
+            UtpPacketType::State => 2,
+            UtpPacketType::Reset => 3,
+            UtpPacketType::Syn => 4,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Result<Self, Error> {
+        match id {
+            0 => Ok(UtpPacketType::Data),
+            1 => Ok(UtpPacketType::Fin),
+            2 => Ok(UtpPacketType::State),
+            3 => Ok(UtpPacketType::Reset),
+            4 => Ok(UtpPacketType::Syn),
+            _ => Err(Error::InvalidUtpPacket),
+        }
+    }
+}
@@ -0,0 +1,308 @@
+use {
+    crate::{
+        pwp::{FromBytes, IntoBytes},
+        utp::{
+            SimulatedLink, UtpConnection, UtpConnectionState, UtpPacket, UtpPacketType, UtpStream,
+        },
+        Error,
+    },
+    std::{
+        collections::{HashMap, VecDeque},
+        fmt,
+        net::{SocketAddr, UdpSocket},
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc, Condvar, Mutex,
+        },
+        thread::{self, JoinHandle},
+        time::{Duration, Instant},
+    },
+};
+
+/// Connection shared between the socket thread and its stream
+#[derive(Debug)]
+pub struct UtpConnectionHandle {
+    connection: Mutex<UtpConnection>,
+    /// notified whenever the connection changed
+    changed: Condvar,
+}
+
+impl UtpConnectionHandle {
+    fn new(connection: UtpConnection) -> Self {
+        Self {
+            connection: Mutex::new(connection),
+            changed: Condvar::new(),
+        }
+    }
+
+    pub fn connection(&self) -> &Mutex<UtpConnection> {
+        &self.connection
+    }
+
+    pub fn changed(&self) -> &Condvar {
+        &self.changed
+    }
+}
+
+/// Datagram held back by the simulated link
+type DelayedDatagram = (Instant, SocketAddr, Vec<u8>);
+
+/// Called when a packet reached a connection
+type Notifier = Box<dyn Fn() + Send + Sync>;
+
+struct SharedSocket {
+    socket: UdpSocket,
+    link: SimulatedLink,
+    /// connections by peer address and id of the packets received
+    connections: Mutex<HashMap<(SocketAddr, u16), Arc<UtpConnectionHandle>>>,
+    /// connections initiated by the peers, not accepted yet
+    incoming: Mutex<VecDeque<UtpStream>>,
+    incoming_changed: Condvar,
+    delayed: Mutex<Vec<DelayedDatagram>>,
+    running: AtomicBool,
+    notifier: Mutex<Option<Notifier>>,
+}
+
+impl fmt::Debug for SharedSocket {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter
+            .debug_struct("SharedSocket")
+            .field("socket", &self.socket)
+            .field("link", &self.link)
+            .finish()
+    }
+}
+
+/// UDP socket carrying uTP connections (BEP 29). A thread receives the
+/// packets, dispatches them to the connections and sends what they have
+/// to send.
+#[derive(Debug)]
+pub struct UtpSocket {
+    shared: Arc<SharedSocket>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl UtpSocket {
+    /// How often the connections are polled when no packet is received
+    const TICK: Duration = Duration::from_millis(5);
+    /// Connections initiated by the peers are refused beyond this backlog
+    const MAX_INCOMING: usize = 32;
+    const MAX_DATAGRAM_SIZE: usize = 2048;
+    pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+
+    pub fn bind(address: SocketAddr) -> Result<Self, Error> {
+        Self::with_link(address, SimulatedLink::default())
+    }
+
+    /// Socket sending its packets through a degraded `link`.
+    pub fn with_link(address: SocketAddr, link: SimulatedLink) -> Result<Self, Error> {
+        let socket = UdpSocket::bind(address).map_err(|_| Error::FailedToBindUtpSocket)?;
+        socket
+            .set_read_timeout(Some(Self::TICK))
+            .map_err(|_| Error::FailedToBindUtpSocket)?;
+
+        let shared = Arc::new(SharedSocket {
+            socket,
+            link,
+            connections: Mutex::new(HashMap::new()),
+            incoming: Mutex::new(VecDeque::new()),
+            incoming_changed: Condvar::new(),
+            delayed: Mutex::new(Vec::new()),
+            running: AtomicBool::new(true),
+            notifier: Mutex::new(None),
+        });
+        let shared_ref = shared.clone();
+        let thread = thread::spawn(move || Self::run(shared_ref));
+
+        Ok(Self {
+            shared,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, Error> {
+        self.shared
+            .socket
+            .local_addr()
+            .map_err(|_| Error::FailedToBindUtpSocket)
+    }
+
+    /// Opens a connection to `address`, failing if it is not acknowledged
+    /// within `timeout`.
+    pub fn connect(&self, address: SocketAddr, timeout: Duration) -> Result<UtpStream, Error> {
+        let handle = {
+            let mut connections = self.shared.connections.lock().unwrap();
+            let receive_id = loop {
+                let receive_id: u16 = rand::random();
+                if !connections.contains_key(&(address, receive_id))
+                    && !connections.contains_key(&(address, receive_id.wrapping_add(1)))
+                {
+                    break receive_id;
+                }
+            };
+            let handle = Arc::new(UtpConnectionHandle::new(UtpConnection::connect(
+                receive_id,
+                Instant::now(),
+            )));
+            connections.insert((address, receive_id), handle.clone());
+            handle
+        };
+
+        let deadline = Instant::now() + timeout;
+        let mut connection = handle.connection.lock().unwrap();
+        while connection.state() == UtpConnectionState::SynSent {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            connection = handle
+                .changed
+                .wait_timeout(connection, deadline - now)
+                .unwrap()
+                .0;
+        }
+
+        if connection.state() != UtpConnectionState::Connected {
+            let receive_id = connection.receive_id();
+            drop(connection);
+            self.shared
+                .connections
+                .lock()
+                .unwrap()
+                .remove(&(address, receive_id));
+            return Err(Error::UtpConnectionFailed);
+        }
+        drop(connection);
+
+        Ok(UtpStream::new(handle, address))
+    }
+
+    /// Calls `notifier` whenever a connection received a packet, for the
+    /// owners of streams that do not want to block on them.
+    pub fn set_notifier(&self, notifier: impl Fn() + Send + Sync + 'static) {
+        *self.shared.notifier.lock().unwrap() = Some(Box::new(notifier));
+    }
+
+    /// Waits for a connection initiated by a peer.
+    pub fn accept(&self) -> Result<UtpStream, Error> {
+        let mut incoming = self.shared.incoming.lock().unwrap();
+        loop {
+            if let Some(stream) = incoming.pop_front() {
+                return Ok(stream);
+            }
+            if !self.shared.running.load(Ordering::Relaxed) {
+                return Err(Error::UtpSocketClosed);
+            }
+            incoming = self
+                .shared
+                .incoming_changed
+                .wait_timeout(incoming, Self::TICK * 100)
+                .unwrap()
+                .0;
+        }
+    }
+
+    fn run(shared: Arc<SharedSocket>) {
+        let mut buffer = [0u8; Self::MAX_DATAGRAM_SIZE];
+        while shared.running.load(Ordering::Relaxed) {
+            if let Ok((length, address)) = shared.socket.recv_from(&mut buffer) {
+                match UtpPacket::from_bytes(&buffer[..length]) {
+                    Ok((packet, _)) => Self::dispatch(&shared, packet, address),
+                    Err(_) => log::debug!("Invalid uTP packet from {}", address),
+                }
+            }
+            Self::poll_connections(&shared);
+            Self::send_delayed(&shared);
+        }
+    }
+
+    fn dispatch(shared: &SharedSocket, packet: UtpPacket, address: SocketAddr) {
+        let now = Instant::now();
+        let mut connections = shared.connections.lock().unwrap();
+
+        let key = match packet.packet_type() {
+            UtpPacketType::Syn => (address, packet.connection_id().wrapping_add(1)),
+            _ => (address, packet.connection_id()),
+        };
+        if let Some(handle) = connections.get(&key) {
+            handle.connection.lock().unwrap().handle_packet(packet, now);
+            handle.changed.notify_all();
+            if let Some(notifier) = shared.notifier.lock().unwrap().as_ref() {
+                notifier();
+            }
+            return;
+        }
+        if packet.packet_type() != UtpPacketType::Syn {
+            return;
+        }
+
+        let mut incoming = shared.incoming.lock().unwrap();
+        if incoming.len() >= Self::MAX_INCOMING {
+            log::debug!("uTP connection of {} refused, too many pending", address);
+            return;
+        }
+        log::debug!("uTP connection initiated by {}", address);
+        let handle = Arc::new(UtpConnectionHandle::new(UtpConnection::accept(
+            &packet, now,
+        )));
+        connections.insert(key, handle.clone());
+        incoming.push_back(UtpStream::new(handle, address));
+        shared.incoming_changed.notify_all();
+    }
+
+    /// Sends the packets of the connections, and forgets the connections
+    /// that are closed.
+    fn poll_connections(shared: &SharedSocket) {
+        let now = Instant::now();
+        let mut connections = shared.connections.lock().unwrap();
+
+        connections.retain(|(address, _), handle| {
+            let mut connection = handle.connection.lock().unwrap();
+            for packet in connection.poll(now) {
+                Self::send(shared, packet.into_bytes(), *address);
+            }
+            handle.changed.notify_all();
+
+            // Only the socket holds the connection once its stream is dropped
+            let abandoned = Arc::strong_count(handle) == 1 && connection.is_shut_down();
+            !(connection.is_closed() || abandoned)
+        });
+    }
+
+    fn send(shared: &SharedSocket, datagram: Vec<u8>, address: SocketAddr) {
+        if shared.link.drops_packet() {
+            return;
+        }
+        if shared.link.delay().is_zero() {
+            let _ = shared.socket.send_to(&datagram, address);
+        } else {
+            let due = Instant::now() + shared.link.delay();
+            shared
+                .delayed
+                .lock()
+                .unwrap()
+                .push((due, address, datagram));
+        }
+    }
+
+    fn send_delayed(shared: &SharedSocket) {
+        let now = Instant::now();
+        let mut delayed = shared.delayed.lock().unwrap();
+        delayed.retain(|(due, address, datagram)| {
+            if *due > now {
+                return true;
+            }
+            let _ = shared.socket.send_to(datagram, address);
+            false
+        });
+    }
+}
+
+impl Drop for UtpSocket {
+    fn drop(&mut self) {
+        self.shared.running.store(false, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
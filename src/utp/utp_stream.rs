@@ -0,0 +1,165 @@
+use {
+    crate::{
+        tcp::Transport,
+        utp::{UtpConnection, UtpConnectionHandle, UtpConnectionState},
+    },
+    std::{
+        cell::Cell,
+        fmt,
+        io::{self, Read, Write},
+        net::SocketAddr,
+        os::fd::RawFd,
+        sync::{Arc, MutexGuard},
+        time::{Duration, Instant},
+    },
+};
+
+/// Byte stream of a uTP connection, blocking like a `TcpStream`
+pub struct UtpStream {
+    handle: Arc<UtpConnectionHandle>,
+    peer_addr: SocketAddr,
+    nonblocking: Cell<bool>,
+    read_timeout: Cell<Option<Duration>>,
+    write_timeout: Cell<Option<Duration>>,
+}
+
+impl UtpStream {
+    pub fn new(handle: Arc<UtpConnectionHandle>, peer_addr: SocketAddr) -> Self {
+        Self {
+            handle,
+            peer_addr,
+            nonblocking: Cell::new(false),
+            read_timeout: Cell::new(None),
+            write_timeout: Cell::new(None),
+        }
+    }
+
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer_addr
+    }
+
+    /// Runs `operation` on the connection until it returns something,
+    /// waiting for the connection to change in between.
+    fn wait_for<T>(
+        &self,
+        timeout: Option<Duration>,
+        mut operation: impl FnMut(&mut UtpConnection) -> Option<io::Result<T>>,
+    ) -> io::Result<T> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut connection = self.handle.connection().lock().unwrap();
+        loop {
+            if let Some(result) = operation(&mut connection) {
+                return result;
+            }
+            if connection.state() == UtpConnectionState::Failed {
+                return Err(io::ErrorKind::ConnectionReset.into());
+            }
+            if self.nonblocking.get() {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            connection = self.wait(connection, deadline)?;
+        }
+    }
+
+    fn wait<'a>(
+        &self,
+        connection: MutexGuard<'a, UtpConnection>,
+        deadline: Option<Instant>,
+    ) -> io::Result<MutexGuard<'a, UtpConnection>> {
+        let changed = self.handle.changed();
+        match deadline {
+            None => Ok(changed.wait(connection).unwrap()),
+            Some(deadline) => {
+                let now = Instant::now();
+                if now >= deadline {
+                    return Err(io::ErrorKind::WouldBlock.into());
+                }
+                Ok(changed.wait_timeout(connection, deadline - now).unwrap().0)
+            }
+        }
+    }
+}
+
+impl Read for UtpStream {
+    /// Returns 0 once the peer closed the connection.
+    fn read(&mut self, bytes: &mut [u8]) -> io::Result<usize> {
+        self.wait_for(self.read_timeout.get(), |connection| {
+            match connection.read(bytes) {
+                0 if !connection.is_finished() && !bytes.is_empty() => None,
+                read_bytes => Some(Ok(read_bytes)),
+            }
+        })
+    }
+}
+
+impl Write for UtpStream {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.wait_for(self.write_timeout.get(), |connection| {
+            if connection.state() == UtpConnectionState::Failed {
+                return Some(Err(io::ErrorKind::BrokenPipe.into()));
+            }
+            match connection.write(bytes) {
+                0 if !bytes.is_empty() => None,
+                written_bytes => Some(Ok(written_bytes)),
+            }
+        })
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transport for UtpStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        self.nonblocking.set(nonblocking);
+        Ok(())
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.read_timeout.set(timeout);
+        Ok(())
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.write_timeout.set(timeout);
+        Ok(())
+    }
+
+    /// Packets are sent on every tick of the socket, there is no batching
+    /// to disable.
+    fn set_nodelay(&self, _nodelay: bool) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn peek(&self, bytes: &mut [u8]) -> io::Result<usize> {
+        self.wait_for(self.read_timeout.get(), |connection| {
+            match connection.peek(bytes) {
+                0 if !connection.is_finished() && !bytes.is_empty() => None,
+                peeked_bytes => Some(Ok(peeked_bytes)),
+            }
+        })
+    }
+
+    /// Packets are received by the thread of the socket, see
+    /// `UtpSocket::set_notifier`.
+    fn raw_fd(&self) -> Option<RawFd> {
+        None
+    }
+}
+
+impl fmt::Debug for UtpStream {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter
+            .debug_struct("UtpStream")
+            .field("peer_addr", &self.peer_addr)
+            .finish()
+    }
+}
+
+impl Drop for UtpStream {
+    fn drop(&mut self) {
+        self.handle.connection().lock().unwrap().close();
+        self.handle.changed().notify_all();
+    }
+}